@@ -0,0 +1,143 @@
+//! Measures the rule's throughput end to end (rustfmt included) on small,
+//! medium and large generated files, in full and on `--file-lines` partial
+//! runs, with a [`RustfmtPool`] and with [chunking](RustfmtRuleOptions::chunk_lines),
+//! so that changes to how rustfmt is run can be compared by numbers.
+//!
+//! Criterion isn't a dependency, so this is its own small harness: each
+//! benchmark runs once to warm up (rustfmt's capability probes are cached
+//! after that), then until it's run `ITERATIONS` times or for `BUDGET`, and
+//! reports its fastest and median runs. Run with `cargo bench`, optionally
+//! with a filter on the benchmarks' names, eg `cargo bench -- large`.
+
+use std::{
+    env,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use tree_sitter::{Parser, Tree};
+use tree_sitter_lint_plugin_rustfmt::api::{
+    discover_edition, RustfmtOptions, RustfmtPool, RustfmtRule, RustfmtRuleOptions,
+};
+
+const ITERATIONS: usize = 20;
+const BUDGET: Duration = Duration::from_secs(5);
+
+/// Misformatted items, cycled through to make the files.
+const ITEMS: &[&str] = &[
+    "use std::{fmt,io};\n",
+    "fn  name(a:u8,b:u16)->u32{ a as u32+b as u32 }\n",
+    "struct Name{a:u8,b:Vec<u16>}\n",
+    "impl Name {\nfn get(&self)->u8{self.a}\n}\n",
+    "fn nested() {\nlet x=1;\nif x>0 {\nprintln!(\"{}\",x);\n}\n}\n",
+    "enum Kind { A,B(u8),C{d:u16} }\n",
+    "const  LIMIT: usize=10;\n",
+];
+
+/// A file of about `lines` lines of misformatted items, separated by blank
+/// lines (where chunks can be split).
+fn generate(lines: usize) -> String {
+    let mut source = String::new();
+    for item in ITEMS.iter().cycle() {
+        if source.lines().count() >= lines {
+            break;
+        }
+        source.push_str(item);
+        source.push('\n');
+    }
+    source
+}
+
+fn parse(source: &[u8]) -> Tree {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    parser.parse(source, None).unwrap()
+}
+
+struct Bench {
+    filter: Option<String>,
+}
+
+impl Bench {
+    /// Times `run` over `source`, unless it's filtered out.
+    fn run(&self, name: &str, source: &str, mut run: impl FnMut(&[u8], &Tree) -> usize) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            return;
+        }
+        let tree = parse(source.as_bytes());
+        let violations = run(source.as_bytes(), &tree);
+        let mut times = Vec::with_capacity(ITERATIONS);
+        let started = Instant::now();
+        while times.len() < ITERATIONS && started.elapsed() < BUDGET {
+            let run_started = Instant::now();
+            black_box(run(black_box(source.as_bytes()), &tree));
+            times.push(run_started.elapsed());
+        }
+        times.sort();
+        let median = times[times.len() / 2];
+        println!(
+            "{name:<24} {:>6} lines {:>5} violations  fastest {:>10.2?}  median {:>10.2?}  {:>8.1} KiB/s  ({} runs)",
+            source.lines().count(),
+            violations,
+            times[0],
+            median,
+            source.len() as f64 / 1024.0 / median.as_secs_f64(),
+            times.len(),
+        );
+    }
+}
+
+fn main() {
+    let bench = Bench {
+        // `cargo bench` passes `--bench` (and other flags) before the filter.
+        filter: env::args().skip(1).find(|arg| !arg.starts_with("--")),
+    };
+    let rule = RustfmtRule::new(RustfmtRuleOptions::default());
+    let sizes = [("small", 20), ("medium", 500), ("large", 5_000)];
+
+    for (size, lines) in sizes {
+        let source = generate(lines);
+        bench.run(&format!("check/{size}"), &source, |source, tree| {
+            rule.check(source, tree).len()
+        });
+    }
+
+    for (size, lines) in sizes {
+        let source = generate(lines);
+        // A few edits' worth of lines in the middle of the file.
+        let middle = lines / 2;
+        let ranges = [middle..middle + 3, middle + 10..middle + 12];
+        bench.run(&format!("check_lines/{size}"), &source, |source, tree| {
+            rule.check_lines(source, tree, &ranges).violations.len()
+        });
+    }
+
+    // With the edition the rule detects, so that it uses the pool.
+    let pool = RustfmtPool::new(
+        RustfmtOptions {
+            edition: env::current_dir()
+                .ok()
+                .as_deref()
+                .and_then(discover_edition),
+            ..Default::default()
+        },
+        2,
+    );
+    let source = generate(500);
+    bench.run("check_with_pool/medium", &source, |source, tree| {
+        rule.check_with_pool(source, tree, &pool).len()
+    });
+
+    let chunked = RustfmtRule::new(RustfmtRuleOptions {
+        chunk_lines: Some(500),
+        ..Default::default()
+    });
+    let source = generate(5_000);
+    bench.run("check_chunked/large", &source, |source, tree| {
+        chunked.check(source, tree).len()
+    });
+}
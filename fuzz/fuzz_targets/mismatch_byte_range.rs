@@ -0,0 +1,54 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use tree_sitter_lint_plugin_rustfmt::{anchor_mismatch, LineOffsets, Mismatch};
+
+#[derive(Arbitrary, Debug)]
+struct Input<'a> {
+    source: &'a [u8],
+    original_begin_line: usize,
+    original_end_line: usize,
+    original_is_empty: bool,
+    split: usize,
+}
+
+fuzz_target!(|input: Input| {
+    let line_offsets = LineOffsets::new(input.source);
+    let line_count = line_offsets.line_count();
+    assert_eq!(
+        line_count,
+        input.source.split_inclusive(|&byte| byte == b'\n').count()
+    );
+    for line in 1..=line_count + 1 {
+        let start = line_offsets.line_start(line).unwrap();
+        assert!(start == 0 || input.source[start - 1] == b'\n' || start == input.source.len());
+    }
+    let (first, second) = input
+        .source
+        .split_at(input.split % (input.source.len() + 1));
+    assert_eq!(LineOffsets::from_chunks([first, second]), line_offsets);
+
+    let mismatch = Mismatch {
+        original_begin_line: input.original_begin_line,
+        original_end_line: input.original_end_line,
+        expected_begin_line: input.original_begin_line,
+        expected_end_line: input.original_end_line,
+        original: if input.original_is_empty {
+            String::new()
+        } else {
+            "x\n".to_owned()
+        },
+        expected: String::new(),
+    };
+    if let Some(range) = line_offsets.mismatch_byte_range(&mismatch) {
+        assert!(range.start <= range.end && range.end <= input.source.len());
+        assert!(input.original_is_empty == (range.start == range.end));
+    }
+    if let Some(anchored) = anchor_mismatch(input.source, &line_offsets, &mismatch) {
+        assert_eq!(
+            &input.source[anchored.range.clone()],
+            anchored.original.as_bytes()
+        );
+    }
+});
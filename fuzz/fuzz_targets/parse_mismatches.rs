@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tree_sitter_lint_plugin_rustfmt::{parse_mismatches, LineOffsets};
+
+fuzz_target!(|data: (&str, &[u8])| {
+    let (json, source) = data;
+    let Ok(files) = parse_mismatches(json) else {
+        return;
+    };
+    let line_offsets = LineOffsets::new(source);
+    for mismatch in files.iter().flat_map(|file| &file.mismatches) {
+        if let Some(range) = line_offsets.mismatch_byte_range(mismatch) {
+            assert!(range.start <= range.end && range.end <= source.len());
+        }
+    }
+});
@@ -0,0 +1,238 @@
+use std::{borrow::Cow, ops::Range};
+
+use crate::{LineOffsets, Mismatch};
+
+/// How many lines around a mismatch's reported lines to search for its
+/// `original` text when it isn't at those lines.
+const SEARCH_LINES: usize = 5;
+
+/// A mismatch located in the buffer it was produced from: `range` holds
+/// exactly `original`, and `expected` is in the same line-ending style.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnchoredMismatch<'a> {
+    pub range: Range<usize>,
+    pub original: Cow<'a, str>,
+    pub expected: Cow<'a, str>,
+}
+
+/// Finds where `mismatch.original` is in `source`, validating the range its
+/// line numbers map to. If the bytes there don't match (version skew, CRLF
+/// line endings, off-by-one line numbers), the text is searched for near those
+/// lines instead and the nearest occurrence is used, with a warning. `None`
+/// means it can't be found, so no fix should be derived from the mismatch.
+pub fn anchor_mismatch<'a>(
+    source: &[u8],
+    line_offsets: &LineOffsets,
+    mismatch: &'a Mismatch,
+) -> Option<AnchoredMismatch<'a>> {
+    let reported_range = line_offsets.mismatch_byte_range(mismatch);
+    if mismatch.original.is_empty() {
+        let mut expected = Cow::Borrowed(mismatch.expected.as_str());
+        // For an empty buffer, the newline the emitter adds after the last
+        // line comes on top of the (empty) line rustfmt's output has, ie
+        // `"\n\n"` for a file that formats to `"\n"`.
+        if source.is_empty() && mismatch.expected.ends_with("\n\n") {
+            expected = Cow::Borrowed(&mismatch.expected[..mismatch.expected.len() - 1]);
+        }
+        if line_offsets.has_crlf() {
+            expected = Cow::Owned(expected.replace('\n', "\r\n"));
+        }
+        return reported_range.map(|range| AnchoredMismatch {
+            range,
+            original: Cow::Borrowed(""),
+            expected,
+        });
+    }
+
+    // rustfmt reports (and expects) `\n` line endings regardless of the input.
+    let mut variants = vec![(
+        Cow::Borrowed(&*mismatch.original),
+        Cow::Borrowed(&*mismatch.expected),
+    )];
+    if line_offsets.has_crlf() {
+        variants.push((
+            Cow::Owned(mismatch.original.replace('\n', "\r\n")),
+            Cow::Owned(mismatch.expected.replace('\n', "\r\n")),
+        ));
+    }
+
+    if let Some(range) = reported_range.as_ref() {
+        let bytes = &source[range.clone()];
+        for (original, expected) in &variants {
+            if bytes == original.as_bytes() {
+                return Some(AnchoredMismatch {
+                    range: range.clone(),
+                    original: original.clone(),
+                    expected: expected.clone(),
+                });
+            }
+            // The emitter terminates every line with a newline, including a
+            // last line that has none in the source.
+            if range.end == source.len() {
+                if let (Some(original), Some(expected)) =
+                    (strip_line_ending(original), strip_line_ending(expected))
+                {
+                    if bytes == original.as_bytes() {
+                        return Some(AnchoredMismatch {
+                            range: range.clone(),
+                            original: Cow::Owned(original.to_owned()),
+                            expected: Cow::Owned(expected.to_owned()),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    let last_line = line_offsets.line_count();
+    let first_search_line = mismatch
+        .original_begin_line
+        .saturating_sub(SEARCH_LINES)
+        .max(1);
+    let last_search_line = mismatch
+        .original_end_line
+        .saturating_add(SEARCH_LINES)
+        .min(last_line);
+    let reported_start = reported_range.as_ref().map_or_else(
+        || {
+            line_offsets
+                .line_start(first_search_line)
+                .unwrap_or_default()
+        },
+        |range| range.start,
+    );
+    let (anchored, variant) = (first_search_line..=last_search_line)
+        .filter_map(|line| line_offsets.line_start(line))
+        .flat_map(|start| {
+            variants
+                .iter()
+                .enumerate()
+                .filter(move |(_, (original, _))| source[start..].starts_with(original.as_bytes()))
+                .map(move |(variant, (original, _))| (start..start + original.len(), variant))
+        })
+        .min_by_key(|(range, _)| range.start.abs_diff(reported_start))?;
+    log::warn!(
+        "rustfmt reported a mismatch at lines {}-{} whose text is at bytes {}-{}",
+        mismatch.original_begin_line,
+        mismatch.original_end_line,
+        anchored.start,
+        anchored.end,
+    );
+    let (original, expected) = variants.swap_remove(variant);
+    Some(AnchoredMismatch {
+        range: anchored,
+        original,
+        expected,
+    })
+}
+
+fn strip_line_ending(text: &str) -> Option<&str> {
+    text.strip_suffix("\r\n")
+        .or_else(|| text.strip_suffix('\n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mismatch(begin_line: usize, end_line: usize, original: &str, expected: &str) -> Mismatch {
+        Mismatch {
+            original_begin_line: begin_line,
+            original_end_line: end_line,
+            expected_begin_line: begin_line,
+            expected_end_line: end_line,
+            original: original.to_owned(),
+            expected: expected.to_owned(),
+        }
+    }
+
+    fn anchor(source: &str, mismatch: &Mismatch) -> Option<(Range<usize>, String)> {
+        anchor_mismatch(
+            source.as_bytes(),
+            &LineOffsets::new(source.as_bytes()),
+            mismatch,
+        )
+        .map(|anchored| {
+            assert_eq!(&source[anchored.range.clone()], anchored.original);
+            (anchored.range, anchored.expected.into_owned())
+        })
+    }
+
+    #[test]
+    fn test_anchor_exact() {
+        let source = "fn a() {}\nfn  b( ) {}\n";
+        assert_eq!(
+            anchor(source, &mismatch(2, 2, "fn  b( ) {}\n", "fn b() {}\n")),
+            Some((10..22, "fn b() {}\n".to_owned()))
+        );
+        assert_eq!(
+            anchor(source, &mismatch(3, 3, "", "\n")),
+            Some((22..22, "\n".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_anchor_without_trailing_newline() {
+        assert_eq!(
+            anchor(
+                "fn a() {}\n\nfn  c() {}",
+                &mismatch(2, 3, "\nfn  c() {}\n", "fn c() {}\n\n")
+            ),
+            Some((10..21, "fn c() {}\n".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_anchor_empty_source() {
+        assert_eq!(
+            anchor("", &mismatch(1, 1, "", "\n\n")),
+            Some((0..0, "\n".to_owned()))
+        );
+        assert_eq!(
+            anchor("", &mismatch(1, 1, "", "\n")),
+            Some((0..0, "\n".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_anchor_crlf() {
+        assert_eq!(
+            anchor(
+                "fn a() {}\r\nfn  b( ) {}\r\n",
+                &mismatch(2, 2, "fn  b( ) {}\n", "fn b() {}\n")
+            ),
+            Some((11..24, "fn b() {}\r\n".to_owned()))
+        );
+        assert_eq!(
+            anchor("fn a() {}\r\n", &mismatch(2, 2, "", "\nfn b() {}\n")),
+            Some((11..11, "\r\nfn b() {}\r\n".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_reanchor() {
+        let source = "fn a() {}\nfn  b( ) {}\nfn c() {}\n";
+        // Off by one in either direction.
+        for begin_line in [1, 3] {
+            assert_eq!(
+                anchor(
+                    source,
+                    &mismatch(begin_line, begin_line, "fn  b( ) {}\n", "fn b() {}\n")
+                ),
+                Some((10..22, "fn b() {}\n".to_owned()))
+            );
+        }
+        // Past the end of the buffer.
+        assert_eq!(
+            anchor(source, &mismatch(4, 4, "fn c() {}\n", "fn c() {}\n")),
+            Some((22..32, "fn c() {}\n".to_owned()))
+        );
+        // The nearest of several occurrences.
+        let source = "x;\ny;\ny;\ny;\nx;\n";
+        assert_eq!(
+            anchor(source, &mismatch(4, 4, "x;\n", "x;\n\n")),
+            Some((12..15, "x;\n\n".to_owned()))
+        );
+        assert_eq!(anchor(source, &mismatch(2, 2, "z;\n", "z;\n")), None);
+    }
+}
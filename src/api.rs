@@ -0,0 +1,47 @@
+//! The intentionally public API, which follows semver: nothing here is
+//! removed or changed incompatibly outside of a major release. Plugin authors
+//! and hosts should import from here.
+//!
+//! The crate root exports the same items. The lower-level pieces the rules
+//! are built from (eg mapping mismatches to byte ranges, or finding skip
+//! regions) are hidden from its docs, and can change in any release.
+
+#[cfg(feature = "metrics")]
+pub use crate::metrics;
+pub use crate::rules;
+#[cfg(feature = "sarif")]
+pub use crate::sarif;
+#[cfg(feature = "schema")]
+pub use crate::schema;
+pub use crate::{
+    changed_item_rows, changed_line_ranges, changed_range_item_rows, check_formatted,
+    check_formatted_file, discover_config, discover_config_for_file, discover_edition,
+    discover_edition_for_file, explain, format_files_batch, format_node, format_snippet,
+    format_source, format_to_string, ignored_unstable_settings, render_patch, run_rustfmt,
+    run_rustfmt_on_lines, Attribution, BlankLinesRule, BlankLinesRuleOptions, BudgetedCheck,
+    CacheKey, ChangedLines, CleanCache, CommandFormatter, CommentMismatches, ConfigFingerprint,
+    DiffStatSummary, FileDiffStat, FileHeaderRule, FileHeaderRuleOptions, FileMismatches,
+    FileStyle, FinalNewlineRule, Fix, FixAttempts, FormatHooks, Formatter, FormatterBackend,
+    FormatterCapabilities, FormatterRule, FormattingReport, FormattingScope, FormattingStatus,
+    GroupImports, HeaderTemplate, InputMode, LanguageFormatters, LineRangeContext, MappedMismatch,
+    Mismatch, MismatchCategory, MismatchFilter, MismatchFormat, MockBackend, NewlineStyle,
+    NodeAnchoring, OrganizeImportsRule, OrganizeImportsRuleOptions, PartialCheck, PendingRuns,
+    Plugin, PluginBuilder, PluginRule, ProcessBackend, QueuedFixes, RecentResults, ReportCallback,
+    RopeOrSlice, RuleConfigError, RuleMode, RunStatus, RustfmtError, RustfmtFormatter,
+    RustfmtOptions, RustfmtPool, RustfmtRule, RustfmtRuleConfig, RustfmtRuleOptions,
+    RustfmtService, RustfmtSetup, RustfmtVersion, Severity, SkipMacroInvocations, SourceBuffer,
+    StderrDiagnostic, StyleDrift, StyleOutlier, StyleProfile, SymlinkPolicy,
+    TrailingWhitespaceRule, Violation, ViolationData, BLANK_LINES_RULE_NAME, BRACE_PLACEMENT,
+    COMMENT_FORMATTING, DOC_CODE_FORMATTING, EXTRA_FINAL_NEWLINES, FILE_HEADER_RULE_NAME,
+    FILE_NEEDS_REFORMATTING, FINAL_NEWLINE_RULE_NAME, FORMATTER_FAILED, FORMATTER_RULE_NAME,
+    FORMATTING_FIX_REJECTED, GENERATED_MARKER_LINES, INCONSISTENT_LINE_ENDINGS, LEFT_UNFORMATTED,
+    LINE_OVERFLOW, LINE_TOO_LONG, LONG_LINE_REFLOW, MALFORMED_FILE_HEADER, MAX_CONCURRENT_RUNS_VAR,
+    MISSING_FILE_HEADER, MISSING_FINAL_NEWLINE, MMAP_THRESHOLD, ORGANIZE_IMPORTS_RULE_NAME,
+    PARTIALLY_FORMATTED, RULE_NAME, RUSTFMT_FAILED, RUSTFMT_TIMED_OUT, RUSTFMT_VERSION_MISMATCH,
+    RUST_LANGUAGE, STDIN_FILE_NAME, TRAILING_WHITESPACE, TRAILING_WHITESPACE_LEFT,
+    TRAILING_WHITESPACE_RULE_NAME, UNEXPECTED_FORMATTING, UNEXPECTED_TRAILING_WHITESPACE,
+    UNORDERED_IMPORTS, UNORGANIZED_IMPORTS, UNRESOLVABLE_FORMATTING_CONFLICT, UNSTABLE_FORMATTING,
+    WRONG_BLANK_LINES, WRONG_INDENTATION,
+};
+#[cfg(feature = "async")]
+pub use crate::{run_rustfmt_async, run_rustfmt_on_lines_async};
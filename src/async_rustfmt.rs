@@ -0,0 +1,233 @@
+//! Running rustfmt without blocking the thread, for hosts (eg language
+//! servers) built on Tokio.
+
+use std::{
+    future::{poll_fn, Future},
+    ops::Range,
+    pin::pin,
+    task::Poll,
+};
+
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{
+    debug_dump::{record_exchange, record_spawn},
+    file_lines_args,
+    limit::slots,
+    rustfmt::{check_unstable_settings, parse_output, RustfmtError},
+    FileMismatches, RustfmtOptions,
+};
+
+/// Like [`run_rustfmt()`](crate::run_rustfmt). rustfmt is killed if the
+/// future is dropped before it finishes.
+pub async fn run_rustfmt_async(
+    source: &[u8],
+    options: &RustfmtOptions,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    run(source, options, &[]).await
+}
+
+/// Like [`run_rustfmt_on_lines()`](crate::run_rustfmt_on_lines).
+pub async fn run_rustfmt_on_lines_async(
+    source: &[u8],
+    options: &RustfmtOptions,
+    lines: &[Range<usize>],
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    // Probed (once) on a blocking thread.
+    let probed_options = options.clone();
+    let supports_file_lines =
+        tokio::task::spawn_blocking(move || probed_options.supports_file_lines())
+            .await
+            .unwrap_or(false);
+    if !supports_file_lines {
+        return run(source, options, &[]).await;
+    }
+    run(source, options, &file_lines_args(lines)).await
+}
+
+async fn run(
+    source: &[u8],
+    options: &RustfmtOptions,
+    extra_args: &[String],
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    let _permit = match options.concurrency_limit() {
+        Some(limit) => Some(slots().clone().acquire_async(limit).await),
+        None => None,
+    };
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+    let result = exchange(source, options, extra_args).await;
+    #[cfg(feature = "metrics")]
+    crate::metrics::record(started.elapsed(), source.len(), &result);
+    result
+}
+
+async fn exchange(
+    source: &[u8],
+    options: &RustfmtOptions,
+    extra_args: &[String],
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    // Probed (once) on a blocking thread, like `supports_file_lines()`.
+    let probed_options = options.clone();
+    let format = tokio::task::spawn_blocking(move || probed_options.output_format())
+        .await
+        .map_err(|error| RustfmtError::Io(error.into()))??;
+    let mut command = Command::from(options.command(extra_args));
+    command.kill_on_drop(true);
+    let mut child = command.spawn().map_err(RustfmtError::SpawnFailed)?;
+    let child_id = child.id().unwrap_or_default();
+    record_spawn(options, command.as_std(), child_id);
+    let mut stdin = child.stdin.take().ok_or_else(|| {
+        RustfmtError::Io(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "rustfmt's stdin isn't piped",
+        ))
+    })?;
+    // Written while the output is read, like `run_rustfmt()` does.
+    let write = async move {
+        let written = stdin.write_all(source).await;
+        drop(stdin);
+        written
+    };
+    let output = async {
+        let (written, output) = join(write, child.wait_with_output()).await;
+        let output = output?;
+        match written {
+            // rustfmt stopped reading, which its exit status and stderr should
+            // explain (like `run_rustfmt()`).
+            Err(error)
+                if error.kind() != std::io::ErrorKind::BrokenPipe || output.status.success() =>
+            {
+                Err(error)
+            }
+            _ => Ok(output),
+        }
+    };
+    let output = match options.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, output)
+            .await
+            .map_err(|_| RustfmtError::TimedOut(timeout))
+            .and_then(|output| Ok(output?)),
+        None => Ok(output.await?),
+    };
+    record_exchange(options, child_id, source.into(), output.as_ref());
+    let output = output?;
+    check_unstable_settings(options, &output)?;
+    options.name_stdin(parse_output(output, source.into(), format))
+}
+
+/// Runs `a` and `b` concurrently, like `tokio::join!` (without the macros
+/// feature).
+async fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+    let (mut a, mut b) = (pin!(a), pin!(b));
+    let (mut a_output, mut b_output) = (None, None);
+    poll_fn(|cx| {
+        if a_output.is_none() {
+            if let Poll::Ready(output) = a.as_mut().poll(cx) {
+                a_output = Some(output);
+            }
+        }
+        if b_output.is_none() {
+            if let Poll::Ready(output) = b.as_mut().poll(cx) {
+                b_output = Some(output);
+            }
+        }
+        if a_output.is_some() && b_output.is_some() {
+            Poll::Ready((a_output.take().unwrap(), b_output.take().unwrap()))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_run_rustfmt_async() {
+        let runtime = runtime();
+        let files = runtime
+            .block_on(run_rustfmt_async(b"fn  a() {}\n", &Default::default()))
+            .unwrap();
+        assert_eq!(files[0].mismatches[0].expected, "fn a() {}\n");
+
+        let files = runtime
+            .block_on(run_rustfmt_on_lines_async(
+                b"fn  a() {}\nfn  b() {}\n",
+                &Default::default(),
+                &[1..2],
+            ))
+            .unwrap();
+        assert_eq!(files[0].mismatches.len(), 1);
+        assert_eq!(files[0].mismatches[0].original, "fn  b() {}\n");
+
+        assert!(matches!(
+            runtime.block_on(run_rustfmt_async(
+                b"fn a() {}\n",
+                &RustfmtOptions {
+                    timeout: Some(Duration::from_nanos(1)),
+                    ..Default::default()
+                }
+            )),
+            Err(RustfmtError::TimedOut(_))
+        ));
+    }
+
+    #[test]
+    fn test_stdin_closed_early() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        std::fs::write(&config_path, "max_width = \"wide\"\n").unwrap();
+        // Bigger than a pipe's buffer, so that writing it fails.
+        let source = "fn a() {}\n".repeat(100_000);
+        assert!(matches!(
+            runtime().block_on(run_rustfmt_async(
+                source.as_bytes(),
+                &RustfmtOptions {
+                    config_path: Some(config_path),
+                    ..Default::default()
+                }
+            )),
+            Err(RustfmtError::NonZeroExit { stderr, .. }) if stderr.contains("max_width")
+        ));
+    }
+
+    #[test]
+    fn test_max_concurrent_runs() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let options = RustfmtOptions {
+            max_concurrent_runs: Some(2),
+            ..Default::default()
+        };
+        let runs = (0..6)
+            .map(|index| {
+                let options = options.clone();
+                runtime.spawn(async move {
+                    let source = format!("fn  a{index}() {{}}\n");
+                    run_rustfmt_async(source.as_bytes(), &options).await
+                })
+            })
+            .collect::<Vec<_>>();
+        for (index, run) in runs.into_iter().enumerate() {
+            let files = runtime.block_on(run).unwrap().unwrap();
+            assert_eq!(
+                files[0].mismatches[0].expected,
+                format!("fn a{index}() {{}}\n")
+            );
+        }
+    }
+}
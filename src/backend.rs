@@ -0,0 +1,177 @@
+use std::{
+    ops::Range,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    file_lines_args,
+    rustfmt::{parse_stdout, stdout, write_and_wait},
+    FileMismatches, Mismatch, MismatchFormat, RustfmtError, RustfmtOptions, STDIN_FILE_NAME,
+};
+
+/// How rustfmt is run for
+/// [`RustfmtRule::check_with_backend()`](crate::RustfmtRule::check_with_backend):
+/// by spawning it ([`ProcessBackend`]), or eg by handing back canned output
+/// ([`MockBackend`]) to test the rule without a toolchain.
+pub trait FormatterBackend {
+    /// What rustfmt writes to stdout when run with `options` (followed by
+    /// `extra_args`, eg `--file-lines`) over `source` as stdin.
+    fn stdout(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        extra_args: &[String],
+    ) -> Result<String, RustfmtError>;
+
+    /// The format of that output (see
+    /// [`RustfmtOptions::supports_emit_json()`]), or `None` for the formatted
+    /// source.
+    fn output_format(
+        &self,
+        options: &RustfmtOptions,
+    ) -> Result<Option<MismatchFormat>, RustfmtError> {
+        options.output_format()
+    }
+
+    /// See [`RustfmtOptions::supports_file_lines()`].
+    fn supports_file_lines(&self, options: &RustfmtOptions) -> bool {
+        options.supports_file_lines()
+    }
+}
+
+/// Spawns rustfmt, like [`run_rustfmt()`](crate::run_rustfmt).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessBackend;
+
+impl FormatterBackend for ProcessBackend {
+    fn stdout(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        extra_args: &[String],
+    ) -> Result<String, RustfmtError> {
+        let output = options.limited(|| {
+            write_and_wait(options.spawn_with_args(extra_args)?, source.into(), options)
+        })?;
+        options.name_stdin(stdout(output))
+    }
+}
+
+/// Answers every run with the same `--emit json` output (in the
+/// [`MismatchFormat::Lines`] format, with `--file-lines` supported), and
+/// remembers each run's extra arguments.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    stdout: String,
+    runs: Mutex<Vec<Vec<String>>>,
+}
+
+impl MockBackend {
+    pub fn new(stdout: impl Into<String>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            runs: Default::default(),
+        }
+    }
+
+    /// Reports `mismatches` for stdin.
+    pub fn from_mismatches(mismatches: Vec<Mismatch>) -> Self {
+        Self::new(
+            serde_json::to_string(&[FileMismatches {
+                name: STDIN_FILE_NAME.to_owned(),
+                mismatches,
+            }])
+            .unwrap(),
+        )
+    }
+
+    /// The extra arguments of each run so far (eg `--file-lines`), in order.
+    pub fn runs(&self) -> Vec<Vec<String>> {
+        self.runs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl FormatterBackend for MockBackend {
+    fn stdout(
+        &self,
+        _source: &[u8],
+        _options: &RustfmtOptions,
+        extra_args: &[String],
+    ) -> Result<String, RustfmtError> {
+        self.runs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(extra_args.to_vec());
+        Ok(self.stdout.clone())
+    }
+
+    fn output_format(
+        &self,
+        _options: &RustfmtOptions,
+    ) -> Result<Option<MismatchFormat>, RustfmtError> {
+        Ok(Some(MismatchFormat::Lines))
+    }
+
+    fn supports_file_lines(&self, _options: &RustfmtOptions) -> bool {
+        true
+    }
+}
+
+/// Runs `backend` over `source`, only formatting `lines` (like
+/// [`run_rustfmt_on_lines()`](crate::run_rustfmt_on_lines)) if they're given.
+pub(crate) fn run_backend(
+    backend: &dyn FormatterBackend,
+    source: &[u8],
+    options: &RustfmtOptions,
+    lines: Option<&[Range<usize>]>,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    let extra_args = match lines {
+        Some(lines) if backend.supports_file_lines(options) => file_lines_args(lines).to_vec(),
+        _ => Default::default(),
+    };
+    let stdout = backend.stdout(source, options, &extra_args)?;
+    parse_stdout(&stdout, source.into(), backend.output_format(options)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_backend() {
+        let mismatch = Mismatch {
+            original_begin_line: 1,
+            original_end_line: 1,
+            expected_begin_line: 1,
+            expected_end_line: 1,
+            original: "fn  a() {}\n".to_owned(),
+            expected: "fn a() {}\n".to_owned(),
+        };
+        let backend = MockBackend::from_mismatches(vec![mismatch.clone()]);
+        // No rustfmt is run, so a missing toolchain doesn't matter.
+        let options = RustfmtOptions {
+            toolchain: Some("no-such-toolchain".to_owned()),
+            ..Default::default()
+        };
+        let files = run_backend(&backend, b"fn  a() {}\n", &options, Some(&[0..1])).unwrap();
+        assert_eq!(files[0].mismatches, [mismatch]);
+        assert_eq!(backend.runs(), [file_lines_args(&[0..1]).to_vec()]);
+    }
+
+    #[test]
+    fn test_process_backend() {
+        let options = RustfmtOptions::default();
+        let source = b"fn  a() {}\nfn  b() {}\n";
+        assert_eq!(
+            run_backend(&ProcessBackend, source, &options, None).unwrap(),
+            crate::run_rustfmt(source, &options).unwrap()
+        );
+        assert_eq!(
+            run_backend(&ProcessBackend, source, &options, Some(&[1..2])).unwrap(),
+            crate::run_rustfmt_on_lines(source, &options, &[1..2]).unwrap()
+        );
+    }
+}
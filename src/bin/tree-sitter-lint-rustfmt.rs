@@ -0,0 +1,489 @@
+//! Runs the rustfmt rule over a list of files and directories, without a
+//! tree-sitter-lint host.
+//!
+//! rustfmt processes are spawned ahead of time through a [`RustfmtPool`], and
+//! files that were clean on a previous run (with the same rustfmt) are
+//! skipped via a [`CleanCache`]. Files are checked and reported in sorted order,
+//! so runs over the same tree are deterministic, which also makes this usable
+//! as a PGO training workload.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use tree_sitter::{Parser, Tree};
+#[cfg(feature = "metrics")]
+use tree_sitter_lint_plugin_rustfmt::metrics;
+use tree_sitter_lint_plugin_rustfmt::{
+    discover_edition, CleanCache, DiffStatSummary, FileDiffStat, FileHeaderRule,
+    FileHeaderRuleOptions, Fix, HeaderTemplate, LineOffsets, RustfmtOptions, RustfmtPool,
+    RustfmtRule, RustfmtRuleOptions, StyleProfile, SymlinkPolicy, Violation, FILE_HEADER_RULE_NAME,
+    RULE_NAME,
+};
+
+const USAGE: &str = "\
+Usage: tree-sitter-lint-rustfmt [OPTIONS] [PATHS]...
+
+Reports (or with --fix, fixes) Rust files that rustfmt would change.
+Directories are searched recursively, skipping files in hidden directories
+and `target` (after resolving symlinks). PATHS defaults to the current
+directory. Each file is formatted with the rustfmt config that applies to it,
+as its Cargo package's edition. Files with an `@generated` marker in their
+first 5 lines are skipped.
+
+Options:
+  --fix                Apply rustfmt's fixes
+  --exclude <GLOB>     Skip files matching GLOB (eg `src/proto/**`, or
+                       `bindings.rs` for any file of that name); repeatable
+  --header <PATH>      Also require files to start with the header comment in
+                       PATH (inserted before formatting with --fix)
+  --profile <NAME>     Format in a house style: default, compact or tall
+  -j, --jobs <N>       Files to check in parallel [default: available cores]
+  --cache-dir <PATH>   [default: .tree-sitter-lint-rustfmt-cache]
+  --no-cache           Don't read or write the cache
+  --link-paths         Apply ignored directories and rustfmt config discovery
+                       to symlinks' own paths rather than their targets
+  --diff-stat          Print each unformatted file's changed regions and
+                       lines, and whether it was fixed, to stderr
+  --metrics            Print rustfmt's total and slowest run times to stderr
+                       (requires the `metrics` feature)
+  -h, --help           Print this help
+";
+
+const DEFAULT_CACHE_DIR: &str = ".tree-sitter-lint-rustfmt-cache";
+
+struct Args {
+    fix: bool,
+    jobs: usize,
+    cache_dir: Option<PathBuf>,
+    header: Option<PathBuf>,
+    exclude: Vec<String>,
+    symlinks: SymlinkPolicy,
+    profile: StyleProfile,
+    diff_stat: bool,
+    metrics: bool,
+    paths: Vec<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        fix: false,
+        jobs: thread::available_parallelism().map_or(1, usize::from),
+        cache_dir: Some(DEFAULT_CACHE_DIR.into()),
+        header: None,
+        exclude: Default::default(),
+        symlinks: Default::default(),
+        profile: Default::default(),
+        diff_stat: false,
+        metrics: false,
+        paths: Default::default(),
+    };
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--fix" => parsed.fix = true,
+            "-j" | "--jobs" => {
+                parsed.jobs = args
+                    .next()
+                    .and_then(|jobs| jobs.parse().ok())
+                    .filter(|&jobs| jobs > 0)
+                    .ok_or_else(|| format!("{arg} takes a positive number"))?;
+            }
+            "--cache-dir" => {
+                parsed.cache_dir = Some(args.next().ok_or("--cache-dir takes a path")?.into());
+            }
+            "--no-cache" => parsed.cache_dir = None,
+            "--header" => {
+                parsed.header = Some(args.next().ok_or("--header takes a path")?.into());
+            }
+            "--exclude" => {
+                parsed
+                    .exclude
+                    .push(args.next().ok_or("--exclude takes a glob pattern")?);
+            }
+            "--profile" => {
+                parsed.profile = args
+                    .next()
+                    .as_deref()
+                    .and_then(StyleProfile::named)
+                    .ok_or("--profile takes default, compact or tall")?;
+            }
+            "--link-paths" => parsed.symlinks = SymlinkPolicy::LinkPath,
+            "--diff-stat" => parsed.diff_stat = true,
+            "--metrics" if cfg!(feature = "metrics") => parsed.metrics = true,
+            "--metrics" => return Err("--metrics requires the `metrics` feature".to_owned()),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => parsed.paths.push(arg.into()),
+        }
+    }
+    if parsed.paths.is_empty() {
+        parsed.paths.push(".".into());
+    }
+    Ok(parsed)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) if error.is_empty() => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprint!("error: {error}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut files = Vec::new();
+    for path in &args.paths {
+        if let Err(error) = collect_rust_files(path, args.symlinks, &mut files) {
+            eprintln!("error: {}: {error}", path.display());
+            return ExitCode::from(2);
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let rule = RustfmtRule::new(RustfmtRuleOptions {
+        rustfmt: RustfmtOptions {
+            profile: args.profile,
+            ..Default::default()
+        },
+        cache: args.cache_dir.clone().map(CleanCache::new),
+        exclude: args.exclude.clone(),
+        exclude_generated: true,
+        ..Default::default()
+    });
+    files.retain(|path| !rule.excludes(path));
+    // The rule only logs errors that aren't specific to a file, so make sure
+    // rustfmt can be run at all, and its output understood.
+    if let Err(error) = rule.options.rustfmt.mismatch_format() {
+        eprintln!("error: {error}");
+        return ExitCode::from(2);
+    }
+    let header = args.header.clone().map(|template| {
+        FileHeaderRule::new(FileHeaderRuleOptions {
+            template: HeaderTemplate::File(template),
+        })
+    });
+    // The edition the rule detects, which files that don't override it (see
+    // `RustfmtRule::for_file()`) can use the pool's processes with.
+    let cwd = env::current_dir().ok();
+    let pool = RustfmtPool::new(
+        RustfmtOptions {
+            edition: cwd.as_deref().and_then(discover_edition),
+            profile: args.profile,
+            ..Default::default()
+        },
+        args.jobs,
+    );
+    let next_file = Mutex::new(files.iter());
+    let outcomes = Mutex::new(Vec::with_capacity(files.len()));
+    let diff_stats = Mutex::new(DiffStatSummary::default());
+    thread::scope(|scope| {
+        for _ in 0..args.jobs {
+            scope.spawn(|| {
+                let mut parser = Parser::new();
+                parser
+                    .set_language(tree_sitter_rust::language())
+                    .expect("tree-sitter-rust should be compatible");
+                while let Some(path) = next_file.lock().unwrap().next() {
+                    let mut rule = rule.for_file(path, args.symlinks);
+                    // The reports of checking the file and, if it was fixed,
+                    // of checking it again.
+                    let reports = Arc::new(Mutex::new(Vec::new()));
+                    if args.diff_stat {
+                        let reports = reports.clone();
+                        rule.to_mut().options.on_report = Some(Arc::new(move |report| {
+                            reports.lock().unwrap().push(report.clone());
+                        }));
+                    }
+                    let check_file = |parser: &mut Parser| {
+                        check_file(path, &rule, header.as_ref(), &pool, parser, args.fix)
+                    };
+                    #[cfg(feature = "metrics")]
+                    let outcome =
+                        metrics::with_label(path.display().to_string(), || check_file(&mut parser));
+                    #[cfg(not(feature = "metrics"))]
+                    let outcome = check_file(&mut parser);
+                    let reports = reports.lock().unwrap();
+                    if let Some(report) = reports.first() {
+                        diff_stats.lock().unwrap().files.push(FileDiffStat::new(
+                            path.clone(),
+                            report,
+                            reports.len() > 1,
+                        ));
+                    }
+                    outcomes.lock().unwrap().push((path, outcome));
+                }
+            });
+        }
+    });
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(path, _)| *path);
+
+    let (mut violation_count, mut error_count) = (0, 0);
+    for (path, outcome) in outcomes {
+        match outcome {
+            Ok(reports) => {
+                for report in &reports {
+                    println!("{}:{report}", path.display());
+                }
+                violation_count += reports.len();
+            }
+            Err(error) => {
+                eprintln!("error: {}: {error}", path.display());
+                error_count += 1;
+            }
+        }
+    }
+
+    if args.diff_stat {
+        let mut diff_stats = diff_stats.into_inner().unwrap();
+        diff_stats.files.sort_by(|a, b| a.path.cmp(&b.path));
+        eprint!("{diff_stats}");
+    }
+
+    #[cfg(feature = "metrics")]
+    if args.metrics {
+        eprint!("{}", metrics::take_summary());
+    }
+
+    match (error_count, violation_count) {
+        (0, 0) => ExitCode::SUCCESS,
+        (0, _) => ExitCode::FAILURE,
+        _ => ExitCode::from(2),
+    }
+}
+
+fn collect_rust_files(
+    path: &Path,
+    symlinks: SymlinkPolicy,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !path.is_dir() {
+        // Explicitly listed files are never skipped.
+        fs::metadata(path)?;
+        files.push(path.to_owned());
+        return Ok(());
+    }
+    let root = SymlinkPolicy::Resolve.policy_path(path);
+    walk_dir(path, &root, symlinks, files)
+}
+
+fn walk_dir(
+    dir: &Path,
+    root: &Path,
+    symlinks: SymlinkPolicy,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if !is_ignored_dir_name(&path) {
+                walk_dir(&path, root, symlinks, files)?;
+            }
+        } else if path.extension().is_some_and(|extension| extension == "rs")
+            && !(symlinks == SymlinkPolicy::Resolve && is_ignored_target(&path, root))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_ignored_dir_name(dir: &Path) -> bool {
+    dir.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy();
+        name.starts_with('.') || name == "target"
+    })
+}
+
+/// Whether `path` is a symlink to a file in an ignored directory, judged by
+/// the directories below where the target and `root` diverge.
+fn is_ignored_target(path: &Path, root: &Path) -> bool {
+    let target = SymlinkPolicy::Resolve.policy_path(path);
+    let common = root
+        .ancestors()
+        .find(|ancestor| target.starts_with(ancestor))
+        .unwrap_or(Path::new(""));
+    let relative = target.strip_prefix(common).unwrap_or(&target);
+    relative.parent().is_some_and(|dirs| {
+        dirs.components()
+            .any(|dir| is_ignored_dir_name(dir.as_ref()))
+    })
+}
+
+/// Returns `<line>:<column>: <message> (<rule>)` for each violation that's
+/// left.
+fn check_file(
+    path: &Path,
+    rule: &RustfmtRule,
+    header: Option<&FileHeaderRule>,
+    pool: &RustfmtPool,
+    parser: &mut Parser,
+    fix: bool,
+) -> io::Result<Vec<String>> {
+    let mut source = fs::read(path)?;
+    let mut tree = parse(parser, &source)?;
+
+    let mut reports = Vec::new();
+    if let Some(header) = header {
+        let violations = header.check(&source, &tree);
+        if fix && violations.iter().any(|violation| violation.fix.is_some()) {
+            // Before formatting, so that rustfmt formats around the new header.
+            source = Fix::apply_all(
+                &source,
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref()),
+            );
+            fs::write(path, &source)?;
+            tree = parse(parser, &source)?;
+        } else {
+            reports.extend(reports_for(&source, &violations, FILE_HEADER_RULE_NAME));
+        }
+    }
+
+    let violations = rule.check_with_pool(&source, &tree, pool);
+    if fix && violations.iter().any(|violation| violation.fix.is_some()) {
+        fs::write(
+            path,
+            Fix::apply_all(
+                &source,
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref()),
+            ),
+        )?;
+        // Report whatever is left in the fixed file.
+        return check_file(path, rule, header, pool, parser, false);
+    }
+    reports.extend(reports_for(&source, &violations, RULE_NAME));
+    Ok(reports)
+}
+
+fn parse(parser: &mut Parser, source: &[u8]) -> io::Result<Tree> {
+    parser
+        .parse(source, None)
+        .ok_or_else(|| io::Error::other("couldn't parse"))
+}
+
+fn reports_for(source: &[u8], violations: &[Violation], rule_name: &str) -> Vec<String> {
+    let line_offsets = LineOffsets::new(source);
+    violations
+        .iter()
+        .map(|violation| {
+            report(
+                source,
+                &line_offsets,
+                violation.range.start,
+                &violation.message,
+                rule_name,
+            )
+        })
+        .collect()
+}
+
+fn report(
+    source: &[u8],
+    line_offsets: &LineOffsets,
+    offset: usize,
+    message: &str,
+    rule_name: &str,
+) -> String {
+    let offset = offset.min(source.len());
+    let line = line_offsets.line_of_offset(offset);
+    let line_start = line_offsets.line_start(line).unwrap_or_default();
+    let column = String::from_utf8_lossy(&source[line_start..offset])
+        .chars()
+        .count()
+        + 1;
+    let message = message.lines().next().unwrap_or_default();
+    format!("{line}:{column}: {message} ({rule_name})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let parse = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+
+        let args = parse(&[]).unwrap();
+        assert!(!args.fix);
+        assert_eq!(args.cache_dir, Some(PathBuf::from(DEFAULT_CACHE_DIR)));
+        assert_eq!(args.paths, [PathBuf::from(".")]);
+
+        let args = parse(&[
+            "--fix",
+            "-j",
+            "3",
+            "--no-cache",
+            "--header",
+            "h",
+            "--exclude",
+            "x/**",
+            "--exclude",
+            "y.rs",
+            "--diff-stat",
+            "--profile",
+            "tall",
+            "a",
+            "b.rs",
+        ])
+        .unwrap();
+        assert!(args.fix);
+        assert_eq!(args.header, Some(PathBuf::from("h")));
+        assert_eq!(args.exclude, ["x/**", "y.rs"]);
+        assert!(args.diff_stat);
+        assert_eq!(args.profile, StyleProfile::Tall);
+        assert_eq!(args.jobs, 3);
+        assert_eq!(args.cache_dir, None);
+        assert_eq!(args.paths, [PathBuf::from("a"), PathBuf::from("b.rs")]);
+
+        assert!(parse(&["--jobs", "0"]).is_err());
+        assert!(parse(&["--exclude"]).is_err());
+        assert!(parse(&["--profile", "grande"]).is_err());
+        assert_eq!(
+            parse(&["--metrics"]).map(|args| args.metrics),
+            if cfg!(feature = "metrics") {
+                Ok(true)
+            } else {
+                Err("--metrics requires the `metrics` feature".to_owned())
+            }
+        );
+        assert!(parse(&["--frobnicate"]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collect_rust_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target/gen")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "").unwrap();
+        fs::write(dir.join("target/gen/generated.rs"), "").unwrap();
+        std::os::unix::fs::symlink("../target/gen/generated.rs", dir.join("src/generated.rs"))
+            .unwrap();
+
+        let collect = |symlinks| {
+            let mut files = Vec::new();
+            collect_rust_files(dir, symlinks, &mut files).unwrap();
+            files.sort();
+            files
+        };
+        assert_eq!(collect(SymlinkPolicy::Resolve), [dir.join("src/lib.rs")]);
+        assert_eq!(
+            collect(SymlinkPolicy::LinkPath),
+            [dir.join("src/generated.rs"), dir.join("src/lib.rs")]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use std::ops::Range;
+
+use tree_sitter::{Node, Tree};
+
+use crate::{Fix, LineOffsets, Violation};
+
+pub const BLANK_LINES_RULE_NAME: &str = "blank-lines-between-items";
+
+pub const WRONG_BLANK_LINES: &str = "wrong_blank_lines";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlankLinesRuleOptions {
+    /// Blank lines required between top-level items.
+    pub between_items: usize,
+    /// Blank lines required before a top-level item that starts with a doc
+    /// comment.
+    pub before_doc_comments: usize,
+    /// Kinds of item (eg `use_declaration`) that may be grouped: consecutive
+    /// items of one of these kinds aren't checked.
+    pub grouped_kinds: Vec<String>,
+}
+
+impl Default for BlankLinesRuleOptions {
+    fn default() -> Self {
+        Self {
+            between_items: 1,
+            before_doc_comments: 1,
+            grouped_kinds: vec![
+                "use_declaration".to_owned(),
+                "extern_crate_declaration".to_owned(),
+            ],
+        }
+    }
+}
+
+/// Requires exactly the configured number of blank lines between top-level
+/// items, which rustfmt only bounds (see its `blank_lines_lower_bound` and
+/// `blank_lines_upper_bound`), so the counts should be within those bounds.
+///
+/// An item's outer attributes and the comments directly above it count as
+/// part of it, so the blank lines are counted above them; a comment that
+/// starts on the line an item ends on belongs to that item.
+#[derive(Clone, Debug, Default)]
+pub struct BlankLinesRule {
+    pub options: BlankLinesRuleOptions,
+}
+
+/// A top-level item along with its attributes and comments.
+struct Unit<'tree> {
+    item: Node<'tree>,
+    start_row: usize,
+    end_row: usize,
+    starts_with_doc_comment: bool,
+}
+
+impl BlankLinesRule {
+    pub fn new(options: BlankLinesRuleOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn check<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        let line_offsets = LineOffsets::new(source);
+        let line_ending = if line_offsets.has_crlf() {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let units = units(source, tree);
+        units
+            .windows(2)
+            .filter_map(|pair| {
+                let [previous, unit] = pair else {
+                    unreachable!()
+                };
+                if unit.start_row <= previous.end_row
+                    || (previous.item.kind() == unit.item.kind()
+                        && self
+                            .options
+                            .grouped_kinds
+                            .iter()
+                            .any(|kind| kind == unit.item.kind()))
+                {
+                    return None;
+                }
+                let found = unit.start_row - previous.end_row - 1;
+                let expected = if unit.starts_with_doc_comment {
+                    self.options.before_doc_comments
+                } else {
+                    self.options.between_items
+                };
+                if found == expected {
+                    return None;
+                }
+                // Rows are 0-based, lines 1-based.
+                let range: Range<usize> = line_offsets.line_start(previous.end_row + 2)?
+                    ..line_offsets.line_start(unit.start_row + 1)?;
+                Some(Violation {
+                    message_id: WRONG_BLANK_LINES,
+                    message: format!(
+                        "Expected {expected} blank line{} before this item, found {found}.",
+                        if expected == 1 { "" } else { "s" }
+                    ),
+                    node: unit.item,
+                    range: range.clone(),
+                    fix: Some(Fix {
+                        range,
+                        replacement: line_ending.repeat(expected),
+                    }),
+                })
+            })
+            .collect()
+    }
+}
+
+fn units<'tree>(source: &[u8], tree: &'tree Tree) -> Vec<Unit<'tree>> {
+    let mut units = Vec::<Unit>::new();
+    // The attributes and comments seen since the last item.
+    let mut leading: Option<(usize, bool)> = None;
+    let mut cursor = tree.walk();
+    for node in tree.root_node().named_children(&mut cursor) {
+        let text = &source[node.byte_range()];
+        let is_inner = node.kind() == "inner_attribute_item"
+            || text.starts_with(b"//!")
+            || text.starts_with(b"/*!");
+        match node.kind() {
+            _ if is_inner => {
+                // Not an item, and whatever follows isn't "between" items.
+                units.clear();
+                leading = None;
+            }
+            "line_comment" | "block_comment" | "attribute_item" => {
+                if let Some(previous) = units.last_mut().filter(|previous| {
+                    leading.is_none() && node.start_position().row == previous.end_row
+                }) {
+                    previous.end_row = end_row(node);
+                    continue;
+                }
+                let is_doc_comment = (text.starts_with(b"///") && !text.starts_with(b"////"))
+                    || (text.starts_with(b"/**") && !text.starts_with(b"/***"));
+                leading.get_or_insert((node.start_position().row, is_doc_comment));
+            }
+            _ => {
+                let (start_row, starts_with_doc_comment) =
+                    leading.take().unwrap_or((node.start_position().row, false));
+                units.push(Unit {
+                    item: node,
+                    start_row,
+                    end_row: end_row(node),
+                    starts_with_doc_comment,
+                });
+            }
+        }
+    }
+    units
+}
+
+/// The row of `node`'s last byte (line comments can include their newline).
+fn end_row(node: Node) -> usize {
+    let end = node.end_position();
+    if end.column == 0 && end.row > node.start_position().row {
+        end.row - 1
+    } else {
+        end.row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    fn fix(source: &str, options: BlankLinesRuleOptions) -> String {
+        let tree = parse(source);
+        let violations = BlankLinesRule::new(options).check(source.as_bytes(), &tree);
+        String::from_utf8(Fix::apply_all(
+            source.as_bytes(),
+            violations
+                .iter()
+                .filter_map(|violation| violation.fix.as_ref()),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let source = "//! Docs.\n\nuse a;\nuse b;\nconst C: u8 = 1;\nfn d() {} // d\n\n\n#[inline]\n// e\nfn e() {}\n";
+        assert_eq!(
+            fix(source, Default::default()),
+            "//! Docs.\n\nuse a;\nuse b;\n\nconst C: u8 = 1;\n\nfn d() {} // d\n\n#[inline]\n// e\nfn e() {}\n"
+        );
+        let source = "fn a() {}\n\n\n\nfn b() {}\n";
+        assert_eq!(
+            fix(
+                source,
+                BlankLinesRuleOptions {
+                    between_items: 2,
+                    ..Default::default()
+                }
+            ),
+            "fn a() {}\n\n\nfn b() {}\n"
+        );
+        let source = "fn a() {}\n\nfn b() {}\n";
+        let tree = parse(source);
+        assert!(BlankLinesRule::default()
+            .check(source.as_bytes(), &tree)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_blank_lines_before_doc_comments() {
+        let source = "fn a() {}\n/// B.\n#[inline]\nfn b() {}\nfn c() {}\n";
+        let tree = parse(source);
+        let violations = BlankLinesRule::new(BlankLinesRuleOptions {
+            between_items: 0,
+            before_doc_comments: 1,
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].node.kind(), "function_item");
+        assert_eq!(violations[0].range, 10..10);
+        assert_eq!(
+            violations[0].message,
+            "Expected 1 blank line before this item, found 0."
+        );
+    }
+
+    #[test]
+    fn test_blank_lines_crlf() {
+        assert_eq!(
+            fix("fn a() {}\r\nfn b() {}\r\n", Default::default()),
+            "fn a() {}\r\n\r\nfn b() {}\r\n"
+        );
+    }
+}
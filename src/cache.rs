@@ -0,0 +1,187 @@
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{FileMismatches, StderrDiagnostic};
+
+/// Remembers which sources rustfmt had no mismatches for, so that checking
+/// them again (eg in a watch loop) doesn't spawn rustfmt at all.
+///
+/// Entries are empty files named after a hash of the source and of a
+/// fingerprint of everything else that affects rustfmt's output (its version,
+/// arguments and config), so the cache never has to be invalidated and can be
+/// shared between processes.
+///
+/// That also makes it safe to check and mark files from many threads (or
+/// processes) at once without any locking: an entry is only ever created,
+/// never changed or removed, and has no contents to be seen half-written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CleanCache {
+    dir: PathBuf,
+}
+
+/// A [`CleanCache`] (or [`RecentResults`]) entry.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey(blake3::Hash);
+
+impl CleanCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn key(fingerprint: &[u8], source: &[u8]) -> CacheKey {
+        let mut hasher = blake3::Hasher::new();
+        // Length-prefixed so that the fingerprint can't bleed into the source.
+        hasher.update(&(fingerprint.len() as u64).to_le_bytes());
+        hasher.update(fingerprint);
+        hasher.update(source);
+        CacheKey(hasher.finalize())
+    }
+
+    pub fn is_clean(&self, key: &CacheKey) -> bool {
+        self.entry_path(key).is_file()
+    }
+
+    pub fn mark_clean(&self, key: &CacheKey) -> io::Result<()> {
+        let path = self.entry_path(key);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, "")
+    }
+
+    /// Entries are spread over 256 subdirectories to keep directories small.
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        let hex = key.0.to_hex();
+        self.dir.join(&hex[..2]).join(&hex[2..])
+    }
+}
+
+/// What rustfmt made of the most recently checked sources, for checks that
+/// mustn't run it (see [`RustfmtRule::check_recent()`](crate::RustfmtRule::check_recent)),
+/// eg an editor's diagnostic-only requests on every keystroke. Full checks
+/// store their results here when it's set as
+/// [`RustfmtRuleOptions::recent_results`](crate::RustfmtRuleOptions::recent_results).
+/// Once full, the least recently stored source is forgotten. Clones share
+/// the results.
+#[derive(Clone, Debug)]
+pub struct RecentResults {
+    capacity: usize,
+    /// The mismatches and what rustfmt left unformatted, by source.
+    entries: Arc<Mutex<VecDeque<(CacheKey, RecentResult)>>>,
+}
+
+type RecentResult = (Vec<FileMismatches>, Vec<StderrDiagnostic>);
+
+impl RecentResults {
+    pub const DEFAULT_CAPACITY: usize = 64;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Default::default(),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<RecentResult> {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, result)| result.clone())
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, result: RecentResult) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|(entry_key, _)| *entry_key != key);
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((key, result));
+    }
+}
+
+impl Default for RecentResults {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_clean_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CleanCache::new(dir.path().join("cache"));
+        let key = CleanCache::key(b"rustfmt 1.6.0", b"fn main() {}\n");
+        assert!(!cache.is_clean(&key));
+
+        cache.mark_clean(&key).unwrap();
+        assert!(cache.is_clean(&key));
+        assert!(!cache.is_clean(&CleanCache::key(b"rustfmt 1.6.0", b"fn main() {}")));
+        assert!(!cache.is_clean(&CleanCache::key(b"rustfmt 1.7.0", b"fn main() {}\n")));
+        assert_ne!(CleanCache::key(b"ab", b"c"), CleanCache::key(b"a", b"bc"));
+    }
+
+    #[test]
+    fn test_recent_results() {
+        let recent = RecentResults::new(2);
+        let key = |source: &str| CleanCache::key(b"rustfmt 1.6.0", source.as_bytes());
+        let result = |name: &str| {
+            (
+                vec![FileMismatches {
+                    name: name.to_owned(),
+                    mismatches: Default::default(),
+                }],
+                Default::default(),
+            )
+        };
+        recent.insert(key("a"), result("a"));
+        recent.insert(key("b"), result("b"));
+        recent.insert(key("a"), result("a2"));
+        recent.insert(key("c"), result("c"));
+        assert_eq!(recent.get(&key("a")), Some(result("a2")));
+        assert_eq!(recent.get(&key("b")), None);
+        assert_eq!(recent.get(&key("c")), Some(result("c")));
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CleanCache::new(dir.path());
+        let keys = (0..1000)
+            .map(|index| CleanCache::key(b"rustfmt 1.6.0", index.to_string().as_bytes()))
+            .collect::<Vec<_>>();
+        // Every key is marked by several threads, while others check it.
+        thread::scope(|scope| {
+            for thread_index in 0..16 {
+                let (cache, keys) = (&cache, &keys);
+                scope.spawn(move || {
+                    for (index, key) in keys.iter().enumerate() {
+                        if index % 4 == thread_index % 4 {
+                            cache.mark_clean(key).unwrap();
+                            assert!(cache.is_clean(key));
+                        } else {
+                            cache.is_clean(key);
+                        }
+                    }
+                });
+            }
+        });
+        assert!(keys.iter().all(|key| cache.is_clean(key)));
+        let entry_count = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|subdir| fs::read_dir(subdir.unwrap().path()).unwrap().count())
+            .sum::<usize>();
+        assert_eq!(entry_count, keys.len());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::Mismatch;
+
+pub const WRONG_INDENTATION: &str = "wrong_indentation";
+pub const UNEXPECTED_TRAILING_WHITESPACE: &str = "unexpected_trailing_whitespace";
+pub const UNORDERED_IMPORTS: &str = "unordered_imports";
+pub const LONG_LINE_REFLOW: &str = "long_line_reflow";
+pub const BRACE_PLACEMENT: &str = "brace_placement";
+
+/// What kind of change a mismatch is, for
+/// [`RustfmtRuleOptions::categorize_mismatches`](crate::RustfmtRuleOptions::categorize_mismatches),
+/// judged from its lines alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MismatchCategory {
+    /// Only the whitespace at the start of lines changes.
+    Indentation,
+    /// Only the whitespace at the end of lines changes.
+    TrailingWhitespace,
+    /// Only `use` declarations change (eg their order).
+    ImportOrder,
+    /// Lines get broken up, eg for being longer than `max_width`: only
+    /// whitespace and trailing commas change, and the longest line gets
+    /// shorter.
+    LongLineReflow,
+    /// Opening braces (or `else`s) get moved up to the end of the line
+    /// before them.
+    BracePlacement,
+}
+
+impl MismatchCategory {
+    /// The category of `mismatch`, if it's one of these.
+    pub fn classify(mismatch: &Mismatch) -> Option<Self> {
+        let original = mismatch.original.lines().collect::<Vec<_>>();
+        let expected = mismatch.expected.lines().collect::<Vec<_>>();
+        let same_lines = |key: fn(&str) -> &str| {
+            original.len() == expected.len()
+                && original
+                    .iter()
+                    .zip(&expected)
+                    .all(|(original, expected)| key(original) == key(expected))
+        };
+        if original.is_empty() && expected.is_empty() {
+            return None;
+        }
+        if same_lines(str::trim_end) {
+            return Some(Self::TrailingWhitespace);
+        }
+        if same_lines(str::trim_start) {
+            return Some(Self::Indentation);
+        }
+        let is_import = |line: &&str| {
+            let line = line.trim();
+            line.is_empty()
+                || line.starts_with("use ")
+                || line.starts_with("pub use ")
+                || (line.starts_with("pub(") && line.contains(") use "))
+        };
+        if original
+            .iter()
+            .chain(&expected)
+            .any(|line| !line.trim().is_empty())
+            && original.iter().all(is_import)
+            && expected.iter().all(is_import)
+        {
+            return Some(Self::ImportOrder);
+        }
+        // rustfmt adds trailing commas to what it breaks up, and removes them
+        // from what it joins.
+        let tokens = |text: &str| {
+            text.chars()
+                .filter(|char| !char.is_whitespace() && *char != ',')
+                .collect::<String>()
+        };
+        if tokens(&mismatch.original) != tokens(&mismatch.expected) {
+            return None;
+        }
+        let starts_block = |line: &&&str| {
+            let line = line.trim_start();
+            line.starts_with('{') || line.starts_with("else")
+        };
+        if original.iter().filter(starts_block).count()
+            > expected.iter().filter(starts_block).count()
+        {
+            return Some(Self::BracePlacement);
+        }
+        let longest = |lines: &[&str]| lines.iter().map(|line| line.chars().count()).max();
+        if expected.len() > original.len() && longest(&expected) < longest(&original) {
+            return Some(Self::LongLineReflow);
+        }
+        None
+    }
+
+    pub fn message_id(self) -> &'static str {
+        match self {
+            Self::Indentation => WRONG_INDENTATION,
+            Self::TrailingWhitespace => UNEXPECTED_TRAILING_WHITESPACE,
+            Self::ImportOrder => UNORDERED_IMPORTS,
+            Self::LongLineReflow => LONG_LINE_REFLOW,
+            Self::BracePlacement => BRACE_PLACEMENT,
+        }
+    }
+
+    /// The violation message, without a trailing period.
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::Indentation => "Unexpected indentation",
+            Self::TrailingWhitespace => "Unexpected trailing whitespace",
+            Self::ImportOrder => "Unexpected import order or formatting",
+            Self::LongLineReflow => "Line too long, rustfmt would break it up",
+            Self::BracePlacement => "Unexpected brace placement",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(original: &str, expected: &str) -> Option<MismatchCategory> {
+        MismatchCategory::classify(&Mismatch {
+            original_begin_line: 1,
+            original_end_line: original.lines().count(),
+            expected_begin_line: 1,
+            expected_end_line: expected.lines().count(),
+            original: original.to_owned(),
+            expected: expected.to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            classify("  let x = 1;\n", "    let x = 1;\n"),
+            Some(MismatchCategory::Indentation)
+        );
+        assert_eq!(
+            classify("let x = 1;  \n", "let x = 1;\n"),
+            Some(MismatchCategory::TrailingWhitespace)
+        );
+        assert_eq!(
+            classify("use b;\nuse a;\n", "use a;\nuse b;\n"),
+            Some(MismatchCategory::ImportOrder)
+        );
+        assert_eq!(
+            classify("pub(crate) use b;\n", "pub(crate) use {b};\n"),
+            Some(MismatchCategory::ImportOrder)
+        );
+        assert_eq!(
+            classify(
+                "    f(aaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbb);\n",
+                "    f(\n        aaaaaaaaaaaaaaaa,\n        bbbbbbbbbbbbbbbbbbbb,\n    );\n"
+            ),
+            Some(MismatchCategory::LongLineReflow)
+        );
+        assert_eq!(
+            classify("fn a()\n{\n", "fn a() {\n"),
+            Some(MismatchCategory::BracePlacement)
+        );
+        assert_eq!(
+            classify("}\nelse {\n", "} else {\n"),
+            Some(MismatchCategory::BracePlacement)
+        );
+        assert_eq!(classify("fn  b( ) {}\n", "fn b() {}\n"), None);
+        assert_eq!(
+            classify("", "use a;\n"),
+            Some(MismatchCategory::ImportOrder)
+        );
+        assert_eq!(classify("", "fn a() {}\n"), None);
+    }
+}
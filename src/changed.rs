@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::line_ranges::merge;
+
+/// The lines of each file that have changed, eg since a branch's merge base,
+/// for [`RustfmtRuleOptions::changed_lines`](crate::RustfmtRuleOptions::changed_lines)
+/// to only format those. Rows are 0-based and half-open, as for
+/// `--file-lines`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChangedLines {
+    files: Arc<HashMap<PathBuf, Vec<Range<usize>>>>,
+}
+
+impl ChangedLines {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The lines added (or changed) in a unified diff, eg the output of
+    /// `git diff -U0 $(git merge-base HEAD main)`, by the paths of the new
+    /// files (without git's `b/` prefix). Lines that are only removed don't
+    /// leave any changed lines behind, and neither do deleted files.
+    pub fn from_diff(diff: &str) -> Self {
+        let mut changed_lines = Self::new();
+        let mut path = None;
+        for line in diff.lines() {
+            if let Some(new_path) = line.strip_prefix("+++ ") {
+                let new_path = new_path.split('\t').next().unwrap_or_default();
+                path = (new_path != "/dev/null")
+                    .then(|| PathBuf::from(new_path.strip_prefix("b/").unwrap_or(new_path)));
+                continue;
+            }
+            let (Some(path), Some(hunk)) = (path.as_ref(), line.strip_prefix("@@ ")) else {
+                continue;
+            };
+            let Some(new_lines) = hunk
+                .split(' ')
+                .find_map(|range| range.strip_prefix('+'))
+                .and_then(parse_hunk_range)
+            else {
+                continue;
+            };
+            changed_lines.insert(path, [new_lines]);
+        }
+        changed_lines
+    }
+
+    /// Adds `rows` to the changed lines of the file at `path`.
+    pub fn insert(
+        &mut self,
+        path: impl Into<PathBuf>,
+        rows: impl IntoIterator<Item = Range<usize>>,
+    ) {
+        let files = Arc::make_mut(&mut self.files);
+        let changed = files.entry(path.into()).or_default();
+        *changed = merge(changed.drain(..).chain(rows));
+    }
+
+    /// The changed lines of the file at `path`, which are none for files
+    /// that aren't in the diff. Relative paths (as in diffs) are matched
+    /// against the end of `path`, so that `src/lib.rs` is also the changed
+    /// lines of `/work/crate/src/lib.rs`.
+    pub fn rows(&self, path: &Path) -> &[Range<usize>] {
+        if let Some(rows) = self.files.get(path) {
+            return rows;
+        }
+        self.files
+            .iter()
+            .filter(|(changed_path, _)| changed_path.is_relative() && path.ends_with(changed_path))
+            .max_by_key(|(changed_path, _)| changed_path.components().count())
+            .map_or(&[], |(_, rows)| rows)
+    }
+}
+
+/// The rows of a hunk's `<start>[,<count>]` range.
+fn parse_hunk_range(range: &str) -> Option<Range<usize>> {
+    let (start, count) = match range.split_once(',') {
+        Some((start, count)) => (start.parse::<usize>().ok()?, count.parse::<usize>().ok()?),
+        None => (range.parse().ok()?, 1),
+    };
+    let start = start.saturating_sub(1);
+    Some(start..start + count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_diff() {
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -3,0 +4,2 @@ fn a() {
++    b();
++    c();
+@@ -10 +12 @@ fn d() {
+-    e( );
++    e();
+@@ -20,2 +21,0 @@ fn f() {
+-    g();
+-    h();
+diff --git a/old.rs b/old.rs
+deleted file mode 100644
+--- a/old.rs
++++ /dev/null
+@@ -1 +0,0 @@
+-fn old() {}
+diff --git a/src/new.rs b/src/new.rs
+new file mode 100644
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1,2 @@
++fn new() {}
++fn newer() {}
+";
+        let changed_lines = ChangedLines::from_diff(diff);
+        assert_eq!(changed_lines.rows(Path::new("src/lib.rs")), [3..5, 11..12]);
+        assert_eq!(
+            changed_lines.rows(Path::new("/work/crate/src/lib.rs")),
+            [3..5, 11..12]
+        );
+        assert_eq!(changed_lines.rows(Path::new("src/new.rs")), [0..2]);
+        assert!(changed_lines.rows(Path::new("old.rs")).is_empty());
+        assert!(changed_lines.rows(Path::new("lib.rs")).is_empty());
+
+        let mut more = changed_lines.clone();
+        more.insert("src/lib.rs", [5..6]);
+        assert_eq!(more.rows(Path::new("src/lib.rs")), [3..6, 11..12]);
+        assert_eq!(changed_lines.rows(Path::new("src/lib.rs")), [3..5, 11..12]);
+    }
+}
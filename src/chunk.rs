@@ -0,0 +1,178 @@
+use std::ops::Range;
+
+use tree_sitter::Tree;
+
+use crate::{
+    stdin_mismatches, FileMismatches, LineOffsets, RustfmtError, RustfmtOptions, StderrDiagnostic,
+    STDIN_FILE_NAME,
+};
+
+/// Splits `source` into chunks (0-based, half-open rows) of at least
+/// `chunk_lines` lines where possible, between top-level items that a single
+/// blank line separates. That blank line isn't in either chunk: rustfmt keeps
+/// it as it is, and without it a chunk would start or end with a blank line,
+/// which rustfmt removes from a file. Nor does this split where items aren't
+/// separated by a blank line, eg a group of `use`s that rustfmt sorts
+/// together.
+pub(crate) fn chunk_rows(tree: &Tree, source: &[u8], chunk_lines: usize) -> Vec<Range<usize>> {
+    let line_offsets = LineOffsets::new(source);
+    let line_count = line_offsets.line_count();
+    let is_blank = |row: usize| {
+        line_offsets
+            .line_range_to_byte_range(row + 1, row + 1)
+            .is_some_and(|range| source[range].iter().all(u8::is_ascii_whitespace))
+    };
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let children = root.children(&mut cursor).collect::<Vec<_>>();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    for pair in children.windows(2) {
+        let last_row = pair[0].end_position().row;
+        let next_row = pair[1].start_position().row;
+        if next_row == last_row + 2 && is_blank(last_row + 1) && last_row + 1 - start >= chunk_lines
+        {
+            chunks.push(start..last_row + 1);
+            start = next_row;
+        }
+    }
+    chunks.push(start..line_count.max(start));
+    chunks
+}
+
+/// Runs `run_rustfmt` on each of the `chunks` of `source` separately (see
+/// [`chunk_rows()`]), passing it the part of `lines` in the chunk (relative
+/// to it) and skipping chunks without any, and puts the mismatches back at
+/// their lines in `source`. So does it with the positions of what rustfmt
+/// [couldn't format](RustfmtError::LeftUnformatted). Any other error is
+/// reported by running rustfmt on the whole of `source` instead, so that it
+/// points into the right lines.
+pub(crate) fn run_chunked(
+    source: &[u8],
+    chunks: &[Range<usize>],
+    options: &RustfmtOptions,
+    lines: Option<&[Range<usize>]>,
+    run_rustfmt: impl Fn(
+        &[u8],
+        &RustfmtOptions,
+        Option<&[Range<usize>]>,
+    ) -> Result<Vec<FileMismatches>, RustfmtError>,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    let line_offsets = LineOffsets::new(source);
+    let mut mismatches = Vec::new();
+    let mut left_unformatted = Vec::<StderrDiagnostic>::new();
+    // How many lines longer the formatted source is than the original,
+    // before the current chunk.
+    let mut line_delta = 0isize;
+    for chunk in chunks {
+        let chunk_lines = lines.map(|lines| {
+            lines
+                .iter()
+                .map(|lines| {
+                    lines.start.clamp(chunk.start, chunk.end) - chunk.start
+                        ..lines.end.clamp(chunk.start, chunk.end) - chunk.start
+                })
+                .filter(|lines| !lines.is_empty())
+                .collect::<Vec<_>>()
+        });
+        if chunk_lines.as_ref().is_some_and(Vec::is_empty) {
+            continue;
+        }
+        let Some(bytes) = line_offsets
+            .line_start(chunk.start + 1)
+            .zip(line_offsets.line_start(chunk.end + 1))
+        else {
+            continue;
+        };
+        let files = match run_rustfmt(&source[bytes.0..bytes.1], options, chunk_lines.as_deref()) {
+            Ok(files) => files,
+            Err(RustfmtError::LeftUnformatted { files, diagnostics }) => {
+                left_unformatted.extend(diagnostics.into_iter().map(|mut diagnostic| {
+                    if let Some((line, _)) = diagnostic.position.as_mut() {
+                        *line += chunk.start;
+                    }
+                    diagnostic
+                }));
+                files
+            }
+            Err(_) => return run_rustfmt(source, options, lines),
+        };
+        let chunk_delta = line_delta + chunk.start as isize;
+        for mut mismatch in stdin_mismatches(files) {
+            line_delta += mismatch.expected.matches('\n').count() as isize
+                - mismatch.original.matches('\n').count() as isize;
+            mismatch.original_begin_line += chunk.start;
+            mismatch.original_end_line += chunk.start;
+            mismatch.expected_begin_line = mismatch
+                .expected_begin_line
+                .saturating_add_signed(chunk_delta);
+            mismatch.expected_end_line = mismatch
+                .expected_end_line
+                .saturating_add_signed(chunk_delta);
+            mismatches.push(mismatch);
+        }
+    }
+    let files = if mismatches.is_empty() {
+        Default::default()
+    } else {
+        vec![FileMismatches {
+            name: STDIN_FILE_NAME.to_owned(),
+            mismatches,
+        }]
+    };
+    if left_unformatted.is_empty() {
+        Ok(files)
+    } else {
+        Err(RustfmtError::LeftUnformatted {
+            files,
+            diagnostics: left_unformatted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_rustfmt, run_rustfmt_on_lines, test_utils::parse};
+
+    const SOURCE: &str =
+        "use b;\nuse a;\n\nfn a() {\n    x( );\n}\n\nfn  b() {}\n\n\n\nfn c() {\n  y();\n}\n";
+
+    fn run(
+        source: &[u8],
+        options: &RustfmtOptions,
+        lines: Option<&[Range<usize>]>,
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        match lines {
+            Some(lines) => run_rustfmt_on_lines(source, options, lines),
+            None => run_rustfmt(source, options),
+        }
+    }
+
+    #[test]
+    fn test_chunk_rows() {
+        let tree = parse(SOURCE);
+        assert_eq!(chunk_rows(&tree, SOURCE.as_bytes(), 1), [0..2, 3..6, 7..14]);
+        assert_eq!(chunk_rows(&tree, SOURCE.as_bytes(), 3), [0..6, 7..14]);
+        assert_eq!(chunk_rows(&tree, SOURCE.as_bytes(), 100), [0..14]);
+    }
+
+    #[test]
+    fn test_run_chunked() {
+        let tree = parse(SOURCE);
+        let chunks = chunk_rows(&tree, SOURCE.as_bytes(), 1);
+        let options = RustfmtOptions::default();
+        assert_eq!(
+            run_chunked(SOURCE.as_bytes(), &chunks, &options, None, run).unwrap(),
+            run_rustfmt(SOURCE.as_bytes(), &options).unwrap()
+        );
+        assert_eq!(
+            run_chunked(SOURCE.as_bytes(), &chunks, &options, Some(&[11..13]), run).unwrap(),
+            run_rustfmt_on_lines(SOURCE.as_bytes(), &options, &[11..13]).unwrap()
+        );
+        assert!(matches!(
+            run_chunked(b"fn a() {}\n\nfn b( {}\n", &[0..1, 2..3], &options, None, run),
+            Err(RustfmtError::NonZeroExit { stderr, .. }) if stderr.contains("<stdin>:3")
+        ));
+    }
+}
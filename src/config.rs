@@ -0,0 +1,222 @@
+use std::{
+    borrow::Cow,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{discover_edition_for_file, RustfmtOptions};
+
+/// rustfmt's default `max_width`.
+pub const DEFAULT_MAX_WIDTH: usize = 100;
+
+/// The file names rustfmt looks for, in order of precedence.
+pub const CONFIG_FILE_NAMES: [&str; 2] = ["rustfmt.toml", ".rustfmt.toml"];
+
+/// The rustfmt config that applies in `dir`, found the way rustfmt finds it:
+/// the first config file in `dir` or the closest of its ancestors.
+pub fn discover_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+/// The rustfmt config that applies to the file at `path`.
+pub fn discover_config_for_file(path: &Path, symlinks: SymlinkPolicy) -> Option<PathBuf> {
+    discover_config(symlinks.policy_path(path).parent()?)
+}
+
+/// Which path of a symlinked file location-based policies (config discovery,
+/// ignored directories) apply to. Hard links have no "real" location, so
+/// they're always treated by the path they were found at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// The file's real location, with all symlinks resolved, so that eg
+    /// `src/generated.rs -> ../target/gen/generated.rs` counts as being in
+    /// `target`.
+    #[default]
+    Resolve,
+    /// The path the file was found at.
+    LinkPath,
+}
+
+impl SymlinkPolicy {
+    /// The path to apply policies to for the file at `path`. Paths that can't
+    /// be resolved (eg dangling links) are used as is. Resolved paths on
+    /// Windows don't have the `\\?\` prefix [`fs::canonicalize()`] gives
+    /// them where they don't need it, so that they compare equal to (and
+    /// display like) unresolved ones.
+    pub fn policy_path(self, path: &Path) -> Cow<'_, Path> {
+        match self {
+            Self::Resolve => fs::canonicalize(path).map_or(Cow::Borrowed(path), |path| {
+                Cow::Owned(without_verbatim_prefix(path))
+            }),
+            Self::LinkPath => Cow::Borrowed(path),
+        }
+    }
+}
+
+/// `path` without a `\\?\` prefix if it's a plain drive path (eg
+/// `\\?\C:\src`), which other tools (and `Path` comparisons) treat as a
+/// different path.
+fn without_verbatim_prefix(path: PathBuf) -> PathBuf {
+    match path.to_str().and_then(|path| path.strip_prefix(r"\\?\")) {
+        Some(unprefixed) if unprefixed.as_bytes().get(1) == Some(&b':') => unprefixed.into(),
+        _ => path,
+    }
+}
+
+impl RustfmtOptions {
+    /// These options with the rustfmt config and edition that apply to the
+    /// file at `path` filled in (unless they're set), eg for an unsaved
+    /// buffer. `path` needn't exist, and a relative one is taken to be
+    /// relative to the current directory.
+    pub fn for_path(&self, path: &Path, symlinks: SymlinkPolicy) -> Self {
+        let path = match env::current_dir() {
+            Ok(cwd) if path.is_relative() => Cow::Owned(cwd.join(path)),
+            _ => Cow::Borrowed(path),
+        };
+        let mut options = self.clone();
+        if options.config_path.is_none() {
+            options.config_path = discover_config_for_file(&path, symlinks);
+        }
+        if options.edition.is_none() {
+            options.edition = discover_edition_for_file(&path, symlinks);
+        }
+        options
+    }
+}
+
+/// The `required_version` declared in the contents of a rustfmt config, if
+/// any. Configs that don't parse are left for rustfmt to complain about.
+pub fn required_version(config: &str) -> Option<String> {
+    let config = config.parse::<toml::Table>().ok()?;
+    Some(config.get("required_version")?.as_str()?.to_owned())
+}
+
+/// The `max_width` declared in the contents of a rustfmt config, if any.
+pub fn max_width(config: &str) -> Option<usize> {
+    let config = config.parse::<toml::Table>().ok()?;
+    config.get("max_width")?.as_integer()?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_discover_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join(".rustfmt.toml"), "").unwrap();
+        assert_eq!(
+            discover_config(&nested),
+            Some(dir.path().join(".rustfmt.toml"))
+        );
+
+        fs::write(dir.path().join("a/rustfmt.toml"), "").unwrap();
+        fs::write(dir.path().join("a/.rustfmt.toml"), "").unwrap();
+        assert_eq!(
+            discover_config(&nested),
+            Some(dir.path().join("a/rustfmt.toml"))
+        );
+    }
+
+    #[test]
+    fn test_without_verbatim_prefix() {
+        assert_eq!(
+            without_verbatim_prefix(r"\\?\C:\src\a.rs".into()),
+            PathBuf::from(r"C:\src\a.rs")
+        );
+        assert_eq!(
+            without_verbatim_prefix(r"\\?\UNC\server\share".into()),
+            PathBuf::from(r"\\?\UNC\server\share")
+        );
+        assert_eq!(
+            without_verbatim_prefix("/src/a.rs".into()),
+            PathBuf::from("/src/a.rs")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_discover_config_for_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target/gen")).unwrap();
+        fs::write(dir.join("rustfmt.toml"), "").unwrap();
+        fs::write(dir.join("target/rustfmt.toml"), "").unwrap();
+        fs::write(dir.join("target/gen/generated.rs"), "").unwrap();
+        let link = dir.join("src/generated.rs");
+        std::os::unix::fs::symlink("../target/gen/generated.rs", &link).unwrap();
+
+        assert_eq!(
+            SymlinkPolicy::Resolve.policy_path(&link),
+            dir.join("target/gen/generated.rs")
+        );
+        assert_eq!(
+            discover_config_for_file(&link, SymlinkPolicy::Resolve),
+            Some(dir.join("target/rustfmt.toml"))
+        );
+        assert_eq!(
+            discover_config_for_file(&link, SymlinkPolicy::LinkPath),
+            Some(dir.join("rustfmt.toml"))
+        );
+
+        let dangling = dir.join("src/dangling.rs");
+        std::os::unix::fs::symlink("missing.rs", &dangling).unwrap();
+        assert_eq!(SymlinkPolicy::Resolve.policy_path(&dangling), dangling);
+    }
+
+    #[test]
+    fn test_for_path() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("rustfmt.toml"), "").unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"a\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        // Neither the file nor its directory exist yet.
+        let options =
+            RustfmtOptions::default().for_path(&dir.path().join("src/new.rs"), Default::default());
+        assert_eq!(options.config_path, Some(dir.path().join("rustfmt.toml")));
+        assert_eq!(options.edition.as_deref(), Some("2021"));
+
+        let options = RustfmtOptions {
+            edition: Some("2018".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options
+                .for_path(&dir.path().join("new.rs"), Default::default())
+                .edition
+                .as_deref(),
+            Some("2018")
+        );
+    }
+
+    #[test]
+    fn test_required_version() {
+        assert_eq!(
+            required_version("edition = \"2021\"\nrequired_version = \"1.5.1\"\n"),
+            Some("1.5.1".to_owned())
+        );
+        assert_eq!(required_version("edition = \"2021\"\n"), None);
+        assert_eq!(required_version("required_version = 1"), None);
+        assert_eq!(required_version("required_version ="), None);
+    }
+
+    #[test]
+    fn test_max_width() {
+        assert_eq!(max_width("max_width = 80\n"), Some(80));
+        assert_eq!(max_width("edition = \"2021\"\n"), None);
+        assert_eq!(max_width("max_width = -1\n"), None);
+    }
+}
@@ -0,0 +1,137 @@
+//! Writing what each rustfmt run is handed and gives back to the
+//! [`debug_dump_dir`](crate::RustfmtOptions::debug_dump_dir), to see exactly
+//! what the rule made its violations from. A run's files are named after when
+//! it was started and its process id, with `.args`, `.stdin`, `.stdout`,
+//! `.stderr` and `.status` extensions.
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{Mutex, OnceLock, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{RopeOrSlice, RustfmtError, RustfmtOptions};
+
+/// The path (without an extension) of the files of each running rustfmt
+/// with a dump directory, by its process id.
+fn running() -> &'static Mutex<HashMap<u32, PathBuf>> {
+    static RUNNING: OnceLock<Mutex<HashMap<u32, PathBuf>>> = OnceLock::new();
+    RUNNING.get_or_init(Default::default)
+}
+
+/// Writes the command line the rustfmt with process id `child_id` was
+/// started with, if `options` have a dump directory.
+pub(crate) fn record_spawn(options: &RustfmtOptions, command: &Command, child_id: u32) {
+    let Some(dir) = options.debug_dump_dir.as_deref() else {
+        return;
+    };
+    let stem = stem(dir, child_id);
+    let lossy = |text: &OsStr| text.to_string_lossy().into_owned();
+    let mut args = command
+        .get_envs()
+        .map(|(name, value)| match value {
+            Some(value) => format!("{}={}", lossy(name), lossy(value)),
+            None => format!("-{}", lossy(name)),
+        })
+        .collect::<Vec<_>>();
+    args.push(lossy(command.get_program()));
+    args.extend(command.get_args().map(lossy));
+    write(&stem, "args", format!("{}\n", args.join("\n")).as_bytes());
+    running()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(child_id, stem);
+}
+
+/// Writes what the rustfmt with process id `child_id` was handed and what
+/// came of it, if `options` have a dump directory.
+pub(crate) fn record_exchange(
+    options: &RustfmtOptions,
+    child_id: u32,
+    stdin: RopeOrSlice,
+    output: Result<&Output, &RustfmtError>,
+) {
+    let Some(dir) = options.debug_dump_dir.as_deref() else {
+        return;
+    };
+    let stem = running()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&child_id)
+        .unwrap_or_else(|| stem(dir, child_id));
+    write(
+        &stem,
+        "stdin",
+        &stdin.chunks().flatten().copied().collect::<Vec<_>>(),
+    );
+    match output {
+        Ok(output) => {
+            write(&stem, "stdout", &output.stdout);
+            write(&stem, "stderr", &output.stderr);
+            write(&stem, "status", format!("{}\n", output.status).as_bytes());
+        }
+        Err(error) => write(&stem, "status", format!("{error}\n").as_bytes()),
+    }
+}
+
+/// The files of a run of the rustfmt with process id `child_id` started now,
+/// without their extension.
+fn stem(dir: &Path, child_id: u32) -> PathBuf {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    dir.join(format!("{}-{child_id}", started.as_millis()))
+}
+
+fn write(stem: &Path, extension: &str, contents: &[u8]) {
+    let path = stem.with_extension(extension);
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&path, contents));
+    if let Err(error) = written {
+        log::warn!("couldn't write {}: {error}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_rustfmt;
+
+    #[test]
+    fn test_debug_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = RustfmtOptions {
+            debug_dump_dir: Some(dir.path().join("dumps")),
+            ..Default::default()
+        };
+        let source = "fn  a() {}\n";
+        run_rustfmt(source.as_bytes(), &options).unwrap();
+
+        let mut files = fs::read_dir(dir.path().join("dumps"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| file.extension().unwrap().to_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["args", "status", "stderr", "stdin", "stdout"]
+        );
+        assert!(files
+            .iter()
+            .all(|file| file.file_stem() == files[0].file_stem()));
+        let read = |extension| fs::read_to_string(files[0].with_extension(extension)).unwrap();
+        assert!(read("args").contains("rustfmt"));
+        assert_eq!(read("stdin"), source);
+        assert!(read("stdout").contains("fn a() {}"));
+        assert!(!read("status").is_empty());
+    }
+}
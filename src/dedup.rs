@@ -0,0 +1,166 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    ops::Range,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    line_ranges::byte_range_to_rows, Fix, LineOffsets, Violation, UNRESOLVABLE_FORMATTING_CONFLICT,
+};
+
+/// The lines a fix replaces, and hashes of the text there and of what it's
+/// replaced with.
+type FixKey = (Range<usize>, u64, u64);
+
+/// The fixes reported so far in one pass of a fixing loop, so that a region
+/// that's already queued for an identical fix (eg by another rule, or by an
+/// earlier check of the same file) isn't reported again. Hosts share one
+/// between the rules they run (see
+/// [`RustfmtRuleOptions::queued_fixes`](crate::RustfmtRuleOptions::queued_fixes)
+/// and [`retain_unqueued()`](Self::retain_unqueued) for other rules), and
+/// call [`next_pass()`](Self::next_pass) once they've applied the pass's
+/// fixes. Clones share the fixes.
+#[derive(Clone, Debug, Default)]
+pub struct QueuedFixes {
+    keys: Arc<Mutex<HashSet<FixKey>>>,
+}
+
+impl QueuedFixes {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Forgets the queued fixes, eg once they've been applied.
+    pub fn next_pass(&self) {
+        self.lock_keys().clear();
+    }
+
+    /// Drops the violations in `violations` whose fix is queued already, and
+    /// queues the fixes of the rest. Violations without a fix are kept.
+    pub fn retain_unqueued(&self, source: &[u8], violations: &mut Vec<Violation<'_>>) {
+        let line_offsets = LineOffsets::new(source);
+        let mut keys = self.lock_keys();
+        violations.retain(|violation| {
+            violation
+                .fix
+                .as_ref()
+                .is_none_or(|fix| keys.insert(fix_key(source, &line_offsets, fix)))
+        });
+    }
+
+    fn lock_keys(&self) -> MutexGuard<'_, HashSet<FixKey>> {
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// How many times each fix has been offered over the passes of a fixing
+/// loop, so that one that keeps being offered (eg because another rule's fix
+/// keeps undoing it) stops being, once it has been `max_attempts` times, and
+/// is reported as `unresolvable_formatting_conflict` without a fix instead.
+/// Every check a fix is reported by counts as an attempt. Hosts share one
+/// between the checks of a fixing loop (see
+/// [`RustfmtRuleOptions::fix_attempts`](crate::RustfmtRuleOptions::fix_attempts)).
+/// Clones share the counts.
+#[derive(Clone, Debug)]
+pub struct FixAttempts {
+    max_attempts: usize,
+    counts: Arc<Mutex<HashMap<FixKey, usize>>>,
+}
+
+impl FixAttempts {
+    pub fn new(max_attempts: usize) -> Self {
+        Self {
+            max_attempts,
+            counts: Default::default(),
+        }
+    }
+
+    /// Counts an attempt for each fix in `violations`, and turns the
+    /// violations whose fix has been attempted too often into
+    /// `unresolvable_formatting_conflict` ones without a fix.
+    pub(crate) fn escalate(&self, source: &[u8], violations: &mut [Violation<'_>]) {
+        let line_offsets = LineOffsets::new(source);
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        for violation in violations {
+            let Some(fix) = violation.fix.as_ref() else {
+                continue;
+            };
+            let count = counts
+                .entry(fix_key(source, &line_offsets, fix))
+                .or_default();
+            *count += 1;
+            if *count > self.max_attempts {
+                violation.message_id = UNRESOLVABLE_FORMATTING_CONFLICT;
+                violation.message = format!(
+                    "rustfmt's fix here didn't stick after {} attempts, eg because another rule's fix conflicts with it.",
+                    self.max_attempts
+                );
+                violation.fix = None;
+            }
+        }
+    }
+}
+
+fn fix_key(source: &[u8], line_offsets: &LineOffsets, fix: &Fix) -> FixKey {
+    let original = source.get(fix.range.clone()).unwrap_or_default();
+    (
+        byte_range_to_rows(line_offsets, &fix.range),
+        hash(original),
+        hash(fix.replacement.as_bytes()),
+    )
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::parse, TrailingWhitespaceRule};
+
+    #[test]
+    fn test_retain_unqueued() {
+        let source = "fn a() {} \nfn b() {}  \n";
+        let tree = parse(source);
+        let queued = QueuedFixes::new();
+        let mut violations = TrailingWhitespaceRule.check(source.as_bytes(), &tree);
+        queued.retain_unqueued(source.as_bytes(), &mut violations);
+        assert_eq!(violations.len(), 2);
+
+        let mut violations = TrailingWhitespaceRule.check(source.as_bytes(), &tree);
+        violations[1].fix = None;
+        violations[0].fix.as_mut().unwrap().replacement = "//".to_owned();
+        violations.push(TrailingWhitespaceRule.check(source.as_bytes(), &tree)[1].clone());
+        queued.retain_unqueued(source.as_bytes(), &mut violations);
+        // A different fix for the first region, and the second without a fix.
+        assert_eq!(violations.len(), 2);
+        assert!(violations[1].fix.is_none());
+
+        queued.next_pass();
+        let mut violations = TrailingWhitespaceRule.check(source.as_bytes(), &tree);
+        queued.retain_unqueued(source.as_bytes(), &mut violations);
+        assert_eq!(violations.len(), 2);
+    }
+
+    #[test]
+    fn test_escalate() {
+        let source = "fn a() {} \nfn b() {}  \n";
+        let tree = parse(source);
+        let attempts = FixAttempts::new(2);
+        for _ in 0..2 {
+            let mut violations = TrailingWhitespaceRule.check(source.as_bytes(), &tree);
+            attempts.escalate(source.as_bytes(), &mut violations);
+            assert!(violations.iter().all(|violation| violation.fix.is_some()));
+        }
+        let mut violations = TrailingWhitespaceRule.check(source.as_bytes(), &tree);
+        violations[1].fix = None;
+        attempts.escalate(source.as_bytes(), &mut violations);
+        assert_eq!(violations[0].message_id, UNRESOLVABLE_FORMATTING_CONFLICT);
+        assert_eq!(violations[0].fix, None);
+        assert_ne!(violations[1].message_id, UNRESOLVABLE_FORMATTING_CONFLICT);
+    }
+}
@@ -0,0 +1,516 @@
+use std::{fmt::Write, ops::Range};
+
+use crate::{FormattingReport, Mismatch, STDIN_FILE_NAME};
+
+/// Renders a mismatch as a unified diff hunk (without file headers). If
+/// `max_lines` is given, the removed and added lines are each cut off after
+/// that many lines.
+pub fn render_hunk(mismatch: &Mismatch, max_lines: Option<usize>) -> String {
+    let original_lines = mismatch.original.lines().collect::<Vec<_>>();
+    let expected_lines = mismatch.expected.lines().collect::<Vec<_>>();
+    let mut hunk = format!(
+        "@@ -{} +{} @@\n",
+        hunk_range(mismatch.original_begin_line, original_lines.len()),
+        hunk_range(mismatch.expected_begin_line, expected_lines.len()),
+    );
+    push_lines(&mut hunk, '-', &original_lines, max_lines);
+    push_lines(&mut hunk, '+', &expected_lines, max_lines);
+    hunk
+}
+
+/// Renders the mismatches of `reports` as one unified patch, eg for CI to
+/// attach so that it can be applied without installing rustfmt. rustfmt's
+/// hunks have no context lines, so the patch has to be applied with
+/// `git apply --unidiff-zero` (or `patch -p1`).
+///
+/// Files are named by the reports' paths (so relative ones are relative to
+/// wherever the patch is applied), or `<stdin>` for reports without one.
+pub fn render_patch(reports: &[FormattingReport]) -> String {
+    let mut patch = String::new();
+    for report in reports
+        .iter()
+        .filter(|report| !report.mismatches.is_empty())
+    {
+        let path = report
+            .path
+            .as_ref()
+            .map_or(STDIN_FILE_NAME.into(), |path| path.to_string_lossy());
+        writeln!(patch, "--- a/{path}\n+++ b/{path}").unwrap();
+        for mismatch in &report.mismatches {
+            patch.push_str(&render_hunk(mismatch, None));
+        }
+    }
+    patch
+}
+
+fn hunk_range(begin_line: usize, line_count: usize) -> String {
+    match line_count {
+        // An empty range refers to the line before it.
+        0 => format!("{},0", begin_line.saturating_sub(1)),
+        1 => begin_line.to_string(),
+        _ => format!("{begin_line},{line_count}"),
+    }
+}
+
+fn push_lines(hunk: &mut String, prefix: char, lines: &[&str], max_lines: Option<usize>) {
+    let shown = max_lines.map_or(lines.len(), |max_lines| max_lines.min(lines.len()));
+    for line in &lines[..shown] {
+        writeln!(hunk, "{prefix}{line}").unwrap();
+    }
+    if shown < lines.len() {
+        writeln!(hunk, "{prefix}... ({} more lines)", lines.len() - shown).unwrap();
+    }
+}
+
+/// The smallest range of `original` that needs replacing (and what to replace
+/// it with) to turn it into `expected`, found by trimming their common prefix
+/// and suffix. Ranges always fall on `char` boundaries.
+pub fn narrow_replacement<'expected>(
+    original: &str,
+    expected: &'expected str,
+) -> (Range<usize>, &'expected str) {
+    let prefix_len = original
+        .chars()
+        .zip(expected.chars())
+        .take_while(|(original, expected)| original == expected)
+        .map(|(char, _)| char.len_utf8())
+        .sum::<usize>();
+    let (original_rest, expected_rest) = (&original[prefix_len..], &expected[prefix_len..]);
+    let suffix_len = original_rest
+        .chars()
+        .rev()
+        .zip(expected_rest.chars().rev())
+        .take_while(|(original, expected)| original == expected)
+        .map(|(char, _)| char.len_utf8())
+        .sum::<usize>();
+    (
+        prefix_len..original.len() - suffix_len,
+        &expected[prefix_len..expected.len() - suffix_len],
+    )
+}
+
+/// `mismatch` without the changes it makes to blank (or whitespace-only)
+/// lines, for [`RustfmtRuleOptions::keep_blank_lines`](crate::RustfmtRuleOptions::keep_blank_lines),
+/// or `None` if that's all it changes. Only the non-blank lines are diffed,
+/// and the changes to them are made around the original blank lines.
+pub(crate) fn without_blank_line_changes(mismatch: Mismatch) -> Option<Mismatch> {
+    let is_blank = |line: &str| line.trim().is_empty();
+    let non_blank = |text: &str| {
+        text.split_inclusive('\n')
+            .filter(|line| !is_blank(line))
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let mut original_lines = mismatch.original.split_inclusive('\n');
+    let mut expected = String::new();
+    for result in ::diff::slice(
+        &non_blank(&mismatch.original),
+        &non_blank(&mismatch.expected),
+    ) {
+        match result {
+            ::diff::Result::Left(_) | ::diff::Result::Both(..) => {
+                for line in original_lines.by_ref() {
+                    if !is_blank(line) {
+                        if matches!(result, ::diff::Result::Both(..)) {
+                            expected.push_str(line);
+                        }
+                        break;
+                    }
+                    expected.push_str(line);
+                }
+            }
+            ::diff::Result::Right(line) => expected.push_str(line),
+        }
+    }
+    expected.extend(original_lines);
+    if expected == mismatch.original {
+        return None;
+    }
+    let expected_line_count = expected.lines().count();
+    Some(Mismatch {
+        expected_end_line: mismatch.expected_begin_line + expected_line_count.saturating_sub(1),
+        expected,
+        ..mismatch
+    })
+}
+
+/// `mismatch` split into mismatches of at most `max_lines` lines on either
+/// side, for [`RustfmtRuleOptions::max_fix_lines`](crate::RustfmtRuleOptions::max_fix_lines):
+/// into the hunks of a line diff of it, and hunks that replace lines with as
+/// many others (eg reindenting them) into runs of up to `max_lines` of them.
+/// Other hunks are left as big as they are.
+pub(crate) fn split_mismatch(mismatch: Mismatch, max_lines: usize) -> Vec<Mismatch> {
+    let max_lines = max_lines.max(1);
+    let original = mismatch.original.split_inclusive('\n').collect::<Vec<_>>();
+    let expected = mismatch.expected.split_inclusive('\n').collect::<Vec<_>>();
+    if original.len() <= max_lines && expected.len() <= max_lines {
+        return vec![mismatch];
+    }
+    let mut parts = Vec::new();
+    for (original_lines, expected_lines) in hunks(&original, &expected) {
+        if original_lines.len() == expected_lines.len() {
+            for offset in (0..original_lines.len()).step_by(max_lines) {
+                let length = max_lines.min(original_lines.len() - offset);
+                parts.push((
+                    original_lines.start + offset..original_lines.start + offset + length,
+                    expected_lines.start + offset..expected_lines.start + offset + length,
+                ));
+            }
+        } else {
+            parts.push((original_lines, expected_lines));
+        }
+    }
+    parts
+        .into_iter()
+        .map(|(original_lines, expected_lines)| Mismatch {
+            original_begin_line: mismatch.original_begin_line + original_lines.start,
+            original_end_line: mismatch.original_begin_line
+                + original_lines
+                    .end
+                    .saturating_sub(1)
+                    .max(original_lines.start),
+            expected_begin_line: mismatch.expected_begin_line + expected_lines.start,
+            expected_end_line: mismatch.expected_begin_line
+                + expected_lines
+                    .end
+                    .saturating_sub(1)
+                    .max(expected_lines.start),
+            original: original[original_lines].concat(),
+            expected: expected[expected_lines].concat(),
+        })
+        .collect()
+}
+
+/// The hunks of a line diff of `original` and `expected`, as their lines in
+/// each.
+fn hunks(original: &[&str], expected: &[&str]) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut hunks = Vec::<(Range<usize>, Range<usize>)>::new();
+    let (mut original_index, mut expected_index) = (0, 0);
+    let mut in_hunk = false;
+    for result in ::diff::slice(original, expected) {
+        if matches!(result, ::diff::Result::Both(..)) {
+            original_index += 1;
+            expected_index += 1;
+            in_hunk = false;
+            continue;
+        }
+        if !in_hunk {
+            hunks.push((
+                original_index..original_index,
+                expected_index..expected_index,
+            ));
+            in_hunk = true;
+        }
+        let hunk = hunks.last_mut().unwrap();
+        if matches!(result, ::diff::Result::Left(_)) {
+            original_index += 1;
+            hunk.0.end = original_index;
+        } else {
+            expected_index += 1;
+            hunk.1.end = expected_index;
+        }
+    }
+    hunks
+}
+
+/// `mismatches` in order, with the ones whose original lines overlap (which
+/// rustfmt can report with `--file-lines` around macros, and which would
+/// corrupt the file if both were fixed) merged into one. Where they overlap,
+/// the earlier mismatch's change wins; the later one's changes to lines after
+/// it are kept, except for changes to lines on both sides of where the
+/// earlier one ends that can't be split there, which are left out (so those
+/// lines stay as they are until the next check).
+pub(crate) fn merge_overlapping(mut mismatches: Vec<Mismatch>) -> Vec<Mismatch> {
+    mismatches.sort_by_key(|mismatch| mismatch.original_begin_line);
+    let mut merged = Vec::<Mismatch>::with_capacity(mismatches.len());
+    for mismatch in mismatches {
+        match merged.last_mut() {
+            Some(last) if is_overlapping(last, &mismatch) => {
+                log::warn!(
+                    "rustfmt reported overlapping mismatches at lines {}-{} and {}-{}, merging them",
+                    last.original_begin_line,
+                    last.original_end_line,
+                    mismatch.original_begin_line,
+                    mismatch.original_end_line
+                );
+                merge_into(last, mismatch);
+            }
+            _ => merged.push(mismatch),
+        }
+    }
+    merged
+}
+
+/// Whether `mismatch` (which doesn't start before `last`) changes or inserts
+/// among the lines `last` replaces.
+fn is_overlapping(last: &Mismatch, mismatch: &Mismatch) -> bool {
+    !last.original.is_empty()
+        && mismatch.original_begin_line <= last.original_end_line
+        && (!mismatch.original.is_empty()
+            || mismatch.original_begin_line > last.original_begin_line)
+}
+
+fn merge_into(last: &mut Mismatch, mismatch: Mismatch) {
+    let original = mismatch.original.split_inclusive('\n').collect::<Vec<_>>();
+    let expected = mismatch.expected.split_inclusive('\n').collect::<Vec<_>>();
+    // The first of `original`'s lines after `last`'s.
+    let first_kept = last.original_end_line + 1 - mismatch.original_begin_line;
+    let mut unchanged = 0;
+    for (original_lines, expected_lines) in hunks(&original, &expected).into_iter().chain([(
+        original.len()..original.len(),
+        expected.len()..expected.len(),
+    )]) {
+        for line in
+            &original[first_kept.max(unchanged).min(original_lines.start)..original_lines.start]
+        {
+            last.expected.push_str(line);
+        }
+        if original_lines.start >= first_kept {
+            last.expected
+                .push_str(&expected[expected_lines.clone()].concat());
+        } else if original_lines.len() == expected_lines.len() {
+            let skipped = first_kept.min(original_lines.end) - original_lines.start;
+            last.expected
+                .push_str(&expected[expected_lines.start + skipped..expected_lines.end].concat());
+        } else if original_lines.end > first_kept {
+            last.expected
+                .push_str(&original[first_kept..original_lines.end].concat());
+        }
+        unchanged = original_lines.end;
+    }
+    if mismatch.original_end_line > last.original_end_line && !original.is_empty() {
+        last.original.push_str(&original[first_kept..].concat());
+        last.original_end_line = mismatch.original_end_line;
+    }
+    last.expected_end_line =
+        last.expected_begin_line + last.expected.lines().count().saturating_sub(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_hunk() {
+        let mismatch = Mismatch {
+            original_begin_line: 2,
+            original_end_line: 2,
+            expected_begin_line: 2,
+            expected_end_line: 4,
+            original: "fn f() { a; b; }\n".to_owned(),
+            expected: "fn f() {\n    a;\n    b;\n}\n".to_owned(),
+        };
+        assert_eq!(
+            render_hunk(&mismatch, None),
+            "@@ -2 +2,4 @@\n-fn f() { a; b; }\n+fn f() {\n+    a;\n+    b;\n+}\n"
+        );
+        assert_eq!(
+            render_hunk(&mismatch, Some(2)),
+            "@@ -2 +2,4 @@\n-fn f() { a; b; }\n+fn f() {\n+    a;\n+... (2 more lines)\n"
+        );
+    }
+
+    #[test]
+    fn test_render_patch() {
+        let mismatch = |line, original: &str, expected: &str| Mismatch {
+            original_begin_line: line,
+            original_end_line: line,
+            expected_begin_line: line,
+            expected_end_line: line,
+            original: original.to_owned(),
+            expected: expected.to_owned(),
+        };
+        let reports = [
+            FormattingReport {
+                path: Some("src/a.rs".into()),
+                ..FormattingReport::from_mismatches(vec![
+                    mismatch(1, "fn  a() {}\n", "fn a() {}\n"),
+                    mismatch(3, "fn  b() {}\n", "fn b() {}\n"),
+                ])
+            },
+            FormattingReport {
+                path: Some("src/c.rs".into()),
+                ..FormattingReport::clean()
+            },
+            FormattingReport::from_mismatches(vec![mismatch(2, "c( );\n", "c();\n")]),
+        ];
+        assert_eq!(
+            render_patch(&reports),
+            "--- a/src/a.rs\n+++ b/src/a.rs\n\
+             @@ -1 +1 @@\n-fn  a() {}\n+fn a() {}\n\
+             @@ -3 +3 @@\n-fn  b() {}\n+fn b() {}\n\
+             --- a/<stdin>\n+++ b/<stdin>\n\
+             @@ -2 +2 @@\n-c( );\n+c();\n"
+        );
+    }
+
+    #[test]
+    fn test_narrow_replacement() {
+        assert_eq!(
+            narrow_replacement("fn  b( ) {}\n", "fn b() {}\n"),
+            (3..7, "b(")
+        );
+        assert_eq!(
+            narrow_replacement("let x = 1;  \n", "let x = 1;\n"),
+            (10..12, "")
+        );
+        assert_eq!(narrow_replacement("a\n", "a\n"), (2..2, ""));
+        assert_eq!(narrow_replacement("", "use a;\n"), (0..0, "use a;\n"));
+        assert_eq!(narrow_replacement("\n\n", "\n"), (1..2, ""));
+        // Multi-byte chars sharing a leading byte aren't split.
+        assert_eq!(narrow_replacement("é", "è"), (0..2, "è"));
+        assert_eq!(narrow_replacement("xéy", "xèy"), (1..3, "è"));
+    }
+
+    #[test]
+    fn test_without_blank_line_changes() {
+        let mismatch = |original: &str, expected: &str| Mismatch {
+            original_begin_line: 1,
+            original_end_line: original.lines().count(),
+            expected_begin_line: 1,
+            expected_end_line: expected.lines().count(),
+            original: original.to_owned(),
+            expected: expected.to_owned(),
+        };
+        assert_eq!(
+            without_blank_line_changes(mismatch("fn a() {}\n\n\n", "fn a() {}\n\n")),
+            None
+        );
+        assert_eq!(
+            without_blank_line_changes(mismatch("{\n\n    a( );\n   \n}\n", "{\n    a();\n}\n")),
+            Some(mismatch(
+                "{\n\n    a( );\n   \n}\n",
+                "{\n\n    a();\n   \n}\n"
+            ))
+        );
+        assert_eq!(
+            without_blank_line_changes(mismatch(
+                "let a =\r\n\r\n    1;\r\nb( );\r\n",
+                "let a = 1;\r\nb();\r\n"
+            )),
+            Some(mismatch(
+                "let a =\r\n\r\n    1;\r\nb( );\r\n",
+                "\r\nlet a = 1;\r\nb();\r\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_render_hunk_insertion() {
+        let mismatch = Mismatch {
+            original_begin_line: 3,
+            original_end_line: 3,
+            expected_begin_line: 3,
+            expected_end_line: 3,
+            original: "".to_owned(),
+            expected: "\n".to_owned(),
+        };
+        assert_eq!(render_hunk(&mismatch, None), "@@ -2,0 +3 @@\n+\n");
+    }
+
+    #[test]
+    fn test_split_mismatch() {
+        let mismatch = Mismatch {
+            original_begin_line: 2,
+            original_end_line: 7,
+            expected_begin_line: 2,
+            expected_end_line: 6,
+            original: "fn a() {\n  b();\n  c();\n  d();\n}\nfn  e() {}\n".to_owned(),
+            expected: "fn a() {\n    b();\n    c();\n    d();\n}\n".to_owned(),
+        };
+        assert_eq!(split_mismatch(mismatch.clone(), 6), vec![mismatch.clone()]);
+        let parts = split_mismatch(mismatch, 2);
+        assert_eq!(
+            parts
+                .iter()
+                .map(|part| (
+                    part.original_begin_line,
+                    part.original_end_line,
+                    part.expected_begin_line,
+                    part.expected_end_line,
+                    &*part.original,
+                    &*part.expected,
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (3, 4, 3, 4, "  b();\n  c();\n", "    b();\n    c();\n"),
+                (5, 5, 5, 5, "  d();\n", "    d();\n"),
+                (7, 7, 7, 7, "fn  e() {}\n", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_overlapping() {
+        // Lines 1-3 are `a!( x );`, `b( );` and `c( );`, with two overlapping
+        // mismatches for line 2 (eg from two `--file-lines` ranges).
+        let files = crate::parse_mismatches(
+            r#"[{"name":"<stdin>","mismatches":[
+                {"original_begin_line":1,"original_end_line":2,"expected_begin_line":1,"expected_end_line":2,"original":"a!( x );\nb( );\n","expected":"a!(x);\nb();\n"},
+                {"original_begin_line":2,"original_end_line":3,"expected_begin_line":2,"expected_end_line":3,"original":"b( );\nc( );\n","expected":"b();\nc();\n"},
+                {"original_begin_line":5,"original_end_line":5,"expected_begin_line":5,"expected_end_line":5,"original":"e( );\n","expected":"e();\n"}
+            ]}]"#,
+        )
+        .unwrap();
+        let merged = merge_overlapping(files[0].mismatches.clone());
+        assert_eq!(
+            merged,
+            [
+                Mismatch {
+                    original_begin_line: 1,
+                    original_end_line: 3,
+                    expected_begin_line: 1,
+                    expected_end_line: 3,
+                    original: "a!( x );\nb( );\nc( );\n".to_owned(),
+                    expected: "a!(x);\nb();\nc();\n".to_owned(),
+                },
+                files[0].mismatches[2].clone(),
+            ]
+        );
+
+        // One inside another, out of order, and an insertion inside it: only
+        // the outer one is kept.
+        let files = crate::parse_mismatches(
+            r#"[{"name":"<stdin>","mismatches":[
+                {"original_begin_line":2,"original_end_line":2,"expected_begin_line":2,"expected_end_line":2,"original":"b( );\n","expected":"b();\n"},
+                {"original_begin_line":1,"original_end_line":3,"expected_begin_line":1,"expected_end_line":1,"original":"a!(\nb( );\n);\n","expected":"a!(b());\n"},
+                {"original_begin_line":3,"original_end_line":2,"expected_begin_line":3,"expected_end_line":3,"original":"","expected":"\n"}
+            ]}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            merge_overlapping(files[0].mismatches.clone()),
+            [files[0].mismatches[1].clone()]
+        );
+
+        // A change to lines on both sides of where the first one ends, which
+        // can't be split there, so that those lines are left as they are.
+        let files = crate::parse_mismatches(
+            r#"[{"name":"<stdin>","mismatches":[
+                {"original_begin_line":1,"original_end_line":2,"expected_begin_line":1,"expected_end_line":2,"original":"a( );\nb( );\n","expected":"a();\nb();\n"},
+                {"original_begin_line":2,"original_end_line":4,"expected_begin_line":2,"expected_end_line":3,"original":"b( );\nc( );\nd( );\n","expected":"b(); c();\nd();\n"}
+            ]}]"#,
+        )
+        .unwrap();
+        let merged = merge_overlapping(files[0].mismatches.clone());
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].original, "a( );\nb( );\nc( );\nd( );\n");
+        assert_eq!(merged[0].expected, "a();\nb();\nc( );\nd( );\n");
+        assert_eq!(merged[0].original_end_line, 4);
+        assert_eq!(merged[0].expected_end_line, 4);
+
+        // Adjacent mismatches, and an insertion before one, aren't merged.
+        let files = crate::parse_mismatches(
+            r#"[{"name":"<stdin>","mismatches":[
+                {"original_begin_line":1,"original_end_line":0,"expected_begin_line":1,"expected_end_line":1,"original":"","expected":"\n"},
+                {"original_begin_line":1,"original_end_line":1,"expected_begin_line":2,"expected_end_line":2,"original":"a( );\n","expected":"a();\n"},
+                {"original_begin_line":2,"original_end_line":2,"expected_begin_line":3,"expected_end_line":3,"original":"b( );\n","expected":"b();\n"}
+            ]}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            merge_overlapping(files[0].mismatches.clone()),
+            files[0].mismatches
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use tree_sitter::{Node, Tree};
+
+use crate::{narrow_replacement, Fix, RustfmtOptions, Violation};
+
+pub const DOC_CODE_FORMATTING: &str = "doc_code_formatting";
+
+const SHELL_HEADER: &str = "fn main() {";
+const INDENT: &str = "    ";
+
+/// Fenced code block attributes rustdoc runs (or at least compiles) as Rust.
+const RUST_ATTRIBUTES: [&str; 7] = [
+    "rust",
+    "ignore",
+    "no_run",
+    "should_panic",
+    "compile_fail",
+    "test_harness",
+    "standalone_crate",
+];
+
+/// A `///` or `//!` comment line.
+struct DocLine<'tree, 'source> {
+    node: Node<'tree>,
+    prefix: &'static str,
+    /// Everything after the prefix, without a trailing `\r`.
+    content: &'source str,
+}
+
+/// Violations for the Rust code blocks in doc comments that rustfmt would
+/// format differently, each formatted as the body of a `fn main()` (as
+/// rustdoc runs it). Blocks that rustfmt can't format (eg `compile_fail`
+/// ones) or that have hidden (`# `) lines are left alone.
+pub(crate) fn doc_code_violations<'tree>(
+    source: &[u8],
+    tree: &'tree Tree,
+    options: &RustfmtOptions,
+    with_fixes: bool,
+) -> Vec<Violation<'tree>> {
+    let mut violations = Vec::new();
+    for run in doc_comment_runs(tree, source) {
+        let mut index = 0;
+        while index < run.len() {
+            let Some((margin, attributes)) = fence(run[index].content) else {
+                index += 1;
+                continue;
+            };
+            let Some(end) = (index + 1..run.len()).find(|&end| fence(run[end].content).is_some())
+            else {
+                break;
+            };
+            if is_rust(attributes) {
+                violations.extend(block_violation(
+                    source,
+                    &run[index + 1..end],
+                    margin,
+                    options,
+                    with_fixes,
+                ));
+            }
+            index = end + 1;
+        }
+    }
+    violations
+}
+
+/// The consecutive doc comment lines (of the same kind, at the same column)
+/// in `tree`.
+fn doc_comment_runs<'tree, 'source>(
+    tree: &'tree Tree,
+    source: &'source [u8],
+) -> Vec<Vec<DocLine<'tree, 'source>>> {
+    let mut runs = Vec::<Vec<DocLine>>::new();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if let Some(line) = doc_line(node, source) {
+            let continues_run = runs.last().and_then(|run| run.last()).is_some_and(|last| {
+                last.prefix == line.prefix
+                    && last.node.start_position().row + 1 == node.start_position().row
+                    && last.node.start_position().column == node.start_position().column
+            });
+            if continues_run {
+                runs.last_mut().unwrap().push(line);
+            } else {
+                runs.push(vec![line]);
+            }
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return runs;
+            }
+        }
+    }
+}
+
+fn doc_line<'tree, 'source>(
+    node: Node<'tree>,
+    source: &'source [u8],
+) -> Option<DocLine<'tree, 'source>> {
+    if node.kind() != "line_comment" {
+        return None;
+    }
+    let text = std::str::from_utf8(&source[node.byte_range()]).ok()?;
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    let prefix = ["///", "//!"]
+        .into_iter()
+        .find(|prefix| text.starts_with(prefix))?;
+    // `////` starts an ordinary comment.
+    if text.starts_with("////") {
+        return None;
+    }
+    Some(DocLine {
+        node,
+        prefix,
+        content: &text[prefix.len()..],
+    })
+}
+
+/// The indentation and attributes of a line opening or closing a fenced
+/// code block.
+fn fence(content: &str) -> Option<(&str, &str)> {
+    let trimmed = content.trim_start();
+    let attributes = trimmed.strip_prefix("```")?;
+    Some((&content[..content.len() - trimmed.len()], attributes.trim()))
+}
+
+fn is_rust(attributes: &str) -> bool {
+    attributes
+        .split(|char: char| char == ',' || char.is_whitespace())
+        .filter(|attribute| !attribute.is_empty())
+        .all(|attribute| RUST_ATTRIBUTES.contains(&attribute) || attribute.starts_with("edition"))
+}
+
+fn block_violation<'tree>(
+    source: &[u8],
+    lines: &[DocLine<'tree, '_>],
+    margin: &str,
+    options: &RustfmtOptions,
+    with_fixes: bool,
+) -> Option<Violation<'tree>> {
+    let (first, last) = (lines.first()?, lines.last()?);
+    let code_lines = lines
+        .iter()
+        .map(|line| match line.content.strip_prefix(margin) {
+            Some(code) => Some(code),
+            None if line.content.trim().is_empty() => Some(""),
+            // Less indented than the fence.
+            None => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if code_lines.iter().all(|line| line.trim().is_empty())
+        || code_lines
+            .iter()
+            .any(|line| matches!(line.trim_start(), "#") || line.trim_start().starts_with("# "))
+    {
+        return None;
+    }
+
+    let body = formatted_body(&code_lines, options)?;
+    if body == code_lines {
+        return None;
+    }
+    let line_ending = if source[first.node.byte_range()].ends_with(b"\r") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let indent = String::from_utf8_lossy(
+        &source
+            [first.node.start_byte() - first.node.start_position().column..first.node.start_byte()],
+    );
+    let replacement = body
+        .iter()
+        .map(|line| match line.as_str() {
+            "" => first.prefix.to_owned(),
+            line => format!("{}{margin}{line}", first.prefix),
+        })
+        .collect::<Vec<_>>()
+        .join(&format!("{line_ending}{indent}"));
+    let range =
+        first.node.start_byte()..last.node.start_byte() + last.prefix.len() + last.content.len();
+    let original = std::str::from_utf8(&source[range.clone()]).ok()?;
+    let (narrowed, replacement) = narrow_replacement(original, &replacement);
+    Some(Violation {
+        message_id: DOC_CODE_FORMATTING,
+        message: "Unexpected formatting in a doc comment code block.".to_owned(),
+        node: first.node,
+        fix: with_fixes.then(|| Fix {
+            range: range.start + narrowed.start..range.start + narrowed.end,
+            replacement: replacement.to_owned(),
+        }),
+        range,
+    })
+}
+
+/// `code_lines` formatted as the body of a `fn main()`, or `None` if rustfmt
+/// fails on them.
+fn formatted_body(code_lines: &[&str], options: &RustfmtOptions) -> Option<Vec<String>> {
+    let shell = format!("{SHELL_HEADER}\n{}\n}}\n", code_lines.join("\n"));
+    let formatted = match crate::format_to_string(shell.as_bytes(), options) {
+        Ok(formatted) => formatted,
+        Err(error) => {
+            log::debug!("not formatting a doc comment code block: {error}");
+            return None;
+        }
+    };
+    let lines = formatted.lines().collect::<Vec<_>>();
+    let [SHELL_HEADER, body @ .., "}"] = &lines[..] else {
+        return None;
+    };
+    Some(
+        body.iter()
+            .map(|line| line.strip_prefix(INDENT).unwrap_or(line).to_owned())
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use super::*;
+    use crate::test_utils::parse;
+
+    fn check(source: &str) -> Vec<(Range<usize>, String)> {
+        let tree = parse(source);
+        doc_code_violations(source.as_bytes(), &tree, &Default::default(), true)
+            .into_iter()
+            .map(|violation| {
+                (
+                    violation.range,
+                    String::from_utf8(violation.fix.unwrap().apply(source.as_bytes())).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_doc_code() {
+        let source = "mod a {\n    /// b\n    ///\n    /// ```\n    /// let c  = d( 1,2 );\n    /// if c { e() }\n    /// ```\n    fn f() {}\n}\n";
+        assert_eq!(
+            check(source),
+            [(
+                42..85,
+                "mod a {\n    /// b\n    ///\n    /// ```\n    /// let c = d(1, 2);\n    /// if c {\n    ///     e()\n    /// }\n    /// ```\n    fn f() {}\n}\n"
+                    .to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_doc_code_blocks_left_alone() {
+        // Formatted already, not Rust, hidden lines, doesn't parse, and an
+        // ordinary comment.
+        for source in [
+            "//! ```\n//! let a = 1;\n//! ```\n",
+            "/// ```text\n/// a  b\n/// ```\nfn c() {}\n",
+            "/// ```\n/// # let a  = 1;\n/// ```\nfn c() {}\n",
+            "/// ```compile_fail\n/// let a  = ;\n/// ```\nfn c() {}\n",
+            "//// ```\n//// let a  = 1;\n//// ```\n",
+        ] {
+            assert_eq!(check(source), [], "{source}");
+        }
+    }
+
+    #[test]
+    fn test_doc_code_nested_margin() {
+        let source = "//! - a:\n//!   ```rust,no_run\n//!   b( );\n//!\n//!   c();\n//!   ```\n";
+        assert_eq!(
+            check(source),
+            [(
+                30..56,
+                "//! - a:\n//!   ```rust,no_run\n//!   b();\n//!\n//!   c();\n//!   ```\n"
+                    .to_owned()
+            )]
+        );
+    }
+}
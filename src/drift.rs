@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use tree_sitter::Tree;
+
+use crate::{imports::detect_group_imports, FormattingReport, GroupImports, Mismatch};
+
+/// The style signals of a file, as recorded by [`StyleDrift`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStyle {
+    pub path: PathBuf,
+    /// How its top-level imports are grouped (see
+    /// [`OrganizeImportsRuleOptions::group_imports`](crate::OrganizeImportsRuleOptions::group_imports)),
+    /// if they're from more than one of `std`, other crates and the crate
+    /// itself.
+    pub group_imports: Option<GroupImports>,
+    /// How many spaces a level of indentation is in the lines rustfmt only
+    /// reindents, or `None` if it doesn't reindent any (as for files that
+    /// are indented the way it's configured to).
+    pub indent_width: Option<usize>,
+}
+
+impl FileStyle {
+    /// The signals of the file at `path`, given the report of its check.
+    pub fn new(path: PathBuf, source: &[u8], tree: &Tree, report: &FormattingReport) -> Self {
+        Self {
+            path,
+            group_imports: detect_group_imports(source, tree),
+            indent_width: indent_width(&report.mismatches),
+        }
+    }
+}
+
+/// A file whose style differs from most others'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StyleOutlier {
+    GroupImports {
+        path: PathBuf,
+        group_imports: GroupImports,
+        usual: GroupImports,
+    },
+    IndentWidth {
+        path: PathBuf,
+        indent_width: Option<usize>,
+        usual: Option<usize>,
+    },
+}
+
+impl StyleOutlier {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::GroupImports { path, .. } | Self::IndentWidth { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for StyleOutlier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let indentation = |indent_width: &Option<usize>| match indent_width {
+            Some(indent_width) => format!("{indent_width}-space indentation"),
+            None => "rustfmt's indentation".to_owned(),
+        };
+        match self {
+            Self::GroupImports {
+                path,
+                group_imports,
+                usual,
+            } => write!(
+                f,
+                "{}: imports are grouped as {group_imports:?}, but most files group them as {usual:?}",
+                path.display()
+            ),
+            Self::IndentWidth {
+                path,
+                indent_width,
+                usual,
+            } => write!(
+                f,
+                "{}: has {}, but most files have {}",
+                path.display(),
+                indentation(indent_width),
+                indentation(usual)
+            ),
+        }
+    }
+}
+
+/// The [`FileStyle`]s of the files of a run, to find the files (eg of crates
+/// whose rustfmt config drifted from the workspace's) that don't look like
+/// the rest. The rule records the files it checks with a path (see
+/// [`RustfmtRuleOptions::style_drift`](crate::RustfmtRuleOptions::style_drift)),
+/// and hosts can [`record()`](Self::record) others. Clones share the files.
+#[derive(Clone, Debug, Default)]
+pub struct StyleDrift {
+    files: Arc<Mutex<HashMap<PathBuf, FileStyle>>>,
+}
+
+impl StyleDrift {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records `file`, replacing what was recorded for its path before.
+    pub fn record(&self, file: FileStyle) {
+        self.files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(file.path.clone(), file);
+    }
+
+    /// The recorded files, by path.
+    pub fn files(&self) -> Vec<FileStyle> {
+        let mut files = self
+            .files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    }
+
+    /// The files whose signals differ from the ones most files have, by
+    /// path. Signals only have a usual value if more files have it than any
+    /// other.
+    pub fn outliers(&self) -> Vec<StyleOutlier> {
+        let files = self.files();
+        let mut outliers = Vec::new();
+        let group_imports = files
+            .iter()
+            .filter_map(|file| Some((file, file.group_imports?)))
+            .collect::<Vec<_>>();
+        if let Some(usual) = usual(group_imports.iter().map(|(_, value)| *value)) {
+            outliers.extend(
+                group_imports
+                    .iter()
+                    .filter(|(_, value)| *value != usual)
+                    .map(|(file, value)| StyleOutlier::GroupImports {
+                        path: file.path.clone(),
+                        group_imports: *value,
+                        usual,
+                    }),
+            );
+        }
+        if let Some(usual) = usual(files.iter().map(|file| file.indent_width)) {
+            outliers.extend(
+                files
+                    .iter()
+                    .filter(|file| file.indent_width != usual)
+                    .map(|file| StyleOutlier::IndentWidth {
+                        path: file.path.clone(),
+                        indent_width: file.indent_width,
+                        usual,
+                    }),
+            );
+        }
+        outliers.sort_by(|a, b| a.path().cmp(b.path()));
+        outliers
+    }
+}
+
+/// The value more of `values` are than any other.
+fn usual<T: Copy + PartialEq>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts = Vec::<(T, usize)>::new();
+    for value in values {
+        match counts.iter_mut().find(|(counted, _)| *counted == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    match counts.as_slice() {
+        [(value, _)] => Some(*value),
+        [(value, count), (_, next_count), ..] if count > next_count => Some(*value),
+        _ => None,
+    }
+}
+
+/// The greatest common divisor of the indentation of the lines of mismatches
+/// that only reindent them.
+fn indent_width(mismatches: &[Mismatch]) -> Option<usize> {
+    let mut width = 0;
+    for mismatch in mismatches {
+        let original = mismatch.original.lines().collect::<Vec<_>>();
+        let expected = mismatch.expected.lines().collect::<Vec<_>>();
+        let is_reindent = original.len() == expected.len()
+            && original
+                .iter()
+                .zip(&expected)
+                .all(|(original, expected)| original.trim_start() == expected.trim_start());
+        if !is_reindent {
+            continue;
+        }
+        for line in original {
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            if indent > 0 && !line.trim().is_empty() {
+                width = gcd(width, indent);
+            }
+        }
+    }
+    (width > 0).then_some(width)
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    fn file_style(path: &str, source: &str, formatted: &str) -> FileStyle {
+        let report = FormattingReport::from_mismatches(
+            crate::fallback::diff_mismatches(source, formatted)
+                .into_iter()
+                .flat_map(|file| file.mismatches)
+                .collect(),
+        );
+        FileStyle::new(path.into(), source.as_bytes(), &parse(source), &report)
+    }
+
+    #[test]
+    fn test_style_drift() {
+        let grouped = "use std::fmt;\n\nuse a;\n";
+        let two_spaces = "fn a() {\n  b();\n  if c {\n    d();\n  }\n}\n";
+        let four_spaces = "fn a() {\n    b();\n    if c {\n        d();\n    }\n}\n";
+        let drift = StyleDrift::new();
+        drift.record(file_style("a.rs", grouped, grouped));
+        drift.record(file_style("b.rs", grouped, grouped));
+        drift.record(file_style(
+            "c.rs",
+            &format!("use a;\nuse std::fmt;\n{two_spaces}"),
+            &format!("use a;\nuse std::fmt;\n{four_spaces}"),
+        ));
+        assert_eq!(drift.files()[2].group_imports, Some(GroupImports::One));
+        assert_eq!(drift.files()[2].indent_width, Some(2));
+        let outliers = drift.outliers();
+        assert_eq!(
+            outliers.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "c.rs: imports are grouped as One, but most files group them as StdExternalCrate",
+                "c.rs: has 2-space indentation, but most files have rustfmt's indentation",
+            ]
+        );
+
+        drift.record(file_style("b.rs", "fn a() {}\n", "fn a() {}\n"));
+        assert_eq!(drift.outliers().len(), 1);
+    }
+}
@@ -0,0 +1,163 @@
+//! Working out which lines a formatter-style rule has to check again after a
+//! fixing run, for `--file-lines` or the like: the items that were edited
+//! since, and where the run's violations were.
+
+use std::ops::Range;
+
+use tree_sitter::{InputEdit, Tree};
+
+use crate::{
+    changed_item_rows, changed_range_item_rows,
+    line_ranges::{byte_range_to_rows, merge},
+    LineOffsets,
+};
+
+/// What's known about a file since the last fixing run, for
+/// [`changed_line_ranges()`].
+#[derive(Clone, Copy, Debug)]
+pub struct LineRangeContext<'a> {
+    /// The file as it is now.
+    pub source: &'a [u8],
+    /// The file's tree, with `edits` applied.
+    pub tree: &'a Tree,
+    /// The edits passed to [`Tree::edit()`] since the last run, in order.
+    pub edits: &'a [InputEdit],
+    /// The [`Tree::changed_ranges()`] between the last run's tree and `tree`,
+    /// if the host has them (see [`changed_range_item_rows()`]).
+    pub changed_ranges: Option<&'a [tree_sitter::Range]>,
+    /// The byte ranges of the violations reported in the last run, in the
+    /// file as it was then. Their lines are checked again whether or not
+    /// they were fixed, since fixing one can leave the lines around it to
+    /// format differently.
+    pub previous_violations: &'a [Range<usize>],
+}
+
+impl<'a> LineRangeContext<'a> {
+    /// A context with just `edits`.
+    pub fn new(source: &'a [u8], tree: &'a Tree, edits: &'a [InputEdit]) -> Self {
+        Self {
+            source,
+            tree,
+            edits,
+            changed_ranges: None,
+            previous_violations: &[],
+        }
+    }
+}
+
+/// The rows (0-based, half-open, sorted and merged) to check again: the
+/// innermost items around the edits (see [`changed_item_rows()`]), and the
+/// rows the previous violations are on now that the edits moved them.
+pub fn changed_line_ranges(context: &LineRangeContext) -> Vec<Range<usize>> {
+    let mut rows = match context.changed_ranges {
+        Some(changed_ranges) => {
+            changed_range_item_rows(context.tree, changed_ranges, context.edits)
+        }
+        None => changed_item_rows(context.tree, context.edits),
+    };
+    let line_offsets = LineOffsets::new(context.source);
+    rows.extend(context.previous_violations.iter().map(|range| {
+        let range = edited_range(range.clone(), context.edits);
+        let start = range.start.min(context.source.len());
+        byte_range_to_rows(
+            &line_offsets,
+            &(start..range.end.clamp(start, context.source.len())),
+        )
+    }));
+    let line_count = line_offsets.line_count().max(1);
+    merge(
+        rows.into_iter()
+            .map(|rows| rows.start.min(line_count)..rows.end.min(line_count)),
+    )
+}
+
+/// Where `range` is after `edits`: moved by the ones before it, and grown to
+/// cover the new text of the ones that overlap it.
+fn edited_range(range: Range<usize>, edits: &[InputEdit]) -> Range<usize> {
+    edits.iter().fold(range, |range, edit| {
+        let moved = |offset: usize| offset - edit.old_end_byte + edit.new_end_byte;
+        if range.end <= edit.start_byte {
+            range
+        } else if range.start >= edit.old_end_byte {
+            moved(range.start)..moved(range.end)
+        } else {
+            range.start.min(edit.start_byte)..moved(range.end.max(edit.old_end_byte))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::test_utils::parse;
+
+    const SOURCE: &str = "fn a() {\n    b( );\n}\n\nfn c() {\n    d( );\n}\n";
+
+    /// Inserting `"// x\n"` at the start of the file.
+    fn insert_line() -> (String, InputEdit) {
+        let edited = format!("// x\n{SOURCE}");
+        let edit = InputEdit {
+            start_byte: 0,
+            old_end_byte: 0,
+            new_end_byte: 5,
+            start_position: Point::new(0, 0),
+            old_end_position: Point::new(0, 0),
+            new_end_position: Point::new(1, 0),
+        };
+        (edited, edit)
+    }
+
+    #[test]
+    fn test_changed_line_ranges() {
+        let (edited, edit) = insert_line();
+        let tree = parse(&edited);
+        let edits = [edit];
+        let context = LineRangeContext::new(edited.as_bytes(), &tree, &edits);
+        // The comment and the item after it.
+        assert_eq!(changed_line_ranges(&context), [0..4]);
+
+        // `d( );` was reported on row 5, which is row 6 now.
+        let d = SOURCE.find("d( )").unwrap();
+        let previous_violations = [d..d + 4];
+        assert_eq!(
+            changed_line_ranges(&LineRangeContext {
+                previous_violations: &previous_violations,
+                ..context
+            }),
+            [0..4, 6..7]
+        );
+
+        // Without edits, just the violations' rows are checked, with ones
+        // past the end of the file on its last row.
+        let tree = parse(SOURCE);
+        let previous_violations = [d..d + 4, 1_000..1_001];
+        assert_eq!(
+            changed_line_ranges(&LineRangeContext {
+                previous_violations: &previous_violations,
+                ..LineRangeContext::new(SOURCE.as_bytes(), &tree, &[])
+            }),
+            [5..7]
+        );
+    }
+
+    #[test]
+    fn test_edited_range() {
+        // Replacing bytes 10..12 with 5 bytes.
+        let edit = InputEdit {
+            start_byte: 10,
+            old_end_byte: 12,
+            new_end_byte: 15,
+            start_position: Point::new(0, 10),
+            old_end_position: Point::new(0, 12),
+            new_end_position: Point::new(0, 15),
+        };
+        assert_eq!(edited_range(2..10, &[edit]), 2..10);
+        assert_eq!(edited_range(12..14, &[edit]), 15..17);
+        assert_eq!(edited_range(8..11, &[edit]), 8..15);
+        assert_eq!(edited_range(11..20, &[edit]), 10..23);
+        // Edits apply one after another.
+        assert_eq!(edited_range(12..14, &[edit, edit]), 18..20);
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use toml::Table;
+
+use crate::{memo::Memo, FileMismatches, RustfmtError, RustfmtOptions, SymlinkPolicy};
+
+/// The edition Cargo uses for packages that don't declare one, which is
+/// rustfmt's default too.
+const DEFAULT_EDITION: &str = "2015";
+
+/// The editions that are tried, newest first, for
+/// [`RustfmtRuleOptions::edition_fallback`](crate::RustfmtRuleOptions::edition_fallback).
+const FALLBACK_EDITIONS: [&str; 3] = ["2021", "2018", "2015"];
+
+/// The keywords that some editions have and others don't, for telling parse
+/// errors that another edition might not have.
+const EDITION_KEYWORDS: [&str; 5] = ["async", "await", "dyn", "try", "gen"];
+
+/// The edition of the Cargo package that `dir` belongs to, as `cargo fmt`
+/// would pass it to rustfmt: the nearest `Cargo.toml`'s `package.edition`,
+/// or its workspace's `workspace.package.edition` if it's inherited.
+///
+/// Results are cached per directory for the life of the process, since this
+/// is looked up for every file that's checked.
+pub fn discover_edition(dir: &Path) -> Option<String> {
+    static EDITIONS: OnceLock<Memo<PathBuf, Option<String>>> = OnceLock::new();
+    EDITIONS
+        .get_or_init(Default::default)
+        .get_or_insert_with(dir, || find_edition(dir))
+}
+
+/// The edition of the Cargo package that the file at `path` belongs to.
+pub fn discover_edition_for_file(path: &Path, symlinks: SymlinkPolicy) -> Option<String> {
+    discover_edition(symlinks.policy_path(path).parent()?)
+}
+
+fn find_edition(dir: &Path) -> Option<String> {
+    let (package_dir, manifest) = dir
+        .ancestors()
+        .find_map(|dir| Some((dir, read_manifest(dir)?)))?;
+    // The closest manifest may be a virtual workspace manifest, which
+    // doesn't make its directory part of any package.
+    let package = manifest.get("package")?.as_table()?;
+    let Some(edition) = package.get("edition") else {
+        return Some(DEFAULT_EDITION.to_owned());
+    };
+    if let Some(edition) = edition.as_str() {
+        return Some(edition.to_owned());
+    }
+    let is_inherited = edition
+        .get("workspace")
+        .and_then(|workspace| workspace.as_bool())
+        == Some(true);
+    if !is_inherited {
+        return None;
+    }
+    let workspace = match package.get("workspace").and_then(|root| root.as_str()) {
+        Some(root) => read_manifest(&package_dir.join(root))?,
+        None => package_dir
+            .ancestors()
+            .filter_map(read_manifest)
+            .find(|manifest| manifest.contains_key("workspace"))?,
+    };
+    Some(
+        workspace
+            .get("workspace")?
+            .get("package")?
+            .get("edition")?
+            .as_str()?
+            .to_owned(),
+    )
+}
+
+/// Runs `run` (which runs rustfmt) with `options`, and if rustfmt fails to
+/// parse the source in a way that [might depend on its
+/// edition](is_edition_related), again with the other
+/// [fallback editions](FALLBACK_EDITIONS) until one works. The edition that
+/// worked is remembered for the [`file_name`](RustfmtOptions::file_name),
+/// and tried first for it from then on. If none works, the first error is
+/// returned.
+pub(crate) fn with_edition_fallback(
+    options: &RustfmtOptions,
+    run: impl Fn(&RustfmtOptions) -> Result<Vec<FileMismatches>, RustfmtError>,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    static WORKING_EDITIONS: OnceLock<Mutex<HashMap<PathBuf, String>>> = OnceLock::new();
+    let working_editions = || {
+        WORKING_EDITIONS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    };
+    let with_edition = |edition: &str| RustfmtOptions {
+        edition: Some(edition.to_owned()),
+        ..options.clone()
+    };
+    let remembered = options
+        .file_name
+        .as_ref()
+        .and_then(|file_name| working_editions().get(file_name).cloned());
+    let tried = remembered
+        .clone()
+        .or_else(|| options.edition.clone())
+        .unwrap_or_else(|| DEFAULT_EDITION.to_owned());
+    let error = match remembered {
+        Some(edition) => run(&with_edition(&edition)),
+        None => run(options),
+    };
+    if !matches!(&error, Err(RustfmtError::NonZeroExit { stderr, .. }) if is_edition_related(stderr))
+    {
+        return error;
+    }
+    for edition in FALLBACK_EDITIONS
+        .into_iter()
+        .filter(|edition| *edition != tried)
+    {
+        let result = run(&with_edition(edition));
+        match &result {
+            Err(RustfmtError::NonZeroExit { .. }) => continue,
+            Ok(_) | Err(RustfmtError::LeftUnformatted { .. }) => {
+                log::info!("rustfmt could only parse the source as edition {edition}");
+                if let Some(file_name) = options.file_name.clone() {
+                    working_editions().insert(file_name, edition.to_owned());
+                }
+            }
+            Err(_) => {}
+        }
+        return result;
+    }
+    error
+}
+
+/// Whether rustfmt's parse errors on `stderr` might not happen in another
+/// edition: they mention editions (eg `` `async fn` is not permitted in Rust
+/// 2015``), or keywords only some editions have.
+fn is_edition_related(stderr: &str) -> bool {
+    stderr.contains("edition")
+        || stderr.contains("Rust 20")
+        || EDITION_KEYWORDS.iter().any(|keyword| {
+            stderr.contains(&format!("found keyword `{keyword}`"))
+                || stderr.contains(&format!("found reserved keyword `{keyword}`"))
+        })
+}
+
+/// The `Cargo.toml` in `dir`, if there's one that parses.
+fn read_manifest(dir: &Path) -> Option<Table> {
+    fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_edition() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let write = |path: &str, contents: &str| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"*\"]\n\n[workspace.package]\nedition = \"2024\"\n",
+        );
+        write(
+            "a/Cargo.toml",
+            "[package]\nname = \"a\"\nedition = \"2021\"\n",
+        );
+        write("b/Cargo.toml", "[package]\nname = \"b\"\n");
+        write(
+            "c/Cargo.toml",
+            "[package]\nname = \"c\"\nedition.workspace = true\n",
+        );
+        fs::create_dir_all(dir.join("a/src/nested")).unwrap();
+
+        assert_eq!(
+            discover_edition(&dir.join("a/src/nested")),
+            Some("2021".to_owned())
+        );
+        assert_eq!(discover_edition(&dir.join("b")), Some("2015".to_owned()));
+        assert_eq!(discover_edition(&dir.join("c")), Some("2024".to_owned()));
+        assert_eq!(discover_edition(dir), None);
+        assert_eq!(
+            discover_edition_for_file(&dir.join("c/src/lib.rs"), SymlinkPolicy::LinkPath),
+            Some("2024".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_with_edition_fallback() {
+        let run = |source: &'static [u8], options: &RustfmtOptions| {
+            let editions = Mutex::new(Vec::new());
+            let result = with_edition_fallback(options, |options| {
+                editions
+                    .lock()
+                    .unwrap()
+                    .push(options.edition.clone().unwrap());
+                crate::run_rustfmt(
+                    source,
+                    &RustfmtOptions {
+                        file_name: None,
+                        ..options.clone()
+                    },
+                )
+            });
+            (result, editions.into_inner().unwrap())
+        };
+        let options = RustfmtOptions {
+            edition: Some("2015".to_owned()),
+            file_name: Some("test_with_edition_fallback.rs".into()),
+            ..Default::default()
+        };
+
+        let (result, editions) = run(b"async fn  a() {}\n", &options);
+        assert_eq!(result.unwrap()[0].mismatches.len(), 1);
+        assert_eq!(editions, ["2015", "2021"]);
+        // The edition that worked is remembered for the file.
+        let (result, editions) = run(b"async fn a() {}\n", &options);
+        assert!(result.is_ok());
+        assert_eq!(editions, ["2021"]);
+
+        // `async` is only a keyword from 2018 on.
+        let (result, editions) = run(
+            b"fn async() {}\n",
+            &RustfmtOptions {
+                edition: Some("2021".to_owned()),
+                file_name: None,
+                ..Default::default()
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(editions, ["2021", "2018", "2015"]);
+
+        // Errors that have nothing to do with the edition aren't retried.
+        let (result, editions) = run(
+            b"fn a( {}\n",
+            &RustfmtOptions {
+                file_name: None,
+                ..options.clone()
+            },
+        );
+        assert!(matches!(result, Err(RustfmtError::NonZeroExit { .. })));
+        assert_eq!(editions, ["2015"]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern};
+
+/// How many lines at the start of a file are searched for an `@generated`
+/// marker (see [`RustfmtRuleOptions::exclude_generated`](crate::RustfmtRuleOptions::exclude_generated)).
+pub const GENERATED_MARKER_LINES: usize = 5;
+
+/// Whether `path` matches any of the glob `patterns`, as described for
+/// [`RustfmtRule::excludes()`](crate::RustfmtRule::excludes).
+pub(crate) fn is_excluded(path: &Path, patterns: &[String]) -> bool {
+    if patterns.is_empty() {
+        return false;
+    }
+    let path = relative_path(path);
+    let file_name = path.file_name().map(Path::new);
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    patterns.iter().any(|pattern| {
+        let compiled = match Pattern::new(pattern) {
+            Ok(compiled) => compiled,
+            Err(error) => {
+                log::warn!("ignoring exclude pattern {pattern:?}: {error}");
+                return false;
+            }
+        };
+        if pattern.contains('/') {
+            compiled.matches_path_with(&path, options)
+        } else {
+            file_name.is_some_and(|file_name| compiled.matches_path_with(file_name, options))
+        }
+    })
+}
+
+/// `path` without `.` components, and relative to the current directory if
+/// it's under it.
+fn relative_path(path: &Path) -> PathBuf {
+    let path = env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(path);
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// Whether one of the first [`GENERATED_MARKER_LINES`] lines of `source` has
+/// `@generated` in it, as code generators (eg prost and cbindgen) write.
+pub(crate) fn has_generated_marker(source: &[u8]) -> bool {
+    source
+        .split(|&byte| byte == b'\n')
+        .take(GENERATED_MARKER_LINES)
+        .any(|line| {
+            line.windows(b"@generated".len())
+                .any(|window| window == b"@generated")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_excluded() {
+        let patterns = ["bindings.rs", "src/proto/**", "src/*_pb.rs"].map(ToOwned::to_owned);
+        for (path, expected) in [
+            ("bindings.rs", true),
+            ("./src/ffi/bindings.rs", true),
+            ("src/proto/a/b.rs", true),
+            ("src/a_pb.rs", true),
+            ("src/a/b_pb.rs", false),
+            ("src/lib.rs", false),
+        ] {
+            assert_eq!(is_excluded(Path::new(path), &patterns), expected, "{path}");
+        }
+        assert!(is_excluded(
+            &env::current_dir().unwrap().join("src/proto/a.rs"),
+            &patterns
+        ));
+        assert!(!is_excluded(Path::new("bindings.rs"), &[]));
+    }
+
+    #[test]
+    fn test_has_generated_marker() {
+        assert!(has_generated_marker(
+            b"// This file is @generated by prost-build.\nfn a() {}\n"
+        ));
+        assert!(has_generated_marker(
+            b"\n\n\n\n/* @generated */\nfn a() {}\n"
+        ));
+        assert!(!has_generated_marker(
+            b"\n\n\n\n\n// @generated\nfn a() {}\n"
+        ));
+        assert!(!has_generated_marker(b"fn a() {}\n"));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    env, fs,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    discover_config, run_rustfmt, stdin_mismatches, Mismatch, RustfmtError, RustfmtOptions,
+};
+
+/// A mismatch rustfmt found, and the config setting responsible for it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Attribution {
+    pub mismatch: Mismatch,
+    /// The setting (eg `max_width = 80`) without which rustfmt wouldn't have
+    /// made the change, or `None` if rustfmt makes it by default.
+    pub setting: Option<String>,
+}
+
+/// Attributes each of the mismatches rustfmt finds in `source` to a setting in
+/// the rustfmt config (the one `options` point to, or that rustfmt would find
+/// from the current directory), eg to work out why formatting in CI differs
+/// from formatting locally.
+///
+/// rustfmt is rerun with configs containing only the first N of the config's
+/// settings, bisecting on N for each mismatch to find the setting that first
+/// produces it. When settings interact, that's the last of them (in the order
+/// they're written) that the change depends on.
+pub fn explain(source: &[u8], options: &RustfmtOptions) -> Result<Vec<Attribution>, RustfmtError> {
+    let mismatches = stdin_mismatches(run_rustfmt(source, options)?).collect::<Vec<_>>();
+    let Some(config_path) = options
+        .config_path
+        .clone()
+        .or_else(|| discover_config(&env::current_dir().ok()?))
+    else {
+        return Ok(mismatches
+            .into_iter()
+            .map(|mismatch| Attribution {
+                mismatch,
+                setting: None,
+            })
+            .collect());
+    };
+    let settings = fs::read_to_string(&config_path)?
+        .parse::<toml::Table>()
+        .map_err(|error| {
+            RustfmtError::OutputParse(format!("{} doesn't parse: {error}", config_path.display()))
+        })?
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let dir = tempfile::tempdir()?;
+    // The mismatches with each number of settings, which are shared between
+    // the bisections.
+    let mut runs = HashMap::from([(settings.len(), mismatches.clone())]);
+    let mut has_mismatch = |setting_count: usize, mismatch: &Mismatch| {
+        let mismatches = match runs.entry(setting_count) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let config_path = dir.path().join(format!("{setting_count}.toml"));
+                let config = settings[..setting_count]
+                    .iter()
+                    .cloned()
+                    .collect::<toml::Table>();
+                fs::write(&config_path, config.to_string())?;
+                let options = RustfmtOptions {
+                    config_path: Some(config_path),
+                    ..options.clone()
+                };
+                entry.insert(stdin_mismatches(run_rustfmt(source, &options)?).collect())
+            }
+        };
+        Ok::<_, RustfmtError>(mismatches.contains(mismatch))
+    };
+    mismatches
+        .into_iter()
+        .map(|mismatch| {
+            if has_mismatch(0, &mismatch)? {
+                return Ok(Attribution {
+                    mismatch,
+                    setting: None,
+                });
+            }
+            // Without the first `without` settings there's no mismatch, with
+            // the first `with` there is.
+            let (mut without, mut with) = (0, settings.len());
+            while with - without > 1 {
+                let middle = (without + with) / 2;
+                if has_mismatch(middle, &mismatch)? {
+                    with = middle;
+                } else {
+                    without = middle;
+                }
+            }
+            let (name, value) = &settings[with - 1];
+            Ok(Attribution {
+                mismatch,
+                setting: Some(format!("{name} = {value}")),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        fs::write(
+            &config_path,
+            "edition = \"2021\"\nmax_width = 40\nnormalize_comments = true\n",
+        )
+        .unwrap();
+        let source = "fn  a() {}\n\n/* b */\nfn c() {}\n\nfn d() { e(f, ggggggggggg, hhhhhhhhhh, iiiiiiiiiii) }\n";
+        let options = RustfmtOptions {
+            config_path: Some(config_path),
+            ..Default::default()
+        };
+        let attributions = explain(source.as_bytes(), &options).unwrap();
+        assert_eq!(
+            attributions
+                .iter()
+                .map(|attribution| (
+                    attribution.mismatch.original_begin_line,
+                    attribution.setting.as_deref()
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (1, None),
+                (3, Some("normalize_comments = true")),
+                (6, Some("max_width = 40"))
+            ]
+        );
+
+        let options = RustfmtOptions {
+            config_path: Some(dir.path().join("nope.toml")),
+            ..Default::default()
+        };
+        assert!(explain(source.as_bytes(), &options).is_err());
+    }
+}
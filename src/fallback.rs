@@ -0,0 +1,87 @@
+use crate::{FileMismatches, Mismatch, STDIN_FILE_NAME};
+
+/// The mismatches rustfmt's `--emit json` would report for `original`, given
+/// what it `formatted` it into, for rustfmts that can only emit the formatted
+/// source (eg stable ones). They're made the way rustfmt makes them: from a
+/// line diff with no context lines.
+pub(crate) fn diff_mismatches(original: &str, formatted: &str) -> Vec<FileMismatches> {
+    let mut mismatches = Vec::<Mismatch>::new();
+    let (mut original_line, mut expected_line) = (1, 1);
+    let mut in_hunk = false;
+    for line in diff::lines(original, formatted) {
+        if matches!(line, diff::Result::Both(..)) {
+            original_line += 1;
+            expected_line += 1;
+            in_hunk = false;
+            continue;
+        }
+        if !in_hunk {
+            mismatches.push(Mismatch {
+                original_begin_line: original_line,
+                original_end_line: original_line,
+                expected_begin_line: expected_line,
+                expected_end_line: expected_line,
+                original: Default::default(),
+                expected: Default::default(),
+            });
+            in_hunk = true;
+        }
+        let mismatch = mismatches.last_mut().unwrap();
+        let (text, end_line, next_line, line) = match line {
+            diff::Result::Left(line) => (
+                &mut mismatch.original,
+                &mut mismatch.original_end_line,
+                &mut original_line,
+                line,
+            ),
+            diff::Result::Right(line) => (
+                &mut mismatch.expected,
+                &mut mismatch.expected_end_line,
+                &mut expected_line,
+                line,
+            ),
+            diff::Result::Both(..) => unreachable!(),
+        };
+        text.push_str(line);
+        text.push('\n');
+        *end_line = *next_line;
+        *next_line += 1;
+    }
+    if mismatches.is_empty() {
+        return Default::default();
+    }
+    vec![FileMismatches {
+        name: STDIN_FILE_NAME.to_owned(),
+        mismatches,
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{run_rustfmt, RustfmtOptions};
+
+    #[test]
+    fn test_run_rustfmt_stable() {
+        let options = RustfmtOptions {
+            toolchain: Some("stable".to_owned()),
+            ..Default::default()
+        };
+        assert!(!options.supports_emit_json());
+        for source in [
+            "fn a() {}\n\n\n\nfn b() {}\n",
+            "fn a() {\n}\n",
+            "fn a() { b(); c(); }\n",
+            "fn a() {}\nfn  b() {}\nfn c() {}\nfn  d() {}\n",
+            "fn a() {}",
+            "fn a() {}\n\n",
+            "fn a() {}\n",
+            "",
+        ] {
+            assert_eq!(
+                run_rustfmt(source.as_bytes(), &options).unwrap(),
+                run_rustfmt(source.as_bytes(), &Default::default()).unwrap(),
+                "{source:?}"
+            );
+        }
+    }
+}
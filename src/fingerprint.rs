@@ -0,0 +1,114 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::SystemTime,
+};
+
+use crate::{memo::Memo, RustfmtError, RustfmtOptions};
+
+/// The config file a fingerprint was computed from, and when it was last
+/// modified, so that editing it changes the fingerprint.
+type ConfigFile = Option<(PathBuf, Option<SystemTime>)>;
+
+/// A hash of everything besides the source that rustfmt's output depends on:
+/// its version, how it's run (the command, toolchain, arguments, which
+/// include the edition, and environment), and the config file it uses. It's part of
+/// [`CleanCache`](crate::CleanCache) keys, so that a change to any of them
+/// invalidates what's cached.
+///
+/// Computed once per run and config file modification, and logged (at info
+/// level) when it is, so that a log shows why everything was formatted again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ConfigFingerprint(blake3::Hash);
+
+impl ConfigFingerprint {
+    /// The fingerprint of running rustfmt with `options` and the config file
+    /// at `config_path`, if any.
+    pub fn new(options: &RustfmtOptions, config_path: Option<&Path>) -> Result<Self, RustfmtError> {
+        static FINGERPRINTS: OnceLock<Memo<(Vec<String>, ConfigFile), ConfigFingerprint>> =
+            OnceLock::new();
+        let mut command = options.environment();
+        command.push(options.program().to_string_lossy().into_owned());
+        command.extend(options.args());
+        let config_file = config_path.map(|config_path| {
+            let modified = fs::metadata(config_path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            (config_path.to_owned(), modified)
+        });
+        FINGERPRINTS
+            .get_or_init(Default::default)
+            .get_or_try_insert_with(&(command.clone(), config_file), || {
+                let version = options.version()?;
+                let config = config_path
+                    .map(|config_path| fs::read(config_path).unwrap_or_default())
+                    .unwrap_or_default();
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(format!("{version}\n{command:?}\n").as_bytes());
+                hasher.update(&config);
+                let fingerprint = Self(hasher.finalize());
+                log::info!(
+                    "rustfmt config fingerprint {fingerprint}: rustfmt {version}, run as {}, with {}",
+                    command.join(" "),
+                    config_path.map_or("no config file".to_owned(), |config_path| {
+                        config_path.display().to_string()
+                    })
+                );
+                Ok(fingerprint)
+            })
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        self.0.as_bytes()
+    }
+}
+
+/// The first 16 hex digits, which are enough to tell fingerprints apart in
+/// logs.
+impl fmt::Display for ConfigFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_hex()[..16])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        fs::write(&config_path, "max_width = 80\n").unwrap();
+        let options = RustfmtOptions::default();
+        let fingerprint = ConfigFingerprint::new(&options, Some(&config_path)).unwrap();
+        assert_eq!(
+            ConfigFingerprint::new(&options, Some(&config_path)).unwrap(),
+            fingerprint
+        );
+        assert_eq!(fingerprint.to_string().len(), 16);
+        assert_ne!(ConfigFingerprint::new(&options, None).unwrap(), fingerprint);
+        let edition_options = RustfmtOptions {
+            edition: Some("2018".to_owned()),
+            ..Default::default()
+        };
+        assert_ne!(
+            ConfigFingerprint::new(&edition_options, Some(&config_path)).unwrap(),
+            fingerprint
+        );
+
+        fs::write(&config_path, "max_width = 90\n").unwrap();
+        let modified = SystemTime::now() + std::time::Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&config_path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_ne!(
+            ConfigFingerprint::new(&options, Some(&config_path)).unwrap(),
+            fingerprint
+        );
+    }
+}
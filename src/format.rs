@@ -0,0 +1,275 @@
+use std::{collections::HashMap, fs, io::Write, ops::Range, path::Path, process::Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    anchor::{anchor_mismatch, AnchoredMismatch},
+    discover_config,
+    rustfmt::{check_unstable_settings, communicate, wait_with_timeout},
+    stdin_mismatches, LineOffsets, Mismatch, RopeOrSlice, RustfmtError, RustfmtOptions,
+};
+
+/// How many files [`format_files_batch()`] hands a single rustfmt, which
+/// keeps its command line well short of the OS's limit.
+const BATCH_SIZE: usize = 256;
+
+/// A mismatch along with the bytes of the formatted source that it replaces.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MappedMismatch {
+    pub mismatch: Mismatch,
+    pub range: Range<usize>,
+}
+
+/// Runs rustfmt over `source` and maps each resulting mismatch to a byte
+/// range, without involving the lint rule.
+pub fn format_source<'a>(
+    source: impl Into<RopeOrSlice<'a>>,
+    options: &RustfmtOptions,
+) -> Result<Vec<MappedMismatch>, RustfmtError> {
+    let source = source.into();
+    let files = options.limited(|| communicate(options.spawn()?, source, options))?;
+    map_mismatches(source, stdin_mismatches(files))
+}
+
+/// Like [`format_source()`] for many sources at once, with one rustfmt per
+/// 256 of them rather than one each, since for a large workspace
+/// spawning rustfmt costs more than formatting. Returns each source's
+/// mismatches (or error) in order.
+///
+/// The sources are formatted as files in a temporary directory rather than
+/// as stdin, so rustfmt's timeout applies to a whole batch, scaled by its
+/// size. A file that fails (eg because it doesn't parse) fails the batch's
+/// rustfmt, in which case the files missing from its output are formatted
+/// one at a time to find out why. So are all of them if rustfmt can only
+/// emit the formatted source (see [`RustfmtOptions::supports_emit_json()`]).
+pub fn format_files_batch<'a>(
+    sources: impl IntoIterator<Item = impl Into<RopeOrSlice<'a>>>,
+    options: &RustfmtOptions,
+) -> Vec<Result<Vec<MappedMismatch>, RustfmtError>> {
+    if !options.supports_emit_json() {
+        return sources
+            .into_iter()
+            .map(|source| format_source(source, options))
+            .collect();
+    }
+    let sources = sources.into_iter().map(Into::into).collect::<Vec<_>>();
+    // rustfmt would otherwise look for a config next to the temporary files
+    // rather than from the directory it runs in, as it does for stdin.
+    let mut options = options.clone();
+    if options.config_path.is_none() {
+        options.config_path = options.working_dir().as_deref().and_then(discover_config);
+    }
+    let mut results = Vec::with_capacity(sources.len());
+    for batch in sources.chunks(BATCH_SIZE) {
+        let (mut formatted, is_complete) = run_batch(batch, &options).unwrap_or_else(|error| {
+            log::debug!("couldn't format a batch of files: {error}");
+            Default::default()
+        });
+        results.extend(batch.iter().enumerate().map(|(index, &source)| {
+            match formatted.remove(&index) {
+                Some(mismatches) => map_mismatches(source, mismatches),
+                // rustfmt leaves files that are already formatted out.
+                None if is_complete => Ok(Default::default()),
+                None => format_source(source, &options),
+            }
+        }));
+    }
+    results
+}
+
+/// The mismatches of each of `batch` that rustfmt got as far as formatting,
+/// by index, and whether it formatted all of them.
+fn run_batch(
+    batch: &[RopeOrSlice],
+    options: &RustfmtOptions,
+) -> Result<(HashMap<usize, Vec<Mismatch>>, bool), RustfmtError> {
+    let dir = tempfile::tempdir()?;
+    let mut args = Vec::new();
+    for (index, source) in batch.iter().enumerate() {
+        let path = dir.path().join(format!("{index}.rs"));
+        let mut file = fs::File::create(&path)?;
+        for chunk in source.chunks() {
+            file.write_all(chunk)?;
+        }
+        args.push(path.to_string_lossy().into_owned());
+    }
+    let output = options.limited(|| {
+        let child = options
+            .command(&args)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(RustfmtError::SpawnFailed)?;
+        match options.timeout {
+            Some(timeout) => wait_with_timeout(child, timeout * batch.len() as u32),
+            None => child.wait_with_output().map_err(RustfmtError::from),
+        }
+    })?;
+    check_unstable_settings(options, &output)?;
+    let mut formatted = HashMap::new();
+    for file in options
+        .mismatch_format()?
+        .parse(&String::from_utf8(output.stdout)?)?
+    {
+        let index = Path::new(&file.name)
+            .file_stem()
+            .and_then(|stem| stem.to_str()?.parse::<usize>().ok())
+            .filter(|&index| index < batch.len())
+            .ok_or_else(|| {
+                RustfmtError::OutputParse(format!("mismatches for unknown file {}", file.name))
+            })?;
+        formatted.insert(index, file.mismatches);
+    }
+    Ok((formatted, output.status.success()))
+}
+
+fn map_mismatches(
+    source: RopeOrSlice,
+    mismatches: impl IntoIterator<Item = Mismatch>,
+) -> Result<Vec<MappedMismatch>, RustfmtError> {
+    let line_offsets = LineOffsets::from_chunks(source.chunks());
+    mismatches
+        .into_iter()
+        .map(|mismatch| {
+            let range = line_offsets.mismatch_byte_range(&mismatch).ok_or_else(|| {
+                RustfmtError::OutputParse(format!(
+                    "mismatch at lines {}-{} but the source only has {} lines",
+                    mismatch.original_begin_line,
+                    mismatch.original_end_line,
+                    line_offsets.line_count()
+                ))
+            })?;
+            Ok(MappedMismatch { mismatch, range })
+        })
+        .collect()
+}
+
+/// `source` as rustfmt would format it, with every mismatch applied.
+pub fn format_to_string<'a>(
+    source: impl Into<RopeOrSlice<'a>>,
+    options: &RustfmtOptions,
+) -> Result<String, RustfmtError> {
+    let source = source.into();
+    let mismatches = format_source(source, options)?;
+    apply_mismatches(
+        &source.chunks().flatten().copied().collect::<Vec<_>>(),
+        mismatches
+            .iter()
+            .map(|MappedMismatch { mismatch, .. }| mismatch),
+    )
+}
+
+/// `source` with every one of `mismatches` (of a run over all of it)
+/// applied.
+pub(crate) fn apply_mismatches<'a>(
+    source: &[u8],
+    mismatches: impl IntoIterator<Item = &'a Mismatch>,
+) -> Result<String, RustfmtError> {
+    let mut formatted = source.to_vec();
+    let line_offsets = LineOffsets::new(source);
+    let mut anchored = mismatches
+        .into_iter()
+        .map(|mismatch| {
+            anchor_mismatch(source, &line_offsets, mismatch).ok_or_else(|| {
+                RustfmtError::OutputParse(format!(
+                    "mismatch at lines {}-{} doesn't match the source",
+                    mismatch.original_begin_line, mismatch.original_end_line
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    anchored.sort_by_key(|anchored| anchored.range.start);
+    for AnchoredMismatch {
+        range, expected, ..
+    } in anchored.into_iter().rev()
+    {
+        formatted.splice(range, expected.bytes());
+    }
+    Ok(String::from_utf8(formatted)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::*;
+
+    #[test]
+    fn test_format_source() {
+        let source = "fn a() {}\nfn  b( ) {}\n";
+        let expected = vec![MappedMismatch {
+            mismatch: Mismatch {
+                original_begin_line: 2,
+                original_end_line: 2,
+                expected_begin_line: 2,
+                expected_end_line: 2,
+                original: "fn  b( ) {}\n".to_owned(),
+                expected: "fn b() {}\n".to_owned(),
+            },
+            range: 10..22,
+        }];
+
+        assert_eq!(
+            format_source(source.as_bytes(), &Default::default()).unwrap(),
+            expected
+        );
+
+        let mut rope = Rope::from_str("fn a() {}\n");
+        rope.append(Rope::from_str("fn  b( ) {}\n"));
+        assert_eq!(format_source(&rope, &Default::default()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_to_string() {
+        assert_eq!(
+            format_to_string(
+                &b"fn  a( ) {}\nfn b() {}\n\n\nfn  c() {}"[..],
+                &Default::default()
+            )
+            .unwrap(),
+            "fn a() {}\nfn b() {}\n\nfn c() {}\n"
+        );
+        assert_eq!(
+            format_to_string(&b""[..], &Default::default()).unwrap(),
+            "\n"
+        );
+        assert_eq!(
+            format_to_string(&b"  "[..], &Default::default()).unwrap(),
+            "\n"
+        );
+    }
+
+    #[test]
+    fn test_format_files_batch() {
+        let sources: [&[u8]; 4] = [
+            b"fn  a() {}\nmod b;\n",
+            b"fn c() {}\n",
+            b"fn d( {}\n",
+            b"fn e( ) {}\n",
+        ];
+        let results = format_files_batch(sources, &Default::default());
+        assert_eq!(results.len(), 4);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &format_source(sources[0], &Default::default()).unwrap()
+        );
+        assert_eq!(results[1].as_ref().unwrap(), &[]);
+        assert!(matches!(results[2], Err(RustfmtError::NonZeroExit { .. })));
+        assert_eq!(
+            results[3].as_ref().unwrap()[0].mismatch.expected,
+            "fn e() {}\n"
+        );
+
+        let results = format_files_batch([sources[1], sources[3]], &Default::default());
+        assert_eq!(results[0].as_ref().unwrap(), &[]);
+        assert_eq!(results[1].as_ref().unwrap()[0].range, 0..11);
+    }
+
+    #[test]
+    fn test_format_source_error() {
+        assert!(matches!(
+            format_source(&b"fn a( {}\n"[..], &Default::default()),
+            Err(RustfmtError::NonZeroExit { .. })
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    ffi::OsString,
+    ops::Range,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    debug_dump::record_spawn,
+    run_rustfmt, run_rustfmt_on_lines,
+    rustfmt::{parse_stdout, stdout, write_and_wait},
+    FileMismatches, RustfmtError, RustfmtOptions,
+};
+
+/// What a [`Formatter`] can do besides formatting whole files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormatterCapabilities {
+    /// Whether [`Formatter::format_ranges()`] only formats the lines it's
+    /// given. Otherwise the rule formats (and checks) whole files.
+    pub format_ranges: bool,
+}
+
+/// A formatter for [`RustfmtRuleOptions::formatter`](crate::RustfmtRuleOptions::formatter)
+/// to check against instead of rustfmt, eg an experimental Rust one, or one
+/// for another language's [`FormatterRule`](crate::FormatterRule). It
+/// reports what it would change in rustfmt's `--emit json` terms, as the
+/// mismatches for [`STDIN_FILE_NAME`](crate::STDIN_FILE_NAME).
+pub trait Formatter: Send + Sync {
+    /// The formatter's name, in messages and cache keys.
+    fn name(&self) -> &str;
+
+    fn capabilities(&self, options: &RustfmtOptions) -> FormatterCapabilities;
+
+    /// What formatting `source` would change. `options` are the rule's
+    /// rustfmt options, which other formatters may just use the
+    /// [`timeout`](RustfmtOptions::timeout) of.
+    fn format_full(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+    ) -> Result<Vec<FileMismatches>, RustfmtError>;
+
+    /// What formatting just `lines` (0-based, half-open) of `source` would
+    /// change, if the formatter's [capable](FormatterCapabilities::format_ranges)
+    /// of it.
+    fn format_ranges(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        lines: &[Range<usize>],
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        let _ = lines;
+        self.format_full(source, options)
+    }
+}
+
+/// rustfmt, which the rule uses unless it's given another [`Formatter`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RustfmtFormatter;
+
+impl Formatter for RustfmtFormatter {
+    fn name(&self) -> &str {
+        "rustfmt"
+    }
+
+    fn capabilities(&self, options: &RustfmtOptions) -> FormatterCapabilities {
+        FormatterCapabilities {
+            format_ranges: options.supports_file_lines(),
+        }
+    }
+
+    fn format_full(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        run_rustfmt(source, options)
+    }
+
+    fn format_ranges(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        lines: &[Range<usize>],
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        run_rustfmt_on_lines(source, options, lines)
+    }
+}
+
+/// A formatter that's a command reading the source from stdin and writing
+/// its formatting of it to stdout (eg `genemichaels --stdin`), whose
+/// mismatches are worked out from a diff, like for rustfmts that can't
+/// `--emit json`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandFormatter {
+    pub name: String,
+    pub program: PathBuf,
+    pub args: Vec<OsString>,
+}
+
+impl CommandFormatter {
+    /// Runs `program` (looked up on `PATH`) with `args`, naming it after the
+    /// program.
+    pub fn new(
+        program: impl Into<PathBuf>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+    ) -> Self {
+        let program = program.into();
+        Self {
+            name: program.to_string_lossy().into_owned(),
+            program,
+            args: args.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Formatter for CommandFormatter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self, _options: &RustfmtOptions) -> FormatterCapabilities {
+        Default::default()
+    }
+
+    fn format_full(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        let output = options.limited(|| {
+            let mut command = Command::new(&self.program);
+            let child = command
+                .args(&self.args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(RustfmtError::SpawnFailed)?;
+            record_spawn(options, &command, child.id());
+            write_and_wait(child, source.into(), options)
+        })?;
+        let stdout = stdout(output)?;
+        parse_stdout(&stdout, source.into(), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rustfmt_formatter() {
+        let options = RustfmtOptions::default();
+        let source = b"fn  a() {}\nfn  b() {}\n";
+        assert_eq!(RustfmtFormatter.name(), "rustfmt");
+        assert!(RustfmtFormatter.capabilities(&options).format_ranges);
+        assert_eq!(
+            RustfmtFormatter
+                .format_ranges(source, &options, &[1..2])
+                .unwrap(),
+            run_rustfmt_on_lines(source, &options, &[1..2]).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_formatter() {
+        let formatter = CommandFormatter::new("sed", ["s/  */ /g"]);
+        assert_eq!(formatter.name(), "sed");
+        assert!(!formatter.capabilities(&Default::default()).format_ranges);
+        let source = b"fn  a() {}\nfn b() {}\n";
+        let files = formatter
+            .format_ranges(source, &Default::default(), &[1..2])
+            .unwrap();
+        assert_eq!(files, run_rustfmt(source, &Default::default()).unwrap());
+
+        assert!(matches!(
+            CommandFormatter::new("no-such-formatter", [""; 0])
+                .format_full(source, &Default::default()),
+            Err(RustfmtError::SpawnFailed(_))
+        ));
+    }
+}
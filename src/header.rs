@@ -0,0 +1,233 @@
+use std::{borrow::Cow, fs, ops::Range, path::PathBuf};
+
+use tree_sitter::Tree;
+
+use crate::{Fix, Violation};
+
+pub const FILE_HEADER_RULE_NAME: &str = "file-header";
+
+pub const MISSING_FILE_HEADER: &str = "missing_file_header";
+pub const MALFORMED_FILE_HEADER: &str = "malformed_file_header";
+
+/// The header comment every file must start with, eg a license notice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeaderTemplate {
+    Text(String),
+    /// Read on every check, so that it can be changed during a session.
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileHeaderRuleOptions {
+    pub template: HeaderTemplate,
+}
+
+/// Requires files to start (after any shebang line) with a header comment.
+///
+/// A leading comment that only differs from the header in whitespace gets
+/// normalized, otherwise the header is inserted above whatever the file
+/// starts with. Hosts should run this before [`RustfmtRule`](crate::RustfmtRule)
+/// so that rustfmt sees (and formats around) the fixed header.
+#[derive(Clone, Debug)]
+pub struct FileHeaderRule {
+    pub options: FileHeaderRuleOptions,
+}
+
+impl FileHeaderRule {
+    pub fn new(options: FileHeaderRuleOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn check<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        let Some(header) = self.header() else {
+            return Default::default();
+        };
+        let root = tree.root_node();
+        let start = header_start(source);
+        let existing = leading_comments_range(source, tree, start);
+        let existing_text = String::from_utf8_lossy(&source[existing.clone()]);
+        if existing_text == header {
+            return Default::default();
+        }
+        let (message_id, message, fix) = if normalized(&existing_text) == normalized(&header) {
+            (
+                MALFORMED_FILE_HEADER,
+                "The file header isn't formatted like the template.",
+                Fix {
+                    range: existing,
+                    replacement: header.into_owned(),
+                },
+            )
+        } else {
+            let is_followed_by_blank_line = source[start..].starts_with(b"\n")
+                || source[start..].starts_with(b"\r\n")
+                || start == source.len();
+            let mut replacement = header.into_owned();
+            if !is_followed_by_blank_line {
+                replacement.push('\n');
+            }
+            (
+                MISSING_FILE_HEADER,
+                "The file doesn't start with the required header.",
+                Fix {
+                    range: start..start,
+                    replacement,
+                },
+            )
+        };
+        vec![Violation {
+            message_id,
+            message: message.to_owned(),
+            node: root,
+            range: fix.range.clone(),
+            fix: Some(fix),
+        }]
+    }
+
+    /// The template, ending in a newline.
+    fn header(&self) -> Option<Cow<'_, str>> {
+        let header = match &self.options.template {
+            HeaderTemplate::Text(text) => Cow::Borrowed(&**text),
+            HeaderTemplate::File(path) => match fs::read_to_string(path) {
+                Ok(text) => Cow::Owned(text),
+                Err(error) => {
+                    log::warn!("couldn't read {}: {error}", path.display());
+                    return None;
+                }
+            },
+        };
+        if header.is_empty() || header.ends_with('\n') {
+            return Some(header);
+        }
+        Some(Cow::Owned(format!("{header}\n")))
+    }
+}
+
+/// Where the header goes: after a shebang line (but not an inner attribute,
+/// which also starts with `#!`).
+fn header_start(source: &[u8]) -> usize {
+    if !source.starts_with(b"#!") || source.starts_with(b"#![") {
+        return 0;
+    }
+    source
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(source.len(), |newline| newline + 1)
+}
+
+/// The comments at `start`, up to the end of the line the last one ends on.
+/// Doc comments are the module's documentation rather than a header.
+fn leading_comments_range(source: &[u8], tree: &Tree, start: usize) -> Range<usize> {
+    let mut end = start;
+    let mut cursor = tree.walk();
+    for node in tree.root_node().children(&mut cursor) {
+        if node.end_byte() <= start {
+            continue;
+        }
+        let is_header_comment = matches!(node.kind(), "line_comment" | "block_comment")
+            && !source[node.byte_range()].starts_with(b"//!")
+            && !source[node.byte_range()].starts_with(b"/*!");
+        let is_adjacent = source[end..node.start_byte()]
+            .iter()
+            .all(|&byte| byte == b' ' || byte == b'\t' || byte == b'\r');
+        if !is_header_comment || !is_adjacent {
+            break;
+        }
+        end = source[node.end_byte()..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(source.len(), |newline| node.end_byte() + newline + 1);
+    }
+    start..end
+}
+
+/// `text` with trailing whitespace and blank lines dropped and runs of spaces
+/// collapsed, for comparing comments that are "the same" header.
+fn normalized(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    const HEADER: &str = "// Copyright 2026 The Authors\n// SPDX-License-Identifier: MIT\n";
+
+    fn check(source: &str) -> Option<(&'static str, Vec<u8>)> {
+        let rule = FileHeaderRule::new(FileHeaderRuleOptions {
+            template: HeaderTemplate::Text(HEADER.trim_end().to_owned()),
+        });
+        let tree = parse(source);
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert!(violations.len() <= 1);
+        let violation = violations.into_iter().next()?;
+        Some((
+            violation.message_id,
+            violation.fix.unwrap().apply(source.as_bytes()),
+        ))
+    }
+
+    #[test]
+    fn test_file_header() {
+        assert_eq!(check(&format!("{HEADER}\nfn a() {{}}\n")), None);
+        assert_eq!(
+            check("fn a() {}\n"),
+            Some((
+                MISSING_FILE_HEADER,
+                format!("{HEADER}\nfn a() {{}}\n").into_bytes()
+            ))
+        );
+        assert_eq!(
+            check("\nfn a() {}\n"),
+            Some((
+                MISSING_FILE_HEADER,
+                format!("{HEADER}\nfn a() {{}}\n").into_bytes()
+            ))
+        );
+        // Module docs aren't a header.
+        assert_eq!(
+            check("//! Docs.\n"),
+            Some((
+                MISSING_FILE_HEADER,
+                format!("{HEADER}\n//! Docs.\n").into_bytes()
+            ))
+        );
+        assert_eq!(
+            check("// Some other comment.\nfn a() {}\n"),
+            Some((
+                MISSING_FILE_HEADER,
+                format!("{HEADER}\n// Some other comment.\nfn a() {{}}\n").into_bytes()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_file_header_normalized() {
+        assert_eq!(
+            check(
+                "//  Copyright 2026 The Authors  \n// SPDX-License-Identifier: MIT\n\nfn a() {}\n"
+            ),
+            Some((
+                MALFORMED_FILE_HEADER,
+                format!("{HEADER}\nfn a() {{}}\n").into_bytes()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_file_header_after_shebang() {
+        let source = format!("#!/usr/bin/env run-cargo-script\n{HEADER}\nfn main() {{}}\n");
+        assert_eq!(check(&source), None);
+        assert_eq!(
+            check("#![allow(dead_code)]\n"),
+            Some((
+                MISSING_FILE_HEADER,
+                format!("{HEADER}\n#![allow(dead_code)]\n").into_bytes()
+            ))
+        );
+    }
+}
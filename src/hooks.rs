@@ -0,0 +1,59 @@
+use crate::{FileMismatches, RustfmtError};
+
+/// Adapts the source the rule hands rustfmt (see
+/// [`RustfmtRuleOptions::format_hooks`](crate::RustfmtRuleOptions::format_hooks)),
+/// eg to hide custom attributes or `cfg_attr` tricks a nightly rustfmt
+/// chokes on behind syntax it formats the same way, and maps what it
+/// reports back.
+pub trait FormatHooks: Send + Sync {
+    /// What to hand rustfmt instead of `source`. It has to have as many lines
+    /// as `source` (changing things within lines), so that rustfmt's line
+    /// numbers still apply to `source`; if it doesn't, rustfmt is given
+    /// `source` as it is.
+    fn pre_format(&self, source: &[u8]) -> Vec<u8>;
+
+    /// The lines rustfmt reports a mismatch as having, before and after
+    /// formatting, with what [`pre_format()`](Self::pre_format) changed
+    /// undone.
+    fn post_format(&self, text: &str) -> String;
+}
+
+/// Runs `run` (which runs rustfmt) on `source` as `hooks` adapt it, with
+/// the mismatches it reports mapped back.
+pub(crate) fn with_hooks(
+    hooks: Option<&dyn FormatHooks>,
+    source: &[u8],
+    run: impl FnOnce(&[u8]) -> Result<Vec<FileMismatches>, RustfmtError>,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    let Some(hooks) = hooks else {
+        return run(source);
+    };
+    let adapted = hooks.pre_format(source);
+    let line_count = |source: &[u8]| source.iter().filter(|&&byte| byte == b'\n').count();
+    if line_count(&adapted) != line_count(source) {
+        log::warn!("format hooks changed how many lines the source has, formatting it as it is");
+        return run(source);
+    }
+    let map_back = |files: Vec<FileMismatches>| {
+        files
+            .into_iter()
+            .map(|mut file| {
+                for mismatch in &mut file.mismatches {
+                    mismatch.original = hooks.post_format(&mismatch.original);
+                    mismatch.expected = hooks.post_format(&mismatch.expected);
+                }
+                file
+            })
+            .collect()
+    };
+    match run(&adapted) {
+        Ok(files) => Ok(map_back(files)),
+        Err(RustfmtError::LeftUnformatted { files, diagnostics }) => {
+            Err(RustfmtError::LeftUnformatted {
+                files: map_back(files),
+                diagnostics,
+            })
+        }
+        Err(error) => Err(error),
+    }
+}
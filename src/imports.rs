@@ -0,0 +1,537 @@
+use std::cmp::Ordering;
+
+use tree_sitter::{Node, Tree};
+
+use crate::{Fix, LineOffsets, Violation};
+
+pub const ORGANIZE_IMPORTS_RULE_NAME: &str = "organize-imports";
+
+pub const UNORGANIZED_IMPORTS: &str = "unorganized_imports";
+
+/// How imports are split into blank-line-separated groups, as in rustfmt's
+/// `group_imports`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupImports {
+    /// Keep the existing groups, sorting each one.
+    #[default]
+    Preserve,
+    /// `std`, `core` and `alloc` imports, then other crates', then `self`,
+    /// `super` and `crate` ones.
+    StdExternalCrate,
+    /// One group.
+    One,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrganizeImportsRuleOptions {
+    pub group_imports: GroupImports,
+    /// Merge the imports of a group that only differ in their last segment,
+    /// eg `use a::b;` and `use a::{c, d};` into `use a::{b, c, d};` (as
+    /// rustfmt's `imports_granularity = "Module"`).
+    pub merge: bool,
+}
+
+/// Sorts (and optionally groups and merges) the `use` declarations of files
+/// and inline modules straight from the tree, without running rustfmt, so it
+/// works on stable toolchains too.
+///
+/// Imports are ordered the way rustfmt orders them up to style edition 2021,
+/// so that the two rules agree. Only runs of declarations with nothing but
+/// whitespace between them are reordered: one with attributes or a comment
+/// on its line stays where it is, and splits the run. Lists in braces are
+/// only sorted when merging, and merged imports are written on one line, for
+/// rustfmt to wrap.
+#[derive(Clone, Debug, Default)]
+pub struct OrganizeImportsRule {
+    pub options: OrganizeImportsRuleOptions,
+}
+
+/// A `use` declaration in a run.
+struct Import {
+    text: String,
+    visibility: String,
+    segments: Vec<String>,
+}
+
+impl OrganizeImportsRule {
+    pub fn new(options: OrganizeImportsRuleOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn check<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        let line_ending = if LineOffsets::new(source).has_crlf() {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let mut violations = Vec::new();
+        let mut containers = vec![tree.root_node()];
+        while let Some(container) = containers.pop() {
+            let mut cursor = container.walk();
+            let children = container.named_children(&mut cursor).collect::<Vec<_>>();
+            containers.extend(
+                children
+                    .iter()
+                    .filter(|child| child.kind() == "mod_item")
+                    .filter_map(|child| child.child_by_field_name("body")),
+            );
+            for run in runs(source, &children) {
+                violations.extend(self.check_run(source, &run, line_ending));
+            }
+        }
+        violations.sort_by_key(|violation| violation.range.start);
+        violations
+    }
+
+    fn check_run<'tree>(
+        &self,
+        source: &[u8],
+        run: &[Node<'tree>],
+        line_ending: &str,
+    ) -> Option<Violation<'tree>> {
+        let mut existing = vec![Vec::new()];
+        for (index, &node) in run.iter().enumerate() {
+            let is_after_blank_line = index > 0
+                && source[run[index - 1].end_byte()..node.start_byte()]
+                    .iter()
+                    .filter(|&&byte| byte == b'\n')
+                    .count()
+                    > 1;
+            if is_after_blank_line {
+                existing.push(Vec::new());
+            }
+            existing.last_mut().unwrap().push(Import::new(source, node));
+        }
+        let existing_texts = existing
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|import| normalized(&import.text))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let groups = match self.options.group_imports {
+            GroupImports::Preserve => existing,
+            GroupImports::One => vec![existing.into_iter().flatten().collect()],
+            GroupImports::StdExternalCrate => {
+                let mut groups = [Vec::new(), Vec::new(), Vec::new()];
+                for import in existing.into_iter().flatten() {
+                    groups[import.std_external_crate_group()].push(import);
+                }
+                groups
+                    .into_iter()
+                    .filter(|group| !group.is_empty())
+                    .collect()
+            }
+        };
+        let organized = groups
+            .into_iter()
+            .map(|group| {
+                let mut group = if self.options.merge {
+                    merged(group)
+                } else {
+                    group
+                        .into_iter()
+                        .map(|import| (import.segments, import.text))
+                        .collect()
+                };
+                group.sort_by(|(a, _), (b, _)| compare_paths(a, b));
+                group.into_iter().map(|(_, text)| text).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let organized_texts = organized
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|text| normalized(text))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        if organized_texts == existing_texts {
+            return None;
+        }
+
+        let first = run[0];
+        let last = run[run.len() - 1];
+        let line_start = source[..first.start_byte()]
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let indent = String::from_utf8_lossy(&source[line_start..first.start_byte()]);
+        let separator = format!("{line_ending}{indent}");
+        let replacement = organized
+            .iter()
+            .map(|group| group.join(&separator))
+            .collect::<Vec<_>>()
+            .join(&format!("{line_ending}{separator}"));
+        let range = first.start_byte()..last.end_byte();
+        Some(Violation {
+            message_id: UNORGANIZED_IMPORTS,
+            message: "Imports aren't sorted and grouped.".to_owned(),
+            node: first,
+            range: range.clone(),
+            fix: Some(Fix { range, replacement }),
+        })
+    }
+}
+
+impl Import {
+    /// Which of the `StdExternalCrate` groups the import belongs in.
+    fn std_external_crate_group(&self) -> usize {
+        match self.segments.first().map(String::as_str) {
+            Some("std" | "core" | "alloc") => 0,
+            Some("self" | "super" | "crate") => 2,
+            _ => 1,
+        }
+    }
+
+    fn new(source: &[u8], node: Node) -> Self {
+        let text_of = |node: Node| String::from_utf8_lossy(&source[node.byte_range()]).into_owned();
+        let mut cursor = node.walk();
+        let visibility = node
+            .named_children(&mut cursor)
+            .find(|child| child.kind() == "visibility_modifier")
+            .map(text_of)
+            .unwrap_or_default();
+        let segments = node
+            .child_by_field_name("argument")
+            .map(|argument| segments(&text_of(argument)))
+            .unwrap_or_default();
+        Self {
+            text: text_of(node),
+            visibility,
+            segments,
+        }
+    }
+
+    /// The path up to the last segment, and the names imported from it, if
+    /// this can be merged with imports from the same path.
+    fn mergeable(&self) -> Option<(String, Vec<String>)> {
+        let (last, prefix) = self.segments.split_last()?;
+        if prefix.is_empty() {
+            return None;
+        }
+        let names = match last.strip_prefix('{') {
+            Some(list) => split_top_level(list.strip_suffix('}')?, ",")
+                .into_iter()
+                .map(|name| collapse_whitespace(name.trim()))
+                .filter(|name| !name.is_empty())
+                .collect(),
+            None => vec![last.clone()],
+        };
+        if names
+            .iter()
+            .any(|name| name.contains("::") || name.contains('{'))
+        {
+            return None;
+        }
+        Some((prefix.join("::"), names))
+    }
+}
+
+/// The runs of `use` declarations among `children` that can be reordered.
+/// How the file's first run of top-level imports is grouped, judged by the
+/// [`GroupImports`] that would leave it as it is (`Preserve` if none other
+/// would), or `None` if they're all in the same `StdExternalCrate` group.
+pub(crate) fn detect_group_imports(source: &[u8], tree: &Tree) -> Option<GroupImports> {
+    let root = tree.root_node();
+    let children = root.named_children(&mut root.walk()).collect::<Vec<_>>();
+    let run = runs(source, &children).into_iter().next()?;
+    let mut groups = vec![Vec::new()];
+    for (index, &node) in run.iter().enumerate() {
+        let is_after_blank_line = index > 0
+            && source[run[index - 1].end_byte()..node.start_byte()]
+                .iter()
+                .filter(|&&byte| byte == b'\n')
+                .count()
+                > 1;
+        if is_after_blank_line {
+            groups.push(Vec::new());
+        }
+        groups
+            .last_mut()
+            .unwrap()
+            .push(Import::new(source, node).std_external_crate_group());
+    }
+    let first = groups[0][0];
+    if groups.iter().flatten().all(|&group| group == first) {
+        return None;
+    }
+    if groups.len() == 1 {
+        return Some(GroupImports::One);
+    }
+    let is_std_external_crate = groups
+        .iter()
+        .all(|group| group.iter().all(|&import| import == group[0]))
+        && groups.windows(2).all(|pair| pair[0][0] < pair[1][0]);
+    Some(if is_std_external_crate {
+        GroupImports::StdExternalCrate
+    } else {
+        GroupImports::Preserve
+    })
+}
+
+fn runs<'tree>(source: &[u8], children: &[Node<'tree>]) -> Vec<Vec<Node<'tree>>> {
+    let mut runs = vec![Vec::new()];
+    for (index, &child) in children.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|index| children[index]);
+        let next = children.get(index + 1);
+        let is_movable = child.kind() == "use_declaration"
+            && previous.is_none_or(|previous| {
+                previous.kind() != "attribute_item"
+                    && previous.end_position().row != child.start_position().row
+            })
+            && next.is_none_or(|next| next.start_position().row != child.end_position().row);
+        let current = runs.last_mut().unwrap();
+        let is_adjacent = current.last().is_none_or(|last: &Node| {
+            source[last.end_byte()..child.start_byte()]
+                .iter()
+                .all(u8::is_ascii_whitespace)
+        });
+        if !is_movable || !is_adjacent {
+            runs.push(Vec::new());
+        }
+        if is_movable {
+            runs.last_mut().unwrap().push(child);
+        }
+    }
+    runs.retain(|run| !run.is_empty());
+    runs
+}
+
+/// The segments and text of `group`'s imports, with the ones from the same
+/// path and with the same visibility merged.
+fn merged(group: Vec<Import>) -> Vec<(Vec<String>, String)> {
+    let mut merged = Vec::<(Option<(String, String, Vec<String>)>, String)>::new();
+    for import in group {
+        let Some((prefix, names)) = import.mergeable() else {
+            merged.push((None, import.text));
+            continue;
+        };
+        let existing = merged.iter_mut().find_map(|(key, _)| {
+            key.as_mut()
+                .filter(|(visibility, existing_prefix, _)| {
+                    *visibility == import.visibility && *existing_prefix == prefix
+                })
+                .map(|(_, _, names)| names)
+        });
+        match existing {
+            Some(existing) => existing.extend(names),
+            None => merged.push((Some((import.visibility, prefix, names)), import.text)),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(key, text)| {
+            let Some((visibility, prefix, mut names)) = key else {
+                return text;
+            };
+            names.sort_by(|a, b| compare_segments(a, b));
+            names.dedup();
+            let visibility = if visibility.is_empty() {
+                visibility
+            } else {
+                format!("{visibility} ")
+            };
+            match names.as_slice() {
+                [name] if !text.contains('{') => format!("{visibility}use {prefix}::{name};"),
+                _ => format!("{visibility}use {prefix}::{{{}}};", names.join(", ")),
+            }
+        })
+        .map(|text| {
+            let segments = text
+                .split_once("use ")
+                .and_then(|(_, argument)| argument.strip_suffix(';'))
+                .map(segments)
+                .unwrap_or_default();
+            (segments, text)
+        })
+        .collect()
+}
+
+/// The `::`-separated segments of a use tree, eg `a`, `b` and `{c, d}` for
+/// `a::b::{c, d}`.
+fn segments(argument: &str) -> Vec<String> {
+    split_top_level(argument, "::")
+        .into_iter()
+        .map(|segment| collapse_whitespace(segment.trim()))
+        .collect()
+}
+
+/// `text` split on `separator`s that aren't inside braces.
+fn split_top_level<'text>(text: &'text str, separator: &str) -> Vec<&'text str> {
+    let mut parts = Vec::new();
+    let mut depth = 0_usize;
+    let mut part_start = 0;
+    let mut index = 0;
+    while index < text.len() {
+        let rest = &text[index..];
+        if depth == 0 && rest.starts_with(separator) {
+            parts.push(&text[part_start..index]);
+            index += separator.len();
+            part_start = index;
+            continue;
+        }
+        match rest.as_bytes()[0] {
+            b'{' => depth += 1,
+            b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        index += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    parts.push(&text[part_start..]);
+    parts
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `text` without whitespace or trailing commas in lists, to compare imports
+/// regardless of how they're laid out.
+fn normalized(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<String>()
+        .replace(",}", "}")
+}
+
+fn compare_paths(a: &[String], b: &[String]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| compare_segments(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+/// rustfmt's order: `self`, `super`, `crate`, names, `*`, then lists.
+fn compare_segments(a: &str, b: &str) -> Ordering {
+    let rank = |segment: &str| match segment {
+        "self" => 0,
+        "super" => 1,
+        "crate" => 2,
+        "*" => 4,
+        _ if segment.starts_with('{') => 5,
+        _ => 3,
+    };
+    rank(a).cmp(&rank(b)).then_with(|| match rank(a) {
+        3 => compare_names(a, b),
+        _ => a.cmp(b),
+    })
+}
+
+/// `snake_case`, then `CamelCase`, then `UPPER_SNAKE_CASE` names, ignoring
+/// aliases (which keep their order).
+fn compare_names(a: &str, b: &str) -> Ordering {
+    let name = |segment: &'_ str| segment.split(" as ").next().unwrap_or_default().to_owned();
+    let case = |name: &str| {
+        if !name.is_empty()
+            && name
+                .chars()
+                .all(|char| char.is_uppercase() || char == '_' || char.is_numeric())
+        {
+            2
+        } else if name.starts_with(char::is_uppercase) {
+            1
+        } else {
+            0
+        }
+    };
+    let (a, b) = (name(a), name(b));
+    case(&a).cmp(&case(&b)).then_with(|| a.cmp(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    fn fix(source: &str, options: OrganizeImportsRuleOptions) -> String {
+        let tree = parse(source);
+        let violations = OrganizeImportsRule::new(options).check(source.as_bytes(), &tree);
+        String::from_utf8(Fix::apply_all(
+            source.as_bytes(),
+            violations
+                .iter()
+                .filter_map(|violation| violation.fix.as_ref()),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_organize_imports() {
+        // What rustfmt makes of it.
+        let source = "use crate::x;\nuse a::*;\nuse a::{c, b};\nuse a::Z;\nuse a::ZZ_TOP;\nuse a::z;\nuse a::b as y;\nuse a::b;\nuse self::q;\nuse super::w;\nuse a;\nuse ::g;\nuse pub_x;\npub use a::aa;\nuse a::b::c;\n\nuse b;\nuse a;\n";
+        let organized = "use self::q;\nuse super::w;\nuse crate::x;\nuse ::g;\nuse a;\npub use a::aa;\nuse a::b as y;\nuse a::b;\nuse a::b::c;\nuse a::z;\nuse a::Z;\nuse a::ZZ_TOP;\nuse a::*;\nuse a::{c, b};\nuse pub_x;\n\nuse a;\nuse b;\n";
+        assert_eq!(fix(source, Default::default()), organized);
+        assert_eq!(fix(organized, Default::default()), organized);
+
+        // Declarations with attributes or comments stay put.
+        let source = "use c;\n#[cfg(test)]\nuse b;\nuse a; // a\nuse e;\nuse d;\n";
+        assert_eq!(
+            fix(source, Default::default()),
+            "use c;\n#[cfg(test)]\nuse b;\nuse a; // a\nuse d;\nuse e;\n"
+        );
+    }
+
+    #[test]
+    fn test_organize_imports_groups() {
+        let source =
+            "mod m {\n    use crate::a;\n    use std::b;\n\n    use c::d;\n    use core::e;\n}\n";
+        let options = OrganizeImportsRuleOptions {
+            group_imports: GroupImports::StdExternalCrate,
+            ..Default::default()
+        };
+        let organized =
+            "mod m {\n    use core::e;\n    use std::b;\n\n    use c::d;\n\n    use crate::a;\n}\n";
+        assert_eq!(fix(source, options.clone()), organized);
+        assert_eq!(fix(organized, options), organized);
+        assert_eq!(
+            fix(
+                "use b;\n\nuse a;\r\n",
+                OrganizeImportsRuleOptions {
+                    group_imports: GroupImports::One,
+                    ..Default::default()
+                }
+            ),
+            "use a;\r\nuse b;\r\n"
+        );
+    }
+
+    #[test]
+    fn test_organize_imports_merge() {
+        let options = OrganizeImportsRuleOptions {
+            merge: true,
+            ..Default::default()
+        };
+        let source =
+            "use a::c;\npub use a::d;\nuse a::{\n    e,\n    b,\n};\nuse a::b::f;\nuse a;\n";
+        let organized = "use a;\nuse a::b::f;\npub use a::d;\nuse a::{b, c, e};\n";
+        assert_eq!(fix(source, options.clone()), organized);
+        assert_eq!(fix(organized, options.clone()), organized);
+        // As rustfmt would wrap it.
+        let wrapped = "use a::{\n    b,\n    c,\n};\n";
+        assert_eq!(fix(wrapped, options), wrapped);
+    }
+
+    #[test]
+    fn test_detect_group_imports() {
+        let detect = |source: &str| detect_group_imports(source.as_bytes(), &parse(source));
+        assert_eq!(detect("fn a() {}\n"), None);
+        assert_eq!(detect("use a;\nuse b::c;\n"), None);
+        assert_eq!(
+            detect("use crate::a;\nuse std::fmt;\nuse b;\n"),
+            Some(GroupImports::One)
+        );
+        assert_eq!(
+            detect("use std::fmt;\n\nuse b;\nuse c;\n\nuse crate::a;\n"),
+            Some(GroupImports::StdExternalCrate)
+        );
+        assert_eq!(
+            detect("use b;\nuse std::fmt;\n\nuse crate::a;\n"),
+            Some(GroupImports::Preserve)
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use std::ops::Range;
+
+use tree_sitter::{InputEdit, Node, Tree};
+
+use crate::{line_ranges::merge, node_cache::NodeCache};
+
+/// The rows (0-based, half-open) of the innermost items enclosing each of
+/// `edits`, after they were applied to `tree`. Items are the direct children
+/// of a file, `mod`, `impl`, `trait` etc, so an edit inside a method only
+/// covers that method, not its whole `impl`.
+///
+/// An edit that isn't inside a single item covers the items it overlaps and
+/// its own rows (eg blank lines between items).
+pub fn changed_item_rows(tree: &Tree, edits: &[InputEdit]) -> Vec<Range<usize>> {
+    let root = tree.root_node();
+    let mut nodes = NodeCache::new(root);
+    let mut rows = Vec::new();
+    for edit in edits {
+        let start = edit.start_byte.min(root.end_byte());
+        let end = edit.new_end_byte.clamp(start, root.end_byte());
+        rows.push(edit.start_position.row..edit.new_end_position.row + 1);
+        push_item_rows(&mut nodes, start..end, &mut rows);
+    }
+    merge(rows)
+}
+
+/// Like [`changed_item_rows()`], but growing the ranges the host got from
+/// [`Tree::changed_ranges()`] between the old and new trees to items rather
+/// than the edits. Those cover the nodes whose syntax actually changed, which
+/// can be fewer than an edit's bytes span (eg when retyping a sibling as it
+/// was) or extend past them (eg when an edit closes a string).
+///
+/// Changes that leave the syntax tree as it was (eg to whitespace) have no
+/// changed range, so the `edits` outside all of them still contribute their
+/// own rows, just not the items around them.
+pub fn changed_range_item_rows(
+    tree: &Tree,
+    changed_ranges: &[tree_sitter::Range],
+    edits: &[InputEdit],
+) -> Vec<Range<usize>> {
+    let root = tree.root_node();
+    let mut nodes = NodeCache::new(root);
+    let mut rows = edits
+        .iter()
+        .filter(|edit| {
+            !changed_ranges.iter().any(|changed| {
+                changed.start_byte <= edit.new_end_byte && edit.start_byte <= changed.end_byte
+            })
+        })
+        .map(|edit| edit.start_position.row..edit.new_end_position.row + 1)
+        .collect::<Vec<_>>();
+    for changed in changed_ranges {
+        let start = changed.start_byte.min(root.end_byte());
+        let end = changed.end_byte.clamp(start, root.end_byte());
+        rows.push(changed.start_point.row..changed.end_point.row + 1);
+        push_item_rows(&mut nodes, start..end, &mut rows);
+    }
+    merge(rows)
+}
+
+/// Pushes the rows of the innermost item enclosing `range`, or of the items
+/// it overlaps if it isn't inside one.
+fn push_item_rows(nodes: &mut NodeCache, range: Range<usize>, rows: &mut Vec<Range<usize>>) {
+    let node = nodes.descendant_for_byte_range(range.clone());
+    match enclosing_item(node) {
+        Some(item) => rows.push(node_rows(item)),
+        None => {
+            let mut cursor = node.walk();
+            rows.extend(
+                node.named_children(&mut cursor)
+                    .filter(|child| {
+                        child.start_byte() <= range.end && range.start <= child.end_byte()
+                    })
+                    .map(node_rows),
+            );
+        }
+    }
+}
+
+/// `node` itself or its closest ancestor that's an item, unless `node` is an
+/// item container (or inside one without being in any of its items).
+pub(crate) fn enclosing_item(node: Node) -> Option<Node> {
+    let mut node = node;
+    loop {
+        if is_item_container(node) {
+            return None;
+        }
+        let parent = node.parent()?;
+        if is_item_container(parent) {
+            return Some(node);
+        }
+        node = parent;
+    }
+}
+
+pub(crate) fn is_item_container(node: Node) -> bool {
+    matches!(node.kind(), "source_file" | "declaration_list")
+}
+
+fn node_rows(node: Node) -> Range<usize> {
+    node.start_position().row..node.end_position().row + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::{test_utils::parse, LineOffsets};
+
+    /// Simulates typing `inserted` at `offset` in `source`, returning the
+    /// edited source and the edit.
+    fn insert(source: &str, offset: usize, inserted: &str) -> (String, InputEdit) {
+        let edited = format!("{}{inserted}{}", &source[..offset], &source[offset..]);
+        let point = |source: &str, offset: usize| {
+            let line_offsets = LineOffsets::new(source.as_bytes());
+            let line = line_offsets.line_of_offset(offset);
+            Point::new(line - 1, offset - line_offsets.line_start(line).unwrap())
+        };
+        let edit = InputEdit {
+            start_byte: offset,
+            old_end_byte: offset,
+            new_end_byte: offset + inserted.len(),
+            start_position: point(source, offset),
+            old_end_position: point(source, offset),
+            new_end_position: point(&edited, offset + inserted.len()),
+        };
+        (edited, edit)
+    }
+
+    const SOURCE: &str = "use a;
+
+fn b() {
+    c();
+}
+
+impl D {
+    fn e() {}
+
+    fn f() {
+        g();
+    }
+}
+";
+
+    #[test]
+    fn test_changed_item_rows() {
+        let (edited, edit) = insert(SOURCE, SOURCE.find("c()").unwrap(), "x");
+        assert_eq!(changed_item_rows(&parse(&edited), &[edit]), [2..5]);
+
+        let (edited, edit) = insert(SOURCE, SOURCE.find("g()").unwrap(), "x");
+        assert_eq!(changed_item_rows(&parse(&edited), &[edit]), [9..12]);
+
+        let (edited, first) = insert(SOURCE, SOURCE.find("e()").unwrap(), "x");
+        let (edited, second) = insert(&edited, 0, "  ");
+        assert_eq!(
+            changed_item_rows(&parse(&edited), &[first, second]),
+            [0..1, 7..8]
+        );
+    }
+
+    #[test]
+    fn test_changed_item_rows_between_items() {
+        let (edited, edit) = insert(SOURCE, SOURCE.find("\nfn b").unwrap(), "\n\n");
+        assert_eq!(changed_item_rows(&parse(&edited), &[edit]), [1..4]);
+
+        let (edited, edit) = insert(SOURCE, SOURCE.find("fn f").unwrap() - 5, "  ");
+        assert_eq!(changed_item_rows(&parse(&edited), &[edit]), [8..9]);
+
+        // Deleting `c();`'s line.
+        let start = SOURCE.find("    c();").unwrap();
+        let end = start + "    c();\n".len();
+        let edited = format!("{}{}", &SOURCE[..start], &SOURCE[end..]);
+        let edit = InputEdit {
+            start_byte: start,
+            old_end_byte: end,
+            new_end_byte: start,
+            start_position: Point::new(3, 0),
+            old_end_position: Point::new(4, 0),
+            new_end_position: Point::new(3, 0),
+        };
+        assert_eq!(changed_item_rows(&parse(&edited), &[edit]), [2..4]);
+    }
+
+    #[test]
+    fn test_changed_range_item_rows() {
+        let reparse = |source: &str, edited: &str, edit: &InputEdit| {
+            let mut tree = parse(source);
+            tree.edit(edit);
+            let mut parser = tree_sitter::Parser::new();
+            parser.set_language(tree_sitter_rust::language()).unwrap();
+            let new_tree = parser.parse(edited, Some(&tree)).unwrap();
+            let changed_ranges = tree.changed_ranges(&new_tree).collect::<Vec<_>>();
+            (new_tree, changed_ranges)
+        };
+
+        let (edited, edit) = insert(SOURCE, SOURCE.find("g()").unwrap(), "x");
+        let (tree, changed_ranges) = reparse(SOURCE, &edited, &edit);
+        assert_eq!(
+            changed_range_item_rows(&tree, &changed_ranges, &[edit]),
+            [9..12]
+        );
+
+        // Only the new item changed, not `fn f` on the edit's last row.
+        let (edited, edit) = insert(SOURCE, SOURCE.find("    fn f").unwrap(), "fn x() {}\n");
+        let (tree, changed_ranges) = reparse(SOURCE, &edited, &edit);
+        assert_eq!(changed_item_rows(&tree, &[edit]), [9..11]);
+        assert_eq!(
+            changed_range_item_rows(&tree, &changed_ranges, &[edit]),
+            [9..10]
+        );
+
+        // Whitespace doesn't change the tree.
+        let (edited, edit) = insert(SOURCE, SOURCE.find("c()").unwrap(), "  ");
+        let (tree, changed_ranges) = reparse(SOURCE, &edited, &edit);
+        assert_eq!(changed_ranges, []);
+        assert_eq!(
+            changed_range_item_rows(&tree, &changed_ranges, &[edit]),
+            [3..4]
+        );
+    }
+}
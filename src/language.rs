@@ -0,0 +1,248 @@
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use tree_sitter::Tree;
+
+use crate::{
+    anchor_mismatch, node_cache::NodeCache, rule::narrowed_fix, Formatter, LineOffsets, RuleMode,
+    RustfmtError, RustfmtOptions, Violation, STDIN_FILE_NAME, UNEXPECTED_FORMATTING,
+};
+
+/// The language rustfmt formats, as tree-sitter-lint names it.
+pub const RUST_LANGUAGE: &str = "rust";
+
+/// The name [`FormatterRule`]s' violations are reported under.
+pub const FORMATTER_RULE_NAME: &str = "formatter";
+
+pub const FORMATTER_FAILED: &str = "formatter_failed";
+
+/// Reports (and fixes) every region of a file of another language that its
+/// formatter would change. That's all it does, so none of
+/// [`RustfmtRule`](crate::RustfmtRule)'s handling of Rust applies: no
+/// `#[rustfmt::skip]` or disable comments, no `rustfmt.toml` or
+/// `Cargo.toml` discovery, and violations are reported on the smallest node
+/// around what's changed, whatever the language's node kinds are.
+#[derive(Clone)]
+pub struct FormatterRule {
+    pub formatter: Arc<dyn Formatter>,
+    /// What the formatter is given, of which
+    /// [`CommandFormatter`](crate::CommandFormatter)s only use the
+    /// [`timeout`](RustfmtOptions::timeout) and
+    /// [`max_concurrent_runs`](RustfmtOptions::max_concurrent_runs).
+    pub options: RustfmtOptions,
+    pub mode: RuleMode,
+}
+
+impl fmt::Debug for FormatterRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FormatterRule")
+            .field("formatter", &self.formatter.name())
+            .field("options", &self.options)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl FormatterRule {
+    pub fn new(formatter: impl Formatter + 'static) -> Self {
+        Self {
+            formatter: Arc::new(formatter),
+            options: Default::default(),
+            mode: Default::default(),
+        }
+    }
+
+    pub fn check<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        let name = self.formatter.name();
+        let files = match self.formatter.format_full(source, &self.options) {
+            Ok(files) => files,
+            Err(error) => {
+                let message = match error {
+                    RustfmtError::NonZeroExit { stderr, .. } if stderr.is_empty() => {
+                        format!("{name} failed.")
+                    }
+                    RustfmtError::NonZeroExit { stderr, .. } => format!("{name} failed: {stderr}"),
+                    RustfmtError::TimedOut(timeout) => format!(
+                        "{name} didn't finish within {timeout:?}, so the file wasn't checked."
+                    ),
+                    error => {
+                        log::warn!("{name} failed: {error}");
+                        return Default::default();
+                    }
+                };
+                return vec![Violation {
+                    message_id: FORMATTER_FAILED,
+                    message,
+                    node: tree.root_node(),
+                    range: 0..0,
+                    fix: None,
+                }];
+            }
+        };
+        let line_offsets = LineOffsets::new(source);
+        let mut nodes = NodeCache::for_source(tree.root_node(), source);
+        files
+            .iter()
+            .filter(|file| file.name == STDIN_FILE_NAME)
+            .flat_map(|file| &file.mismatches)
+            .filter_map(|mismatch| {
+                let anchored = anchor_mismatch(source, &line_offsets, mismatch)?;
+                let fix = narrowed_fix(&anchored);
+                Some(Violation {
+                    message_id: UNEXPECTED_FORMATTING,
+                    message: "Unexpected formatting.".to_owned(),
+                    node: nodes.descendant_for_byte_range(fix.range.clone()),
+                    range: fix.range.clone(),
+                    fix: (self.mode == RuleMode::Fix).then_some(fix),
+                })
+            })
+            .collect()
+    }
+}
+
+/// The rules checking the files of the languages other than Rust against
+/// their formatters, by the names tree-sitter-lint gives the languages, eg a
+/// [`CommandFormatter`](crate::CommandFormatter) running `prettier
+/// --stdin-filepath x.ts`. A host instantiating the plugin for a language
+/// asks for its rule with
+/// [`instantiate_for_language()`](Self::instantiate_for_language).
+#[derive(Clone, Debug, Default)]
+pub struct LanguageFormatters {
+    rules: BTreeMap<String, FormatterRule>,
+}
+
+impl LanguageFormatters {
+    /// Checks the files of `language` against `formatter`, in place of any
+    /// formatter it already had. Rust's files are always checked against
+    /// rustfmt.
+    pub fn register(&mut self, language: impl Into<String>, formatter: impl Formatter + 'static) {
+        self.register_rule(language, FormatterRule::new(formatter));
+    }
+
+    /// Like [`register()`](Self::register) with a rule of its own.
+    pub fn register_rule(&mut self, language: impl Into<String>, rule: FormatterRule) {
+        let language = language.into();
+        if language == RUST_LANGUAGE {
+            return;
+        }
+        self.rules.insert(language, rule);
+    }
+
+    /// The languages whose files are checked: Rust, and the ones with a
+    /// formatter.
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages = vec![RUST_LANGUAGE];
+        languages.extend(self.rules.keys().map(String::as_str));
+        languages
+    }
+
+    /// The rule to check the files of `language` with, or `None` for Rust
+    /// (which is checked against rustfmt) and languages without a formatter.
+    pub fn instantiate_for_language(&self, language: &str) -> Option<&FormatterRule> {
+        self.rules.get(language)
+    }
+
+    pub(crate) fn rules_mut(&mut self) -> impl Iterator<Item = &mut FormatterRule> {
+        self.rules.values_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        test_utils::{parse, SqueezeSpaces},
+        CommandFormatter, FileMismatches, Fix, FormatterCapabilities,
+    };
+
+    #[test]
+    fn test_language_formatters() {
+        let mut formatters = LanguageFormatters::default();
+        formatters.register(
+            "typescript",
+            CommandFormatter::new("prettier", ["--stdin-filepath", "x.ts"]),
+        );
+        formatters.register(
+            RUST_LANGUAGE,
+            CommandFormatter::new("genemichaels", ["--stdin"]),
+        );
+        assert_eq!(formatters.languages(), [RUST_LANGUAGE, "typescript"]);
+        assert_eq!(
+            formatters
+                .instantiate_for_language("typescript")
+                .map(|rule| rule.formatter.name()),
+            Some("prettier")
+        );
+        assert!(formatters.instantiate_for_language(RUST_LANGUAGE).is_none());
+        assert!(formatters.instantiate_for_language("python").is_none());
+    }
+
+    #[test]
+    fn test_formatter_rule() {
+        // What rustfmt would leave alone is just text to another language's
+        // formatter. The Rust grammar is the only one at hand, but the rule
+        // doesn't look at node kinds.
+        let source = "#[rustfmt::skip]\nlet  a = 1;\n// tree-sitter-lint-disable rustfmt\nb  c\n";
+        let tree = parse(source);
+        let mut rule = FormatterRule::new(SqueezeSpaces);
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| (violation.message_id, violation.range.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (UNEXPECTED_FORMATTING, 21..22),
+                (UNEXPECTED_FORMATTING, 67..68)
+            ]
+        );
+        assert_eq!(
+            Fix::apply_all(
+                source.as_bytes(),
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref())
+            ),
+            b"#[rustfmt::skip]\nlet a = 1;\n// tree-sitter-lint-disable rustfmt\nb c\n"
+        );
+
+        rule.mode = RuleMode::Check;
+        assert!(rule
+            .check(source.as_bytes(), &tree)
+            .iter()
+            .all(|violation| violation.fix.is_none()));
+    }
+
+    #[test]
+    fn test_formatter_rule_failed() {
+        struct Hanging;
+
+        impl Formatter for Hanging {
+            fn name(&self) -> &str {
+                "hanging"
+            }
+
+            fn capabilities(&self, _options: &RustfmtOptions) -> FormatterCapabilities {
+                Default::default()
+            }
+
+            fn format_full(
+                &self,
+                _source: &[u8],
+                _options: &RustfmtOptions,
+            ) -> Result<Vec<FileMismatches>, RustfmtError> {
+                Err(RustfmtError::TimedOut(Duration::from_secs(1)))
+            }
+        }
+
+        let tree = parse("a");
+        let violations = FormatterRule::new(Hanging).check(b"a", &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, FORMATTER_FAILED);
+        assert_eq!(
+            violations[0].message,
+            "hanging didn't finish within 1s, so the file wasn't checked."
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+    io,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+use tree_sitter::Tree;
+
+use crate::{
+    node_cache::NodeCache, run_rustfmt, FileMismatches, RustfmtError, RustfmtOptions, Violation,
+};
+
+pub const LINE_TOO_LONG: &str = "line_too_long";
+
+/// How many unfinished (or finished but not yet picked up) runs
+/// [`PendingRuns`] holds on to, dropping the oldest beyond that.
+const MAX_PENDING_RUNS: usize = 16;
+
+type RunResult = Result<Vec<FileMismatches>, RustfmtError>;
+
+/// A run's key (see [`run_key()`]) and where its result turns up.
+type Run = (u64, Receiver<RunResult>);
+
+/// The result of [`RustfmtRule::check_within()`](crate::RustfmtRule::check_within).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BudgetedCheck<'tree> {
+    pub violations: Vec<Violation<'tree>>,
+    /// Whether rustfmt didn't finish within the budget, so that `violations`
+    /// are only trailing whitespace and overlong lines. The file should be
+    /// checked again in the next pass, which picks up rustfmt's result.
+    pub deferred: bool,
+}
+
+/// rustfmt runs that outlived the budget of a
+/// [`RustfmtRule::check_within()`](crate::RustfmtRule::check_within), which
+/// carry on in the background for the next pass over the same source to use.
+/// Clones share the runs.
+#[derive(Clone, Debug, Default)]
+pub struct PendingRuns {
+    runs: Arc<Mutex<VecDeque<Run>>>,
+}
+
+impl PendingRuns {
+    /// What rustfmt makes of `source`, if it's done within `budget` (counting
+    /// from when an earlier pass started it).
+    pub(crate) fn run_within(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        budget: Duration,
+    ) -> Option<RunResult> {
+        let key = run_key(source, options);
+        let pending = {
+            let mut runs = self.lock_runs();
+            runs.iter()
+                .position(|(run_key, _)| *run_key == key)
+                .and_then(|index| runs.remove(index))
+        };
+        let receiver = match pending {
+            Some((_, receiver)) => receiver,
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                let (source, options) = (source.to_vec(), options.clone());
+                thread::spawn(move || {
+                    // The receiver is gone if the run was dropped.
+                    let _ = sender.send(run_rustfmt(&source, &options));
+                });
+                receiver
+            }
+        };
+        match receiver.recv_timeout(budget) {
+            Ok(result) => Some(result),
+            Err(RecvTimeoutError::Timeout) => {
+                let mut runs = self.lock_runs();
+                if runs.len() == MAX_PENDING_RUNS {
+                    runs.pop_front();
+                }
+                runs.push_back((key, receiver));
+                None
+            }
+            Err(RecvTimeoutError::Disconnected) => Some(Err(RustfmtError::Io(io::Error::other(
+                "rustfmt's thread panicked",
+            )))),
+        }
+    }
+
+    fn lock_runs(&self) -> MutexGuard<'_, VecDeque<Run>> {
+        self.runs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn run_key(source: &[u8], options: &RustfmtOptions) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    options.args().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Lines of more than `max_width` characters, from the first character past
+/// the limit. rustfmt can't always shorten them, so these come without fixes.
+pub(crate) fn line_width_violations<'tree>(
+    source: &[u8],
+    tree: &'tree Tree,
+    max_width: usize,
+) -> Vec<Violation<'tree>> {
+    let mut nodes = NodeCache::for_source(tree.root_node(), source);
+    let mut violations = Vec::new();
+    let mut line_start = 0;
+    for line in source.split_inclusive(|&byte| byte == b'\n') {
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        let overflow = String::from_utf8_lossy(content)
+            .char_indices()
+            .nth(max_width)
+            .map(|(index, _)| index);
+        // Lossy decoding only changes the offsets of lines that aren't UTF-8,
+        // which rustfmt wouldn't accept anyway.
+        if let Some(overflow) = overflow.filter(|&overflow| overflow < content.len()) {
+            let range = line_start + overflow..line_start + content.len();
+            violations.push(Violation {
+                message_id: LINE_TOO_LONG,
+                message: format!("Line is longer than {max_width} characters."),
+                node: nodes.descendant_for_byte_range(range.clone()),
+                range,
+                fix: None,
+            });
+        }
+        line_start += line.len();
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    #[test]
+    fn test_line_width_violations() {
+        let source = "fn a() {}\nfn bbbbbbbb() {}\r\n// é é é é é\n";
+        let tree = parse(source);
+        assert_eq!(
+            line_width_violations(source.as_bytes(), &tree, 10)
+                .into_iter()
+                .map(|violation| &source[violation.range])
+                .collect::<Vec<_>>(),
+            ["b() {}", " é"]
+        );
+    }
+
+    #[test]
+    fn test_run_within() {
+        let pending = PendingRuns::default();
+        let options = RustfmtOptions::default();
+        assert!(pending
+            .run_within(b"fn  a() {}\n", &options, Duration::ZERO)
+            .is_none());
+        assert_eq!(pending.lock_runs().len(), 1);
+
+        let files = pending
+            .run_within(b"fn  a() {}\n", &options, Duration::from_secs(60))
+            .unwrap()
+            .unwrap();
+        assert_eq!(files[0].mismatches[0].expected, "fn a() {}\n");
+        assert_eq!(pending.lock_runs().len(), 0);
+    }
+}
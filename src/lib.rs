@@ -1,14 +1,160 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
+//! Lint rules that report (and fix) what rustfmt would change, for
+//! tree-sitter based linters (see [`rules`]). See [`api`] for the parts of
+//! the crate that are kept stable.
 
+#![cfg_attr(test, allow(clippy::single_range_in_vec_init))]
+
+mod anchor;
+pub mod api;
+#[cfg(feature = "async")]
+mod async_rustfmt;
+mod backend;
+mod blank_lines;
+mod cache;
+mod category;
+mod changed;
+mod chunk;
+mod config;
+mod debug_dump;
+mod dedup;
+mod diff;
+mod doc_code;
+mod drift;
+mod edited_lines;
+mod edition;
+mod exclude;
+mod explain;
+mod fallback;
+mod fingerprint;
+mod format;
+mod formatter;
+mod header;
+mod hooks;
+mod imports;
+mod incremental;
+mod language;
+mod latency;
+mod limit;
+mod line_ranges;
+mod memo;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod mirror;
+mod mismatch;
+mod newline;
+mod node_cache;
+mod plugin;
+mod pool;
+mod profile;
+mod range;
+mod report;
+mod rule;
+mod rule_config;
+pub mod rules;
+mod run_status;
+mod rustfmt;
+#[cfg(feature = "sarif")]
+pub mod sarif;
+#[cfg(feature = "schema")]
+pub mod schema;
+mod scope;
+mod service;
+mod skip;
+mod snippet;
+mod source;
+mod stderr;
+mod summary;
 #[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+mod test_utils;
+mod testing;
+mod verify;
+mod version;
+mod whitespace;
+
+#[cfg(feature = "async")]
+pub use async_rustfmt::{run_rustfmt_async, run_rustfmt_on_lines_async};
+pub use backend::{FormatterBackend, MockBackend, ProcessBackend};
+pub use blank_lines::{
+    BlankLinesRule, BlankLinesRuleOptions, BLANK_LINES_RULE_NAME, WRONG_BLANK_LINES,
+};
+pub use cache::{CacheKey, CleanCache, RecentResults};
+pub use category::{
+    MismatchCategory, BRACE_PLACEMENT, LONG_LINE_REFLOW, UNEXPECTED_TRAILING_WHITESPACE,
+    UNORDERED_IMPORTS, WRONG_INDENTATION,
+};
+pub use changed::ChangedLines;
+pub use config::{discover_config, discover_config_for_file, SymlinkPolicy};
+pub use dedup::{FixAttempts, QueuedFixes};
+pub use diff::render_patch;
+pub use doc_code::DOC_CODE_FORMATTING;
+pub use drift::{FileStyle, StyleDrift, StyleOutlier};
+pub use edited_lines::{changed_line_ranges, LineRangeContext};
+pub use edition::{discover_edition, discover_edition_for_file};
+pub use exclude::GENERATED_MARKER_LINES;
+pub use explain::{explain, Attribution};
+pub use fingerprint::ConfigFingerprint;
+pub use format::{format_files_batch, format_source, format_to_string, MappedMismatch};
+pub use formatter::{CommandFormatter, Formatter, FormatterCapabilities, RustfmtFormatter};
+pub use header::{
+    FileHeaderRule, FileHeaderRuleOptions, HeaderTemplate, FILE_HEADER_RULE_NAME,
+    MALFORMED_FILE_HEADER, MISSING_FILE_HEADER,
+};
+pub use hooks::FormatHooks;
+pub use imports::{
+    GroupImports, OrganizeImportsRule, OrganizeImportsRuleOptions, ORGANIZE_IMPORTS_RULE_NAME,
+    UNORGANIZED_IMPORTS,
+};
+pub use incremental::{changed_item_rows, changed_range_item_rows};
+pub use language::{
+    FormatterRule, LanguageFormatters, FORMATTER_FAILED, FORMATTER_RULE_NAME, RUST_LANGUAGE,
+};
+pub use latency::{BudgetedCheck, PendingRuns, LINE_TOO_LONG};
+pub use limit::MAX_CONCURRENT_RUNS_VAR;
+pub use mirror::InputMode;
+pub use mismatch::{FileMismatches, Mismatch, MismatchFormat, STDIN_FILE_NAME};
+pub use newline::NewlineStyle;
+pub use plugin::{Plugin, PluginBuilder, PluginRule};
+pub use pool::RustfmtPool;
+pub use profile::StyleProfile;
+pub use report::{FormattingReport, FormattingStatus, ReportCallback, RustfmtSetup};
+pub use rule::{
+    CommentMismatches, Fix, MismatchFilter, NodeAnchoring, PartialCheck, RuleMode, RustfmtRule,
+    RustfmtRuleOptions, Severity, Violation, ViolationData, COMMENT_FORMATTING,
+    FILE_NEEDS_REFORMATTING, FORMATTING_FIX_REJECTED, INCONSISTENT_LINE_ENDINGS, LEFT_UNFORMATTED,
+    LINE_OVERFLOW, PARTIALLY_FORMATTED, RULE_NAME, RUSTFMT_FAILED, RUSTFMT_TIMED_OUT,
+    RUSTFMT_VERSION_MISMATCH, TRAILING_WHITESPACE_LEFT, UNEXPECTED_FORMATTING,
+    UNRESOLVABLE_FORMATTING_CONFLICT, UNSTABLE_FORMATTING,
+};
+pub use rule_config::{RuleConfigError, RustfmtRuleConfig};
+pub use run_status::RunStatus;
+pub use rustfmt::{
+    run_rustfmt, run_rustfmt_on_lines, RustfmtError, RustfmtOptions, SkipMacroInvocations,
+};
+pub use scope::FormattingScope;
+pub use service::RustfmtService;
+pub use snippet::{format_node, format_snippet};
+pub use source::{RopeOrSlice, SourceBuffer, MMAP_THRESHOLD};
+pub use stderr::{ignored_unstable_settings, StderrDiagnostic};
+pub use summary::{DiffStatSummary, FileDiffStat};
+pub use testing::{check_formatted, check_formatted_file};
+pub use version::RustfmtVersion;
+pub use whitespace::{
+    FinalNewlineRule, TrailingWhitespaceRule, EXTRA_FINAL_NEWLINES, FINAL_NEWLINE_RULE_NAME,
+    MISSING_FINAL_NEWLINE, TRAILING_WHITESPACE, TRAILING_WHITESPACE_RULE_NAME,
+};
+
+// The lower-level pieces the rules are built from, for the crate's own
+// binary and fuzz targets. They aren't part of the [`api`], so they can
+// change in any release.
+#[doc(hidden)]
+pub use crate::{
+    anchor::{anchor_mismatch, AnchoredMismatch},
+    config::{max_width, required_version, CONFIG_FILE_NAMES, DEFAULT_MAX_WIDTH},
+    diff::{narrow_replacement, render_hunk},
+    mismatch::{parse_mismatches, stdin_mismatches},
+    range::LineOffsets,
+    rustfmt::file_lines_args,
+    skip::{attribute_skip_regions, is_file_skipped, overlaps, skip_regions},
+    stderr::{parse_stderr, parse_stderr_as},
+    verify::is_pure_reformat,
+};
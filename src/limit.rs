@@ -0,0 +1,161 @@
+use std::{
+    env,
+    sync::{Arc, Condvar, Mutex, OnceLock, PoisonError},
+};
+
+use crate::RustfmtOptions;
+
+/// The environment variable with the default for
+/// [`RustfmtOptions::max_concurrent_runs`], eg for hosts that don't expose
+/// the option.
+pub const MAX_CONCURRENT_RUNS_VAR: &str = "TREE_SITTER_LINT_RUSTFMT_MAX_CONCURRENT_RUNS";
+
+impl RustfmtOptions {
+    /// The limit on how many rustfmts run at once, from
+    /// [`max_concurrent_runs`](Self::max_concurrent_runs) or else the
+    /// environment.
+    pub(crate) fn concurrency_limit(&self) -> Option<usize> {
+        static FROM_ENV: OnceLock<Option<usize>> = OnceLock::new();
+        self.max_concurrent_runs.or_else(|| {
+            *FROM_ENV.get_or_init(|| {
+                let value = env::var(MAX_CONCURRENT_RUNS_VAR).ok()?;
+                match value.parse() {
+                    Ok(limit) if limit > 0 => Some(limit),
+                    _ => {
+                        log::warn!("ignoring {MAX_CONCURRENT_RUNS_VAR}={value:?}, which isn't a positive number");
+                        None
+                    }
+                }
+            })
+        })
+    }
+
+    /// Runs `run` (which runs rustfmt) once fewer than the
+    /// [limit](Self::concurrency_limit) of rustfmts are running, counting
+    /// every limited run in the process (whatever its own limit).
+    pub(crate) fn limited<T>(&self, run: impl FnOnce() -> T) -> T {
+        let _permit = self
+            .concurrency_limit()
+            .map(|limit| slots().clone().acquire(limit));
+        run()
+    }
+}
+
+/// Counts the limited rustfmts running, for [`RustfmtOptions::limited()`]
+/// (and the async runs). A run waits for fewer than its own limit to be
+/// running, so with different limits about, the most that run at once is the
+/// highest of them.
+#[derive(Debug, Default)]
+pub(crate) struct Slots {
+    running: Mutex<usize>,
+    freed: Condvar,
+    #[cfg(feature = "async")]
+    freed_async: tokio::sync::Notify,
+}
+
+impl Slots {
+    /// Waits for fewer than `limit` runs to be running and takes a slot,
+    /// until the permit is dropped.
+    fn acquire(self: Arc<Self>, limit: usize) -> Permit {
+        let mut running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        while *running >= limit {
+            running = self
+                .freed
+                .wait(running)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *running += 1;
+        drop(running);
+        Permit(self)
+    }
+
+    /// Like [`acquire()`](Self::acquire), without blocking the thread.
+    #[cfg(feature = "async")]
+    pub(crate) async fn acquire_async(self: Arc<Self>, limit: usize) -> Permit {
+        loop {
+            // Listening before looking, so that a slot freed in between
+            // isn't missed.
+            let mut freed = std::pin::pin!(self.freed_async.notified());
+            freed.as_mut().enable();
+            {
+                let mut running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+                if *running < limit {
+                    *running += 1;
+                    break;
+                }
+            }
+            freed.await;
+        }
+        Permit(self)
+    }
+
+    #[cfg(test)]
+    fn running(&self) -> usize {
+        *self.running.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) struct Permit(Arc<Slots>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self
+            .0
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) -= 1;
+        // Runs with different limits wait on the same slots, so the one a
+        // slot is enough for may not be the first.
+        self.0.freed.notify_all();
+        #[cfg(feature = "async")]
+        self.0.freed_async.notify_waiters();
+    }
+}
+
+/// The slots of all the limited runs in the process.
+pub(crate) fn slots() -> &'static Arc<Slots> {
+    static SLOTS: OnceLock<Arc<Slots>> = OnceLock::new();
+    SLOTS.get_or_init(Default::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_limited() {
+        // Slots of its own, so that other tests' runs don't count.
+        let slots = Arc::new(Slots::default());
+        let running = AtomicUsize::new(0);
+        let most_running = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for index in 0..12 {
+                let slots = slots.clone();
+                let (running, most_running) = (&running, &most_running);
+                scope.spawn(move || {
+                    // Different limits still share the slots.
+                    let _permit = slots.acquire(if index % 2 == 0 { 3 } else { 4 });
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_running.fetch_max(now_running, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(most_running.into_inner() <= 4);
+        assert_eq!(slots.running(), 0);
+
+        let options = RustfmtOptions {
+            max_concurrent_runs: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(options.limited(|| 1), 1);
+        assert_eq!(RustfmtOptions::default().limited(|| 1), 1);
+    }
+}
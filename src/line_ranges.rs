@@ -0,0 +1,137 @@
+//! Helpers for 0-based, half-open row ranges (as in tree-sitter `Point`s), the
+//! representation used for the lines passed to rustfmt's `--file-lines`.
+
+use std::ops::Range;
+
+use crate::LineOffsets;
+
+/// The fraction of a file's lines above which [`normalize()`] gives up on
+/// `--file-lines` and has the whole file formatted, which is about as fast
+/// and doesn't need a long `--file-lines` argument.
+pub const FULL_FILE_COVERAGE: f64 = 0.8;
+
+/// The rows containing any of the bytes in `byte_range`. An empty byte range
+/// covers the row it's on.
+pub fn byte_range_to_rows(line_offsets: &LineOffsets, byte_range: &Range<usize>) -> Range<usize> {
+    let start_row = line_offsets.line_of_offset(byte_range.start) - 1;
+    let end_row = if byte_range.is_empty() {
+        start_row + 1
+    } else {
+        line_offsets.line_of_offset(byte_range.end - 1)
+    };
+    start_row..end_row
+}
+
+/// `lines` with all of the rows in `removed` (and any empty ranges) taken out.
+pub fn subtract(lines: &[Range<usize>], removed: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut remaining = lines
+        .iter()
+        .filter(|lines| !lines.is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    for removed in removed.iter().filter(|removed| !removed.is_empty()) {
+        remaining = remaining
+            .into_iter()
+            .flat_map(|lines| {
+                [
+                    lines.start..lines.end.min(removed.start),
+                    lines.start.max(removed.end)..lines.end,
+                ]
+            })
+            .filter(|lines| !lines.is_empty())
+            .collect();
+    }
+    remaining
+}
+
+/// `lines` sorted, with overlapping and adjacent ranges (and empty ones)
+/// merged.
+pub fn merge(lines: impl IntoIterator<Item = Range<usize>>) -> Vec<Range<usize>> {
+    let mut lines = lines
+        .into_iter()
+        .filter(|lines| !lines.is_empty())
+        .collect::<Vec<_>>();
+    lines.sort_by_key(|lines| lines.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(lines.len());
+    for lines in lines {
+        match merged.last_mut() {
+            Some(last) if lines.start <= last.end => last.end = last.end.max(lines.end),
+            _ => merged.push(lines),
+        }
+    }
+    merged
+}
+
+/// The rows in both `lines` and `other`, [merged](merge).
+pub fn intersect(lines: &[Range<usize>], other: &[Range<usize>]) -> Vec<Range<usize>> {
+    merge(lines.iter().flat_map(|lines| {
+        other
+            .iter()
+            .map(move |other| lines.start.max(other.start)..lines.end.min(other.end))
+    }))
+}
+
+/// `lines` [merged](merge) and clamped to a file's `line_count` rows, or
+/// `None` if they cover more than [`FULL_FILE_COVERAGE`] of them, for the
+/// whole file to be formatted instead.
+pub fn normalize(lines: &[Range<usize>], line_count: usize) -> Option<Vec<Range<usize>>> {
+    let lines = merge(
+        lines
+            .iter()
+            .map(|lines| lines.start.min(line_count)..lines.end.min(line_count)),
+    );
+    let covered = lines.iter().map(Range::len).sum::<usize>();
+    (covered as f64 <= line_count as f64 * FULL_FILE_COVERAGE).then_some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_range_to_rows() {
+        let line_offsets = LineOffsets::new(b"ab\ncd\nef\n");
+        assert_eq!(byte_range_to_rows(&line_offsets, &(0..3)), 0..1);
+        assert_eq!(byte_range_to_rows(&line_offsets, &(1..4)), 0..2);
+        assert_eq!(byte_range_to_rows(&line_offsets, &(3..9)), 1..3);
+        assert_eq!(byte_range_to_rows(&line_offsets, &(4..4)), 1..2);
+    }
+
+    #[test]
+    fn test_subtract() {
+        assert_eq!(
+            subtract(&[0..10, 12..14], &[2..4, 6..7]),
+            [0..2, 4..6, 7..10, 12..14]
+        );
+        assert!(subtract(&[0..10], &[0..20]).is_empty());
+        assert_eq!(subtract(&[3..5, 0..2, 6..6], &[1..4, 8..8]), [4..5, 0..1]);
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(
+            merge([5..7, 0..2, 1..3, 3..4, 9..9, 6..6, 10..12]),
+            [0..4, 5..7, 10..12]
+        );
+    }
+
+    #[test]
+    fn test_intersect() {
+        assert_eq!(
+            intersect(&[0..4, 6..10], &[2..7, 9..12, 20..21]),
+            [2..4, 6..7, 9..10]
+        );
+        assert!(intersect(&[0..4], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(&[8..12, 0..2, 1..3, 20..30, 5..5], 10),
+            Some(vec![0..3, 8..10])
+        );
+        assert_eq!(normalize(&[0..4, 4..8], 10), Some(vec![0..8]));
+        assert_eq!(normalize(&[0..4, 3..9], 10), None);
+        assert_eq!(normalize(&[], 0), Some(vec![]));
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    borrow::Borrow,
+    collections::{hash_map::DefaultHasher, HashMap},
+    convert::Infallible,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+const SHARD_COUNT: usize = 16;
+
+/// A value that's being, or has been, computed.
+type Slot<V> = Arc<Mutex<Option<V>>>;
+
+/// Values that are computed once per key and then remembered for the life of
+/// the process (eg rustfmt's version per toolchain), which files checked in
+/// parallel all look up.
+///
+/// Keys are spread over shards that are only locked to find a key's slot, and
+/// each value is computed with just its own slot locked. So threads wait for
+/// each other to compute the same value rather than computing it again, but
+/// never for different values.
+pub(crate) struct Memo<K, V> {
+    shards: [Mutex<HashMap<K, Slot<V>>>; SHARD_COUNT],
+}
+
+impl<K, V> Default for Memo<K, V> {
+    fn default() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Default::default()),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> Memo<K, V> {
+    pub(crate) fn get_or_insert_with<Q>(&self, key: &Q, compute: impl FnOnce() -> V) -> V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let Ok(value) = self.get_or_try_insert_with(key, || Ok::<_, Infallible>(compute()));
+        value
+    }
+
+    /// Like [`get_or_insert_with()`](Self::get_or_insert_with), but errors
+    /// aren't remembered, so `compute` is tried again next time.
+    pub(crate) fn get_or_try_insert_with<Q, E>(
+        &self,
+        key: &Q,
+        compute: impl FnOnce() -> Result<V, E>,
+    ) -> Result<V, E>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let slot = {
+            let mut shard = lock(&self.shards[shard_index(key)]);
+            match shard.get(key) {
+                Some(slot) => slot.clone(),
+                None => shard.entry(key.to_owned()).or_default().clone(),
+            }
+        };
+        // A slot is only poisoned by `compute` panicking, before it's filled.
+        let mut value = lock(&slot);
+        if let Some(value) = value.as_ref() {
+            return Ok(value.clone());
+        }
+        let computed = compute()?;
+        *value = Some(computed.clone());
+        Ok(computed)
+    }
+}
+
+fn shard_index(key: &(impl Hash + ?Sized)) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % SHARD_COUNT
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_computes_each_value_once() {
+        let memo = Memo::<String, usize>::default();
+        let computed = Default::default();
+        let computed: &[AtomicUsize; 10] = &computed;
+        thread::scope(|scope| {
+            for _ in 0..16 {
+                scope.spawn(|| {
+                    for index in 0..1000 {
+                        let key = index % 10;
+                        let value = memo.get_or_insert_with(&*key.to_string(), || {
+                            computed[key].fetch_add(1, Ordering::Relaxed);
+                            key * 2
+                        });
+                        assert_eq!(value, key * 2);
+                    }
+                });
+            }
+        });
+        assert!(computed
+            .iter()
+            .all(|computed| computed.load(Ordering::Relaxed) == 1));
+    }
+
+    #[test]
+    fn test_doesnt_remember_errors() {
+        let memo = Memo::<u8, u8>::default();
+        assert_eq!(memo.get_or_try_insert_with(&1, || Err(())), Err(()));
+        assert_eq!(memo.get_or_try_insert_with(&1, || Ok::<_, ()>(2)), Ok(2));
+        assert_eq!(memo.get_or_try_insert_with(&1, || Err(())), Ok(2));
+    }
+}
@@ -0,0 +1,180 @@
+//! Timing and size of every rustfmt invocation, so that the files that
+//! dominate formatting time can be found. Invocations are recorded from any
+//! thread into one process-wide list.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{stdin_mismatches, FileMismatches, RustfmtError};
+
+/// How many of the slowest invocations the summary's `Display` lists.
+const SLOWEST_SHOWN: usize = 10;
+
+static INVOCATIONS: Mutex<Vec<Invocation>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static LABEL: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Invocation {
+    /// The [label](with_label) in effect on the thread that ran rustfmt,
+    /// usually the file's path.
+    pub label: Option<String>,
+    /// From writing the source to rustfmt's stdin until it exited. Process
+    /// startup isn't included when it came from a [`RustfmtPool`](crate::RustfmtPool).
+    pub wall_time: Duration,
+    pub stdin_bytes: usize,
+    pub mismatch_count: usize,
+    /// `None` if rustfmt was killed by a signal or couldn't be talked to.
+    pub exit_code: Option<i32>,
+}
+
+/// The invocations recorded so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub invocations: Vec<Invocation>,
+}
+
+impl Summary {
+    pub fn total_wall_time(&self) -> Duration {
+        self.invocations
+            .iter()
+            .map(|invocation| invocation.wall_time)
+            .sum()
+    }
+
+    pub fn total_stdin_bytes(&self) -> usize {
+        self.invocations
+            .iter()
+            .map(|invocation| invocation.stdin_bytes)
+            .sum()
+    }
+
+    pub fn total_mismatch_count(&self) -> usize {
+        self.invocations
+            .iter()
+            .map(|invocation| invocation.mismatch_count)
+            .sum()
+    }
+
+    pub fn failure_count(&self) -> usize {
+        self.invocations
+            .iter()
+            .filter(|invocation| invocation.exit_code != Some(0))
+            .count()
+    }
+
+    /// The `n` invocations that took longest, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&Invocation> {
+        let mut invocations = self.invocations.iter().collect::<Vec<_>>();
+        invocations.sort_by_key(|invocation| std::cmp::Reverse(invocation.wall_time));
+        invocations.truncate(n);
+        invocations
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} rustfmt invocations ({} failed) took {:.2?} for {} bytes, with {} mismatches",
+            self.invocations.len(),
+            self.failure_count(),
+            self.total_wall_time(),
+            self.total_stdin_bytes(),
+            self.total_mismatch_count()
+        )?;
+        for invocation in self.slowest(SLOWEST_SHOWN) {
+            writeln!(
+                f,
+                "  {:>10.2?}  {:>8} bytes  {}",
+                invocation.wall_time,
+                invocation.stdin_bytes,
+                invocation.label.as_deref().unwrap_or("<unlabeled>")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` with the rustfmt invocations it makes on this thread labeled
+/// `label`.
+pub fn with_label<T>(label: impl Into<String>, f: impl FnOnce() -> T) -> T {
+    let previous = LABEL.replace(Some(label.into()));
+    let result = f();
+    LABEL.set(previous);
+    result
+}
+
+/// The invocations recorded so far.
+pub fn summary() -> Summary {
+    Summary {
+        invocations: lock().clone(),
+    }
+}
+
+/// The invocations recorded so far, forgetting them.
+pub fn take_summary() -> Summary {
+    Summary {
+        invocations: std::mem::take(&mut *lock()),
+    }
+}
+
+pub(crate) fn record(
+    wall_time: Duration,
+    stdin_bytes: usize,
+    result: &Result<Vec<FileMismatches>, RustfmtError>,
+) {
+    let (mismatch_count, exit_code) = match result {
+        Ok(files) => (stdin_mismatches(files.clone()).count(), Some(0)),
+        Err(RustfmtError::NonZeroExit { status, .. }) => (0, status.code()),
+        Err(_) => (0, None),
+    };
+    let invocation = Invocation {
+        label: LABEL.with_borrow(Clone::clone),
+        wall_time,
+        stdin_bytes,
+        mismatch_count,
+        exit_code,
+    };
+    lock().push(invocation);
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Vec<Invocation>> {
+    INVOCATIONS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_rustfmt;
+
+    #[test]
+    fn test_metrics() {
+        with_label("test_metrics.rs", || {
+            run_rustfmt(b"fn  a() {}\n", &Default::default()).unwrap();
+            assert!(run_rustfmt(b"fn a( {}\n", &Default::default()).is_err());
+        });
+        run_rustfmt(b"fn a() {}\n", &Default::default()).unwrap();
+
+        let summary = Summary {
+            invocations: summary()
+                .invocations
+                .into_iter()
+                .filter(|invocation| invocation.label.as_deref() == Some("test_metrics.rs"))
+                .collect(),
+        };
+        assert_eq!(summary.invocations.len(), 2);
+        assert_eq!(summary.total_stdin_bytes(), 20);
+        assert_eq!(summary.total_mismatch_count(), 1);
+        assert_eq!(summary.failure_count(), 1);
+        assert_eq!(summary.invocations[1].exit_code, Some(1));
+        assert_eq!(summary.slowest(1).len(), 1);
+        assert!(summary.to_string().contains("test_metrics.rs"));
+    }
+}
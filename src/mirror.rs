@@ -0,0 +1,238 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    debug_dump::{record_exchange, record_spawn},
+    discover_config,
+    rustfmt::{check_unstable_settings, parse_output, wait_with_timeout},
+    FileMismatches, RustfmtError, RustfmtOptions, STDIN_FILE_NAME,
+};
+
+/// The rustfmt config settings that only apply to files rustfmt is given the
+/// paths of.
+const PATH_SETTINGS: [&str; 1] = ["ignore"];
+
+/// How rustfmt is handed the source to format (see
+/// [`RustfmtOptions::input_mode`]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum InputMode {
+    /// As a temporary file if the config has settings that need one (eg
+    /// `ignore`), or else as stdin.
+    #[default]
+    Auto,
+    Stdin,
+    /// As a temporary file, at the same path relative to a copy of the
+    /// config file as the [`file_name`](RustfmtOptions::file_name) has to
+    /// the config file, so that settings that match paths (eg `ignore`
+    /// globs) apply to it. Only for rustfmts that can
+    /// [`--emit json`](RustfmtOptions::supports_emit_json), and files with a
+    /// name.
+    TempFile,
+}
+
+impl RustfmtOptions {
+    /// Whether rustfmt is handed the source as a temporary file rather than
+    /// as stdin, see [`input_mode`](Self::input_mode).
+    pub(crate) fn uses_temp_file(&self) -> bool {
+        if self.file_name.is_none() {
+            return false;
+        }
+        let is_wanted = match self.input_mode {
+            InputMode::Stdin => false,
+            InputMode::TempFile => true,
+            InputMode::Auto => self
+                .resolved_config_path()
+                .and_then(|config_path| fs::read_to_string(config_path).ok())
+                .and_then(|config| config.parse::<toml::Table>().ok())
+                .is_some_and(|config| PATH_SETTINGS.iter().any(|key| config.contains_key(*key))),
+        };
+        is_wanted && self.supports_emit_json()
+    }
+
+    /// The config file rustfmt is given or would find where it runs.
+    fn resolved_config_path(&self) -> Option<PathBuf> {
+        match self.config_path.as_ref() {
+            Some(config_path) if config_path.is_dir() => discover_config(config_path),
+            Some(config_path) => Some(config_path.clone()),
+            None => discover_config(&self.working_dir()?),
+        }
+    }
+}
+
+/// Runs rustfmt over `source` written to a temporary file mirroring the
+/// [`file_name`](RustfmtOptions::file_name)'s place relative to its config,
+/// with `extra_args` for the temporary file's path. The mismatches and errors
+/// are reported for stdin, as if it had been formatted from there.
+pub(crate) fn run_on_temp_file(
+    source: &[u8],
+    options: &RustfmtOptions,
+    extra_args: impl FnOnce(&str) -> Vec<String>,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    let dir = tempfile::tempdir()?;
+    let mut options = options.clone();
+    let file_name = match (options.file_name.as_ref(), options.working_dir()) {
+        (Some(file_name), Some(working_dir)) if file_name.is_relative() => {
+            working_dir.join(file_name)
+        }
+        (Some(file_name), _) => file_name.clone(),
+        (None, _) => PathBuf::from("stdin.rs"),
+    };
+    let config_path = options.resolved_config_path();
+    let relative_path = config_path
+        .as_deref()
+        .and_then(Path::parent)
+        .and_then(|config_dir| file_name.strip_prefix(config_dir).ok())
+        .map(Path::to_owned)
+        .or_else(|| file_name.file_name().map(PathBuf::from))
+        .unwrap_or_else(|| "stdin.rs".into());
+    if let Some(config_path) = config_path.as_ref() {
+        let copied = dir.path().join(config_path.file_name().unwrap_or_default());
+        fs::copy(config_path, &copied)?;
+        options.config_path = Some(copied);
+    }
+    let path = dir.path().join(relative_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, source)?;
+    let path = path.to_string_lossy().into_owned();
+
+    let mut args = extra_args(&path);
+    args.push(path.clone());
+    let mut output = options.limited(|| {
+        let mut command = options.command(&args);
+        let child = command
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(RustfmtError::SpawnFailed)?;
+        let child_id = child.id();
+        record_spawn(&options, &command, child_id);
+        let output = match options.timeout {
+            Some(timeout) => wait_with_timeout(child, timeout),
+            None => child.wait_with_output().map_err(RustfmtError::from),
+        };
+        // What's in the temporary file, rather than stdin.
+        record_exchange(&options, child_id, source.into(), output.as_ref());
+        output
+    })?;
+    check_unstable_settings(&options, &output)?;
+    output.stderr = String::from_utf8_lossy(&output.stderr)
+        .replace(&path, STDIN_FILE_NAME)
+        .into_bytes();
+    let for_stdin = |files: Vec<FileMismatches>| {
+        files
+            .into_iter()
+            .map(|file| FileMismatches {
+                name: if file.name == path {
+                    STDIN_FILE_NAME.to_owned()
+                } else {
+                    file.name
+                },
+                ..file
+            })
+            .collect()
+    };
+    let result = match parse_output(output, source.into(), options.output_format()?) {
+        Ok(files) => Ok(for_stdin(files)),
+        Err(RustfmtError::LeftUnformatted { files, diagnostics }) => {
+            Err(RustfmtError::LeftUnformatted {
+                files: for_stdin(files),
+                diagnostics,
+            })
+        }
+        Err(error) => Err(error),
+    };
+    options.name_stdin(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_rustfmt, run_rustfmt_on_lines};
+
+    /// A nightly stand-in for rustfmt that, given a file next to its config,
+    /// logs its arguments and reports the file's first line as misformatted.
+    #[cfg(unix)]
+    #[test]
+    fn test_temp_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("args");
+        let fake_rustfmt = dir.path().join("fake-rustfmt");
+        fs::write(
+            &fake_rustfmt,
+            format!(
+                r#"#!/bin/sh
+case "$*" in *--version*) echo 'rustfmt 1.6.0-nightly (f5b8f44e 2023-08-17)'; exit 0;; esac
+for arg; do file=$arg; done
+[ -f "$file" ] || exit 0
+[ -f "$(dirname "$file")/../rustfmt.toml" ] || exit 3
+echo "$@" >> {}
+printf '%s\n' '[{{"name":"'"$file"'","mismatches":[{{"original_begin_line":1,"original_end_line":1,"expected_begin_line":1,"expected_end_line":1,"original":"fn  a() {{}}\n","expected":"fn a() {{}}\n"}}]}}]'
+"#,
+                log.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&fake_rustfmt, fs::Permissions::from_mode(0o755)).unwrap();
+        let workspace = dir.path().join("workspace");
+        fs::create_dir(&workspace).unwrap();
+        fs::write(
+            workspace.join("rustfmt.toml"),
+            "ignore = [\"src/generated.rs\"]\n",
+        )
+        .unwrap();
+        let options = RustfmtOptions {
+            command: Some(fake_rustfmt),
+            config_path: Some(workspace.clone()),
+            file_name: Some(workspace.join("src/lib.rs")),
+            ..Default::default()
+        };
+        assert!(options.uses_temp_file());
+        assert!(!RustfmtOptions {
+            input_mode: InputMode::Stdin,
+            ..options.clone()
+        }
+        .uses_temp_file());
+        assert!(!RustfmtOptions {
+            file_name: None,
+            ..options.clone()
+        }
+        .uses_temp_file());
+
+        let files = run_rustfmt(b"fn  a() {}\n", &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, STDIN_FILE_NAME);
+        assert_eq!(files[0].mismatches[0].expected, "fn a() {}\n");
+        let files = run_rustfmt_on_lines(b"fn  a() {}\n", &options, &[0..1]).unwrap();
+        assert_eq!(files[0].name, STDIN_FILE_NAME);
+
+        let log = fs::read_to_string(log).unwrap();
+        let runs = log.lines().collect::<Vec<_>>();
+        assert_eq!(runs.len(), 2);
+        assert!(runs[0].ends_with("/src/lib.rs"), "{}", runs[0]);
+        assert!(
+            !runs[0].contains(&*workspace.to_string_lossy()),
+            "{}",
+            runs[0]
+        );
+        assert!(
+            runs[1].contains(r#"--file-lines [{"file":"/"#),
+            "{}",
+            runs[1]
+        );
+        assert!(
+            runs[1].contains(r#"/src/lib.rs","range":[1,1]}]"#),
+            "{}",
+            runs[1]
+        );
+    }
+}
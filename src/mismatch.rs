@@ -0,0 +1,242 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::RustfmtVersion;
+
+/// The name rustfmt reports for input read from stdin.
+pub const STDIN_FILE_NAME: &str = "<stdin>";
+
+/// One entry of rustfmt's `--emit json` output.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FileMismatches {
+    pub name: String,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// A single hunk where rustfmt's output differs from the input.
+///
+/// Line numbers are 1-based and inclusive. When `original` is empty the hunk
+/// is a pure insertion before `original_begin_line`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Mismatch {
+    pub original_begin_line: usize,
+    pub original_end_line: usize,
+    pub expected_begin_line: usize,
+    pub expected_end_line: usize,
+    pub original: String,
+    pub expected: String,
+}
+
+/// The shapes rustfmt's `--emit json` output has had, each of which is
+/// parsed into [`FileMismatches`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MismatchFormat {
+    /// Hunks with 1-based line ranges, as in [`Mismatch`].
+    Lines,
+}
+
+impl MismatchFormat {
+    /// The rustfmt versions (as semver requirements) emitting each format.
+    /// `--emit json` was added in 1.4.24, and rustfmt 2 is unknown territory.
+    pub const SUPPORTED_VERSIONS: [(&'static str, Self); 1] = [(">=1.4.24, <2", Self::Lines)];
+
+    /// The format `version` emits, or `None` if it isn't a supported version.
+    pub fn for_version(version: RustfmtVersion) -> Option<Self> {
+        Self::SUPPORTED_VERSIONS
+            .into_iter()
+            .find(|(requirement, _)| version.meets_requirement(requirement) == Some(true))
+            .map(|(_, format)| format)
+    }
+
+    /// Parses rustfmt's stdout, which can have more than the JSON in it (some
+    /// versions print warnings there too), or have been cut short: the
+    /// entries whole enough to parse are salvaged from it, and
+    /// it's an error if there are none.
+    pub fn parse(self, json: &str) -> serde_json::Result<Vec<FileMismatches>> {
+        match self {
+            Self::Lines => serde_json::from_str(json).or_else(|error| salvage(json).ok_or(error)),
+        }
+    }
+}
+
+/// The entries of the JSON array in `output` (preferring the first with any
+/// that parse, since other output can have brackets in it too), up to the
+/// first that doesn't parse. The rest of `output` is logged.
+fn salvage<T: DeserializeOwned>(output: &str) -> Option<Vec<T>> {
+    let mut arrays = output.match_indices('[').filter_map(|(start, _)| {
+        let (entries, len) = salvage_array(&output[start..])?;
+        Some((entries, start..start + len))
+    });
+    let first = arrays.next()?;
+    let (entries, range) = if first.0.is_empty() {
+        arrays
+            .find(|(entries, _)| !entries.is_empty())
+            .unwrap_or(first)
+    } else {
+        first
+    };
+    let ignored = [output[..range.start].trim(), output[range.end..].trim()];
+    log::warn!("ignoring rustfmt output around its mismatches: {ignored:?}");
+    Some(entries)
+}
+
+/// The entries at the start of `array` (after its `[`) that parse, and how
+/// many bytes they (and the `]`, if they're all of them) take up. `None` if
+/// none parse and the array isn't empty.
+fn salvage_array<T: DeserializeOwned>(array: &str) -> Option<(Vec<T>, usize)> {
+    let mut entries = Vec::new();
+    let mut len = 1;
+    loop {
+        len = array.len() - array[len..].trim_start().len();
+        if array[len..].starts_with(']') {
+            return Some((entries, len + 1));
+        }
+        if !entries.is_empty() {
+            if !array[len..].starts_with(',') {
+                break;
+            }
+            len += 1;
+        }
+        let mut values = serde_json::Deserializer::from_str(&array[len..]).into_iter::<T>();
+        match values.next() {
+            Some(Ok(entry)) => {
+                entries.push(entry);
+                len += values.byte_offset();
+            }
+            _ => break,
+        }
+    }
+    (!entries.is_empty()).then_some((entries, len))
+}
+
+/// Parses output in the format of current rustfmts. See [`MismatchFormat`]
+/// for other versions.
+pub fn parse_mismatches(json: &str) -> serde_json::Result<Vec<FileMismatches>> {
+    MismatchFormat::Lines.parse(json)
+}
+
+/// The mismatches in the formatted input itself. Depending on the config,
+/// rustfmt can also report entries for the files of out-of-line child
+/// modules (`mod foo;`), which aren't part of the buffer being checked and
+/// are skipped.
+pub fn stdin_mismatches(files: Vec<FileMismatches>) -> impl Iterator<Item = Mismatch> {
+    files.into_iter().flat_map(|file| {
+        if file.name == STDIN_FILE_NAME {
+            file.mismatches
+        } else {
+            log::debug!(
+                "skipping {} rustfmt mismatches in {}",
+                file.mismatches.len(),
+                file.name
+            );
+            Default::default()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mismatches() {
+        let parsed = parse_mismatches(
+            r#"[{"name":"<stdin>","mismatches":[{"original_begin_line":1,"original_end_line":2,"expected_begin_line":1,"expected_end_line":2,"original":"fn main( ) {\nlet x=1;\n","expected":"fn main() {\n    let x = 1;\n"}]}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            parsed,
+            vec![FileMismatches {
+                name: STDIN_FILE_NAME.to_owned(),
+                mismatches: vec![Mismatch {
+                    original_begin_line: 1,
+                    original_end_line: 2,
+                    expected_begin_line: 1,
+                    expected_end_line: 2,
+                    original: "fn main( ) {\nlet x=1;\n".to_owned(),
+                    expected: "fn main() {\n    let x = 1;\n".to_owned(),
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stdin_mismatches() {
+        // A child module's entry reported before stdin's.
+        let files = parse_mismatches(
+            r#"[{"name":"foo.rs","mismatches":[{"original_begin_line":1,"original_end_line":1,"expected_begin_line":1,"expected_end_line":1,"original":"fn  x() {}\n","expected":"fn x() {}\n"}]},{"name":"<stdin>","mismatches":[{"original_begin_line":2,"original_end_line":2,"expected_begin_line":2,"expected_end_line":2,"original":"fn  a() {}\n","expected":"fn a() {}\n"}]}]"#,
+        )
+        .unwrap();
+        let mismatches = stdin_mismatches(files).collect::<Vec<_>>();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].original, "fn  a() {}\n");
+
+        assert_eq!(stdin_mismatches(Default::default()).count(), 0);
+    }
+
+    #[test]
+    fn test_for_version() {
+        assert_eq!(
+            MismatchFormat::for_version(RustfmtVersion::new(1, 8, 0)),
+            Some(MismatchFormat::Lines)
+        );
+        assert_eq!(
+            MismatchFormat::for_version(RustfmtVersion::new(1, 4, 24)),
+            Some(MismatchFormat::Lines)
+        );
+        assert_eq!(
+            MismatchFormat::for_version(RustfmtVersion::new(1, 4, 23)),
+            None
+        );
+        assert_eq!(
+            MismatchFormat::for_version(RustfmtVersion::new(2, 0, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_mismatches_malformed() {
+        assert!(parse_mismatches("").is_err());
+        assert!(parse_mismatches("[{\"name\":\"<stdin>\"}]").is_err());
+        assert!(parse_mismatches(
+            r#"[{"name":"<stdin>","mismatches":[{"original_begin_line":-1,"original_end_line":2,"expected_begin_line":1,"expected_end_line":2,"original":"","expected":""}]}]"#,
+        )
+        .is_err());
+        assert!(parse_mismatches("warning: [unknown] isn't a setting\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_mismatches_salvaged() {
+        let file = |name: &str| {
+            format!(
+                r#"{{"name":"{name}","mismatches":[{{"original_begin_line":1,"original_end_line":1,"expected_begin_line":1,"expected_end_line":1,"original":"fn  a() {{}}\n","expected":"fn a() {{}}\n"}}]}}"#
+            )
+        };
+        let names = |json: &str| {
+            parse_mismatches(json)
+                .unwrap()
+                .into_iter()
+                .map(|file| file.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&format!(
+                "Warning: can't set `[unstable]` []\n[{},\n{}]\nWarning: done\n",
+                file("a.rs"),
+                file("<stdin>")
+            )),
+            ["a.rs", "<stdin>"]
+        );
+        assert_eq!(
+            names(&format!("[{},{}", file("a.rs"), &file("<stdin>")[..40])),
+            ["a.rs"]
+        );
+        assert_eq!(
+            names("Warning: nothing to format\n[]\n"),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::{borrow::Cow, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+/// The line endings of the rule's fixes, like rustfmt's `newline_style`
+/// setting. rustfmt's mismatches don't show line endings (it reports `\n`
+/// whatever the file has), so this is what decides them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum NewlineStyle {
+    /// The line ending of the file's first line (`\n` if it has none).
+    #[default]
+    Auto,
+    /// `\n`.
+    Unix,
+    /// `\r\n`.
+    Windows,
+}
+
+impl NewlineStyle {
+    /// The line ending this style means for `source`.
+    pub fn line_ending(self, source: &[u8]) -> &'static str {
+        match self {
+            Self::Auto => match source.iter().position(|&byte| byte == b'\n') {
+                Some(newline) if newline > 0 && source[newline - 1] == b'\r' => "\r\n",
+                _ => "\n",
+            },
+            Self::Unix => "\n",
+            Self::Windows => "\r\n",
+        }
+    }
+}
+
+/// `text` with all its line endings (`\n` or `\r\n`) replaced by
+/// `line_ending`.
+pub(crate) fn with_line_endings<'a>(text: Cow<'a, str>, line_ending: &str) -> Cow<'a, str> {
+    let is_consistent = text
+        .match_indices('\n')
+        .all(|(newline, _)| text[..newline].ends_with('\r') == (line_ending == "\r\n"));
+    if is_consistent {
+        return text;
+    }
+    Cow::Owned(text.replace("\r\n", "\n").replace('\n', line_ending))
+}
+
+/// The byte ranges of the line endings in `source` that aren't
+/// `line_ending`.
+pub(crate) fn inconsistent_line_endings(source: &[u8], line_ending: &str) -> Vec<Range<usize>> {
+    source
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| byte == b'\n')
+        .filter_map(|(newline, _)| {
+            let range = if newline > 0 && source[newline - 1] == b'\r' {
+                newline - 1..newline + 1
+            } else {
+                newline..newline + 1
+            };
+            (source[range.clone()] != *line_ending.as_bytes()).then_some(range)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_ending() {
+        assert_eq!(NewlineStyle::Auto.line_ending(b"a\r\nb\n"), "\r\n");
+        assert_eq!(NewlineStyle::Auto.line_ending(b"a\nb\r\n"), "\n");
+        assert_eq!(NewlineStyle::Auto.line_ending(b"a"), "\n");
+        assert_eq!(NewlineStyle::Unix.line_ending(b"a\r\n"), "\n");
+        assert_eq!(NewlineStyle::Windows.line_ending(b"a\n"), "\r\n");
+    }
+
+    #[test]
+    fn test_with_line_endings() {
+        assert!(matches!(
+            with_line_endings("a\nb\n".into(), "\n"),
+            Cow::Borrowed("a\nb\n")
+        ));
+        assert_eq!(with_line_endings("a\r\nb\n".into(), "\n"), "a\nb\n");
+        assert_eq!(with_line_endings("a\r\nb\n".into(), "\r\n"), "a\r\nb\r\n");
+        assert_eq!(with_line_endings("a".into(), "\r\n"), "a");
+    }
+
+    #[test]
+    fn test_inconsistent_line_endings() {
+        let source = b"a\r\nb\nc\r\n\n";
+        assert_eq!(inconsistent_line_endings(source, "\r\n"), [4..5, 8..9]);
+        assert_eq!(inconsistent_line_endings(source, "\n"), [1..3, 6..8]);
+    }
+}
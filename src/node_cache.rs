@@ -0,0 +1,149 @@
+use std::{fmt, ops::Range};
+
+use tree_sitter::Node;
+
+/// How many recent lookups a [`NodeCache`] remembers.
+const CAPACITY: usize = 16;
+
+/// Memoizes [`Node::descendant_for_byte_range()`] lookups in one tree.
+///
+/// Mismatches tend to cluster in a few items, so rather than descending from
+/// the root each time, a lookup starts from the smallest recently found node
+/// that strictly encloses the range (and therefore lies on the root's path to
+/// the result).
+///
+/// The tree can be stale (eg during rapid edits, when the host hasn't parsed
+/// the buffer again yet), so ranges are clamped to end within the root (and
+/// the buffer, for a cache made [`for_source()`](Self::for_source)), and
+/// ranges that still aren't in the tree are reported on the root node.
+pub(crate) struct NodeCache<'tree> {
+    root: Node<'tree>,
+    source_len: Option<usize>,
+    /// Most recent last.
+    found: Vec<(Range<usize>, Node<'tree>)>,
+    /// Whether the tree and buffer were found to disagree, which is only
+    /// warned about once.
+    warned: bool,
+}
+
+impl<'tree> NodeCache<'tree> {
+    pub fn new(root: Node<'tree>) -> Self {
+        Self {
+            root,
+            source_len: None,
+            found: Vec::with_capacity(CAPACITY),
+            warned: false,
+        }
+    }
+
+    /// A cache for looking up ranges in `source`, which `root` is the tree
+    /// of (or was, before it changed).
+    pub fn for_source(root: Node<'tree>, source: &[u8]) -> Self {
+        Self {
+            source_len: Some(source.len()),
+            ..Self::new(root)
+        }
+    }
+
+    pub fn descendant_for_byte_range(&mut self, range: Range<usize>) -> Node<'tree> {
+        let range = self.clamped(range);
+        if let Some((_, node)) = self.found.iter().find(|(found, _)| *found == range) {
+            return *node;
+        }
+        let start = self
+            .found
+            .iter()
+            .map(|(_, node)| *node)
+            .filter(|node| node.start_byte() < range.start && range.end < node.end_byte())
+            .min_by_key(|node| node.byte_range().len())
+            .unwrap_or(self.root);
+        let Some(node) = start.descendant_for_byte_range(range.start, range.end) else {
+            self.warn(format_args!(
+                "no node at bytes {}..{} of the syntax tree, reporting on the root node",
+                range.start, range.end
+            ));
+            return self.root;
+        };
+        if self.found.len() == CAPACITY {
+            self.found.remove(0);
+        }
+        self.found.push((range, node));
+        node
+    }
+
+    /// `range`, ending within both the root's extent and the buffer's.
+    fn clamped(&mut self, range: Range<usize>) -> Range<usize> {
+        let end = self
+            .source_len
+            .map_or(self.root.end_byte(), |len| len.min(self.root.end_byte()));
+        let start = range.start.min(end);
+        let clamped = start..range.end.clamp(start, end);
+        if clamped != range {
+            self.warn(format_args!(
+                "bytes {}..{} aren't in the syntax tree (bytes {}..{}) and buffer ({} bytes), clamping them to {}..{}",
+                range.start,
+                range.end,
+                self.root.start_byte(),
+                self.root.end_byte(),
+                self.source_len.map_or_else(|| "?".to_owned(), |len| len.to_string()),
+                clamped.start,
+                clamped.end
+            ));
+        }
+        clamped
+    }
+
+    fn warn(&mut self, message: fmt::Arguments) {
+        if !self.warned {
+            log::warn!("the syntax tree doesn't match the buffer: {message}");
+            self.warned = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    #[test]
+    fn test_node_cache() {
+        let source = "mod m {\n    fn a() {\n        b(c, d);\n    }\n}\n\nfn e() {}\n";
+        let tree = parse(source);
+        let root = tree.root_node();
+        let mut cache = NodeCache::new(root);
+        // Twice over, so that the second round starts from cached nodes.
+        for _ in 0..2 {
+            for start in 0..=source.len() {
+                for end in start..=source.len() {
+                    assert_eq!(
+                        Some(cache.descendant_for_byte_range(start..end)),
+                        root.descendant_for_byte_range(start, end),
+                        "{start}..{end}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stale_tree() {
+        // The tree of the buffer before `fn b() {}` was appended.
+        let tree = parse("fn a() {}\n");
+        let root = tree.root_node();
+        let source = b"fn a() {}\nfn b() {}\n";
+        let mut cache = NodeCache::for_source(root, source);
+        assert_eq!(cache.descendant_for_byte_range(13..14), root);
+        assert_eq!(cache.descendant_for_byte_range(30..40), root);
+        assert_eq!(cache.descendant_for_byte_range(3..4).kind(), "identifier");
+
+        // And of a longer one, with bytes past the buffer's end.
+        let tree = parse("fn a() {}\nfn b() {}\n");
+        let root = tree.root_node();
+        let mut cache = NodeCache::for_source(root, b"fn a() {}\n");
+        assert_eq!(
+            cache.descendant_for_byte_range(13..14),
+            cache.descendant_for_byte_range(10..10)
+        );
+    }
+}
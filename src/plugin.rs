@@ -0,0 +1,470 @@
+use tree_sitter::Tree;
+
+use crate::{
+    rules::RuleRegistry, BlankLinesRule, FileHeaderRule, FinalNewlineRule, Formatter,
+    FormatterRule, GroupImports, LanguageFormatters, OrganizeImportsRule,
+    OrganizeImportsRuleOptions, RunStatus, RustfmtRule, RustfmtRuleOptions, TrailingWhitespaceRule,
+    Violation, BLANK_LINES_RULE_NAME, FILE_HEADER_RULE_NAME, FINAL_NEWLINE_RULE_NAME,
+    FORMATTER_RULE_NAME, ORGANIZE_IMPORTS_RULE_NAME, RULE_NAME, RUST_LANGUAGE,
+    TRAILING_WHITESPACE_RULE_NAME,
+};
+
+/// The plugin's rules, each of which is enabled by being set, either to one
+/// of the presets or picking rules with a [`PluginBuilder`].
+///
+/// Fixes from different rules can overlap (eg rustfmt's and
+/// [`TrailingWhitespaceRule`]'s), so hosts should apply one rule's fixes at a
+/// time and check again. A [`FileHeaderRule`] needs a template, so no preset
+/// sets one.
+#[derive(Clone, Debug, Default)]
+pub struct Plugin {
+    pub file_header: Option<FileHeaderRule>,
+    pub rustfmt: Option<RustfmtRule>,
+    pub trailing_whitespace: Option<TrailingWhitespaceRule>,
+    pub final_newline: Option<FinalNewlineRule>,
+    pub blank_lines: Option<BlankLinesRule>,
+    pub organize_imports: Option<OrganizeImportsRule>,
+    /// The rules checking the files of languages other than Rust (see
+    /// [`PluginBuilder::with_language_formatter()`]).
+    pub language_formatters: LanguageFormatters,
+    /// Where [`check()`](Self::check) records the violations it finds.
+    pub run_status: Option<RunStatus>,
+}
+
+impl Plugin {
+    /// The names of all of the plugin's rules, in the order they're checked.
+    pub const RULE_NAMES: [&'static str; 6] = [
+        FILE_HEADER_RULE_NAME,
+        RULE_NAME,
+        TRAILING_WHITESPACE_RULE_NAME,
+        FINAL_NEWLINE_RULE_NAME,
+        BLANK_LINES_RULE_NAME,
+        ORGANIZE_IMPORTS_RULE_NAME,
+    ];
+
+    /// A plugin with no rules enabled, to add them to.
+    pub fn builder() -> PluginBuilder {
+        Default::default()
+    }
+
+    /// The names of the enabled rules, in the order they're checked.
+    pub fn rule_names(&self) -> Vec<&'static str> {
+        [
+            self.file_header.is_some(),
+            self.rustfmt.is_some(),
+            self.trailing_whitespace.is_some(),
+            self.final_newline.is_some(),
+            self.blank_lines.is_some(),
+            self.organize_imports.is_some(),
+        ]
+        .into_iter()
+        .zip(Self::RULE_NAMES)
+        .filter_map(|(is_enabled, rule_name)| is_enabled.then_some(rule_name))
+        .collect()
+    }
+
+    /// Just rustfmt.
+    pub fn minimal() -> Self {
+        Self {
+            rustfmt: Some(Default::default()),
+            ..Default::default()
+        }
+    }
+
+    /// rustfmt, plus the whitespace rules that still work when it can't be
+    /// run.
+    pub fn recommended() -> Self {
+        Self {
+            trailing_whitespace: Some(TrailingWhitespaceRule),
+            final_newline: Some(FinalNewlineRule),
+            ..Self::minimal()
+        }
+    }
+
+    /// The recommended rules with rustfmt's fixes verified and checked for
+    /// idempotency, plus exact blank lines between items and imports grouped
+    /// by `std`, external and local crates.
+    pub fn strict() -> Self {
+        Self {
+            rustfmt: Some(RustfmtRule::new(RustfmtRuleOptions {
+                verify_fixes: true,
+                check_idempotency: true,
+                ..Default::default()
+            })),
+            blank_lines: Some(Default::default()),
+            organize_imports: Some(OrganizeImportsRule::new(OrganizeImportsRuleOptions {
+                group_imports: GroupImports::StdExternalCrate,
+                ..Default::default()
+            })),
+            ..Self::recommended()
+        }
+    }
+
+    /// The languages the plugin checks files of: Rust, and the ones with a
+    /// [formatter](Self::language_formatters).
+    pub fn languages(&self) -> Vec<&str> {
+        self.language_formatters.languages()
+    }
+
+    /// The violations in a file of `language`: [`check()`](Self::check)'s
+    /// for Rust, or else those of the language's
+    /// [`FormatterRule`], which are reported as [`FORMATTER_RULE_NAME`]'s.
+    /// Languages without one have none.
+    pub fn check_language<'tree>(
+        &self,
+        language: &str,
+        source: &[u8],
+        tree: &'tree Tree,
+    ) -> Vec<(&'static str, Violation<'tree>)> {
+        if language == RUST_LANGUAGE {
+            return self.check(source, tree);
+        }
+        let Some(rule) = self.language_formatters.instantiate_for_language(language) else {
+            return Default::default();
+        };
+        let violations = rule.check(source, tree);
+        if let Some(run_status) = self.run_status.as_ref() {
+            run_status.record(FORMATTER_RULE_NAME, &violations);
+            run_status.record_file();
+        }
+        violations
+            .into_iter()
+            .map(|violation| (FORMATTER_RULE_NAME, violation))
+            .collect()
+    }
+
+    /// The violations of every enabled rule, with the names of the rules
+    /// that reported them. They're recorded in the
+    /// [`run_status`](Self::run_status) if there's one.
+    pub fn check<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+    ) -> Vec<(&'static str, Violation<'tree>)> {
+        let mut violations = Vec::new();
+        let mut extend = |rule_name, rule_violations: Vec<Violation<'tree>>| {
+            if let Some(run_status) = self.run_status.as_ref() {
+                run_status.record(rule_name, &rule_violations);
+            }
+            violations.extend(
+                rule_violations
+                    .into_iter()
+                    .map(|violation| (rule_name, violation)),
+            );
+        };
+        if let Some(rule) = self.file_header.as_ref() {
+            extend(FILE_HEADER_RULE_NAME, rule.check(source, tree));
+        }
+        if let Some(rule) = self.rustfmt.as_ref() {
+            extend(RULE_NAME, rule.check(source, tree));
+        }
+        if let Some(rule) = self.trailing_whitespace.as_ref() {
+            extend(TRAILING_WHITESPACE_RULE_NAME, rule.check(source, tree));
+        }
+        if let Some(rule) = self.final_newline.as_ref() {
+            extend(FINAL_NEWLINE_RULE_NAME, rule.check(source, tree));
+        }
+        if let Some(rule) = self.blank_lines.as_ref() {
+            extend(BLANK_LINES_RULE_NAME, rule.check(source, tree));
+        }
+        if let Some(rule) = self.organize_imports.as_ref() {
+            extend(ORGANIZE_IMPORTS_RULE_NAME, rule.check(source, tree));
+        }
+        if let Some(run_status) = self.run_status.as_ref() {
+            run_status.record_file();
+        }
+        violations
+    }
+}
+
+/// One of the plugin's rules, with its options.
+#[derive(Clone, Debug)]
+pub enum PluginRule {
+    FileHeader(FileHeaderRule),
+    Rustfmt(Box<RustfmtRule>),
+    TrailingWhitespace(TrailingWhitespaceRule),
+    FinalNewline(FinalNewlineRule),
+    BlankLines(BlankLinesRule),
+    OrganizeImports(OrganizeImportsRule),
+}
+
+impl PluginRule {
+    /// The rule called `name` (one of [`Plugin::RULE_NAMES`]) with its
+    /// default options, or `None` if there's no such rule or it has to be
+    /// configured (like [`FileHeaderRule`]'s template). See
+    /// [`RuleRegistry::instantiate()`].
+    pub fn named(name: &str) -> Option<Self> {
+        RuleRegistry::new().instantiate(name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FileHeader(_) => FILE_HEADER_RULE_NAME,
+            Self::Rustfmt(_) => RULE_NAME,
+            Self::TrailingWhitespace(_) => TRAILING_WHITESPACE_RULE_NAME,
+            Self::FinalNewline(_) => FINAL_NEWLINE_RULE_NAME,
+            Self::BlankLines(_) => BLANK_LINES_RULE_NAME,
+            Self::OrganizeImports(_) => ORGANIZE_IMPORTS_RULE_NAME,
+        }
+    }
+}
+
+macro_rules! impl_from_rule {
+    ($($rule:ident => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$rule> for PluginRule {
+                fn from(rule: $rule) -> Self {
+                    Self::$variant(rule)
+                }
+            }
+        )*
+    };
+}
+
+impl From<RustfmtRule> for PluginRule {
+    fn from(rule: RustfmtRule) -> Self {
+        Self::Rustfmt(Box::new(rule))
+    }
+}
+
+impl_from_rule!(
+    FileHeaderRule => FileHeader,
+    TrailingWhitespaceRule => TrailingWhitespace,
+    FinalNewlineRule => FinalNewline,
+    BlankLinesRule => BlankLines,
+    OrganizeImportsRule => OrganizeImports,
+);
+
+/// Builds a [`Plugin`] with just the rules a host wants, eg
+/// `PluginBuilder::default().with_rule(RustfmtRule::default())`. Adding a
+/// rule that's already there replaces it.
+#[derive(Clone, Debug, Default)]
+pub struct PluginBuilder {
+    plugin: Plugin,
+    registry: RuleRegistry,
+    max_concurrent_formatters: Option<usize>,
+}
+
+impl PluginBuilder {
+    pub fn with_rule(mut self, rule: impl Into<PluginRule>) -> Self {
+        let plugin = &mut self.plugin;
+        match rule.into() {
+            PluginRule::FileHeader(rule) => plugin.file_header = Some(rule),
+            PluginRule::Rustfmt(rule) => plugin.rustfmt = Some(*rule),
+            PluginRule::TrailingWhitespace(rule) => plugin.trailing_whitespace = Some(rule),
+            PluginRule::FinalNewline(rule) => plugin.final_newline = Some(rule),
+            PluginRule::BlankLines(rule) => plugin.blank_lines = Some(rule),
+            PluginRule::OrganizeImports(rule) => plugin.organize_imports = Some(rule),
+        }
+        self
+    }
+
+    /// Adds each of the rules called `names` with its default options in
+    /// the [registry](Self::registry), eg from a host's config. Fails with
+    /// the first name that can't be added.
+    pub fn with_rules_named<'name>(
+        self,
+        names: impl IntoIterator<Item = &'name str>,
+    ) -> Result<Self, &'name str> {
+        names.into_iter().try_fold(self, |builder, name| {
+            let rule = builder.registry.instantiate(name).ok_or(name)?;
+            Ok(builder.with_rule(rule))
+        })
+    }
+
+    /// The rules [`with_rules_named()`](Self::with_rules_named) adds by name
+    /// (the plugin's own, with their default options, unless it's given
+    /// another).
+    pub fn registry(mut self, registry: RuleRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Checks the files of `language` (as the host names it to
+    /// [`Plugin::check_language()`]) against `formatter`, eg a
+    /// [`CommandFormatter`](crate::CommandFormatter) running `prettier
+    /// --stdin-filepath x.ts`. Its output is all that's checked: none of
+    /// the Rust rules' handling applies (see [`FormatterRule`]).
+    pub fn with_language_formatter(
+        mut self,
+        language: impl Into<String>,
+        formatter: impl Formatter + 'static,
+    ) -> Self {
+        self.plugin
+            .language_formatters
+            .register(language, formatter);
+        self
+    }
+
+    /// Like [`with_language_formatter()`](Self::with_language_formatter)
+    /// with a rule of its own.
+    pub fn with_language_rule(mut self, language: impl Into<String>, rule: FormatterRule) -> Self {
+        self.plugin
+            .language_formatters
+            .register_rule(language, rule);
+        self
+    }
+
+    /// Limits how many rustfmts the plugin's rules run at once, across all
+    /// of the host's threads (eg when it lints files in parallel, which can
+    /// run a machine out of memory otherwise), whichever rules are added
+    /// before or after. See [`RustfmtOptions::max_concurrent_runs`](crate::RustfmtOptions::max_concurrent_runs).
+    pub fn max_concurrent_formatters(mut self, limit: usize) -> Self {
+        self.max_concurrent_formatters = Some(limit.max(1));
+        self
+    }
+
+    /// Records what the plugin checks in `run_status`, eg to exit with its
+    /// [`exit_code()`](RunStatus::exit_code) once the run is over.
+    pub fn run_status(mut self, run_status: RunStatus) -> Self {
+        self.plugin.run_status = Some(run_status);
+        self
+    }
+
+    pub fn build(mut self) -> Plugin {
+        if let Some(limit) = self.max_concurrent_formatters {
+            let plugin = &mut self.plugin;
+            for options in plugin
+                .rustfmt
+                .iter_mut()
+                .map(|rule| &mut rule.options.rustfmt)
+                .chain(
+                    plugin
+                        .language_formatters
+                        .rules_mut()
+                        .map(|rule| &mut rule.options),
+                )
+            {
+                options.max_concurrent_runs = Some(limit);
+            }
+        }
+        self.plugin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{parse, SqueezeSpaces},
+        UNEXPECTED_FORMATTING,
+    };
+
+    #[test]
+    fn test_presets() {
+        let source = "use crate::a;\nuse std::b;\nfn c() {} \nfn d() {}";
+        let tree = parse(source);
+        let rule_names = |plugin: Plugin| {
+            let mut rule_names = plugin
+                .check(source.as_bytes(), &tree)
+                .into_iter()
+                .map(|(rule_name, _)| rule_name)
+                .collect::<Vec<_>>();
+            rule_names.dedup();
+            rule_names
+        };
+        assert_eq!(rule_names(Plugin::minimal()), [RULE_NAME]);
+        assert_eq!(
+            rule_names(Plugin::recommended()),
+            [
+                RULE_NAME,
+                TRAILING_WHITESPACE_RULE_NAME,
+                FINAL_NEWLINE_RULE_NAME
+            ]
+        );
+        assert_eq!(
+            rule_names(Plugin::strict()),
+            [
+                RULE_NAME,
+                TRAILING_WHITESPACE_RULE_NAME,
+                FINAL_NEWLINE_RULE_NAME,
+                BLANK_LINES_RULE_NAME,
+                ORGANIZE_IMPORTS_RULE_NAME
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let plugin = Plugin::builder()
+            .with_rule(TrailingWhitespaceRule)
+            .with_rule(RustfmtRule::default())
+            .build();
+        assert_eq!(
+            plugin.rule_names(),
+            [RULE_NAME, TRAILING_WHITESPACE_RULE_NAME]
+        );
+
+        let plugin = PluginBuilder::default()
+            .with_rules_named([BLANK_LINES_RULE_NAME, FINAL_NEWLINE_RULE_NAME])
+            .unwrap()
+            .build();
+        assert_eq!(
+            plugin.rule_names(),
+            [FINAL_NEWLINE_RULE_NAME, BLANK_LINES_RULE_NAME]
+        );
+        assert_eq!(
+            PluginBuilder::default()
+                .with_rules_named([RULE_NAME, FILE_HEADER_RULE_NAME, "nope"])
+                .unwrap_err(),
+            FILE_HEADER_RULE_NAME
+        );
+        assert_eq!(Plugin::strict().rule_names(), &Plugin::RULE_NAMES[1..]);
+
+        let plugin = Plugin::builder()
+            .max_concurrent_formatters(4)
+            .with_rule(RustfmtRule::default())
+            .build();
+        assert_eq!(
+            plugin.rustfmt.unwrap().options.rustfmt.max_concurrent_runs,
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn test_language_formatter() {
+        let plugin = Plugin::builder()
+            .with_rule(TrailingWhitespaceRule)
+            .with_rule(RustfmtRule::default())
+            .with_language_formatter("text", SqueezeSpaces)
+            .max_concurrent_formatters(2)
+            .build();
+        assert_eq!(plugin.languages(), [RUST_LANGUAGE, "text"]);
+        assert_eq!(
+            plugin
+                .language_formatters
+                .instantiate_for_language("text")
+                .unwrap()
+                .options
+                .max_concurrent_runs,
+            Some(2)
+        );
+
+        // The rustfmt rule would leave the skipped item alone. The Rust
+        // grammar is the only one at hand, but the text's formatter rule
+        // doesn't look at node kinds.
+        let source = "#[rustfmt::skip]\nfn  a() {} \n";
+        let tree = parse(source);
+        let violations = plugin.check_language("text", source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        let (rule_name, violation) = &violations[0];
+        assert_eq!(*rule_name, FORMATTER_RULE_NAME);
+        assert_eq!(violation.message_id, UNEXPECTED_FORMATTING);
+        assert_eq!(
+            violation.fix.as_ref().unwrap().apply(source.as_bytes()),
+            b"#[rustfmt::skip]\nfn a() {} \n"
+        );
+
+        assert_eq!(
+            plugin
+                .check_language(RUST_LANGUAGE, source.as_bytes(), &tree)
+                .into_iter()
+                .map(|(rule_name, _)| rule_name)
+                .collect::<Vec<_>>(),
+            [TRAILING_WHITESPACE_RULE_NAME]
+        );
+        assert!(plugin
+            .check_language("python", source.as_bytes(), &tree)
+            .is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    process::Child,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    rustfmt::{communicate, RustfmtError, RustfmtOptions},
+    FileMismatches,
+};
+
+/// Keeps up to `size` rustfmt processes spawned ahead of time.
+///
+/// A rustfmt process only formats the single input it reads from stdin, so
+/// processes can't be reused. Instead each request takes an already-started
+/// process and immediately spawns its replacement, which moves process
+/// startup (including the rustup proxy's toolchain resolution) off of the
+/// request's critical path.
+#[derive(Debug)]
+pub struct RustfmtPool {
+    options: RustfmtOptions,
+    size: usize,
+    idle: Mutex<Vec<Child>>,
+}
+
+impl RustfmtPool {
+    pub fn new(options: RustfmtOptions, size: usize) -> Self {
+        let pool = Self {
+            options,
+            size,
+            idle: Default::default(),
+        };
+        pool.fill();
+        pool
+    }
+
+    pub fn options(&self) -> &RustfmtOptions {
+        &self.options
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn format(&self, source: &[u8]) -> Result<Vec<FileMismatches>, RustfmtError> {
+        let child = match self.take_healthy() {
+            Some(child) => child,
+            None => self.options.spawn()?,
+        };
+        self.fill();
+        communicate(child, source.into(), &self.options)
+    }
+
+    /// Tops the pool back up to `size` idle processes, replacing any that
+    /// have exited.
+    pub fn fill(&self) {
+        let mut idle = self.lock_idle();
+        idle.retain_mut(is_healthy);
+        while idle.len() < self.size {
+            match self.options.spawn() {
+                Ok(child) => idle.push(child),
+                // Leave the pool short, `format()` will spawn on demand and
+                // surface the error.
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn take_healthy(&self) -> Option<Child> {
+        let mut idle = self.lock_idle();
+        while let Some(mut child) = idle.pop() {
+            if is_healthy(&mut child) {
+                return Some(child);
+            }
+        }
+        None
+    }
+
+    fn lock_idle(&self) -> MutexGuard<'_, Vec<Child>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for RustfmtPool {
+    fn drop(&mut self) {
+        for mut child in self.lock_idle().drain(..) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn is_healthy(child: &mut Child) -> bool {
+    matches!(child.try_wait(), Ok(None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let pool = RustfmtPool::new(Default::default(), 2);
+        assert_eq!(pool.lock_idle().len(), 2);
+
+        for _ in 0..3 {
+            let files = pool.format(b"fn main( ) {}\n").unwrap();
+            assert_eq!(files[0].mismatches[0].expected, "fn main() {}\n");
+            assert_eq!(pool.lock_idle().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_restarts_exited_processes() {
+        let pool = RustfmtPool::new(Default::default(), 1);
+        for child in pool.lock_idle().iter_mut() {
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+
+        let files = pool.format(b"fn main( ) {}\n").unwrap();
+        assert_eq!(files[0].mismatches.len(), 1);
+        assert!(pool.lock_idle().iter_mut().all(is_healthy));
+    }
+
+    #[test]
+    fn test_size_zero() {
+        let pool = RustfmtPool::new(Default::default(), 0);
+        assert!(pool.format(b"fn main() {}\n").unwrap().is_empty());
+        assert!(pool.lock_idle().is_empty());
+    }
+}
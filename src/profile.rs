@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+/// A house style to format with, as a set of rustfmt settings passed via
+/// `--config` (so they override the config file's), for teams that would
+/// rather pick one through a single option than keep a `rustfmt.toml` in
+/// every crate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum StyleProfile {
+    /// rustfmt's own defaults (or the config file's settings).
+    #[default]
+    Default,
+    /// Fits more on a line: small constructs are kept on one line up to
+    /// `max_width`, and parameters, single-expression functions and `where`
+    /// clauses are packed together, without trailing commas.
+    Compact,
+    /// Breaks things up: whatever doesn't fit rustfmt's smallest widths gets
+    /// a line of its own, as do parameters and imports.
+    Tall,
+}
+
+impl StyleProfile {
+    pub const ALL: [Self; 3] = [Self::Default, Self::Compact, Self::Tall];
+
+    /// The profile with this [`name()`](Self::name).
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|profile| profile.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Compact => "compact",
+            Self::Tall => "tall",
+        }
+    }
+
+    /// The stable settings the profile sets, as `name=value`.
+    pub fn config_overrides(self) -> &'static [&'static str] {
+        match self {
+            Self::Default => &[],
+            Self::Compact => &["use_small_heuristics=Max", "fn_params_layout=Compressed"],
+            Self::Tall => &["use_small_heuristics=Off", "fn_params_layout=Vertical"],
+        }
+    }
+
+    /// The unstable settings the profile sets, which are only passed to
+    /// rustfmts that [run](crate::RustfmtOptions::supports_emit_json) with
+    /// `--unstable-features`.
+    pub fn unstable_config_overrides(self) -> &'static [&'static str] {
+        match self {
+            Self::Default => &[],
+            Self::Compact => &[
+                "fn_single_line=true",
+                "where_single_line=true",
+                "trailing_comma=Never",
+            ],
+            Self::Tall => &["imports_layout=Vertical"],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format_to_string, RustfmtOptions};
+
+    #[test]
+    fn test_named() {
+        for profile in StyleProfile::ALL {
+            assert_eq!(StyleProfile::named(profile.name()), Some(profile));
+        }
+        assert_eq!(StyleProfile::named("grande"), None);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let source = "fn a(b: u8, c: u8) -> u8 {\n    b + c\n}\n";
+        let format = |profile| {
+            format_to_string(
+                source.as_bytes(),
+                &RustfmtOptions {
+                    profile,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        assert_eq!(format(StyleProfile::Default), source);
+        assert_eq!(
+            format(StyleProfile::Compact),
+            "fn a(b: u8, c: u8) -> u8 { b + c }\n"
+        );
+        assert_eq!(
+            format(StyleProfile::Tall),
+            "fn a(\n    b: u8,\n    c: u8,\n) -> u8 {\n    b + c\n}\n"
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use std::ops::Range;
+
+use crate::Mismatch;
+
+/// Byte offsets of the start of every line in a buffer.
+///
+/// Lines are 1-based, matching rustfmt's mismatch line numbers. A buffer with
+/// `n` lines also has a start offset for line `n + 1`, which is the end of the
+/// buffer.
+///
+/// Lines end after their `\n`, so a `\r\n` line ending belongs to its line
+/// too. Nothing here assumes the buffer is valid UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineOffsets {
+    starts: Vec<usize>,
+    has_crlf: bool,
+}
+
+impl LineOffsets {
+    pub fn new(source: &[u8]) -> Self {
+        Self::from_chunks([source])
+    }
+
+    /// Builds the offsets for a buffer given as consecutive chunks (eg of a
+    /// rope), which may split `\r\n` line endings and UTF-8 sequences.
+    pub fn from_chunks<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let mut starts = vec![0];
+        let mut has_crlf = false;
+        let mut len = 0;
+        let mut last_byte = None;
+        for chunk in chunks {
+            for (index, &byte) in chunk.iter().enumerate() {
+                if byte == b'\n' {
+                    starts.push(len + index + 1);
+                    has_crlf |= last_byte == Some(b'\r');
+                }
+                last_byte = Some(byte);
+            }
+            len += chunk.len();
+        }
+        if last_byte.is_some_and(|byte| byte != b'\n') {
+            starts.push(len);
+        }
+        Self { starts, has_crlf }
+    }
+
+    /// Whether any line ends with `\r\n`.
+    pub fn has_crlf(&self) -> bool {
+        self.has_crlf
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.starts.get(line.checked_sub(1)?).copied()
+    }
+
+    /// The 1-based line containing the byte at `offset`. Offsets at or past the
+    /// end of the buffer belong to its last line (line 1 if it's empty).
+    pub fn line_of_offset(&self, offset: usize) -> usize {
+        self.starts
+            .partition_point(|&start| start <= offset)
+            .min(self.starts.len() - 1)
+            .max(1)
+    }
+
+    /// The bytes covered by the inclusive line range `begin_line..=end_line`,
+    /// including the final line's newline if it has one.
+    pub fn line_range_to_byte_range(
+        &self,
+        begin_line: usize,
+        end_line: usize,
+    ) -> Option<Range<usize>> {
+        if begin_line == 0 || begin_line > end_line || end_line > self.line_count() {
+            return None;
+        }
+        Some(self.line_start(begin_line)?..self.line_start(end_line + 1)?)
+    }
+
+    /// The byte offset of a 1-based line and (character) column as reported in
+    /// rustc diagnostics. Columns past the end of the line are clamped to
+    /// before its line ending. The offset is always at a character boundary;
+    /// each invalid UTF-8 sequence counts as one column, as it's displayed as
+    /// one replacement character.
+    pub fn position_to_byte_offset(
+        &self,
+        source: &[u8],
+        line: usize,
+        column: usize,
+    ) -> Option<usize> {
+        let line_start = self.line_start(line)?;
+        let line_end = self.line_start(line + 1).unwrap_or(line_start);
+        let line = &source[line_start..line_end];
+        let line = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .unwrap_or(line);
+        let mut column_starts = Vec::new();
+        let mut offset = 0;
+        for chunk in line.utf8_chunks() {
+            column_starts.extend(
+                chunk
+                    .valid()
+                    .char_indices()
+                    .map(|(char_offset, _)| offset + char_offset),
+            );
+            offset += chunk.valid().len();
+            if !chunk.invalid().is_empty() {
+                column_starts.push(offset);
+                offset += chunk.invalid().len();
+            }
+        }
+        Some(
+            line_start
+                + column_starts
+                    .get(column.saturating_sub(1))
+                    .copied()
+                    .unwrap_or(line.len()),
+        )
+    }
+
+    /// The bytes of the buffer that `mismatch` says should be replaced by its
+    /// `expected` text, or `None` if its line numbers don't fit this buffer.
+    pub fn mismatch_byte_range(&self, mismatch: &Mismatch) -> Option<Range<usize>> {
+        if mismatch.original.is_empty() {
+            let start = self.line_start(mismatch.original_begin_line)?;
+            return Some(start..start);
+        }
+        self.line_range_to_byte_range(mismatch.original_begin_line, mismatch.original_end_line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ropey::Rope;
+
+    use super::*;
+    use crate::RopeOrSlice;
+
+    fn mismatch(begin: usize, end: usize, original: &str) -> Mismatch {
+        Mismatch {
+            original_begin_line: begin,
+            original_end_line: end,
+            expected_begin_line: begin,
+            expected_end_line: end,
+            original: original.to_owned(),
+            expected: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_line_offsets() {
+        let line_offsets = LineOffsets::new(b"ab\ncd\n");
+        assert_eq!(line_offsets.line_count(), 2);
+        assert_eq!(line_offsets.line_start(0), None);
+        assert_eq!(line_offsets.line_start(1), Some(0));
+        assert_eq!(line_offsets.line_start(2), Some(3));
+        assert_eq!(line_offsets.line_start(3), Some(6));
+        assert_eq!(line_offsets.line_start(4), None);
+
+        let line_offsets = LineOffsets::new(b"ab\ncd");
+        assert_eq!(line_offsets.line_count(), 2);
+        assert_eq!(line_offsets.line_start(3), Some(5));
+
+        assert_eq!(LineOffsets::new(b"").line_count(), 0);
+    }
+
+    #[test]
+    fn test_from_chunks() {
+        for chunks in [
+            &[&b"ab\n"[..], b"cd\ne", b"", b"f"][..],
+            &[b"ab\ncd", b"\n", b"ef"],
+            &[b"ab\ncd\nef"],
+        ] {
+            let line_offsets = LineOffsets::from_chunks(chunks.iter().copied());
+            assert_eq!(line_offsets.starts, [0, 3, 6, 8]);
+            assert!(!line_offsets.has_crlf());
+        }
+        assert_eq!(LineOffsets::from_chunks([&b"ab\n"[..], b""]).starts, [0, 3]);
+    }
+
+    #[test]
+    fn test_line_of_offset() {
+        let line_offsets = LineOffsets::new(b"ab\ncd\n");
+        assert_eq!(line_offsets.line_of_offset(0), 1);
+        assert_eq!(line_offsets.line_of_offset(2), 1);
+        assert_eq!(line_offsets.line_of_offset(3), 2);
+        assert_eq!(line_offsets.line_of_offset(6), 2);
+
+        let line_offsets = LineOffsets::new(b"ab\ncd");
+        assert_eq!(line_offsets.line_of_offset(5), 2);
+        assert_eq!(LineOffsets::new(b"").line_of_offset(0), 1);
+    }
+
+    #[test]
+    fn test_line_range_to_byte_range() {
+        let line_offsets = LineOffsets::new("a\nébc\nd".as_bytes());
+        assert_eq!(line_offsets.line_range_to_byte_range(1, 1), Some(0..2));
+        assert_eq!(line_offsets.line_range_to_byte_range(2, 3), Some(2..8));
+        assert_eq!(line_offsets.line_range_to_byte_range(0, 1), None);
+        assert_eq!(line_offsets.line_range_to_byte_range(2, 1), None);
+        assert_eq!(line_offsets.line_range_to_byte_range(3, 4), None);
+    }
+
+    #[test]
+    fn test_position_to_byte_offset() {
+        let source = "fn main() {\n    let x = é(;\n}".as_bytes();
+        let line_offsets = LineOffsets::new(source);
+        assert_eq!(line_offsets.position_to_byte_offset(source, 1, 1), Some(0));
+        assert_eq!(
+            line_offsets.position_to_byte_offset(source, 2, 14),
+            Some(26)
+        );
+        assert_eq!(
+            line_offsets.position_to_byte_offset(source, 2, 99),
+            Some(28)
+        );
+        assert_eq!(line_offsets.position_to_byte_offset(source, 3, 2), Some(30));
+        assert_eq!(line_offsets.position_to_byte_offset(source, 4, 1), Some(30));
+        assert_eq!(line_offsets.position_to_byte_offset(source, 5, 1), None);
+
+        let source = b"a\xffb\r\n\xe9\xff\r\n";
+        let line_offsets = LineOffsets::new(source);
+        assert_eq!(line_offsets.position_to_byte_offset(source, 1, 2), Some(1));
+        assert_eq!(line_offsets.position_to_byte_offset(source, 1, 3), Some(2));
+        assert_eq!(line_offsets.position_to_byte_offset(source, 1, 9), Some(3));
+        // `\xe9\xff`: a truncated sequence followed by an invalid byte.
+        assert_eq!(line_offsets.position_to_byte_offset(source, 2, 2), Some(6));
+        assert_eq!(line_offsets.position_to_byte_offset(source, 2, 9), Some(7));
+    }
+
+    #[test]
+    fn test_crlf() {
+        let line_offsets = LineOffsets::new(b"ab\r\ncd\r\n");
+        assert!(line_offsets.has_crlf());
+        assert_eq!(line_offsets.line_range_to_byte_range(2, 2), Some(4..8));
+        // Split between chunks.
+        assert!(LineOffsets::from_chunks([&b"ab\r"[..], b"\ncd"]).has_crlf());
+        assert!(!LineOffsets::new(b"ab\r\rcd\n\r").has_crlf());
+    }
+
+    /// The slice and rope code paths agree, however the buffer is split.
+    #[test]
+    fn test_chunked_buffers() {
+        const PIECES: [&str; 6] = ["a", "\n", "\r", "\r\n", "é", "🦀"];
+        // A fixed xorshift sequence, so failures are reproducible.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+        for _ in 0..50 {
+            let len = next() % 3000;
+            let text = (0..len)
+                .map(|_| PIECES[next() % PIECES.len()])
+                .collect::<String>();
+            let expected = LineOffsets::new(text.as_bytes());
+            assert_eq!(expected.has_crlf(), text.contains("\r\n"));
+
+            let rope = Rope::from_str(&text);
+            assert_eq!(
+                LineOffsets::from_chunks(RopeOrSlice::Rope(&rope).chunks()),
+                expected
+            );
+            // Arbitrary byte splits, including inside `\r\n` and characters.
+            let (first, second) = text.as_bytes().split_at(next() % (text.len() + 1));
+            let (second, third) = second.split_at(next() % (second.len() + 1));
+            assert_eq!(LineOffsets::from_chunks([first, second, third]), expected);
+        }
+    }
+
+    #[test]
+    fn test_mismatch_byte_range() {
+        let line_offsets = LineOffsets::new(b"fn a() {}\n\n\nfn b() {}\n");
+        assert_eq!(
+            line_offsets.mismatch_byte_range(&mismatch(2, 3, "\n\n")),
+            Some(10..12)
+        );
+        assert_eq!(
+            line_offsets.mismatch_byte_range(&mismatch(5, 5, "")),
+            Some(22..22)
+        );
+        assert_eq!(line_offsets.mismatch_byte_range(&mismatch(6, 6, "")), None);
+        assert_eq!(
+            line_offsets.mismatch_byte_range(&mismatch(4, 9, "fn b() {}\n")),
+            None
+        );
+    }
+}
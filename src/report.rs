@@ -0,0 +1,113 @@
+use std::{path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Mismatch;
+
+/// Receives a [`FormattingReport`] for every full check of a file (see
+/// [`RustfmtRuleOptions::on_report`](crate::RustfmtRuleOptions::on_report)).
+pub type ReportCallback = Arc<dyn Fn(&FormattingReport) + Send + Sync>;
+
+/// What rustfmt made of a file, in a form other tools can consume (it
+/// serializes to JSON with serde).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FormattingReport {
+    /// The file the report is for, if the rule was told (see
+    /// [`RustfmtRule::check_as()`](crate::RustfmtRule::check_as)).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(flatten)]
+    pub status: FormattingStatus,
+    /// As rustfmt reported them, before skip regions and the rule's filter
+    /// are applied.
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FormattingStatus {
+    Clean,
+    Unformatted,
+    /// rustfmt couldn't format the file (or wasn't run because of an unmet
+    /// `required_version`).
+    Failed {
+        message: String,
+    },
+}
+
+impl FormattingReport {
+    pub fn is_clean(&self) -> bool {
+        self.status == FormattingStatus::Clean
+    }
+
+    pub(crate) fn clean() -> Self {
+        Self {
+            path: None,
+            status: FormattingStatus::Clean,
+            mismatches: Default::default(),
+        }
+    }
+
+    pub(crate) fn failed(message: String) -> Self {
+        Self {
+            path: None,
+            status: FormattingStatus::Failed { message },
+            mismatches: Default::default(),
+        }
+    }
+
+    pub(crate) fn from_mismatches(mismatches: Vec<Mismatch>) -> Self {
+        Self {
+            path: None,
+            status: if mismatches.is_empty() {
+                FormattingStatus::Clean
+            } else {
+                FormattingStatus::Unformatted
+            },
+            mismatches,
+        }
+    }
+}
+
+/// The rustfmt a rule runs and how it's set up, for working out why two
+/// machines (eg CI and a developer's) disagree about a file's formatting
+/// (see [`RustfmtRule::setup()`](crate::RustfmtRule::setup)). It serializes
+/// to JSON with serde.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RustfmtSetup {
+    /// The command run, before rustfmt's own arguments.
+    pub command: Vec<String>,
+    /// What rustfmt prints for `--version`, or `None` if it can't be run.
+    pub version: Option<String>,
+    pub toolchain: Option<String>,
+    /// The config file rustfmt is given or would find.
+    pub config_path: Option<PathBuf>,
+    /// The edition it's given, whether set or discovered.
+    pub edition: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let report = FormattingReport::failed("oops".to_owned());
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            json,
+            r#"{"status":"failed","message":"oops","mismatches":[]}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<FormattingReport>(&json).unwrap(),
+            report
+        );
+        assert_eq!(
+            serde_json::to_string(&FormattingReport::clean()).unwrap(),
+            r#"{"status":"clean","mismatches":[]}"#
+        );
+    }
+}
@@ -0,0 +1,3562 @@
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    env, fmt, fs, iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tree_sitter::{InputEdit, Node, Tree};
+
+use crate::{
+    anchor::{anchor_mismatch, AnchoredMismatch},
+    backend::{run_backend, FormatterBackend},
+    cache::{CacheKey, CleanCache, RecentResults},
+    category::MismatchCategory,
+    changed::ChangedLines,
+    changed_line_ranges,
+    chunk::{chunk_rows, run_chunked},
+    dedup::{FixAttempts, QueuedFixes},
+    diff::{
+        merge_overlapping, narrow_replacement, render_hunk, split_mismatch,
+        without_blank_line_changes,
+    },
+    discover_config, discover_edition,
+    doc_code::doc_code_violations,
+    drift::{FileStyle, StyleDrift},
+    edition::with_edition_fallback,
+    exclude::{has_generated_marker, is_excluded},
+    formatter::{Formatter, RustfmtFormatter},
+    hooks::{with_hooks, FormatHooks},
+    incremental::{enclosing_item, is_item_container},
+    latency::{line_width_violations, BudgetedCheck, PendingRuns},
+    line_ranges::{byte_range_to_rows, intersect, normalize, subtract},
+    max_width,
+    newline::{inconsistent_line_endings, with_line_endings},
+    node_cache::NodeCache,
+    parse_stderr_as,
+    report::{FormattingReport, ReportCallback, RustfmtSetup},
+    required_version,
+    scope::FormattingScope,
+    skip::{
+        attribute_skip_regions, is_file_skipped, overlaps, skip_regions,
+        skipped_macro_invocation_ranges,
+    },
+    stdin_mismatches,
+    verify::is_pure_reformat,
+    ConfigFingerprint, FileMismatches, LineOffsets, LineRangeContext, Mismatch, NewlineStyle,
+    RustfmtError, RustfmtOptions, RustfmtPool, SkipMacroInvocations, StderrDiagnostic,
+    SymlinkPolicy, TrailingWhitespaceRule, DEFAULT_MAX_WIDTH, STDIN_FILE_NAME,
+};
+
+pub const RULE_NAME: &str = "rustfmt";
+
+pub const UNEXPECTED_FORMATTING: &str = "unexpected_formatting";
+pub const RUSTFMT_FAILED: &str = "rustfmt_failed";
+pub const FORMATTING_FIX_REJECTED: &str = "formatting_fix_rejected";
+pub const RUSTFMT_VERSION_MISMATCH: &str = "rustfmt_version_mismatch";
+pub const UNSTABLE_FORMATTING: &str = "unstable_formatting";
+pub const COMMENT_FORMATTING: &str = "comment_formatting";
+pub const RUSTFMT_TIMED_OUT: &str = "rustfmt_timed_out";
+pub const LINE_OVERFLOW: &str = "line_overflow";
+pub const TRAILING_WHITESPACE_LEFT: &str = "trailing_whitespace_left";
+pub const LEFT_UNFORMATTED: &str = "left_unformatted";
+pub const PARTIALLY_FORMATTED: &str = "partially_formatted";
+pub const INCONSISTENT_LINE_ENDINGS: &str = "inconsistent_line_endings";
+pub const UNRESOLVABLE_FORMATTING_CONFLICT: &str = "unresolvable_formatting_conflict";
+pub const FILE_NEEDS_REFORMATTING: &str = "file_needs_reformatting";
+
+const VERBOSE_MESSAGE_MAX_LINES: usize = 10;
+
+/// Decides whether a mismatch should be reported. Receives the node the
+/// violation would be reported on.
+pub type MismatchFilter = Arc<dyn Fn(&Mismatch, Node) -> bool + Send + Sync>;
+
+/// Whether the rule offers fixes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RuleMode {
+    #[default]
+    Fix,
+    /// Only report mismatches, eg for CI.
+    Check,
+}
+
+/// Which node a violation is reported on, for hosts that key violations by
+/// node kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum NodeAnchoring {
+    /// The smallest node covering the mismatch.
+    #[default]
+    Smallest,
+    /// The statement (or else the item) enclosing that node.
+    Statement,
+    /// The item (a direct child of a file, `mod`, `impl` etc) enclosing that
+    /// node.
+    Item,
+    /// Always the root node.
+    Root,
+    /// The smallest named node covering what rustfmt changes, rather than
+    /// the whole lines of the mismatch, with a violation per item if the
+    /// change spans several. Only the first of those has the fix.
+    Changed,
+}
+
+/// What to do with mismatches that only change lines inside comments, eg
+/// trailing spaces in an ASCII diagram, or `//` comments that rustfmt
+/// rewraps with `wrap_comments`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CommentMismatches {
+    #[default]
+    Report,
+    /// Report them as `comment_formatting`, with this severity (see
+    /// [`RustfmtRule::severity_of()`]).
+    Downgrade(Severity),
+    Ignore,
+}
+
+/// How hosts should treat the rule's violations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    #[default]
+    Error,
+}
+
+#[derive(Clone, Default)]
+pub struct RustfmtRuleOptions {
+    pub rustfmt: RustfmtOptions,
+    /// The formatter to check against instead of rustfmt (see [`Formatter`]).
+    /// [`RustfmtRule::check_with_pool()`], [`RustfmtRule::check_within()`]
+    /// and `check_with_runtime()` only run rustfmt in their own way, and
+    /// doc comments are still formatted with rustfmt.
+    pub formatter: Option<Arc<dyn Formatter>>,
+    /// Adapts the source before it's formatted, and what the formatter
+    /// reports after (see [`FormatHooks`]). Doc comments' code isn't adapted.
+    pub format_hooks: Option<Arc<dyn FormatHooks>>,
+    pub mode: RuleMode,
+    pub severity: Severity,
+    pub node_anchoring: NodeAnchoring,
+    pub comment_mismatches: CommentMismatches,
+    /// Only format the lines of the nodes in this scope, leaving the rest of
+    /// the file as it is. [`RustfmtRule::check_with_pool()`] runs its own
+    /// rustfmt for scoped checks.
+    pub scope: Option<FormattingScope>,
+    /// Only format the lines that changed in the files checked with
+    /// [`RustfmtRule::check_as()`] (within the [`scope`](Self::scope), if
+    /// there's one too), eg to leave a legacy codebase's formatting alone
+    /// apart from what a branch touches. Files with no changed lines aren't
+    /// formatted at all.
+    pub changed_lines: Option<ChangedLines>,
+    /// Mismatches for which this returns `false` are not reported.
+    pub filter: Option<MismatchFilter>,
+    /// Attributes (by path, eg `sqlx::test` or `cynic::schema`) whose items
+    /// are left alone like ones marked `#[rustfmt::skip]`, eg because
+    /// rustfmt's formatting of what they're applied to differs between
+    /// versions. See [`attribute_skip_regions()`].
+    pub skip_attributes: Vec<String>,
+    /// Glob patterns (eg `src/proto/**` or `bindings.rs`) for files that
+    /// aren't checked at all, eg because they're generated. Only applies to
+    /// files whose path the rule is given (see [`RustfmtRule::excludes()`]).
+    pub exclude: Vec<String>,
+    /// Don't check files with an `@generated` marker in their first
+    /// [`GENERATED_MARKER_LINES`](crate::GENERATED_MARKER_LINES) lines.
+    pub exclude_generated: bool,
+    /// When checking specific lines, format at most this many (sorted) line
+    /// ranges per pass and defer the rest, to bound the latency of each pass.
+    pub max_ranges_per_pass: Option<usize>,
+    /// Leave blank lines as they are, only reporting (and fixing) what
+    /// rustfmt changes about the other lines, eg so that it doesn't collapse
+    /// runs of blank lines or remove them at the start of blocks. Only the
+    /// non-blank lines of each mismatch are compared, and changes to them are
+    /// made around the original blank lines.
+    pub keep_blank_lines: bool,
+    /// The line endings the fixes use.
+    pub newline_style: NewlineStyle,
+    /// Also report (and fix) each line ending that isn't the
+    /// [`newline_style`](Self::newline_style)'s as
+    /// `inconsistent_line_endings`, unless a formatting fix next to it
+    /// changes it already. Only done by full checks.
+    pub report_inconsistent_line_endings: bool,
+    /// Format files with more lines than this in chunks of about this many
+    /// lines, split between top-level items, with a rustfmt run each, since
+    /// rustfmt gets slow on very large files. Chunks are only split where a
+    /// blank line separates the items, and chunks without any of the lines
+    /// being checked aren't formatted at all.
+    pub chunk_lines: Option<usize>,
+    /// Split mismatches of more than this many lines (on either side) where
+    /// they change separate runs of lines, or reindent them, and report what's
+    /// still bigger without a fix, for hosts that reject (or can't usefully
+    /// show) big fixes, eg rustfmt reindenting a whole `impl`.
+    pub max_fix_lines: Option<usize>,
+    /// Report at most this many violations of rustfmt's mismatches in a
+    /// file, and the rest of the mismatches as one `file_needs_reformatting`
+    /// violation over the whole file, so that a file that's never been
+    /// formatted doesn't flood an editor with violations. Its fix is the
+    /// fixes of the mismatches it stands for, in one, so that what the other
+    /// options leave alone (skipped regions, unchecked lines etc) still is.
+    pub max_violations_per_file: Option<usize>,
+    /// Include a (trimmed) diff of each mismatch in its violation message.
+    pub verbose_messages: bool,
+    /// Report the mismatches of common kinds (see [`MismatchCategory`]) with
+    /// a message id of their own rather than `unexpected_formatting`, eg so
+    /// that some kinds can be allowed while others are enforced.
+    pub categorize_mismatches: bool,
+    /// Re-parse the result of each fix and only offer it if the code's tokens
+    /// and syntax tree structure are unchanged, reporting it as
+    /// `formatting_fix_rejected` otherwise.
+    pub verify_fixes: bool,
+    /// Run rustfmt again on the result of applying all fixes, and report the
+    /// regions it would still change as `unstable_formatting`, without a fix,
+    /// so that a fixing loop doesn't oscillate on rustfmt output that isn't
+    /// idempotent. Only done by [`RustfmtRule::check()`] and
+    /// [`RustfmtRule::check_with_pool()`].
+    pub check_idempotency: bool,
+    /// Also report (and fix) the Rust code blocks in `///` and `//!` doc
+    /// comments that rustfmt would format differently, which it only does
+    /// itself with the unstable `format_code_in_doc_comments` (so that being
+    /// set too makes for overlapping fixes). Only done by full checks.
+    pub format_doc_comments: bool,
+    /// When rustfmt can't parse a file in a way that might depend on its
+    /// edition (eg `async fn` in 2015, or `async` as a name in 2018 on),
+    /// format it as 2021, 2018 and then 2015 until one works. The edition
+    /// that worked is used for the file from then on.
+    pub edition_fallback: bool,
+    /// Skip rustfmt for sources it had no mismatches for before.
+    pub cache: Option<CleanCache>,
+    /// Don't report violations whose fix is already queued in the current
+    /// pass of a fixing loop (see [`QueuedFixes`]), and queue the fixes of
+    /// the ones that are reported.
+    pub queued_fixes: Option<QueuedFixes>,
+    /// Stop offering fixes that have been offered too many times in a fixing
+    /// loop already (see [`FixAttempts`]).
+    pub fix_attempts: Option<FixAttempts>,
+    /// Called with what rustfmt made of the file on every full check (not
+    /// [`RustfmtRule::check_lines()`]), eg so that other rules can tell
+    /// whether the file is formatted.
+    pub on_report: Option<ReportCallback>,
+    /// Keep the results of full checks for [`RustfmtRule::check_recent()`].
+    pub recent_results: Option<RecentResults>,
+    /// Records the [`FileStyle`] of every full check of a file with a path
+    /// (see [`RustfmtRule::check_as()`]), to find the ones that don't look
+    /// like the rest.
+    pub style_drift: Option<StyleDrift>,
+}
+
+impl fmt::Debug for RustfmtRuleOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RustfmtRuleOptions")
+            .field("rustfmt", &self.rustfmt)
+            .field(
+                "formatter",
+                &self.formatter.as_ref().map(|formatter| formatter.name()),
+            )
+            .field(
+                "format_hooks",
+                &self.format_hooks.as_ref().map(|_| "<hooks>"),
+            )
+            .field("mode", &self.mode)
+            .field("severity", &self.severity)
+            .field("node_anchoring", &self.node_anchoring)
+            .field("comment_mismatches", &self.comment_mismatches)
+            .field("scope", &self.scope)
+            .field("changed_lines", &self.changed_lines)
+            .field("filter", &self.filter.as_ref().map(|_| "<filter>"))
+            .field("skip_attributes", &self.skip_attributes)
+            .field("exclude", &self.exclude)
+            .field("exclude_generated", &self.exclude_generated)
+            .field("max_ranges_per_pass", &self.max_ranges_per_pass)
+            .field("keep_blank_lines", &self.keep_blank_lines)
+            .field("newline_style", &self.newline_style)
+            .field(
+                "report_inconsistent_line_endings",
+                &self.report_inconsistent_line_endings,
+            )
+            .field("max_fix_lines", &self.max_fix_lines)
+            .field("max_violations_per_file", &self.max_violations_per_file)
+            .field("verbose_messages", &self.verbose_messages)
+            .field("categorize_mismatches", &self.categorize_mismatches)
+            .field("verify_fixes", &self.verify_fixes)
+            .field("check_idempotency", &self.check_idempotency)
+            .field("format_doc_comments", &self.format_doc_comments)
+            .field("edition_fallback", &self.edition_fallback)
+            .field("cache", &self.cache)
+            .field("queued_fixes", &self.queued_fixes)
+            .field("fix_attempts", &self.fix_attempts)
+            .field("chunk_lines", &self.chunk_lines)
+            .field("on_report", &self.on_report.as_ref().map(|_| "<callback>"))
+            .field("recent_results", &self.recent_results)
+            .field("style_drift", &self.style_drift)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Fix {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl Fix {
+    /// This fix with its range moved `offset` bytes later.
+    pub fn shifted(self, offset: usize) -> Self {
+        Self {
+            range: self.range.start + offset..self.range.end + offset,
+            ..self
+        }
+    }
+
+    /// `source` with this fix applied.
+    pub fn apply(&self, source: &[u8]) -> Vec<u8> {
+        let mut fixed =
+            Vec::with_capacity(source.len() - self.range.len() + self.replacement.len());
+        fixed.extend_from_slice(&source[..self.range.start]);
+        fixed.extend_from_slice(self.replacement.as_bytes());
+        fixed.extend_from_slice(&source[self.range.end..]);
+        fixed
+    }
+
+    /// `source` with all of `fixes` applied. A fix overlapping one that
+    /// starts earlier is left out.
+    pub fn apply_all<'fix>(source: &[u8], fixes: impl IntoIterator<Item = &'fix Fix>) -> Vec<u8> {
+        let mut fixed = Vec::with_capacity(source.len());
+        let mut copied_up_to = 0;
+        for fix in Self::applicable(fixes) {
+            fixed.extend_from_slice(&source[copied_up_to..fix.range.start]);
+            fixed.extend_from_slice(fix.replacement.as_bytes());
+            copied_up_to = fix.range.end;
+        }
+        fixed.extend_from_slice(&source[copied_up_to..]);
+        fixed
+    }
+
+    /// The fixes [`apply_all()`](Self::apply_all) applies, in order.
+    fn applicable<'fix>(fixes: impl IntoIterator<Item = &'fix Fix>) -> Vec<&'fix Fix> {
+        let mut fixes = fixes.into_iter().collect::<Vec<_>>();
+        fixes.sort_by_key(|fix| (fix.range.start, fix.range.end));
+        let mut applicable = Vec::<&Fix>::with_capacity(fixes.len());
+        for fix in fixes {
+            if applicable
+                .last()
+                .is_none_or(|last| last.range.end <= fix.range.start)
+            {
+                applicable.push(fix);
+            }
+        }
+        applicable
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation<'tree> {
+    pub message_id: &'static str,
+    pub message: String,
+    pub node: Node<'tree>,
+    /// What to highlight: for rustfmt's mismatches, from the first to the
+    /// last character it changes, rather than the mismatch's whole lines.
+    pub range: Range<usize>,
+    pub fix: Option<Fix>,
+}
+
+impl Violation<'_> {
+    /// This violation with its range and fix moved `offset` bytes later, eg
+    /// from injected code to where it is in the host document. The node is
+    /// still the injected code's.
+    pub fn shifted(self, offset: usize) -> Self {
+        Self {
+            range: self.range.start + offset..self.range.end + offset,
+            fix: self.fix.map(|fix| fix.shifted(offset)),
+            ..self
+        }
+    }
+
+    pub fn data(&self) -> ViolationData {
+        ViolationData {
+            message_id: self.message_id.to_owned(),
+            message: self.message.clone(),
+            node_kind: self.node.kind().to_owned(),
+            range: self.range.clone(),
+            fix: self.fix.clone(),
+        }
+    }
+}
+
+/// A [`Violation`] with just the kind of its node, for handing to other
+/// tools, eg as JSON.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ViolationData {
+    pub message_id: String,
+    pub message: String,
+    pub node_kind: String,
+    pub range: Range<usize>,
+    pub fix: Option<Fix>,
+}
+
+/// The result of checking only some lines of a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialCheck<'tree> {
+    pub violations: Vec<Violation<'tree>>,
+    /// Line ranges that weren't formatted in this pass because of
+    /// [`RustfmtRuleOptions::max_ranges_per_pass`]. They should be checked in
+    /// the next pass.
+    pub deferred: Vec<Range<usize>>,
+}
+
+/// Reports (and fixes) every region of a file that rustfmt would change.
+#[derive(Clone, Debug, Default)]
+pub struct RustfmtRule {
+    pub options: RustfmtRuleOptions,
+}
+
+impl RustfmtRule {
+    pub fn new(options: RustfmtRuleOptions) -> Self {
+        Self { options }
+    }
+
+    /// The severity hosts should report this rule's violations with.
+    pub fn severity(&self) -> Severity {
+        self.options.severity
+    }
+
+    /// The severity hosts should report `violation` with, which is lower for
+    /// [downgraded](CommentMismatches::Downgrade) comment mismatches.
+    pub fn severity_of(&self, violation: &Violation) -> Severity {
+        match self.options.comment_mismatches {
+            CommentMismatches::Downgrade(severity)
+                if violation.message_id == COMMENT_FORMATTING =>
+            {
+                severity
+            }
+            _ => self.options.severity,
+        }
+    }
+
+    pub fn check<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        self.check_with(source, tree, |source, rustfmt_options, lines| {
+            self.run_formatter(source, rustfmt_options, lines)
+        })
+    }
+
+    /// This rule with the rustfmt config and edition that apply to the file
+    /// at `path` (which needn't exist, eg for an unsaved buffer), since
+    /// rustfmt reading stdin only looks for them from the directory it runs in.
+    /// Settings that are the same as that directory's are left as
+    /// they are, so that a [`RustfmtPool`] set up for it still applies.
+    pub fn for_file(&self, path: &Path, symlinks: SymlinkPolicy) -> Cow<'_, Self> {
+        let options = &self.options.rustfmt;
+        let mut rustfmt = options.for_path(path, symlinks);
+        let cwd = options.working_dir();
+        if options.config_path.is_none()
+            && rustfmt.config_path == cwd.as_deref().and_then(discover_config)
+        {
+            rustfmt.config_path = None;
+        }
+        if options.edition.is_none() && rustfmt.edition == cwd.as_deref().and_then(discover_edition)
+        {
+            rustfmt.edition = None;
+        }
+        if rustfmt == *options {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(Self::new(RustfmtRuleOptions {
+            rustfmt,
+            ..self.options.clone()
+        }))
+    }
+
+    /// The rustfmt the rule runs and the config and edition it gives it,
+    /// which a host can log (or attach to its report) once per run. For a
+    /// file of its own, take the setup of its [`for_file()`](Self::for_file)
+    /// rule.
+    pub fn setup(&self) -> RustfmtSetup {
+        let (options, _) = self.resolve_rustfmt_options();
+        RustfmtSetup {
+            command: options.invocation(),
+            version: options.version_output().ok(),
+            toolchain: options.toolchain.clone(),
+            config_path: self.config_path(),
+            edition: options.edition.clone(),
+        }
+    }
+
+    /// Whether `path` matches one of the
+    /// [`exclude`](RustfmtRuleOptions::exclude) patterns. Relative paths (and
+    /// absolute ones under the current directory, which are made relative to
+    /// it) are matched as a whole, with `*` not crossing `/` but `**` crossing
+    /// any number of directories. Patterns without a `/` just match file
+    /// names.
+    pub fn excludes(&self, path: &Path) -> bool {
+        is_excluded(path, &self.options.exclude)
+    }
+
+    /// Like [`check()`](Self::check), for a buffer that would be at `path`
+    /// (see [`for_file()`](Self::for_file)). Reports have `path` set.
+    /// [Excluded](Self::excludes) files aren't checked (or reported).
+    pub fn check_as<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        path: &Path,
+    ) -> Vec<Violation<'tree>> {
+        if self.excludes(path) {
+            return Default::default();
+        }
+        let mut rule = self.for_file(path, SymlinkPolicy::Resolve);
+        if rule.options.rustfmt.file_name.is_none() {
+            rule.to_mut().options.rustfmt.file_name = Some(path.to_owned());
+        }
+        if let Some(on_report) = self.options.on_report.clone() {
+            let path = path.to_owned();
+            rule.to_mut().options.on_report = Some(Arc::new(move |report| {
+                on_report(&FormattingReport {
+                    path: Some(path.clone()),
+                    ..report.clone()
+                })
+            }));
+        }
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        if self.options.style_drift.is_some() {
+            let on_report = rule.options.on_report.clone();
+            let reports = reports.clone();
+            rule.to_mut().options.on_report = Some(Arc::new(move |report| {
+                reports
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(report.clone());
+                if let Some(on_report) = on_report.as_ref() {
+                    on_report(report);
+                }
+            }));
+        }
+        if let Some(changed_lines) = self.options.changed_lines.as_ref() {
+            let rows = changed_lines.rows(path);
+            let rows = match rule.options.scope.as_ref() {
+                Some(scope) => intersect(&scope.rows(tree, source), rows),
+                None => rows.to_vec(),
+            };
+            rule.to_mut().options.scope = Some(FormattingScope::Lines(rows));
+        }
+        let violations = rule.check(source, tree);
+        if let Some(style_drift) = self.options.style_drift.as_ref() {
+            let reports = reports.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(report) = reports.first() {
+                style_drift.record(FileStyle::new(path.to_owned(), source, tree, report));
+            }
+        }
+        violations
+    }
+
+    /// Like [`check()`](Self::check) without running rustfmt: reports what it
+    /// found on the last full check of the same source (see
+    /// [`RustfmtRuleOptions::recent_results`]), or `None` if there was none
+    /// (eg that rustfmt failed on), for hosts to check it in full, eg an
+    /// editor's diagnostic-only requests. Only rustfmt's mismatches and what
+    /// it couldn't format are reported (so not eg
+    /// [doc comment code](RustfmtRuleOptions::format_doc_comments)), and
+    /// fixes aren't tracked.
+    pub fn check_recent<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+    ) -> Option<Vec<Violation<'tree>>> {
+        if self.is_left_alone(source, tree) {
+            return Some(Default::default());
+        }
+        let recent_results = self.options.recent_results.as_ref()?;
+        let (rustfmt_options, emulated_skip_macro_invocations) = self.resolve_rustfmt_options();
+        let key = self.cache_key(&rustfmt_options, source)?;
+        if let Some(cache) = self.options.cache.as_ref() {
+            if cache.is_clean(&key) {
+                return Some(Default::default());
+            }
+        }
+        let (files, diagnostics) = recent_results.get(&key)?;
+        let rustfmt_result = if diagnostics.is_empty() {
+            Ok(files)
+        } else {
+            Err(RustfmtError::LeftUnformatted { files, diagnostics })
+        };
+        Some(self.violations(
+            source,
+            tree,
+            rustfmt_result,
+            &self.skip_regions(tree, source),
+            emulated_skip_macro_invocations,
+        ))
+    }
+
+    /// Like [`check()`](Self::check), for Rust code that a host document
+    /// (eg markdown, or a proc macro's virtual file) has injected at
+    /// `offset`: `source` and `tree` are just the injected code, and the
+    /// violations' ranges and fixes are [shifted](Violation::shifted) to the
+    /// host document's bytes.
+    pub fn check_injected<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        offset: usize,
+    ) -> Vec<Violation<'tree>> {
+        self.check(source, tree)
+            .into_iter()
+            .map(|violation| violation.shifted(offset))
+            .collect()
+    }
+
+    /// Like [`check()`](Self::check) but takes a warm rustfmt process from
+    /// `pool`, as long as the pool was set up with the options this rule runs
+    /// rustfmt with.
+    pub fn check_with_pool<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        pool: &RustfmtPool,
+    ) -> Vec<Violation<'tree>> {
+        self.check_with(source, tree, |source, rustfmt_options, lines| {
+            if lines.is_none()
+                && self.options.formatter.is_none()
+                && pool.options() == rustfmt_options
+            {
+                pool.format(source)
+            } else {
+                self.run_formatter(source, rustfmt_options, lines)
+            }
+        })
+    }
+
+    /// Like [`check()`](Self::check) but runs rustfmt through `backend`, eg a
+    /// [`MockBackend`](crate::MockBackend) for testing how the rule maps
+    /// rustfmt's output without a toolchain.
+    pub fn check_with_backend<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        backend: &dyn FormatterBackend,
+    ) -> Vec<Violation<'tree>> {
+        self.check_with(source, tree, |source, rustfmt_options, lines| {
+            run_backend(backend, source, rustfmt_options, lines)
+        })
+    }
+
+    /// Like [`check()`](Self::check), running rustfmt on `runtime` (see
+    /// [`run_rustfmt_async()`](crate::run_rustfmt_async)) for hosts that have
+    /// one anyway. Like [`Handle::block_on()`](tokio::runtime::Handle::block_on),
+    /// this mustn't be called from one of the runtime's tasks, and relies on
+    /// the runtime's worker threads (so a multi-threaded one) to drive
+    /// rustfmt's I/O. Other [formatters](RustfmtRuleOptions::formatter) are
+    /// run like by [`check()`](Self::check).
+    #[cfg(feature = "async")]
+    pub fn check_with_runtime<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        runtime: &tokio::runtime::Handle,
+    ) -> Vec<Violation<'tree>> {
+        if self.options.formatter.is_some() {
+            return self.check(source, tree);
+        }
+        self.check_with(source, tree, |source, rustfmt_options, lines| {
+            runtime.block_on(async {
+                match lines {
+                    None => crate::run_rustfmt_async(source, rustfmt_options).await,
+                    Some(lines) => {
+                        crate::run_rustfmt_on_lines_async(source, rustfmt_options, lines).await
+                    }
+                }
+            })
+        })
+    }
+
+    /// Like [`check()`](Self::check) for hosts (eg editors) that need an
+    /// answer within `budget`. If rustfmt takes longer, it's left running in
+    /// `pending`, and only trailing whitespace and lines longer than the
+    /// config's `max_width` are reported until a later pass over the same
+    /// source finds it finished. Passes keep waiting for it for up to
+    /// `budget`, so the file's reported as formatted as soon as rustfmt can
+    /// manage. Only rustfmt's first run over the file is budgeted (not eg
+    /// [idempotency checks](RustfmtRuleOptions::check_idempotency) or scoped
+    /// checks).
+    pub fn check_within<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        budget: Duration,
+        pending: &PendingRuns,
+    ) -> BudgetedCheck<'tree> {
+        if self.is_left_alone(source, tree) {
+            return BudgetedCheck {
+                violations: Default::default(),
+                deferred: false,
+            };
+        }
+        let (rustfmt_options, _) = self.resolve_rustfmt_options();
+        let is_cached = self
+            .cache_entry(&rustfmt_options, source)
+            .is_some_and(|(cache, key)| cache.is_clean(&key));
+        let mut rustfmt_result = None;
+        if self.options.scope.is_none()
+            && self.options.formatter.is_none()
+            && !is_cached
+            && self.version_mismatch_violation(tree).is_none()
+        {
+            match pending.run_within(source, &rustfmt_options, budget) {
+                Some(result) => rustfmt_result = Some(result),
+                None => {
+                    return BudgetedCheck {
+                        violations: self.cheap_violations(source, tree),
+                        deferred: true,
+                    }
+                }
+            }
+        }
+        let rustfmt_result = Cell::new(rustfmt_result);
+        BudgetedCheck {
+            violations: self.check_with(source, tree, |source, rustfmt_options, lines| {
+                rustfmt_result
+                    .take()
+                    .unwrap_or_else(|| self.run_formatter(source, rustfmt_options, lines))
+            }),
+            deferred: false,
+        }
+    }
+
+    /// What [`check_within()`](Self::check_within) reports while rustfmt is
+    /// still running.
+    fn cheap_violations<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        let max_width = self
+            .config_path()
+            .and_then(|config_path| fs::read_to_string(config_path).ok())
+            .and_then(|config| max_width(&config))
+            .unwrap_or(DEFAULT_MAX_WIDTH);
+        let mut violations = TrailingWhitespaceRule.check(source, tree);
+        if self.options.mode == RuleMode::Check {
+            for violation in &mut violations {
+                violation.fix = None;
+            }
+        }
+        violations.extend(line_width_violations(source, tree, max_width));
+        violations.sort_by_key(|violation| violation.range.start);
+        violations
+    }
+
+    fn check_with<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        run_rustfmt: impl Fn(
+            &[u8],
+            &RustfmtOptions,
+            Option<&[Range<usize>]>,
+        ) -> Result<Vec<FileMismatches>, RustfmtError>,
+    ) -> Vec<Violation<'tree>> {
+        if self.is_left_alone(source, tree) {
+            return Default::default();
+        }
+        if let Some(violation) = self.version_mismatch_violation(tree) {
+            self.report(|| FormattingReport::failed(violation.message.clone()));
+            return vec![violation];
+        }
+        let run_rustfmt =
+            |source: &[u8], rustfmt_options: &RustfmtOptions, lines: Option<&[Range<usize>]>| {
+                self.run_adapted(source, rustfmt_options, |source, rustfmt_options| {
+                    run_rustfmt(source, rustfmt_options, lines)
+                })
+            };
+        let (rustfmt_options, emulated_skip_macro_invocations) = self.resolve_rustfmt_options();
+        let cache_entry = self.cache_entry(&rustfmt_options, source);
+        if let Some((cache, key)) = cache_entry.as_ref() {
+            if cache.is_clean(key) {
+                self.report(FormattingReport::clean);
+                return Default::default();
+            }
+        }
+        let scope_rows = self
+            .options
+            .scope
+            .as_ref()
+            .map(|scope| scope.rows(tree, source));
+        let rustfmt_result = match (scope_rows.as_deref(), self.chunks(tree, source)) {
+            (Some([]), _) => Ok(Default::default()),
+            (rows, Some(chunks)) => {
+                run_chunked(source, &chunks, &rustfmt_options, rows, run_rustfmt)
+            }
+            (rows, None) => run_rustfmt(source, &rustfmt_options, rows),
+        };
+        let doc_code_violations = if self.options.format_doc_comments {
+            doc_code_violations(
+                source,
+                tree,
+                &rustfmt_options,
+                self.options.mode == RuleMode::Fix,
+            )
+        } else {
+            Default::default()
+        };
+        let line_ending_violations = if self.options.report_inconsistent_line_endings {
+            self.line_ending_violations(source, tree)
+        } else {
+            Default::default()
+        };
+        if let (Some(recent_results), None) =
+            (self.options.recent_results.as_ref(), scope_rows.as_ref())
+        {
+            let result = match rustfmt_result.as_ref() {
+                Ok(files) => Some((files.clone(), Default::default())),
+                Err(RustfmtError::LeftUnformatted { files, diagnostics }) => {
+                    Some((files.clone(), diagnostics.clone()))
+                }
+                Err(_) => None,
+            };
+            if let (Some(result), Some(key)) = (result, self.cache_key(&rustfmt_options, source)) {
+                recent_results.insert(key, result);
+            }
+        }
+        self.report(|| match rustfmt_result.as_ref() {
+            Ok(files) | Err(RustfmtError::LeftUnformatted { files, .. }) => {
+                FormattingReport::from_mismatches(stdin_mismatches(files.clone()).collect())
+            }
+            Err(error) => FormattingReport::failed(error.to_string()),
+        });
+        if let (Some((cache, key)), Ok(files)) = (cache_entry.as_ref(), rustfmt_result.as_ref()) {
+            let is_clean = doc_code_violations.is_empty()
+                && line_ending_violations.is_empty()
+                && files
+                    .iter()
+                    .all(|file| file.name != STDIN_FILE_NAME || file.mismatches.is_empty());
+            if is_clean {
+                if let Err(error) = cache.mark_clean(key) {
+                    log::warn!("couldn't write to {}: {error}", cache.dir().display());
+                }
+            }
+        }
+        let violations = self.violations(
+            source,
+            tree,
+            rustfmt_result,
+            &self.skip_regions(tree, source),
+            emulated_skip_macro_invocations,
+        );
+        let mut violations = if self.options.check_idempotency {
+            self.mark_unstable(source, tree, violations, |fixed| {
+                run_rustfmt(fixed, &rustfmt_options, None)
+            })
+        } else {
+            violations
+        };
+        // A formatting fix next to a line ending can change it too, eg by
+        // inserting the `\r` before its `\n`.
+        let line_ending_violations = line_ending_violations
+            .into_iter()
+            .filter(|line_ending| {
+                !violations.iter().any(|violation| {
+                    violation.fix.as_ref().is_some_and(|fix| {
+                        fix.range.start <= line_ending.range.end
+                            && line_ending.range.start <= fix.range.end
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        violations.extend(line_ending_violations);
+        violations.extend(doc_code_violations);
+        if let Some(scope_rows) = scope_rows {
+            // Whatever rustfmt changes outside the scope anyway, eg if it
+            // doesn't support `--file-lines` or when checking idempotency.
+            let line_offsets = LineOffsets::new(source);
+            violations.retain(|violation| {
+                let rows = byte_range_to_rows(&line_offsets, &violation.range);
+                violation.message_id == RUSTFMT_FAILED
+                    || scope_rows
+                        .iter()
+                        .any(|scope_rows| overlaps(&rows, scope_rows))
+            });
+        }
+        self.track_fixes(source, &mut violations);
+        violations
+    }
+
+    /// The line endings that aren't the
+    /// [`newline_style`](RustfmtRuleOptions::newline_style)'s.
+    fn line_ending_violations<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+    ) -> Vec<Violation<'tree>> {
+        let line_ending = self.options.newline_style.line_ending(source);
+        let message = format!(
+            "Expected {} line endings.",
+            if line_ending == "\r\n" { "CRLF" } else { "LF" }
+        );
+        let mut nodes = NodeCache::for_source(tree.root_node(), source);
+        inconsistent_line_endings(source, line_ending)
+            .into_iter()
+            .map(|range| Violation {
+                message_id: INCONSISTENT_LINE_ENDINGS,
+                message: message.clone(),
+                node: nodes.descendant_for_byte_range(range.clone()),
+                fix: (self.options.mode == RuleMode::Fix).then(|| Fix {
+                    range: range.clone(),
+                    replacement: line_ending.to_owned(),
+                }),
+                range,
+            })
+            .collect()
+    }
+
+    /// Applies [`queued_fixes`](RustfmtRuleOptions::queued_fixes) and
+    /// [`fix_attempts`](RustfmtRuleOptions::fix_attempts).
+    fn track_fixes(&self, source: &[u8], violations: &mut Vec<Violation<'_>>) {
+        if let Some(queued_fixes) = self.options.queued_fixes.as_ref() {
+            queued_fixes.retain_unqueued(source, violations);
+        }
+        if let Some(fix_attempts) = self.options.fix_attempts.as_ref() {
+            fix_attempts.escalate(source, violations);
+        }
+    }
+
+    fn report(&self, report: impl FnOnce() -> FormattingReport) {
+        if let Some(on_report) = self.options.on_report.as_ref() {
+            on_report(&report());
+        }
+    }
+
+    /// Turns the violations whose fixes rustfmt would change again once
+    /// they're all applied into `unstable_formatting` ones without a fix. Any
+    /// other region it would change is reported as unstable too.
+    fn mark_unstable<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        mut violations: Vec<Violation<'tree>>,
+        run_rustfmt: impl FnOnce(&[u8]) -> Result<Vec<FileMismatches>, RustfmtError>,
+    ) -> Vec<Violation<'tree>> {
+        let fixes = violations
+            .iter()
+            .filter_map(|violation| violation.fix.clone())
+            .collect::<Vec<_>>();
+        if fixes.is_empty() {
+            return violations;
+        }
+        let fixed = Fix::apply_all(source, &fixes);
+        let files = match run_rustfmt(&fixed) {
+            Ok(files) | Err(RustfmtError::LeftUnformatted { files, .. }) => files,
+            Err(error) => {
+                log::warn!("couldn't check that rustfmt's fixes are stable: {error}");
+                return violations;
+            }
+        };
+        let applied = Fix::applicable(&fixes);
+        let fixed_line_offsets = LineOffsets::new(&fixed);
+        let mut nodes = NodeCache::for_source(tree.root_node(), source);
+        for mismatch in self.mismatches(files) {
+            let Some(range) = fixed_line_offsets.mismatch_byte_range(&mismatch) else {
+                continue;
+            };
+            let unstable = unfixed_offset(&applied, range.start, false)
+                ..unfixed_offset(&applied, range.end, true);
+            let message = format!(
+                "rustfmt's formatting isn't stable here: formatting lines {}-{} again after fixing changes them further.",
+                mismatch.original_begin_line, mismatch.original_end_line
+            );
+            let mut is_reported = false;
+            for violation in violations
+                .iter_mut()
+                .filter(|violation| overlaps(&violation.range, &unstable))
+            {
+                violation.message_id = UNSTABLE_FORMATTING;
+                violation.message.clone_from(&message);
+                violation.fix = None;
+                is_reported = true;
+            }
+            if !is_reported {
+                violations.push(Violation {
+                    message_id: UNSTABLE_FORMATTING,
+                    message,
+                    node: nodes.descendant_for_byte_range(unstable.clone()),
+                    range: unstable,
+                    fix: None,
+                });
+            }
+        }
+        violations
+    }
+
+    /// Only checks the given 0-based half-open line ranges (eg the lines
+    /// touched since the last pass). Lines in skip regions (eg
+    /// `#[rustfmt::skip]` items, or after a disable comment) are left out.
+    /// Checks the whole file if rustfmt doesn't
+    /// [support](RustfmtOptions::supports_file_lines) `--file-lines` (or the
+    /// [formatter](RustfmtRuleOptions::formatter) can't
+    /// [format ranges](crate::FormatterCapabilities::format_ranges)), or if the lines
+    /// are most of it.
+    pub fn check_lines<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        lines: &[Range<usize>],
+    ) -> PartialCheck<'tree> {
+        if self.is_left_alone(source, tree) {
+            return PartialCheck {
+                violations: Default::default(),
+                deferred: Default::default(),
+            };
+        }
+        if !self
+            .formatter()
+            .capabilities(&self.options.rustfmt)
+            .format_ranges
+        {
+            return PartialCheck {
+                violations: self.check(source, tree),
+                deferred: Default::default(),
+            };
+        }
+        if let Some(violation) = self.version_mismatch_violation(tree) {
+            return PartialCheck {
+                violations: vec![violation],
+                deferred: Default::default(),
+            };
+        }
+        let (rustfmt_options, emulated_skip_macro_invocations) = self.resolve_rustfmt_options();
+        if let Some((cache, key)) = self.cache_entry(&rustfmt_options, source) {
+            if cache.is_clean(&key) {
+                return PartialCheck {
+                    violations: Default::default(),
+                    deferred: Default::default(),
+                };
+            }
+        }
+        let skip_regions = self.skip_regions(tree, source);
+        let line_offsets = LineOffsets::new(source);
+        // `None` if the whole file is formatted instead.
+        let mut lines = normalize(
+            &subtract(
+                lines,
+                &skip_regions
+                    .iter()
+                    .map(|region| byte_range_to_rows(&line_offsets, region))
+                    .collect::<Vec<_>>(),
+            ),
+            line_offsets.line_count(),
+        );
+        let deferred = match (lines.as_mut(), self.options.max_ranges_per_pass) {
+            (Some(lines), Some(max_ranges_per_pass)) if lines.len() > max_ranges_per_pass => {
+                lines.split_off(max_ranges_per_pass)
+            }
+            _ => Default::default(),
+        };
+        let mut violations = if lines.as_ref().is_some_and(Vec::is_empty) {
+            Default::default()
+        } else {
+            let rustfmt_result = match self.chunks(tree, source) {
+                Some(chunks) => run_chunked(
+                    source,
+                    &chunks,
+                    &rustfmt_options,
+                    lines.as_deref(),
+                    |source, rustfmt_options, lines| {
+                        self.run_adapted_formatter(source, rustfmt_options, lines)
+                    },
+                ),
+                None => self.run_adapted_formatter(source, &rustfmt_options, lines.as_deref()),
+            };
+            self.violations(
+                source,
+                tree,
+                rustfmt_result,
+                &skip_regions,
+                emulated_skip_macro_invocations,
+            )
+        };
+        self.track_fixes(source, &mut violations);
+        PartialCheck {
+            violations,
+            deferred,
+        }
+    }
+
+    /// The chunks to format `source` in, if it's split into several (see
+    /// [`chunk_lines`](RustfmtRuleOptions::chunk_lines)).
+    fn chunks(&self, tree: &Tree, source: &[u8]) -> Option<Vec<Range<usize>>> {
+        let chunks = chunk_rows(tree, source, self.options.chunk_lines?);
+        (chunks.len() > 1).then_some(chunks)
+    }
+
+    /// Whether `source` is left alone (without running rustfmt) because of
+    /// [`exclude_generated`](RustfmtRuleOptions::exclude_generated), or
+    /// because it's [`#![rustfmt::skip]`](is_file_skipped).
+    fn is_left_alone(&self, source: &[u8], tree: &Tree) -> bool {
+        (self.options.exclude_generated && has_generated_marker(source))
+            || is_file_skipped(tree, source)
+    }
+
+    /// Reported instead of formatting if the rustfmt config in effect has a
+    /// `required_version` that the installed rustfmt doesn't meet (rustfmt
+    /// would refuse to format the file anyway).
+    fn version_mismatch_violation<'tree>(&self, tree: &'tree Tree) -> Option<Violation<'tree>> {
+        if self.options.formatter.is_some() {
+            return None;
+        }
+        let options = &self.options.rustfmt;
+        let config_path = self.config_path()?;
+        let requirement = required_version(&fs::read_to_string(&config_path).ok()?)?;
+        let version = options.version().ok()?;
+        if version.meets_requirement(&requirement)? {
+            return None;
+        }
+        Some(Violation {
+            message_id: RUSTFMT_VERSION_MISMATCH,
+            message: format!(
+                "{} requires rustfmt {requirement}, but rustfmt {version} is installed.",
+                config_path.display()
+            ),
+            node: tree.root_node(),
+            range: 0..0,
+            fix: None,
+        })
+    }
+
+    /// The [formatter](RustfmtRuleOptions::formatter) the rule checks
+    /// against.
+    fn formatter(&self) -> &dyn Formatter {
+        self.options
+            .formatter
+            .as_deref()
+            .unwrap_or(&RustfmtFormatter)
+    }
+
+    /// Runs the formatter over `source`, only formatting `lines` if they're
+    /// given.
+    fn run_formatter(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        lines: Option<&[Range<usize>]>,
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        match lines {
+            None => self.formatter().format_full(source, options),
+            Some(lines) => self.formatter().format_ranges(source, options, lines),
+        }
+    }
+
+    /// [`run_formatter()`](Self::run_formatter) as
+    /// [adapted](Self::run_adapted), for checks that don't go through
+    /// `check_with()`.
+    fn run_adapted_formatter(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        lines: Option<&[Range<usize>]>,
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        self.run_adapted(source, options, |source, options| {
+            self.run_formatter(source, options, lines)
+        })
+    }
+
+    /// Runs `run` (which runs the formatter) with the
+    /// [`format_hooks`](RustfmtRuleOptions::format_hooks) and the
+    /// [`edition_fallback`](RustfmtRuleOptions::edition_fallback).
+    fn run_adapted(
+        &self,
+        source: &[u8],
+        options: &RustfmtOptions,
+        run: impl Fn(&[u8], &RustfmtOptions) -> Result<Vec<FileMismatches>, RustfmtError>,
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        with_hooks(self.options.format_hooks.as_deref(), source, |source| {
+            if self.options.edition_fallback {
+                with_edition_fallback(options, |options| run(source, options))
+            } else {
+                run(source, options)
+            }
+        })
+    }
+
+    /// The rustfmt config file rustfmt will use.
+    fn config_path(&self) -> Option<PathBuf> {
+        match self.options.rustfmt.config_path.as_ref() {
+            Some(config_path) if config_path.is_dir() => discover_config(config_path),
+            Some(config_path) => Some(config_path.clone()),
+            None => discover_config(&self.options.rustfmt.working_dir()?),
+        }
+    }
+
+    /// Where `source` would be found in the cache, if caching is enabled and
+    /// the installed rustfmt's version can be determined.
+    fn cache_entry(
+        &self,
+        rustfmt_options: &RustfmtOptions,
+        source: &[u8],
+    ) -> Option<(&CleanCache, CacheKey)> {
+        let cache = self.options.cache.as_ref()?;
+        Some((cache, self.cache_key(rustfmt_options, source)?))
+    }
+
+    /// The key of `source` in a [`CleanCache`] or [`RecentResults`], if the
+    /// installed rustfmt's version can be determined.
+    fn cache_key(&self, rustfmt_options: &RustfmtOptions, source: &[u8]) -> Option<CacheKey> {
+        let config_fingerprint =
+            ConfigFingerprint::new(rustfmt_options, self.config_path().as_deref()).ok()?;
+        // Everything besides the source that the violations depend on.
+        let fingerprint = format!(
+            "{} {} {config_fingerprint}\n{:?}\n{}\n{:?} {}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.formatter().name(),
+            self.options.scope,
+            self.options.format_doc_comments,
+            self.options.newline_style,
+            self.options.report_inconsistent_line_endings
+        );
+        Some(CleanCache::key(fingerprint.as_bytes(), source))
+    }
+
+    /// Only checks the innermost items enclosing `edits` (as passed to
+    /// [`Tree::edit()`] since the last pass), so that a keystroke in an editor
+    /// doesn't reformat the whole file. See [`changed_item_rows()`](crate::changed_item_rows), and
+    /// [`changed_line_ranges()`] for checking the last pass's violations
+    /// again too.
+    pub fn check_edits<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        edits: &[InputEdit],
+    ) -> PartialCheck<'tree> {
+        let context = LineRangeContext::new(source, tree, edits);
+        self.check_lines(source, tree, &changed_line_ranges(&context))
+    }
+
+    /// Like [`check_edits()`](Self::check_edits), for hosts that also have
+    /// the [`Tree::changed_ranges()`] between the previous tree and `tree`.
+    /// See [`changed_range_item_rows()`](crate::changed_range_item_rows).
+    pub fn check_changed_ranges<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        changed_ranges: &[tree_sitter::Range],
+        edits: &[InputEdit],
+    ) -> PartialCheck<'tree> {
+        let context = LineRangeContext {
+            changed_ranges: Some(changed_ranges),
+            ..LineRangeContext::new(source, tree, edits)
+        };
+        self.check_lines(source, tree, &changed_line_ranges(&context))
+    }
+
+    /// Fills in the [detected](discover_edition) edition if none is set, and
+    /// drops the settings the installed rustfmt can't be given, returning the
+    /// ones the rule has to emulate by filtering mismatches instead.
+    fn resolve_rustfmt_options(&self) -> (Cow<'_, RustfmtOptions>, Option<&SkipMacroInvocations>) {
+        let mut options = Cow::Borrowed(&self.options.rustfmt);
+        if options.edition.is_none() {
+            if let Some(edition) = options.working_dir().and_then(|dir| discover_edition(&dir)) {
+                options.to_mut().edition = Some(edition);
+            }
+        }
+        let Some(skip_macro_invocations) = self.options.rustfmt.skip_macro_invocations.as_ref()
+        else {
+            return (options, None);
+        };
+        let is_supported = skip_macro_invocations.config_value().is_some()
+            && options
+                .version()
+                .is_ok_and(|version| version >= SkipMacroInvocations::MIN_RUSTFMT_VERSION);
+        if is_supported {
+            return (options, None);
+        }
+        options.to_mut().skip_macro_invocations = None;
+        (options, Some(skip_macro_invocations))
+    }
+
+    /// The [skip regions](skip_regions), along with the items annotated with
+    /// [`skip_attributes`](RustfmtRuleOptions::skip_attributes).
+    fn skip_regions(&self, tree: &Tree, source: &[u8]) -> Vec<Range<usize>> {
+        let mut regions = skip_regions(tree, source);
+        regions.extend(attribute_skip_regions(
+            tree,
+            source,
+            &self.options.skip_attributes,
+        ));
+        regions
+    }
+
+    /// The mismatches in `files` to report, see
+    /// [`keep_blank_lines`](RustfmtRuleOptions::keep_blank_lines) and
+    /// [`max_fix_lines`](RustfmtRuleOptions::max_fix_lines).
+    fn mismatches(&self, files: Vec<FileMismatches>) -> impl Iterator<Item = Mismatch> {
+        let keep_blank_lines = self.options.keep_blank_lines;
+        let max_fix_lines = self.options.max_fix_lines;
+        merge_overlapping(stdin_mismatches(files).collect())
+            .into_iter()
+            .filter_map(move |mismatch| {
+                if keep_blank_lines {
+                    without_blank_line_changes(mismatch)
+                } else {
+                    Some(mismatch)
+                }
+            })
+            .flat_map(move |mismatch| match max_fix_lines {
+                Some(max_fix_lines) => split_mismatch(mismatch, max_fix_lines),
+                None => vec![mismatch],
+            })
+    }
+
+    /// Whether `mismatch` is small enough to fix, see
+    /// [`max_fix_lines`](RustfmtRuleOptions::max_fix_lines).
+    fn is_fixable(&self, mismatch: &Mismatch) -> bool {
+        self.options.max_fix_lines.is_none_or(|max_fix_lines| {
+            mismatch.original.lines().count() <= max_fix_lines.max(1)
+                && mismatch.expected.lines().count() <= max_fix_lines.max(1)
+        })
+    }
+
+    fn violations<'tree>(
+        &self,
+        source: &[u8],
+        tree: &'tree Tree,
+        rustfmt_result: Result<Vec<FileMismatches>, RustfmtError>,
+        skip_regions: &[Range<usize>],
+        emulated_skip_macro_invocations: Option<&SkipMacroInvocations>,
+    ) -> Vec<Violation<'tree>> {
+        let line_offsets = LineOffsets::new(source);
+        let (files, left_unformatted) = match rustfmt_result {
+            Ok(files) => (files, Default::default()),
+            Err(RustfmtError::LeftUnformatted { files, diagnostics }) => (files, diagnostics),
+            Err(error) => {
+                return error_violations(
+                    error,
+                    &self.options.rustfmt.stdin_file_name(),
+                    source,
+                    &line_offsets,
+                    tree,
+                )
+            }
+        };
+        // A whole mismatch gets dropped if it touches a skipped range, so
+        // surrounding lines in the same hunk go unreported too.
+        let mut skipped_ranges = skip_regions.to_vec();
+        if let Some(skip) = emulated_skip_macro_invocations {
+            skipped_ranges.extend(skipped_macro_invocation_ranges(tree, source, skip));
+        }
+        let line_ending = self.options.newline_style.line_ending(source);
+        let mut nodes = NodeCache::for_source(tree.root_node(), source);
+        // Each mismatch's, which `split_violation()` can make several of.
+        let mismatch_violations = self
+            .mismatches(files)
+            .filter_map(|mismatch| {
+                let anchored =
+                    anchor_mismatch(source, &line_offsets, &mismatch).map(|mut anchored| {
+                        anchored.expected = with_line_endings(anchored.expected, line_ending);
+                        anchored
+                    });
+                let range = match anchored.as_ref() {
+                    Some(anchored) => anchored.range.clone(),
+                    None => {
+                        log::warn!(
+                            "couldn't find the text rustfmt reported at lines {}-{}, not offering a fix",
+                            mismatch.original_begin_line,
+                            mismatch.original_end_line
+                        );
+                        line_offsets.mismatch_byte_range(&mismatch)?
+                    }
+                };
+                if skipped_ranges
+                    .iter()
+                    .any(|skipped| overlaps(&range, skipped))
+                {
+                    return None;
+                }
+                let mut anchors = match self.options.node_anchoring {
+                    NodeAnchoring::Changed => {
+                        let changed = anchored
+                            .as_ref()
+                            .map_or(range.clone(), |anchored| narrowed_fix(anchored).range);
+                        changed_anchors(&mut nodes, tree.root_node(), changed)
+                    }
+                    // Reported on the node of the mismatch's lines, but at
+                    // just what changes on them.
+                    anchoring => vec![(
+                        anchor_node(&mut nodes, tree.root_node(), source, &range, anchoring),
+                        anchored
+                            .as_ref()
+                            .map_or(range, |anchored| narrowed_fix(anchored).range),
+                    )],
+                }
+                .into_iter();
+                let (node, range) = anchors.next()?;
+                if let Some(filter) = self.options.filter.as_ref() {
+                    if !filter(&mismatch, node) {
+                        return None;
+                    }
+                }
+                let is_comment_only = self.options.comment_mismatches
+                    != CommentMismatches::Report
+                    && is_comment_only(source, &line_offsets, &mut nodes, &mismatch);
+                if is_comment_only && self.options.comment_mismatches == CommentMismatches::Ignore
+                {
+                    return None;
+                }
+                let fix = match self.options.mode {
+                    RuleMode::Fix if self.is_fixable(&mismatch) => {
+                        anchored.as_ref().map(narrowed_fix)
+                    }
+                    _ => None,
+                };
+                if self.options.verify_fixes
+                    && fix.as_ref().is_some_and(|fix| {
+                        !is_pure_reformat(tree.language(), source, &fix.apply(source))
+                    })
+                {
+                    let violation = Violation {
+                        message_id: FORMATTING_FIX_REJECTED,
+                        message: "Not applying rustfmt's fix because it would change more than formatting.".to_owned(),
+                        node,
+                        range,
+                        fix: None,
+                    };
+                    return Some(split_violation(violation, anchors));
+                }
+                let category = self
+                    .options
+                    .categorize_mismatches
+                    .then(|| MismatchCategory::classify(&mismatch))
+                    .flatten();
+                let (message_id, message) = match category {
+                    _ if is_comment_only => {
+                        (COMMENT_FORMATTING, "Unexpected formatting in a comment")
+                    }
+                    Some(category) => (category.message_id(), category.message()),
+                    None => (UNEXPECTED_FORMATTING, "Unexpected formatting"),
+                };
+                let violation = Violation {
+                    message_id,
+                    message: if self.options.verbose_messages {
+                        format!(
+                            "{message}:\n{}",
+                            render_hunk(&mismatch, Some(VERBOSE_MESSAGE_MAX_LINES))
+                        )
+                    } else {
+                        format!("{message}.")
+                    },
+                    node,
+                    fix,
+                    range,
+                };
+                Some(split_violation(violation, anchors))
+            })
+            .collect::<Vec<_>>();
+        let mut violations = match self.options.max_violations_per_file {
+            Some(max_violations) => {
+                self.collapse_violations(mismatch_violations, max_violations, source, tree)
+            }
+            None => mismatch_violations.into_iter().flatten().collect(),
+        };
+        let left_unformatted_violations = left_unformatted_violations(
+            &left_unformatted,
+            source,
+            &line_offsets,
+            &mut nodes,
+            &skipped_ranges,
+        );
+        let summary = partially_formatted_violation(
+            &left_unformatted,
+            &left_unformatted_violations,
+            &line_offsets,
+            tree,
+        );
+        violations.extend(left_unformatted_violations);
+        violations.extend(summary);
+        violations
+    }
+
+    /// Keeps the violations of as many mismatches as fit in
+    /// `max_violations` (all of each mismatch's, see `split_violation()`),
+    /// and replaces the rest with one `file_needs_reformatting` violation on
+    /// the whole file, whose fix is the rest's fixes in one.
+    fn collapse_violations<'tree>(
+        &self,
+        mismatch_violations: Vec<Vec<Violation<'tree>>>,
+        max_violations: usize,
+        source: &[u8],
+        tree: &'tree Tree,
+    ) -> Vec<Violation<'tree>> {
+        if mismatch_violations.iter().map(Vec::len).sum::<usize>() <= max_violations {
+            return mismatch_violations.into_iter().flatten().collect();
+        }
+        let mut violations = Vec::new();
+        let mut mismatches = mismatch_violations.into_iter().peekable();
+        while let Some(next) =
+            mismatches.next_if(|next| violations.len() + next.len() <= max_violations)
+        {
+            violations.extend(next);
+        }
+        let collapsed = mismatches.collect::<Vec<_>>();
+        let fix = merged_fix(
+            source,
+            collapsed
+                .iter()
+                .flatten()
+                .filter_map(|violation| violation.fix.as_ref()),
+        );
+        violations.push(Violation {
+            message_id: FILE_NEEDS_REFORMATTING,
+            message: format!(
+                "File requires full reformatting ({} more {}).",
+                collapsed.len(),
+                if collapsed.len() == 1 {
+                    "mismatch"
+                } else {
+                    "mismatches"
+                }
+            ),
+            node: tree.root_node(),
+            range: 0..source.len(),
+            fix,
+        });
+        violations
+    }
+}
+
+/// One fix doing all of `fixes` (but those [`Fix::apply_all()`] leaves out),
+/// from the first's start to the last's end, or `None` without any.
+fn merged_fix<'fix>(source: &[u8], fixes: impl IntoIterator<Item = &'fix Fix>) -> Option<Fix> {
+    let fixes = Fix::applicable(fixes);
+    let range = fixes.first()?.range.start..fixes.last()?.range.end;
+    let fixed = Fix::apply_all(source, fixes);
+    let replacement = &fixed[range.start..fixed.len() - (source.len() - range.end)];
+    Some(Fix {
+        range,
+        replacement: String::from_utf8(replacement.to_vec()).ok()?,
+    })
+}
+
+/// The node to report a mismatch at `range` on.
+fn anchor_node<'tree>(
+    nodes: &mut NodeCache<'tree>,
+    root: Node<'tree>,
+    source: &[u8],
+    range: &Range<usize>,
+    anchoring: NodeAnchoring,
+) -> Node<'tree> {
+    match anchoring {
+        // `Changed` anchors are found by `changed_anchors()` instead.
+        NodeAnchoring::Smallest | NodeAnchoring::Changed => {
+            return nodes.descendant_for_byte_range(range.clone())
+        }
+        NodeAnchoring::Root => return root,
+        NodeAnchoring::Statement | NodeAnchoring::Item => {}
+    }
+    // Mismatches are whole lines, whose indentation and line ending would
+    // otherwise make the smallest node the enclosing block or file.
+    let text = &source[range.clone()];
+    let start = range.start + text.len() - text.trim_ascii_start().len();
+    let end = start.max(range.end - (text.len() - text.trim_ascii_end().len()));
+    let node = nodes.descendant_for_byte_range(start..end);
+    let enclosing = match anchoring {
+        NodeAnchoring::Statement => enclosing_statement(node).or_else(|| enclosing_item(node)),
+        _ => enclosing_item(node),
+    };
+    enclosing.unwrap_or(root)
+}
+
+/// For [`NodeAnchoring::Changed`], the nodes to report the change in
+/// `changed` on, with the part of it each one covers.
+fn changed_anchors<'tree>(
+    nodes: &mut NodeCache<'tree>,
+    root: Node<'tree>,
+    changed: Range<usize>,
+) -> Vec<(Node<'tree>, Range<usize>)> {
+    // An insertion, eg of a space, is between the nodes on either side.
+    let changed = if changed.is_empty() {
+        changed.start.saturating_sub(1)..(changed.end + 1).min(root.end_byte())
+    } else {
+        changed
+    };
+    let node = nodes.descendant_for_byte_range(changed.clone());
+    if is_item_container(node) {
+        let items = node
+            .named_children(&mut node.walk())
+            .filter(|item| item.start_byte() < changed.end && changed.start < item.end_byte())
+            .collect::<Vec<_>>();
+        if !items.is_empty() {
+            return items
+                .into_iter()
+                .map(|item| {
+                    let part =
+                        changed.start.max(item.start_byte())..changed.end.min(item.end_byte());
+                    (named(nodes.descendant_for_byte_range(part.clone())), part)
+                })
+                .collect();
+        }
+    }
+    vec![(named(node), changed)]
+}
+
+fn named(node: Node) -> Node {
+    let mut node = node;
+    while !node.is_named() {
+        let Some(parent) = node.parent() else {
+            break;
+        };
+        node = parent;
+    }
+    node
+}
+
+/// `violation`, followed by copies of it for the rest of the nodes it's
+/// reported on. Only the first has the fix, which covers all of them.
+fn split_violation<'tree>(
+    violation: Violation<'tree>,
+    rest: impl Iterator<Item = (Node<'tree>, Range<usize>)>,
+) -> Vec<Violation<'tree>> {
+    let rest = rest
+        .map(|(node, range)| Violation {
+            node,
+            range,
+            fix: None,
+            ..violation.clone()
+        })
+        .collect::<Vec<_>>();
+    iter::once(violation).chain(rest).collect()
+}
+
+/// `node` itself or its closest ancestor that's directly inside a block.
+fn enclosing_statement(node: Node) -> Option<Node> {
+    let mut node = node;
+    loop {
+        let parent = node.parent()?;
+        if parent.kind() == "block" {
+            return Some(node);
+        }
+        node = parent;
+    }
+}
+
+/// Maps an offset in a buffer with `applied` fixes applied back to the
+/// original buffer. Offsets inside a fix's replacement map to the start (or,
+/// for the `end` of a range, the end) of the range it replaced.
+fn unfixed_offset(applied: &[&Fix], offset: usize, end: bool) -> usize {
+    let mut shift = 0;
+    for fix in applied {
+        let fixed_start = fix.range.start.wrapping_add_signed(shift);
+        if offset <= fixed_start {
+            break;
+        }
+        if offset < fixed_start + fix.replacement.len() {
+            return if end { fix.range.end } else { fix.range.start };
+        }
+        shift += fix.replacement.len() as isize - fix.range.len() as isize;
+    }
+    offset.wrapping_add_signed(-shift)
+}
+
+/// Whether the lines `mismatch` changes only have comments on them (besides
+/// whitespace), so that it doesn't touch any code. Comments that rustfmt
+/// rewraps (with `wrap_comments`) into more or fewer lines count too, as long
+/// as all of the lines were and stay `//` comments.
+fn is_comment_only(
+    source: &[u8],
+    line_offsets: &LineOffsets,
+    nodes: &mut NodeCache,
+    mismatch: &Mismatch,
+) -> bool {
+    let original = mismatch.original.lines().collect::<Vec<_>>();
+    let expected = mismatch.expected.lines().collect::<Vec<_>>();
+    let mut is_comment_line = |index| {
+        let line = mismatch.original_begin_line + index;
+        let Some(start) = line_offsets.line_start(line) else {
+            return false;
+        };
+        let end = line_offsets
+            .line_start(line + 1)
+            .map_or(source.len(), |end| end.min(source.len()));
+        let is_whitespace = |byte: &u8| byte.is_ascii_whitespace();
+        let span = match (
+            source[start..end]
+                .iter()
+                .position(|byte| !is_whitespace(byte)),
+            source[start..end]
+                .iter()
+                .rposition(|byte| !is_whitespace(byte)),
+        ) {
+            (Some(first), Some(last)) => start + first..start + last + 1,
+            _ => start..end,
+        };
+        let mut node = Some(nodes.descendant_for_byte_range(span.clone()));
+        while let Some(current) = node {
+            if matches!(current.kind(), "line_comment" | "block_comment") {
+                return current.start_byte() <= span.start && span.end <= current.end_byte();
+            }
+            node = current.parent();
+        }
+        false
+    };
+    if original.len() != expected.len() {
+        let is_rewrap = !original.is_empty()
+            && original
+                .iter()
+                .chain(&expected)
+                .all(|line| line.trim_start().starts_with("//"));
+        return is_rewrap && (0..original.len()).all(is_comment_line);
+    }
+    original
+        .iter()
+        .zip(&expected)
+        .enumerate()
+        .filter(|(_, (original, expected))| original != expected)
+        .all(|(index, _)| is_comment_line(index))
+}
+
+/// Limits the fix to the bytes that actually change, so that it's less likely
+/// to conflict with other rules' fixes.
+pub(crate) fn narrowed_fix(anchored: &AnchoredMismatch) -> Fix {
+    let (narrowed_range, replacement) = narrow_replacement(&anchored.original, &anchored.expected);
+    let start = anchored.range.start;
+    Fix {
+        range: start + narrowed_range.start..start + narrowed_range.end,
+        replacement: replacement.to_owned(),
+    }
+}
+
+/// The one place rustfmt errors are surfaced: failures caused by the file
+/// being checked are reported on it, anything else (rustfmt not being
+/// installed, broken pipes etc) is logged so that it doesn't get reported on
+/// every file of a run.
+fn error_violations<'tree>(
+    error: RustfmtError,
+    file_name: &str,
+    source: &[u8],
+    line_offsets: &LineOffsets,
+    tree: &'tree Tree,
+) -> Vec<Violation<'tree>> {
+    match error {
+        RustfmtError::NonZeroExit { stderr, .. } => {
+            rustfmt_failed_violations(&stderr, file_name, source, line_offsets, tree)
+        }
+        error @ RustfmtError::TimedOut(_) => vec![Violation {
+            message_id: RUSTFMT_TIMED_OUT,
+            message: format!("{error}, so the file wasn't checked."),
+            node: tree.root_node(),
+            range: 0..0,
+            fix: None,
+        }],
+        error => {
+            log::warn!("rustfmt failed: {error}");
+            Default::default()
+        }
+    }
+}
+
+/// Reports what rustfmt said it couldn't format (see
+/// [`RustfmtError::LeftUnformatted`]) from where it points to the end of that
+/// line, without a fix.
+fn left_unformatted_violations<'tree>(
+    diagnostics: &[StderrDiagnostic],
+    source: &[u8],
+    line_offsets: &LineOffsets,
+    nodes: &mut NodeCache<'tree>,
+    skipped_ranges: &[Range<usize>],
+) -> Vec<Violation<'tree>> {
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| {
+            let (line, column) = diagnostic.position?;
+            let start = line_offsets.position_to_byte_offset(source, line, column)?;
+            let line_end = source[start..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(source.len(), |length| start + length);
+            let end = if source[..line_end].ends_with(b"\r") {
+                line_end - 1
+            } else {
+                line_end
+            };
+            let range = start..end;
+            if skipped_ranges
+                .iter()
+                .any(|skipped| overlaps(&range, skipped))
+            {
+                return None;
+            }
+            let message_id = if diagnostic
+                .message
+                .starts_with("line formatted, but exceeded maximum width")
+            {
+                LINE_OVERFLOW
+            } else if diagnostic
+                .message
+                .starts_with("left behind trailing whitespace")
+            {
+                TRAILING_WHITESPACE_LEFT
+            } else {
+                LEFT_UNFORMATTED
+            };
+            Some(Violation {
+                message_id,
+                message: format!("rustfmt couldn't format this line: {}.", diagnostic.message),
+                node: nodes.descendant_for_byte_range(range.clone()),
+                range,
+                fix: None,
+            })
+        })
+        .collect()
+}
+
+/// Sums up the regions rustfmt couldn't format (see
+/// [`left_unformatted_violations()`]) at the start of the file, including the
+/// ones it didn't say where they are, since (eg for deeply nested or
+/// generated code) they're easily lost among its other changes.
+fn partially_formatted_violation<'tree>(
+    diagnostics: &[StderrDiagnostic],
+    violations: &[Violation],
+    line_offsets: &LineOffsets,
+    tree: &'tree Tree,
+) -> Option<Violation<'tree>> {
+    let mut regions = violations
+        .iter()
+        .map(|violation| {
+            format!(
+                "line {}",
+                byte_range_to_rows(line_offsets, &violation.range).start + 1
+            )
+        })
+        .collect::<Vec<_>>();
+    let unlocated = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.position.is_none())
+        .count();
+    if unlocated > 0 {
+        regions.push(format!(
+            "{unlocated} other region{}",
+            if unlocated == 1 { "" } else { "s" }
+        ));
+    }
+    if regions.is_empty() {
+        return None;
+    }
+    Some(Violation {
+        message_id: PARTIALLY_FORMATTED,
+        message: format!(
+            "rustfmt left some of the file unformatted ({}), so only its other changes are reported.",
+            regions.join(", ")
+        ),
+        node: tree.root_node(),
+        range: 0..0,
+        fix: None,
+    })
+}
+
+/// Reports each error rustfmt printed where it points into the file, or once
+/// at the start of the file if none of them do.
+fn rustfmt_failed_violations<'tree>(
+    stderr: &str,
+    file_name: &str,
+    source: &[u8],
+    line_offsets: &LineOffsets,
+    tree: &'tree Tree,
+) -> Vec<Violation<'tree>> {
+    let errors = parse_stderr_as(stderr, file_name)
+        .into_iter()
+        .filter(|diagnostic| diagnostic.level == "error")
+        .collect::<Vec<_>>();
+    let mut nodes = NodeCache::for_source(tree.root_node(), source);
+    let mut violations = errors
+        .iter()
+        .filter_map(|error| {
+            let (line, column) = error.position?;
+            let offset = line_offsets.position_to_byte_offset(source, line, column)?;
+            Some(Violation {
+                message_id: RUSTFMT_FAILED,
+                message: format!("rustfmt failed: {}", error.message),
+                node: nodes.descendant_for_byte_range(offset..offset),
+                range: offset..offset,
+                fix: None,
+            })
+        })
+        .collect::<Vec<_>>();
+    if violations.is_empty() {
+        violations.push(Violation {
+            message_id: RUSTFMT_FAILED,
+            message: match errors.first() {
+                Some(error) => format!("rustfmt failed: {}", error.message),
+                None => "rustfmt failed.".to_owned(),
+            },
+            node: tree.root_node(),
+            range: 0..0,
+            fix: None,
+        });
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::{
+        file_lines_args, format_to_string, parse_mismatches, test_utils::parse, FormattingStatus,
+        MockBackend, DOC_CODE_FORMATTING, TRAILING_WHITESPACE, UNORDERED_IMPORTS,
+        WRONG_INDENTATION,
+    };
+
+    #[test]
+    fn test_check() {
+        let source = "fn a() {}\nfn  b( ) {}\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::default().check(source.as_bytes(), &tree);
+
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert_eq!(violations[0].range, 13..17);
+        assert_eq!(violations[0].node.kind(), "source_file");
+        assert_eq!(
+            violations[0].fix,
+            Some(Fix {
+                range: 13..17,
+                replacement: "b(".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_with_pool() {
+        let source = "fn a() {}\nfn  b( ) {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::default();
+        let pool = RustfmtPool::new(rule.resolve_rustfmt_options().0.into_owned(), 1);
+        assert_eq!(
+            RustfmtRule::default().check_with_pool(source.as_bytes(), &tree, &pool),
+            RustfmtRule::default().check(source.as_bytes(), &tree)
+        );
+    }
+
+    #[test]
+    fn test_apply_all() {
+        let fix = |range: Range<usize>, replacement: &str| Fix {
+            range,
+            replacement: replacement.to_owned(),
+        };
+        assert_eq!(
+            Fix::apply_all(
+                b"abcdef",
+                &[
+                    fix(4..5, "E"),
+                    fix(0..2, "AB"),
+                    fix(1..3, "x"),
+                    fix(3..3, "-")
+                ]
+            ),
+            b"ABc-dEf"
+        );
+    }
+
+    #[test]
+    fn test_format_macro_bodies() {
+        let source = "macro_rules! m {
+    ( $a:expr , $b:ident ) => { foo( $a,$b ) };
+    ( $x:expr ) => {{ let v = vec![ $x ]; v }};
+}
+
+fn f() {
+    m!( 1,x );
+    let v = vec![aaaaaaaaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbbbbbbbbbbbb, cccccccccccccccccccccccccc, d];
+    println!(\"{}\", m!( 1 ));
+}
+";
+        let tree = parse(source);
+        for (format_macro_matchers, format_macro_bodies) in
+            [(Some(true), Some(true)), (Some(false), Some(false))]
+        {
+            let rustfmt = RustfmtOptions {
+                format_macro_matchers,
+                format_macro_bodies,
+                ..Default::default()
+            };
+            let rule = RustfmtRule::new(RustfmtRuleOptions {
+                rustfmt: rustfmt.clone(),
+                ..Default::default()
+            });
+            let violations = rule.check(source.as_bytes(), &tree);
+            let fixed = Fix::apply_all(
+                source.as_bytes(),
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref()),
+            );
+            assert_eq!(
+                String::from_utf8(fixed).unwrap(),
+                format_to_string(source.as_bytes(), &rustfmt).unwrap()
+            );
+            let macro_definition_untouched = violations
+                .iter()
+                .all(|violation| violation.range.start > source.find("fn f").unwrap());
+            assert_eq!(
+                macro_definition_untouched,
+                format_macro_bodies == Some(false)
+            );
+        }
+    }
+
+    #[test]
+    fn test_required_version() {
+        let source = "fn  a( ) {}\n";
+        let tree = parse(source);
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                config_path: Some(config_path.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        fs::write(&config_path, "required_version = \"0.0.1\"\n").unwrap();
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, RUSTFMT_VERSION_MISMATCH);
+        assert!(violations[0].message.contains("requires rustfmt 0.0.1"));
+
+        fs::write(&config_path, "required_version = \">=1\"\n").unwrap();
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+    }
+
+    #[test]
+    fn test_edition() {
+        let source = "fn f() {\n    foo(async move { 1 });\n}\n";
+        let tree = parse(source);
+        // This crate's own edition.
+        assert!(RustfmtRule::default()
+            .check(source.as_bytes(), &tree)
+            .is_empty());
+
+        let violations = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                edition: Some("2015".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, RUSTFMT_FAILED);
+    }
+
+    #[test]
+    fn test_setup() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        fs::write(&config_path, "").unwrap();
+        let setup = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                config_path: Some(dir.path().to_owned()),
+                edition: Some("2018".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .setup();
+        assert_eq!(setup.command, ["rustfmt", "+nightly"]);
+        assert!(setup.version.unwrap().starts_with("rustfmt 1."));
+        assert_eq!(setup.toolchain.as_deref(), Some("nightly"));
+        assert_eq!(setup.config_path, Some(config_path));
+        assert_eq!(setup.edition.as_deref(), Some("2018"));
+
+        let setup = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                command: Some("no-such-rustfmt".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .setup();
+        assert_eq!(setup.version, None);
+        // This crate's own edition.
+        assert_eq!(setup.edition.as_deref(), Some("2021"));
+    }
+
+    #[test]
+    fn test_check_edits() {
+        let source = "impl A {\n    fn  b( ) {}\n\n    fn  c( ) {}\n}\n";
+        let tree = parse(source);
+        // Typing the second space of `fn  c`.
+        let edit = InputEdit {
+            start_byte: 33,
+            old_end_byte: 33,
+            new_end_byte: 34,
+            start_position: Point::new(3, 7),
+            old_end_position: Point::new(3, 7),
+            new_end_position: Point::new(3, 8),
+        };
+        let check = RustfmtRule::default().check_edits(source.as_bytes(), &tree, &[edit]);
+        assert_eq!(check.violations.len(), 1);
+        assert_eq!(&source[check.violations[0].range.clone()], " c( ");
+    }
+
+    #[test]
+    fn test_check_changed_ranges() {
+        let source = "impl A {\n    fn  b( ) {}\n\n    fn  c( ) {}\n}\n";
+        let tree = parse(source);
+        let changed = tree_sitter::Range {
+            start_byte: 13,
+            end_byte: 23,
+            start_point: Point::new(1, 4),
+            end_point: Point::new(1, 14),
+        };
+        let check =
+            RustfmtRule::default().check_changed_ranges(source.as_bytes(), &tree, &[changed], &[]);
+        assert_eq!(check.violations.len(), 1);
+        assert_eq!(&source[check.violations[0].range.clone()], " b( ");
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        let source = "const  A: u8 = 1;\nfn a() {\n    b( );\n    c( );\n}\n";
+        let tree = parse(source);
+        let mut changed_lines = ChangedLines::new();
+        changed_lines.insert("src/a.rs", [0..1, 3..4]);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            changed_lines: Some(changed_lines),
+            ..Default::default()
+        });
+        let check_as = |rule: &RustfmtRule, path: &str| {
+            rule.check_as(source.as_bytes(), &tree, Path::new(path))
+                .iter()
+                .map(|violation| source[..violation.range.start].matches('\n').count())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(check_as(&rule, "src/a.rs"), [0, 3]);
+        assert!(check_as(&rule, "src/b.rs").is_empty());
+        assert_eq!(rule.check(source.as_bytes(), &tree).len(), 2);
+
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            scope: Some(FormattingScope::NodeKinds(vec!["block".to_owned()])),
+            ..rule.options.clone()
+        });
+        assert_eq!(check_as(&rule, "src/a.rs"), [3]);
+    }
+
+    #[test]
+    fn test_check_as() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("rustfmt.toml"), "tab_spaces = 2\n").unwrap();
+        let source = "fn a() {\n  b();\n}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::default();
+        assert_eq!(rule.check(source.as_bytes(), &tree).len(), 1);
+        assert_eq!(
+            rule.check_as(source.as_bytes(), &tree, &dir.path().join("unsaved.rs")),
+            []
+        );
+
+        let style_drift = StyleDrift::new();
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            style_drift: Some(style_drift.clone()),
+            ..Default::default()
+        });
+        let other_dir = tempfile::tempdir().unwrap();
+        rule.check_as(source.as_bytes(), &tree, &other_dir.path().join("a.rs"));
+        assert_eq!(style_drift.files()[0].indent_width, Some(2));
+    }
+
+    #[test]
+    fn test_check_recent() {
+        let source = "fn  a() {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            recent_results: Some(RecentResults::default()),
+            ..Default::default()
+        });
+        assert_eq!(rule.check_recent(source.as_bytes(), &tree), None);
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            rule.check_recent(source.as_bytes(), &tree),
+            Some(violations)
+        );
+
+        let edited = "fn  ab() {}\n";
+        assert_eq!(rule.check_recent(edited.as_bytes(), &parse(edited)), None);
+    }
+
+    #[test]
+    fn test_check_injected() {
+        let host = "# A\n\n```rust\nfn  a() {}\n```\n";
+        let offset = host.find("fn").unwrap();
+        let source = &host[offset..host.rfind("```").unwrap()];
+        let tree = parse(source);
+        let violations = RustfmtRule::default().check_injected(source.as_bytes(), &tree, offset);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(&host[violations[0].range.clone()], " ");
+        assert_eq!(
+            Fix::apply_all(
+                host.as_bytes(),
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref())
+            ),
+            b"# A\n\n```rust\nfn a() {}\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_for_file() {
+        let rule = RustfmtRule::default();
+        assert!(matches!(
+            rule.for_file(Path::new(file!()), SymlinkPolicy::Resolve),
+            Cow::Borrowed(_)
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        fs::write(nested.join("rustfmt.toml"), "").unwrap();
+        fs::write(
+            nested.join("Cargo.toml"),
+            "[package]\nname = \"nested\"\nedition = \"2018\"\n",
+        )
+        .unwrap();
+        let for_file = rule.for_file(&nested.join("src/lib.rs"), SymlinkPolicy::Resolve);
+        assert_eq!(
+            for_file.options.rustfmt.config_path,
+            Some(nested.join("rustfmt.toml"))
+        );
+        assert_eq!(for_file.options.rustfmt.edition.as_deref(), Some("2018"));
+    }
+
+    #[test]
+    fn test_scope() {
+        let source = "const  A: u8 = 1;\nfn a() {\n    b( );\n}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            scope: Some(FormattingScope::NodeKinds(vec!["block".to_owned()])),
+            ..Default::default()
+        });
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            String::from_utf8(violations[0].fix.as_ref().unwrap().apply(source.as_bytes()))
+                .unwrap(),
+            "const  A: u8 = 1;\nfn a() {\n    b();\n}\n"
+        );
+
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            scope: Some(FormattingScope::NodeKinds(vec!["struct_item".to_owned()])),
+            ..Default::default()
+        });
+        assert_eq!(rule.check(source.as_bytes(), &tree), []);
+    }
+
+    #[test]
+    fn test_comment_mismatches() {
+        // A comment, and code with a trailing comment.
+        let source = "fn a() {\n    // x  \n    c();\n    c();\n    b( ); // d  \n}\n";
+        let tree = parse(source);
+        let check = |comment_mismatches| {
+            let rule = RustfmtRule::new(RustfmtRuleOptions {
+                comment_mismatches,
+                ..Default::default()
+            });
+            let violations = rule.check(source.as_bytes(), &tree);
+            violations
+                .iter()
+                .map(|violation| (violation.message_id, rule.severity_of(violation)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            check(CommentMismatches::Report),
+            [
+                (UNEXPECTED_FORMATTING, Severity::Error),
+                (UNEXPECTED_FORMATTING, Severity::Error)
+            ]
+        );
+        assert_eq!(
+            check(CommentMismatches::Downgrade(Severity::Warning)),
+            [
+                (COMMENT_FORMATTING, Severity::Warning),
+                (UNEXPECTED_FORMATTING, Severity::Error)
+            ]
+        );
+        assert_eq!(
+            check(CommentMismatches::Ignore),
+            [(UNEXPECTED_FORMATTING, Severity::Error)]
+        );
+    }
+
+    #[test]
+    fn test_comment_mismatches_rewrapped() {
+        let source = "// A comment that rustfmt rewraps.\nfn a() {} // d\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            comment_mismatches: CommentMismatches::Ignore,
+            ..Default::default()
+        });
+        let mismatch = |original_end_line, original: &str, expected: &str| FileMismatches {
+            name: STDIN_FILE_NAME.to_owned(),
+            mismatches: vec![Mismatch {
+                original_begin_line: 1,
+                original_end_line,
+                expected_begin_line: 1,
+                expected_end_line: 2,
+                original: original.to_owned(),
+                expected: expected.to_owned(),
+            }],
+        };
+        let violations =
+            |mismatch| rule.violations(source.as_bytes(), &tree, Ok(vec![mismatch]), &[], None);
+        assert!(violations(mismatch(
+            1,
+            "// A comment that rustfmt rewraps.\n",
+            "// A comment that\n// rustfmt rewraps.\n"
+        ))
+        .is_empty());
+        // Rewrapping the comment into code's line isn't comment-only.
+        assert_eq!(
+            violations(mismatch(
+                2,
+                "// A comment that rustfmt rewraps.\nfn a() {} // d\n",
+                "// A comment that rustfmt\n// rewraps. fn a() {} // d\n"
+            ))
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_timed_out() {
+        let source = "fn a() {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                timeout: Some(Duration::from_nanos(1)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, RUSTFMT_TIMED_OUT);
+        assert_eq!(
+            violations[0].message,
+            "rustfmt didn't finish within 1ns, so the file wasn't checked."
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_check_with_runtime() {
+        let source = "fn a() {}\nfn  b( ) {}\n";
+        let tree = parse(source);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let rule = RustfmtRule::default();
+        assert_eq!(
+            rule.check_with_runtime(source.as_bytes(), &tree, runtime.handle()),
+            rule.check(source.as_bytes(), &tree)
+        );
+    }
+
+    #[test]
+    fn test_check_mode() {
+        let source = "fn a() {}\nfn  b( ) {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            mode: RuleMode::Check,
+            severity: Severity::Warning,
+            verify_fixes: true,
+            ..Default::default()
+        });
+        let violations = rule.check(source.as_bytes(), &tree);
+
+        assert_eq!(rule.severity(), Severity::Warning);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert_eq!(violations[0].fix, None);
+    }
+
+    #[test]
+    fn test_check_within() {
+        let source = "fn  a() {} \n";
+        let tree = parse(source);
+        let rule = RustfmtRule::default();
+        let pending = PendingRuns::default();
+        let check = |budget| {
+            let BudgetedCheck {
+                violations,
+                deferred,
+            } = rule.check_within(source.as_bytes(), &tree, budget, &pending);
+            (
+                violations
+                    .into_iter()
+                    .map(|violation| violation.message_id)
+                    .collect::<Vec<_>>(),
+                deferred,
+            )
+        };
+
+        assert_eq!(check(Duration::ZERO), (vec![TRAILING_WHITESPACE], true));
+        assert_eq!(
+            check(Duration::from_secs(60)),
+            (vec![UNEXPECTED_FORMATTING], false)
+        );
+    }
+
+    #[test]
+    fn test_crlf() {
+        let source = "fn a() {}\r\nfn  b( ) {}\r\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::default().check(source.as_bytes(), &tree);
+
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 14..18);
+        assert_eq!(
+            violations[0].fix.as_ref().unwrap().apply(source.as_bytes()),
+            b"fn a() {}\r\nfn b() {}\r\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_and_unterminated_files() {
+        let rule = RustfmtRule::default();
+        for (source, formatted) in [
+            ("", "\n"),
+            ("\n", "\n"),
+            ("fn a() {}", "fn a() {}\n"),
+            ("// c", "// c\n"),
+            ("fn a() {}\nfn  b() {}", "fn a() {}\nfn b() {}\n"),
+            ("   ", "\n"),
+            ("   \n\n", "\n"),
+            ("\t\n \n", "\n"),
+        ] {
+            let tree = parse(source);
+            let fixes = rule
+                .check(source.as_bytes(), &tree)
+                .into_iter()
+                .filter_map(|violation| violation.fix)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                String::from_utf8(Fix::apply_all(source.as_bytes(), &fixes)).unwrap(),
+                formatted,
+                "{source:?}"
+            );
+            let fixes = rule
+                .check_lines(source.as_bytes(), &tree, &[0..1])
+                .violations
+                .into_iter()
+                .filter_map(|violation| violation.fix)
+                .collect::<Vec<_>>();
+            assert!(
+                fixes.iter().all(|fix| fix.range.end <= source.len()),
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_stale_tree() {
+        let source = "fn a() {}\n\nfn  b() {}\n";
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            node_anchoring: NodeAnchoring::Changed,
+            ..Default::default()
+        });
+        // Parsed before `fn  b() {}` was added, and after it was removed
+        // again.
+        for stale in ["fn a() {}\n", "fn a() {}\n\nfn  b() {}\n\nfn c() {}\n"] {
+            let tree = parse(stale);
+            let violations = rule.check(source.as_bytes(), &tree);
+            assert_eq!(violations.len(), 1, "{stale:?}");
+            assert_eq!(violations[0].range, 14..15);
+            assert_eq!(
+                violations[0].fix.as_ref().unwrap().apply(source.as_bytes()),
+                b"fn a() {}\n\nfn b() {}\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_newline_style() {
+        let source = "fn a() {}\r\nfn  b( ) {}\nfn c() {}\n";
+        let tree = parse(source);
+        let check = |newline_style| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                newline_style,
+                report_inconsistent_line_endings: true,
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree)
+        };
+
+        let violations = check(NewlineStyle::Auto);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert_eq!(violations[1].message_id, INCONSISTENT_LINE_ENDINGS);
+        assert_eq!(violations[1].message, "Expected CRLF line endings.");
+        assert_eq!(violations[1].range, 32..33);
+        let fixes = violations
+            .iter()
+            .filter_map(|violation| violation.fix.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            Fix::apply_all(source.as_bytes(), &fixes),
+            b"fn a() {}\r\nfn b() {}\r\nfn c() {}\r\n"
+        );
+
+        let violations = check(NewlineStyle::Unix);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[1].message_id, INCONSISTENT_LINE_ENDINGS);
+        assert_eq!(violations[1].range, 9..11);
+        assert_eq!(
+            violations[0].fix.as_ref().unwrap().apply(source.as_bytes()),
+            b"fn a() {}\r\nfn b() {}\nfn c() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CleanCache::new(dir.path());
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            cache: Some(cache.clone()),
+            ..Default::default()
+        });
+        let (rustfmt_options, _) = rule.resolve_rustfmt_options();
+
+        let clean = "fn a() {}\n";
+        let (_, clean_key) = rule
+            .cache_entry(&rustfmt_options, clean.as_bytes())
+            .unwrap();
+        assert!(rule.check(clean.as_bytes(), &parse(clean)).is_empty());
+        assert!(cache.is_clean(&clean_key));
+
+        let unformatted = "fn  a() {}\n";
+        let (_, unformatted_key) = rule
+            .cache_entry(&rustfmt_options, unformatted.as_bytes())
+            .unwrap();
+        assert_eq!(
+            rule.check(unformatted.as_bytes(), &parse(unformatted))
+                .len(),
+            1
+        );
+        assert!(!cache.is_clean(&unformatted_key));
+
+        // Hits don't run rustfmt at all.
+        let pool = RustfmtPool::new(
+            RustfmtOptions {
+                toolchain: Some("no-such-toolchain".to_owned()),
+                ..Default::default()
+            },
+            0,
+        );
+        cache.mark_clean(&unformatted_key).unwrap();
+        assert!(rule
+            .check_with_pool(unformatted.as_bytes(), &parse(unformatted), &pool)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_child_module_mismatches() {
+        let source = "mod foo;\nfn  a() {}\n";
+        let tree = parse(source);
+        let mismatch = |line, original: &str, expected: &str| Mismatch {
+            original_begin_line: line,
+            original_end_line: line,
+            expected_begin_line: line,
+            expected_end_line: line,
+            original: original.to_owned(),
+            expected: expected.to_owned(),
+        };
+        let violations = RustfmtRule::default().check_with(source.as_bytes(), &tree, |_, _, _| {
+            Ok(vec![
+                FileMismatches {
+                    name: "foo.rs".to_owned(),
+                    mismatches: vec![mismatch(7, "fn  x() {}\n", "fn x() {}\n")],
+                },
+                FileMismatches {
+                    name: STDIN_FILE_NAME.to_owned(),
+                    mismatches: vec![mismatch(2, "fn  a() {}\n", "fn a() {}\n")],
+                },
+                FileMismatches {
+                    name: "foo/bar.rs".to_owned(),
+                    mismatches: vec![mismatch(1, "mod foo;\n", "mod foo;\n\n")],
+                },
+            ])
+        });
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 12..13);
+    }
+
+    #[test]
+    fn test_check_idempotency() {
+        let source = "fn a() {}\nfn  b( ) {}\nfn c() {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            check_idempotency: true,
+            ..Default::default()
+        });
+        let mismatch = |line, original: &str, expected: &str| FileMismatches {
+            name: STDIN_FILE_NAME.to_owned(),
+            mismatches: vec![Mismatch {
+                original_begin_line: line,
+                original_end_line: line,
+                expected_begin_line: line,
+                expected_end_line: line,
+                original: original.to_owned(),
+                expected: expected.to_owned(),
+            }],
+        };
+        let check = |second_pass: Vec<FileMismatches>| {
+            rule.check_with(source.as_bytes(), &tree, |formatted, _, _| {
+                Ok(if formatted == source.as_bytes() {
+                    vec![mismatch(2, "fn  b( ) {}\n", "fn b() {}\n")]
+                } else {
+                    assert_eq!(formatted, b"fn a() {}\nfn b() {}\nfn c() {}\n");
+                    second_pass.clone()
+                })
+            })
+        };
+
+        let violations = check(vec![]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert!(violations[0].fix.is_some());
+
+        // The fixed line would flip back.
+        let violations = check(vec![mismatch(2, "fn b() {}\n", "fn  b( ) {}\n")]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, UNSTABLE_FORMATTING);
+        assert_eq!(violations[0].range, 13..17);
+        assert_eq!(violations[0].fix, None);
+
+        // Formatting the fix changes another line.
+        let violations = check(vec![mismatch(3, "fn c() {}\n", "fn c() {}\n\n")]);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert_eq!(violations[1].message_id, UNSTABLE_FORMATTING);
+        assert_eq!(violations[1].range, 22..32);
+    }
+
+    #[test]
+    fn test_unfixed_offset() {
+        let fix = |range: Range<usize>, replacement: &str| Fix {
+            range,
+            replacement: replacement.to_owned(),
+        };
+        // "aXXbc" -> "aYYYb"
+        let fixes = [fix(1..3, "YYY"), fix(4..5, "")];
+        let applied = fixes.iter().collect::<Vec<_>>();
+        let unfixed = |offset, end| unfixed_offset(&applied, offset, end);
+        assert_eq!(unfixed(0, false), 0);
+        assert_eq!(unfixed(1, false), 1);
+        assert_eq!(unfixed(2, false), 1);
+        assert_eq!(unfixed(2, true), 3);
+        assert_eq!(unfixed(4, false), 3);
+        assert_eq!(unfixed(5, true), 4);
+    }
+
+    #[test]
+    fn test_verify_fixes() {
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            verify_fixes: true,
+            ..Default::default()
+        });
+
+        let source = "fn  a( ) {}\n";
+        let tree = parse(source);
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert!(violations[0].fix.is_some());
+
+        // As if rustfmt (or our mapping) were broken.
+        let source = "fn a() {}\n";
+        let tree = parse(source);
+        let violations = rule.violations(
+            source.as_bytes(),
+            &tree,
+            Ok(vec![FileMismatches {
+                name: STDIN_FILE_NAME.to_owned(),
+                mismatches: vec![Mismatch {
+                    original_begin_line: 1,
+                    original_end_line: 1,
+                    expected_begin_line: 1,
+                    expected_end_line: 1,
+                    original: "fn a() {}\n".to_owned(),
+                    expected: "fn b() {}\n".to_owned(),
+                }],
+            }]),
+            &[],
+            None,
+        );
+        assert_eq!(violations[0].message_id, FORMATTING_FIX_REJECTED);
+        assert_eq!(violations[0].fix, None);
+    }
+
+    #[test]
+    fn test_format_hooks() {
+        /// Hides a made-up `@hot` marker from rustfmt as an attribute.
+        struct HotMarker;
+
+        impl FormatHooks for HotMarker {
+            fn pre_format(&self, source: &[u8]) -> Vec<u8> {
+                String::from_utf8_lossy(source)
+                    .replace("@hot", "#[hot]")
+                    .into_bytes()
+            }
+
+            fn post_format(&self, text: &str) -> String {
+                text.replace("#[hot]", "@hot")
+            }
+        }
+
+        /// Changes how many lines the source has, so it isn't used.
+        struct AddsLines;
+
+        impl FormatHooks for AddsLines {
+            fn pre_format(&self, source: &[u8]) -> Vec<u8> {
+                [b"\n", source].concat()
+            }
+
+            fn post_format(&self, text: &str) -> String {
+                text.to_owned()
+            }
+        }
+
+        let source = "@hot fn  a() {}\n";
+        let tree = parse(source);
+        let check = |format_hooks| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                format_hooks,
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree)
+        };
+        let violations = check(Some(Arc::new(HotMarker)));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert_eq!(
+            String::from_utf8(violations[0].fix.as_ref().unwrap().apply(source.as_bytes()))
+                .unwrap(),
+            "@hot\nfn a() {}\n"
+        );
+        for format_hooks in [None, Some(Arc::new(AddsLines) as Arc<dyn FormatHooks>)] {
+            let violations = check(format_hooks);
+            assert_eq!(violations.len(), 1);
+            assert_eq!(violations[0].message_id, RUSTFMT_FAILED);
+        }
+
+        let violations = RustfmtRule::new(RustfmtRuleOptions {
+            format_hooks: Some(Arc::new(HotMarker)),
+            ..Default::default()
+        })
+        .check_lines(source.as_bytes(), &tree, &[0..1])
+        .violations;
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+    }
+
+    #[test]
+    fn test_edition_fallback() {
+        let source = "async fn  a() {}\n";
+        let tree = parse(source);
+        let message_ids = |edition_fallback| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                rustfmt: RustfmtOptions {
+                    edition: Some("2015".to_owned()),
+                    ..Default::default()
+                },
+                edition_fallback,
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree)
+            .into_iter()
+            .map(|violation| violation.message_id)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(message_ids(false), [RUSTFMT_FAILED]);
+        assert_eq!(message_ids(true), [UNEXPECTED_FORMATTING]);
+    }
+
+    #[test]
+    fn test_overlapping_mismatches() {
+        let source = "fn a() {\n    a!( x );\n    b( );\n    c( );\n}\n";
+        let tree = parse(source);
+        // As from two `--file-lines` ranges that both reached line 3.
+        let files = parse_mismatches(
+            r#"[{"name":"<stdin>","mismatches":[
+                {"original_begin_line":2,"original_end_line":3,"expected_begin_line":2,"expected_end_line":3,"original":"    a!( x );\n    b( );\n","expected":"    a!(x);\n    b();\n"},
+                {"original_begin_line":3,"original_end_line":4,"expected_begin_line":3,"expected_end_line":4,"original":"    b( );\n    c( );\n","expected":"    b();\n    c();\n"}
+            ]}]"#,
+        )
+        .unwrap();
+        let violations =
+            RustfmtRule::default().violations(source.as_bytes(), &tree, Ok(files), &[], None);
+        assert_eq!(violations.len(), 1);
+        // Hosts applying every fix one after another get the formatted file.
+        let mut fixed = source.as_bytes().to_owned();
+        for fix in violations
+            .iter()
+            .rev()
+            .filter_map(|violation| violation.fix.as_ref())
+        {
+            fixed = fix.apply(&fixed);
+        }
+        assert_eq!(
+            String::from_utf8(fixed).unwrap(),
+            "fn a() {\n    a!(x);\n    b();\n    c();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_categorize_mismatches() {
+        let source = "use b;\nuse a;\n\nfn a() {\n  let x = 1;\n}\n\nfn  b() {}\n";
+        let tree = parse(source);
+        let message_ids = |categorize_mismatches| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                categorize_mismatches,
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree)
+            .into_iter()
+            .map(|violation| violation.message_id)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            message_ids(true),
+            [
+                UNORDERED_IMPORTS,
+                UNORDERED_IMPORTS,
+                WRONG_INDENTATION,
+                UNEXPECTED_FORMATTING
+            ]
+        );
+        assert_eq!(message_ids(false), [UNEXPECTED_FORMATTING; 4]);
+    }
+
+    #[test]
+    fn test_verbose_messages() {
+        let source = "fn  b( ) {}\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::new(RustfmtRuleOptions {
+            verbose_messages: true,
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+
+        assert_eq!(
+            violations[0].message,
+            "Unexpected formatting:\n@@ -1 +1 @@\n-fn  b( ) {}\n+fn b() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_macro_invocations() {
+        let source = "fn a() {\n    foo!( a,b );\n}\n\nfn b() {\n    bar!( a,b );\n}\n\nfn c() {\n    qux!( a,b );\n}\n";
+        let tree = parse(source);
+        let reported_lines = |names: &[&str]| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                rustfmt: RustfmtOptions {
+                    skip_macro_invocations: Some(SkipMacroInvocations::Names(
+                        names.iter().map(|&name| name.to_owned()).collect(),
+                    )),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree)
+            .into_iter()
+            .map(|violation| violation.node.start_position().row)
+            .collect::<Vec<_>>()
+        };
+
+        // Passed through to rustfmt.
+        assert_eq!(reported_lines(&["foo"]), [4, 8]);
+        // Emulated.
+        assert_eq!(reported_lines(&["foo", "bar"]), [8]);
+    }
+
+    #[test]
+    fn test_skip_regions() {
+        let source = "fn  a() {}\n\n// tree-sitter-lint-disable-next-line rustfmt\nfn  b() {}\n\nfn  c() {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::default();
+
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].range, 3..4);
+        assert_eq!(violations[1].range, 73..74);
+
+        let PartialCheck { violations, .. } = rule.check_lines(source.as_bytes(), &tree, &[3..6]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 73..74);
+    }
+
+    #[test]
+    fn test_skip_inner_attributes() {
+        let source = "mod a {\n    #![rustfmt::skip]\n    fn  b() {}\n}\nfn  c() {}\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::default().check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 50..51);
+
+        // rustfmt isn't run at all for skipped files, so nothing's reported.
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            on_report: Some({
+                let reports = reports.clone();
+                Arc::new(move |report: &FormattingReport| {
+                    reports.lock().unwrap().push(report.clone())
+                })
+            }),
+            ..Default::default()
+        });
+        for source in [
+            "//! Docs.\n#![rustfmt::skip]\nfn  a() {}\n",
+            "#![rustfmt::skip]\nfn  a() {}\n",
+        ] {
+            assert!(rule.check(source.as_bytes(), &parse(source)).is_empty());
+        }
+        assert!(reports.lock().unwrap().is_empty());
+        let source = "fn  a() {}\n";
+        assert_eq!(rule.check(source.as_bytes(), &parse(source)).len(), 1);
+        assert_eq!(reports.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_skip_attributes() {
+        let source = "fn  a() {}\n#[sqlx::test]\nasync fn  b() {}\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::new(RustfmtRuleOptions {
+            skip_attributes: vec!["sqlx::test".to_owned()],
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 3..4);
+    }
+
+    #[test]
+    fn test_exclude() {
+        let source = "// @generated\nfn  a() {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            exclude: vec!["src/gen/**".to_owned()],
+            ..Default::default()
+        });
+        assert!(rule
+            .check_as(source.as_bytes(), &tree, Path::new("src/gen/a.rs"))
+            .is_empty());
+        assert_eq!(
+            rule.check_as(source.as_bytes(), &tree, Path::new("src/a.rs"))
+                .len(),
+            1
+        );
+
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            exclude_generated: true,
+            ..Default::default()
+        });
+        assert!(rule.check(source.as_bytes(), &tree).is_empty());
+        assert!(rule
+            .check_lines(source.as_bytes(), &tree, &[0..2])
+            .violations
+            .is_empty());
+    }
+
+    #[test]
+    fn test_chunk_lines() {
+        let source = "fn a() {\n    x( );\n}\n\nfn  b() {}\n\nfn c() {\n  y();\n}\n";
+        let tree = parse(source);
+        let chunked = RustfmtRule::new(RustfmtRuleOptions {
+            chunk_lines: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(
+            chunked.chunks(&tree, source.as_bytes()),
+            Some(vec![0..3, 4..9])
+        );
+        let fixes = |violations: Vec<Violation>| {
+            violations
+                .into_iter()
+                .map(|violation| (violation.range, violation.fix))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(chunked.check(source.as_bytes(), &tree).len(), 3);
+        assert_eq!(
+            fixes(chunked.check(source.as_bytes(), &tree)),
+            fixes(RustfmtRule::default().check(source.as_bytes(), &tree))
+        );
+        assert_eq!(
+            fixes(
+                chunked
+                    .check_lines(source.as_bytes(), &tree, &[6..9])
+                    .violations
+            ),
+            fixes(
+                RustfmtRule::default()
+                    .check_lines(source.as_bytes(), &tree, &[6..9])
+                    .violations
+            )
+        );
+    }
+
+    #[test]
+    fn test_keep_blank_lines() {
+        let source = "fn a() {\n\n    b( );\n}\n\n\n\nfn c() {}\n";
+        let tree = parse(source);
+        let check = |keep_blank_lines| {
+            let fixed = Fix::apply_all(
+                source.as_bytes(),
+                RustfmtRule::new(RustfmtRuleOptions {
+                    keep_blank_lines,
+                    check_idempotency: true,
+                    ..Default::default()
+                })
+                .check(source.as_bytes(), &tree)
+                .iter()
+                .filter_map(|violation| violation.fix.as_ref()),
+            );
+            String::from_utf8(fixed).unwrap()
+        };
+        assert_eq!(check(false), "fn a() {\n    b();\n}\n\nfn c() {}\n");
+        assert_eq!(check(true), "fn a() {\n\n    b();\n}\n\n\n\nfn c() {}\n");
+    }
+
+    #[test]
+    fn test_max_fix_lines() {
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            max_fix_lines: Some(2),
+            ..Default::default()
+        });
+
+        let source = "fn a() {\n  b();\n  c();\n  d();\n}\n";
+        let tree = parse(source);
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.range.clone())
+                .collect::<Vec<_>>(),
+            [11..16, 25..25]
+        );
+        let fixed = Fix::apply_all(
+            source.as_bytes(),
+            violations
+                .iter()
+                .filter_map(|violation| violation.fix.as_ref()),
+        );
+        assert_eq!(
+            String::from_utf8(fixed).unwrap(),
+            "fn a() {\n    b();\n    c();\n    d();\n}\n"
+        );
+
+        let source = "fn a() { b(); c(); }\n";
+        let tree = parse(source);
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].fix, None);
+    }
+
+    #[test]
+    fn test_max_violations_per_file() {
+        let source = "fn  a() {}\n\nfn  b() {}\n\nfn  c() {}\n\nfn  d() {}\n";
+        let formatted = format_to_string(source.as_bytes(), &Default::default()).unwrap();
+        let tree = parse(source);
+        let rule = |options| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                max_violations_per_file: Some(2),
+                ..options
+            })
+        };
+        let apply_all = |violations: &[Violation]| {
+            String::from_utf8(Fix::apply_all(
+                source.as_bytes(),
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref()),
+            ))
+            .unwrap()
+        };
+        assert_eq!(
+            RustfmtRule::new(RustfmtRuleOptions {
+                max_violations_per_file: Some(4),
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree)
+            .len(),
+            4
+        );
+
+        let violations = rule(Default::default()).check(source.as_bytes(), &tree);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.message_id)
+                .collect::<Vec<_>>(),
+            [
+                UNEXPECTED_FORMATTING,
+                UNEXPECTED_FORMATTING,
+                FILE_NEEDS_REFORMATTING
+            ]
+        );
+        assert_eq!(
+            violations[2].message,
+            "File requires full reformatting (2 more mismatches)."
+        );
+        assert_eq!(violations[2].range, 0..source.len());
+        assert_eq!(violations[2].node, tree.root_node());
+        // Applying them all is still formatting the file.
+        assert_eq!(apply_all(&violations), formatted);
+
+        // Only formatting the lines that are checked,
+        let violations = rule(Default::default())
+            .check_lines(source.as_bytes(), &tree, &[0..5])
+            .violations;
+        assert_eq!(violations.len(), 3);
+        assert_eq!(
+            violations[2].message,
+            "File requires full reformatting (1 more mismatch)."
+        );
+        assert_eq!(
+            apply_all(&violations),
+            "fn a() {}\n\nfn b() {}\n\nfn c() {}\n\nfn  d() {}\n"
+        );
+
+        // and none of what's skipped.
+        let source = "fn  a() {}\n\nfn  b() {}\n\nfn  c() {}\n\n// tree-sitter-lint-disable rustfmt\nfn  d() {}\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::new(RustfmtRuleOptions {
+            max_violations_per_file: Some(1),
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(
+            violations[1].message,
+            "File requires full reformatting (2 more mismatches)."
+        );
+        assert_eq!(
+            String::from_utf8(Fix::apply_all(
+                source.as_bytes(),
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref()),
+            ))
+            .unwrap(),
+            "fn a() {}\n\nfn b() {}\n\nfn c() {}\n\n// tree-sitter-lint-disable rustfmt\nfn  d() {}\n"
+        );
+
+        // Counting mismatches (of two lines, each reported on both of their
+        // items), not violations.
+        let source = "fn  a() {}\nfn  b() {}\n\nfn  c() {}\nfn  d() {}\n";
+        let formatted = format_to_string(source.as_bytes(), &Default::default()).unwrap();
+        let tree = parse(source);
+        let violations = rule(RustfmtRuleOptions {
+            node_anchoring: NodeAnchoring::Changed,
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 3);
+        assert_eq!(
+            violations[2].message,
+            "File requires full reformatting (1 more mismatch)."
+        );
+        assert_eq!(
+            String::from_utf8(Fix::apply_all(
+                source.as_bytes(),
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref()),
+            ))
+            .unwrap(),
+            formatted
+        );
+
+        let violations = rule(RustfmtRuleOptions {
+            mode: RuleMode::Check,
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+        assert!(violations.iter().all(|violation| violation.fix.is_none()));
+    }
+
+    #[test]
+    fn test_check_with_backend() {
+        let source = "fn a() {}\nstruct  B;\n";
+        let tree = parse(source);
+        let backend = MockBackend::from_mismatches(vec![Mismatch {
+            original_begin_line: 2,
+            original_end_line: 2,
+            expected_begin_line: 2,
+            expected_end_line: 2,
+            original: "struct  B;\n".to_owned(),
+            expected: "struct B;\n".to_owned(),
+        }]);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                toolchain: Some("no-such-toolchain".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let violations = rule.check_with_backend(source.as_bytes(), &tree, &backend);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 17..18);
+        assert_eq!(
+            violations[0].fix,
+            Some(Fix {
+                range: 17..18,
+                replacement: "".to_owned(),
+            })
+        );
+
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            scope: Some(FormattingScope::NodeKinds(vec!["struct_item".to_owned()])),
+            ..rule.options.clone()
+        });
+        assert_eq!(
+            rule.check_with_backend(source.as_bytes(), &tree, &backend)
+                .len(),
+            1
+        );
+        assert_eq!(backend.runs()[1], file_lines_args(&[1..2]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_formatter() {
+        let source = "fn a() {}\nstruct  B;\n";
+        let tree = parse(source);
+        // Only the formatter is run, so a missing toolchain doesn't matter.
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                toolchain: Some("no-such-toolchain".to_owned()),
+                ..Default::default()
+            },
+            formatter: Some(Arc::new(crate::CommandFormatter::new("sed", ["s/  */ /g"]))),
+            ..Default::default()
+        });
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].fix,
+            Some(Fix {
+                range: 17..18,
+                replacement: "".to_owned(),
+            })
+        );
+        // It can't format ranges, so the whole file is checked.
+        assert_eq!(
+            rule.check_lines(source.as_bytes(), &tree, &[0..1])
+                .violations
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_queued_fixes() {
+        let source = "fn  a() {}\n\nfn  b() {}\n";
+        let tree = parse(source);
+        let queued_fixes = QueuedFixes::new();
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            queued_fixes: Some(queued_fixes.clone()),
+            ..Default::default()
+        });
+        assert_eq!(rule.check(source.as_bytes(), &tree).len(), 2);
+        assert!(rule.check(source.as_bytes(), &tree).is_empty());
+        assert!(rule
+            .check_lines(source.as_bytes(), &tree, &[0..1])
+            .violations
+            .is_empty());
+
+        queued_fixes.next_pass();
+        assert_eq!(
+            rule.check_lines(source.as_bytes(), &tree, &[0..1])
+                .violations
+                .len(),
+            1
+        );
+        assert_eq!(rule.check(source.as_bytes(), &tree).len(), 1);
+    }
+
+    #[test]
+    fn test_fix_attempts() {
+        let source = "fn  a() {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            fix_attempts: Some(FixAttempts::new(1)),
+            ..Default::default()
+        });
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert!(violations[0].fix.is_some());
+        let violations = rule
+            .check_lines(source.as_bytes(), &tree, &[0..1])
+            .violations;
+        assert_eq!(violations[0].message_id, UNRESOLVABLE_FORMATTING_CONFLICT);
+        assert_eq!(violations[0].fix, None);
+    }
+
+    #[test]
+    fn test_check_lines() {
+        let source = "fn  a() {}\n\nfn  b() {}\n\nfn  c() {}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            max_ranges_per_pass: Some(2),
+            ..Default::default()
+        });
+
+        let PartialCheck {
+            violations,
+            deferred,
+        } = rule.check_lines(source.as_bytes(), &tree, &[4..5, 0..1, 1..1, 2..3]);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.range.clone())
+                .collect::<Vec<_>>(),
+            [3..4, 15..16]
+        );
+        assert_eq!(deferred.len(), 1);
+        assert_eq!(deferred[0], 4..5);
+
+        let PartialCheck {
+            violations,
+            deferred,
+        } = rule.check_lines(source.as_bytes(), &tree, &deferred);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 27..28);
+        assert!(deferred.is_empty());
+
+        // Overlapping ranges covering most of the file have it all formatted
+        // at once, rather than deferring some.
+        let PartialCheck {
+            violations,
+            deferred,
+        } = rule.check_lines(source.as_bytes(), &tree, &[2..9, 0..3]);
+        assert_eq!(violations.len(), 3);
+        assert!(deferred.is_empty());
+    }
+
+    #[test]
+    fn test_rustfmt_failed() {
+        let source = "fn main() {\n    let x = é(;\n}\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::default().check(source.as_bytes(), &tree);
+
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message_id, RUSTFMT_FAILED);
+        assert_eq!(
+            violations[0].message,
+            "rustfmt failed: mismatched closing delimiter: `}`"
+        );
+        assert_eq!(violations[0].range, 26..26);
+        assert_eq!(violations[0].fix, None);
+    }
+
+    #[test]
+    fn test_rustfmt_failed_as() {
+        let source = "fn main() {\n    let x = é(;\n}\n";
+        let tree = parse(source);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            on_report: Some({
+                let reports = reports.clone();
+                Arc::new(move |report: &FormattingReport| {
+                    reports.lock().unwrap().push(report.clone())
+                })
+            }),
+            ..Default::default()
+        });
+        let violations = rule.check_as(source.as_bytes(), &tree, Path::new("src/main.rs"));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 26..26);
+        let reports = reports.lock().unwrap();
+        let FormattingStatus::Failed { message } = &reports[0].status else {
+            panic!("{:?}", reports[0]);
+        };
+        assert!(message.contains("--> src/main.rs:2:"), "{message}");
+        assert!(!message.contains(STDIN_FILE_NAME), "{message}");
+    }
+
+    #[test]
+    fn test_left_unformatted() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        fs::write(&config_path, "error_on_line_overflow = true\n").unwrap();
+        let long_line = format!("    let x = {};", "a".repeat(112));
+        let source = format!("fn a() {{\n{long_line}\n}}\nfn  b() {{}}\n");
+        let tree = parse(&source);
+        let violations = RustfmtRule::new(RustfmtRuleOptions {
+            rustfmt: RustfmtOptions {
+                config_path: Some(config_path),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+
+        assert_eq!(violations.len(), 3);
+        assert_eq!(violations[0].message_id, UNEXPECTED_FORMATTING);
+        assert!(violations[0].fix.is_some());
+        assert_eq!(violations[1].message_id, LINE_OVERFLOW);
+        assert_eq!(
+            violations[1].message,
+            "rustfmt couldn't format this line: line formatted, but exceeded maximum width (maximum: 100 (see `max_width` option), found: 125)."
+        );
+        assert_eq!(violations[1].range, 109..134);
+        assert_eq!(violations[1].fix, None);
+        assert_eq!(violations[2].message_id, PARTIALLY_FORMATTED);
+        assert_eq!(
+            violations[2].message,
+            "rustfmt left some of the file unformatted (line 2), so only its other changes are reported."
+        );
+        assert_eq!(violations[2].range, 0..0);
+    }
+
+    #[test]
+    fn test_node_anchoring() {
+        let source = "fn a() {\n    let x  = 1;\n}\n";
+        let tree = parse(source);
+        let anchored_kind = |node_anchoring| {
+            let violations = RustfmtRule::new(RustfmtRuleOptions {
+                node_anchoring,
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree);
+            assert_eq!(violations.len(), 1);
+            assert_eq!(violations[0].range, 19..20);
+            violations[0].node.kind()
+        };
+        assert_eq!(anchored_kind(NodeAnchoring::Smallest), "block");
+        assert_eq!(anchored_kind(NodeAnchoring::Statement), "let_declaration");
+        assert_eq!(anchored_kind(NodeAnchoring::Item), "function_item");
+        assert_eq!(anchored_kind(NodeAnchoring::Root), "source_file");
+    }
+
+    #[test]
+    fn test_violation_data() {
+        let source = "fn  a() {}\n";
+        let tree = parse(source);
+        let data = RustfmtRule::default().check(source.as_bytes(), &tree)[0].data();
+        assert_eq!(data.node_kind, "source_file");
+        let json = serde_json::to_string(&data).unwrap();
+        assert_eq!(
+            json,
+            r#"{"message_id":"unexpected_formatting","message":"Unexpected formatting.","node_kind":"source_file","range":{"start":3,"end":4},"fix":{"range":{"start":3,"end":4},"replacement":""}}"#
+        );
+        assert_eq!(serde_json::from_str::<ViolationData>(&json).unwrap(), data);
+    }
+
+    #[test]
+    fn test_changed_node_anchoring() {
+        let check = |source: &str| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                node_anchoring: NodeAnchoring::Changed,
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &parse(source))
+            .into_iter()
+            .map(|violation| {
+                (
+                    violation.node.kind(),
+                    violation.range,
+                    violation.fix.is_some(),
+                )
+            })
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            check("fn a() {\n    let x  = 1;\n}\n"),
+            [("let_declaration", 19..20, true)]
+        );
+        assert_eq!(
+            check("fn a() {\n    b(c,d);\n}\n"),
+            [("arguments", 16..18, true)]
+        );
+        // One hunk, changing both functions.
+        let source = "fn a( ) {}\nfn b( ) {}\n";
+        assert_eq!(
+            check(source),
+            [
+                ("function_item", 5..10, true),
+                ("function_item", 11..17, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_on_report() {
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            on_report: Some(Arc::new({
+                let reports = reports.clone();
+                move |report: &FormattingReport| reports.lock().unwrap().push(report.clone())
+            })),
+            ..Default::default()
+        });
+        for source in ["fn a() {}\n", "fn  a() {}\n", "fn a( {}\n"] {
+            rule.check(source.as_bytes(), &parse(source));
+        }
+        let source = "fn  a() {}\n";
+        rule.check_as(source.as_bytes(), &parse(source), Path::new("src/a.rs"));
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 4);
+        assert!(reports[0].is_clean());
+        assert_eq!(reports[1].status, FormattingStatus::Unformatted);
+        assert_eq!(reports[1].mismatches[0].original, "fn  a() {}\n");
+        assert!(matches!(reports[2].status, FormattingStatus::Failed { .. }));
+        assert!(reports[..3].iter().all(|report| report.path.is_none()));
+        assert_eq!(reports[3].path.as_deref(), Some(Path::new("src/a.rs")));
+    }
+
+    #[test]
+    fn test_filter() {
+        let source = "fn  a( ) {}\nmod m {\nfn  b( ) {}\n}\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::new(RustfmtRuleOptions {
+            filter: Some(Arc::new(|mismatch, node| {
+                !mismatch.original.contains("fn  b") && node.kind() != "mod_item"
+            })),
+            ..Default::default()
+        })
+        .check(source.as_bytes(), &tree);
+
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].range, 3..7);
+    }
+
+    #[test]
+    fn test_format_doc_comments() {
+        let source = "/// ```\n/// a( );\n/// ```\nfn  b() {}\n";
+        let tree = parse(source);
+        let check = |format_doc_comments| {
+            RustfmtRule::new(RustfmtRuleOptions {
+                format_doc_comments,
+                ..Default::default()
+            })
+            .check(source.as_bytes(), &tree)
+            .into_iter()
+            .map(|violation| violation.message_id)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(check(false), [UNEXPECTED_FORMATTING]);
+        assert_eq!(check(true), [UNEXPECTED_FORMATTING, DOC_CODE_FORMATTING]);
+    }
+}
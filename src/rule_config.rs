@@ -0,0 +1,521 @@
+use std::{collections::BTreeMap, error::Error, fmt, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    CommentMismatches, FormattingScope, InputMode, NewlineStyle, NodeAnchoring, RuleMode,
+    RustfmtRuleOptions, Severity, SkipMacroInvocations, StyleProfile,
+};
+
+/// The [`RustfmtRuleOptions`] a host's config can set (eg the rule's entry
+/// in its config file), by their names there. Unset options keep their
+/// defaults. Options that take code (a [`Formatter`](crate::Formatter), a
+/// [filter](crate::MismatchFilter), callbacks) or state shared between checks
+/// (a [cache](crate::CleanCache) etc) can only be set in code.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, deny_unknown_fields)]
+pub struct RustfmtRuleConfig {
+    /// See [`RustfmtOptions::toolchain`](crate::RustfmtOptions::toolchain).
+    pub toolchain: Option<String>,
+    /// See [`RustfmtOptions::command`](field@crate::RustfmtOptions::command).
+    pub command: Option<PathBuf>,
+    /// See [`RustfmtOptions::args_prefix`](crate::RustfmtOptions::args_prefix).
+    pub args_prefix: Option<Vec<String>>,
+    /// See [`RustfmtOptions::config_path`](crate::RustfmtOptions::config_path).
+    pub config_path: Option<PathBuf>,
+    /// See [`RustfmtOptions::edition`](crate::RustfmtOptions::edition).
+    pub edition: Option<String>,
+    /// [`RustfmtOptions::timeout`](crate::RustfmtOptions::timeout), in
+    /// seconds, or `0` for no timeout.
+    pub timeout_secs: Option<u64>,
+    /// See [`RustfmtOptions::max_concurrent_runs`](crate::RustfmtOptions::max_concurrent_runs).
+    pub max_concurrent_runs: Option<usize>,
+    /// See [`RustfmtOptions::env_clear`](crate::RustfmtOptions::env_clear).
+    pub env_clear: Option<bool>,
+    /// Environment variables to set for rustfmt, or to remove if they're
+    /// `null` (see [`RustfmtOptions::env`](crate::RustfmtOptions::env)).
+    pub env: Option<BTreeMap<String, Option<String>>>,
+    /// See [`RustfmtOptions::current_dir`](crate::RustfmtOptions::current_dir).
+    pub current_dir: Option<PathBuf>,
+    /// See [`RustfmtOptions::debug_dump_dir`](crate::RustfmtOptions::debug_dump_dir).
+    pub debug_dump_dir: Option<PathBuf>,
+    /// See [`RustfmtOptions::strict_config`](crate::RustfmtOptions::strict_config).
+    pub strict_config: Option<bool>,
+    pub profile: Option<StyleProfile>,
+    pub input_mode: Option<InputMode>,
+    /// Macro names, or `["*"]` for all of them (see
+    /// [`SkipMacroInvocations`]).
+    pub skip_macro_invocations: Option<Vec<String>>,
+    pub format_macro_matchers: Option<bool>,
+    pub format_macro_bodies: Option<bool>,
+    pub mode: Option<RuleMode>,
+    pub severity: Option<Severity>,
+    pub node_anchoring: Option<NodeAnchoring>,
+    pub comment_mismatches: Option<CommentMismatches>,
+    /// Shorthand for `comment_mismatches = "ignore"`, leaving the
+    /// formatting of comment-only lines (including rewrapped comments) alone.
+    pub ignore_comment_only_changes: Option<bool>,
+    /// The kinds of the nodes to only format (see
+    /// [`FormattingScope::NodeKinds`]).
+    pub scope: Option<Vec<String>>,
+    pub skip_attributes: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub exclude_generated: Option<bool>,
+    pub max_ranges_per_pass: Option<usize>,
+    pub keep_blank_lines: Option<bool>,
+    pub newline_style: Option<NewlineStyle>,
+    pub report_inconsistent_line_endings: Option<bool>,
+    pub chunk_lines: Option<usize>,
+    pub max_fix_lines: Option<usize>,
+    pub max_violations_per_file: Option<usize>,
+    pub verbose_messages: Option<bool>,
+    pub categorize_mismatches: Option<bool>,
+    pub verify_fixes: Option<bool>,
+    pub check_idempotency: Option<bool>,
+    pub format_doc_comments: Option<bool>,
+    pub edition_fallback: Option<bool>,
+}
+
+/// Why a [`RustfmtRuleConfig`] couldn't be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleConfigError {
+    /// The config isn't a map of options.
+    NotAMap,
+    /// There's no option called `option`, but there's one called
+    /// `suggestion`, which is spelled similarly.
+    UnknownOption {
+        option: String,
+        suggestion: Option<&'static str>,
+    },
+    /// `option`'s value isn't one it can have.
+    InvalidValue { option: String, message: String },
+    /// The options can't both be set this way.
+    Conflict {
+        options: [&'static str; 2],
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for RuleConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAMap => write!(
+                f,
+                "the rule's options should be a map of option names to values"
+            ),
+            Self::UnknownOption {
+                option,
+                suggestion: Some(suggestion),
+            } => write!(f, "unknown option `{option}`, did you mean `{suggestion}`?"),
+            Self::UnknownOption {
+                option,
+                suggestion: None,
+            } => write!(f, "unknown option `{option}`"),
+            Self::InvalidValue { option, message } => {
+                write!(f, "invalid value for option `{option}`: {message}")
+            }
+            Self::Conflict {
+                options: [a, b],
+                reason,
+            } => write!(f, "options `{a}` and `{b}` conflict: {reason}"),
+        }
+    }
+}
+
+impl Error for RuleConfigError {}
+
+impl RustfmtRuleConfig {
+    /// The names of the options.
+    pub const OPTION_NAMES: [&'static str; 39] = [
+        "toolchain",
+        "command",
+        "args_prefix",
+        "config_path",
+        "edition",
+        "timeout_secs",
+        "max_concurrent_runs",
+        "env_clear",
+        "env",
+        "current_dir",
+        "debug_dump_dir",
+        "strict_config",
+        "profile",
+        "input_mode",
+        "skip_macro_invocations",
+        "format_macro_matchers",
+        "format_macro_bodies",
+        "mode",
+        "severity",
+        "node_anchoring",
+        "comment_mismatches",
+        "ignore_comment_only_changes",
+        "scope",
+        "skip_attributes",
+        "exclude",
+        "exclude_generated",
+        "max_ranges_per_pass",
+        "keep_blank_lines",
+        "newline_style",
+        "report_inconsistent_line_endings",
+        "chunk_lines",
+        "max_fix_lines",
+        "max_violations_per_file",
+        "verbose_messages",
+        "categorize_mismatches",
+        "verify_fixes",
+        "check_idempotency",
+        "format_doc_comments",
+        "edition_fallback",
+    ];
+
+    /// Reads the options in `value` (eg from a host's JSON or YAML config),
+    /// checking each option and then that they go together, and reporting
+    /// the first that doesn't by its name.
+    pub fn from_value(value: &Value) -> Result<Self, RuleConfigError> {
+        let options = value.as_object().ok_or(RuleConfigError::NotAMap)?;
+        for (option, option_value) in options {
+            if !Self::OPTION_NAMES.contains(&option.as_str()) {
+                return Err(RuleConfigError::UnknownOption {
+                    option: option.clone(),
+                    suggestion: suggestion(option),
+                });
+            }
+            // Each option on its own first, so that the error is only about
+            // it.
+            let single = Value::Object(Map::from_iter([(option.clone(), option_value.clone())]));
+            serde_json::from_value::<Self>(single).map_err(|error| {
+                RuleConfigError::InvalidValue {
+                    option: option.clone(),
+                    message: error.to_string(),
+                }
+            })?;
+        }
+        let config =
+            serde_json::from_value::<Self>(value.clone()).expect("every option parses on its own");
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the options that have to be positive, and the ones that don't
+    /// go together.
+    pub fn validate(&self) -> Result<(), RuleConfigError> {
+        for (option, value) in [
+            ("max_concurrent_runs", self.max_concurrent_runs),
+            ("max_ranges_per_pass", self.max_ranges_per_pass),
+            ("chunk_lines", self.chunk_lines),
+            ("max_fix_lines", self.max_fix_lines),
+            ("max_violations_per_file", self.max_violations_per_file),
+        ] {
+            if value == Some(0) {
+                return Err(RuleConfigError::InvalidValue {
+                    option: option.to_owned(),
+                    message: "must be more than 0".to_owned(),
+                });
+            }
+        }
+        if self.mode == Some(RuleMode::Check) {
+            let fix_options = [
+                ("verify_fixes", self.verify_fixes == Some(true)),
+                ("max_fix_lines", self.max_fix_lines.is_some()),
+            ];
+            if let Some((option, _)) = fix_options.into_iter().find(|(_, is_set)| *is_set) {
+                return Err(RuleConfigError::Conflict {
+                    options: ["mode", option],
+                    reason: "it only applies to fixes, which `mode = \"check\"` doesn't offer",
+                });
+            }
+        }
+        if self.comment_mismatches.is_some() && self.ignore_comment_only_changes.is_some() {
+            return Err(RuleConfigError::Conflict {
+                options: ["comment_mismatches", "ignore_comment_only_changes"],
+                reason: "both say what to do with comment-only mismatches",
+            });
+        }
+        if self.scope.is_some() && self.chunk_lines.is_some() {
+            return Err(RuleConfigError::Conflict {
+                options: ["scope", "chunk_lines"],
+                reason: "scoped checks aren't split into chunks",
+            });
+        }
+        Ok(())
+    }
+
+    /// Sets the options in this config on `options`.
+    pub fn apply(self, options: &mut RustfmtRuleOptions) {
+        fn set<T>(option: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *option = value;
+            }
+        }
+
+        let rustfmt = &mut options.rustfmt;
+        if self.toolchain.is_some() {
+            rustfmt.toolchain = self.toolchain;
+        }
+        if self.command.is_some() {
+            rustfmt.command = self.command;
+        }
+        set(&mut rustfmt.args_prefix, self.args_prefix);
+        if self.config_path.is_some() {
+            rustfmt.config_path = self.config_path;
+        }
+        if self.edition.is_some() {
+            rustfmt.edition = self.edition;
+        }
+        if let Some(secs) = self.timeout_secs {
+            rustfmt.timeout = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+        if self.max_concurrent_runs.is_some() {
+            rustfmt.max_concurrent_runs = self.max_concurrent_runs;
+        }
+        set(&mut rustfmt.env_clear, self.env_clear);
+        if let Some(env) = self.env {
+            rustfmt.env = env.into_iter().collect();
+        }
+        if self.current_dir.is_some() {
+            rustfmt.current_dir = self.current_dir;
+        }
+        if self.debug_dump_dir.is_some() {
+            rustfmt.debug_dump_dir = self.debug_dump_dir;
+        }
+        set(&mut rustfmt.strict_config, self.strict_config);
+        set(&mut rustfmt.profile, self.profile);
+        set(&mut rustfmt.input_mode, self.input_mode);
+        if let Some(names) = self.skip_macro_invocations {
+            rustfmt.skip_macro_invocations = Some(if names.iter().any(|name| name == "*") {
+                SkipMacroInvocations::All
+            } else {
+                SkipMacroInvocations::Names(names)
+            });
+        }
+        if self.format_macro_matchers.is_some() {
+            rustfmt.format_macro_matchers = self.format_macro_matchers;
+        }
+        if self.format_macro_bodies.is_some() {
+            rustfmt.format_macro_bodies = self.format_macro_bodies;
+        }
+        set(&mut options.mode, self.mode);
+        set(&mut options.severity, self.severity);
+        set(&mut options.node_anchoring, self.node_anchoring);
+        set(&mut options.comment_mismatches, self.comment_mismatches);
+        if let Some(ignore) = self.ignore_comment_only_changes {
+            options.comment_mismatches = if ignore {
+                CommentMismatches::Ignore
+            } else {
+                CommentMismatches::Report
+            };
+        }
+        if let Some(kinds) = self.scope {
+            options.scope = Some(FormattingScope::NodeKinds(kinds));
+        }
+        set(&mut options.skip_attributes, self.skip_attributes);
+        set(&mut options.exclude, self.exclude);
+        set(&mut options.exclude_generated, self.exclude_generated);
+        if self.max_ranges_per_pass.is_some() {
+            options.max_ranges_per_pass = self.max_ranges_per_pass;
+        }
+        set(&mut options.keep_blank_lines, self.keep_blank_lines);
+        set(&mut options.newline_style, self.newline_style);
+        set(
+            &mut options.report_inconsistent_line_endings,
+            self.report_inconsistent_line_endings,
+        );
+        if self.chunk_lines.is_some() {
+            options.chunk_lines = self.chunk_lines;
+        }
+        if self.max_fix_lines.is_some() {
+            options.max_fix_lines = self.max_fix_lines;
+        }
+        if self.max_violations_per_file.is_some() {
+            options.max_violations_per_file = self.max_violations_per_file;
+        }
+        set(&mut options.verbose_messages, self.verbose_messages);
+        set(
+            &mut options.categorize_mismatches,
+            self.categorize_mismatches,
+        );
+        set(&mut options.verify_fixes, self.verify_fixes);
+        set(&mut options.check_idempotency, self.check_idempotency);
+        set(&mut options.format_doc_comments, self.format_doc_comments);
+        set(&mut options.edition_fallback, self.edition_fallback);
+    }
+}
+
+impl RustfmtRuleOptions {
+    /// The default options with the ones in `value` set (see
+    /// [`RustfmtRuleConfig::from_value()`]).
+    pub fn from_config(value: &Value) -> Result<Self, RuleConfigError> {
+        let mut options = Self::default();
+        RustfmtRuleConfig::from_value(value)?.apply(&mut options);
+        Ok(options)
+    }
+}
+
+/// The option name closest to `option`, if it's close enough to be a typo
+/// of it.
+fn suggestion(option: &str) -> Option<&'static str> {
+    RustfmtRuleConfig::OPTION_NAMES
+        .iter()
+        .map(|name| (edit_distance(option, name), *name))
+        .filter(|&(distance, name)| distance <= (name.len() / 3).max(1))
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, name)| name)
+}
+
+/// The Levenshtein distance between `a` and `b`, in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            current.push(
+                (previous[j] + usize::from(a_char != *b_char))
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        let options = RustfmtRuleOptions::from_config(&json!({
+            "toolchain": "nightly-2024-01-01",
+            "timeout_secs": 5,
+            "skip_macro_invocations": ["*"],
+            "mode": "check",
+            "severity": "warning",
+            "node_anchoring": "item",
+            "comment_mismatches": {"downgrade": "warning"},
+            "scope": ["block"],
+            "exclude": ["src/gen/**"],
+            "newline_style": "windows",
+            "profile": "compact",
+            "input_mode": "temp_file",
+            "args_prefix": ["fmt-wrapped", "--"],
+            "env_clear": true,
+            "env": {"RUSTUP_HOME": "/opt/rustup", "RUSTFMT_LOG": null},
+            "current_dir": "/src",
+        }))
+        .unwrap();
+        assert_eq!(
+            options.rustfmt.toolchain.as_deref(),
+            Some("nightly-2024-01-01")
+        );
+        assert_eq!(options.rustfmt.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(options.rustfmt.args_prefix, ["fmt-wrapped", "--"]);
+        assert!(options.rustfmt.env_clear);
+        assert_eq!(
+            options.rustfmt.env,
+            [
+                ("RUSTFMT_LOG".to_owned(), None),
+                ("RUSTUP_HOME".to_owned(), Some("/opt/rustup".to_owned()))
+            ]
+        );
+        assert_eq!(options.rustfmt.current_dir, Some(PathBuf::from("/src")));
+        assert_eq!(
+            options.rustfmt.skip_macro_invocations,
+            Some(SkipMacroInvocations::All)
+        );
+        assert_eq!(options.rustfmt.profile, StyleProfile::Compact);
+        assert_eq!(options.rustfmt.input_mode, InputMode::TempFile);
+        assert_eq!(options.mode, RuleMode::Check);
+        assert_eq!(options.severity, Severity::Warning);
+        assert_eq!(options.node_anchoring, NodeAnchoring::Item);
+        assert_eq!(
+            options.comment_mismatches,
+            CommentMismatches::Downgrade(Severity::Warning)
+        );
+        assert!(matches!(
+            options.scope,
+            Some(FormattingScope::NodeKinds(kinds)) if kinds == ["block"]
+        ));
+        assert_eq!(options.exclude, ["src/gen/**"]);
+        assert_eq!(options.newline_style, NewlineStyle::Windows);
+        // Unset options keep their defaults.
+        assert_eq!(
+            options.rustfmt.edition,
+            RustfmtRuleOptions::default().rustfmt.edition
+        );
+        assert!(!options.verify_fixes);
+
+        assert!(RustfmtRuleOptions::from_config(&json!({})).is_ok());
+        assert_eq!(
+            RustfmtRuleOptions::from_config(&json!({"timeout_secs": 0}))
+                .unwrap()
+                .rustfmt
+                .timeout,
+            None
+        );
+    }
+
+    #[test]
+    fn test_from_config_errors() {
+        let error = |value: Value| {
+            RustfmtRuleOptions::from_config(&value)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error(json!(["check"])),
+            "the rule's options should be a map of option names to values"
+        );
+        assert_eq!(
+            error(json!({"verify_fix": true})),
+            "unknown option `verify_fix`, did you mean `verify_fixes`?"
+        );
+        assert_eq!(error(json!({"colour": true})), "unknown option `colour`");
+        assert_eq!(
+            error(json!({"exclude_generated": true, "mode": "dry_run"})),
+            "invalid value for option `mode`: unknown variant `dry_run`, expected `fix` or `check`"
+        );
+        assert!(error(json!({"chunk_lines": "many"}))
+            .starts_with("invalid value for option `chunk_lines`: invalid type: string \"many\""));
+        assert_eq!(
+            error(json!({"max_fix_lines": 0})),
+            "invalid value for option `max_fix_lines`: must be more than 0"
+        );
+        assert_eq!(
+            error(json!({"mode": "check", "verify_fixes": true})),
+            "options `mode` and `verify_fixes` conflict: it only applies to fixes, which `mode = \"check\"` doesn't offer"
+        );
+        assert!(
+            RustfmtRuleOptions::from_config(&json!({"mode": "check", "verify_fixes": false}))
+                .is_ok()
+        );
+        assert_eq!(
+            error(json!({"comment_mismatches": "report", "ignore_comment_only_changes": true})),
+            "options `comment_mismatches` and `ignore_comment_only_changes` conflict: both say what to do with comment-only mismatches"
+        );
+        assert_eq!(
+            RustfmtRuleOptions::from_config(&json!({"ignore_comment_only_changes": true}))
+                .unwrap()
+                .comment_mismatches,
+            CommentMismatches::Ignore
+        );
+    }
+
+    #[test]
+    fn test_option_names() {
+        let Value::Object(options) = serde_json::to_value(RustfmtRuleConfig::default()).unwrap()
+        else {
+            panic!("a config should serialize as a map");
+        };
+        let mut names = RustfmtRuleConfig::OPTION_NAMES;
+        names.sort();
+        assert_eq!(options.keys().collect::<Vec<_>>(), names);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(suggestion("toolchian"), Some("toolchain"));
+    }
+}
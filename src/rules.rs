@@ -0,0 +1,172 @@
+//! The plugin's rules, and the [`RuleRegistry`] a
+//! [`PluginBuilder`](crate::PluginBuilder) looks them up in by name (eg for
+//! the rules a host's config enables).
+
+pub use crate::{
+    BlankLinesRule, FileHeaderRule, FinalNewlineRule, FormatterRule, OrganizeImportsRule,
+    PluginRule, RustfmtRule, TrailingWhitespaceRule, BLANK_LINES_RULE_NAME, FILE_HEADER_RULE_NAME,
+    FINAL_NEWLINE_RULE_NAME, FORMATTER_RULE_NAME, ORGANIZE_IMPORTS_RULE_NAME, RULE_NAME,
+    TRAILING_WHITESPACE_RULE_NAME,
+};
+
+/// A rule that a [`RuleRegistry`] can add by name.
+#[derive(Clone, Copy, Debug)]
+pub struct RegisteredRule {
+    pub name: &'static str,
+    /// What the rule reports, in a line.
+    pub description: &'static str,
+    /// The rule with its default options, or `None` if it has to be
+    /// configured before it can be added (like [`FileHeaderRule`]'s
+    /// template).
+    pub default: Option<fn() -> PluginRule>,
+}
+
+/// The rules that can be added to a plugin by name, in the order the plugin
+/// checks them. Hosts can [`register()`](Self::register) other defaults for
+/// them, and hand the registry to
+/// [`PluginBuilder::registry()`](crate::PluginBuilder::registry).
+#[derive(Clone, Debug)]
+pub struct RuleRegistry {
+    rules: Vec<RegisteredRule>,
+}
+
+impl Default for RuleRegistry {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                RegisteredRule {
+                    name: FILE_HEADER_RULE_NAME,
+                    description: "Files start with the header of a template.",
+                    default: None,
+                },
+                RegisteredRule {
+                    name: RULE_NAME,
+                    description: "Code is formatted the way rustfmt formats it.",
+                    default: Some(|| RustfmtRule::default().into()),
+                },
+                RegisteredRule {
+                    name: TRAILING_WHITESPACE_RULE_NAME,
+                    description: "Lines don't end with whitespace.",
+                    default: Some(|| TrailingWhitespaceRule.into()),
+                },
+                RegisteredRule {
+                    name: FINAL_NEWLINE_RULE_NAME,
+                    description: "Files end with a single newline.",
+                    default: Some(|| FinalNewlineRule.into()),
+                },
+                RegisteredRule {
+                    name: BLANK_LINES_RULE_NAME,
+                    description: "Items are separated by a set number of blank lines.",
+                    default: Some(|| BlankLinesRule::default().into()),
+                },
+                RegisteredRule {
+                    name: ORGANIZE_IMPORTS_RULE_NAME,
+                    description: "Imports are sorted and grouped.",
+                    default: Some(|| OrganizeImportsRule::default().into()),
+                },
+            ],
+        }
+    }
+}
+
+impl RuleRegistry {
+    /// The plugin's own rules.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn rules(&self) -> &[RegisteredRule] {
+        &self.rules
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|rule| rule.name).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredRule> {
+        self.rules.iter().find(|rule| rule.name == name)
+    }
+
+    /// The rule called `name` with its registered default options, or `None`
+    /// if there's no such rule or it has to be configured.
+    pub fn instantiate(&self, name: &str) -> Option<PluginRule> {
+        self.get(name)?.default.map(|default| default())
+    }
+
+    /// Registers `rule` in place of the one with the same name, eg to add
+    /// rustfmt with other default options. The plugin's rules are a fixed
+    /// set, so `rule` has to be one of the registered ones, and its default
+    /// has to build that rule. Otherwise it isn't registered, and its name
+    /// is returned.
+    pub fn register(&mut self, rule: RegisteredRule) -> Result<(), &'static str> {
+        let registered = self
+            .rules
+            .iter_mut()
+            .find(|registered| registered.name == rule.name)
+            .ok_or(rule.name)?;
+        if rule
+            .default
+            .is_some_and(|default| default().name() != rule.name)
+        {
+            return Err(rule.name);
+        }
+        *registered = rule;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Plugin, RustfmtRuleOptions};
+
+    #[test]
+    fn test_rule_registry() {
+        let mut registry = RuleRegistry::new();
+        assert_eq!(registry.names(), Plugin::RULE_NAMES);
+        assert!(registry.instantiate(FILE_HEADER_RULE_NAME).is_none());
+        assert!(registry.instantiate("nope").is_none());
+        assert_eq!(
+            registry
+                .instantiate(FINAL_NEWLINE_RULE_NAME)
+                .map(|rule| rule.name()),
+            Some(FINAL_NEWLINE_RULE_NAME)
+        );
+
+        registry
+            .register(RegisteredRule {
+                name: RULE_NAME,
+                description: "Code is formatted the way rustfmt formats it, verifying its fixes.",
+                default: Some(|| {
+                    RustfmtRule::new(RustfmtRuleOptions {
+                        verify_fixes: true,
+                        ..Default::default()
+                    })
+                    .into()
+                }),
+            })
+            .unwrap();
+        assert_eq!(registry.names(), Plugin::RULE_NAMES);
+
+        let strict_rustfmt = RegisteredRule {
+            name: "strict_rustfmt",
+            description: "Code is formatted the way rustfmt formats it, verifying its fixes.",
+            default: Some(|| RustfmtRule::default().into()),
+        };
+        assert_eq!(registry.register(strict_rustfmt), Err("strict_rustfmt"));
+        assert_eq!(
+            registry.register(RegisteredRule {
+                name: FINAL_NEWLINE_RULE_NAME,
+                ..strict_rustfmt
+            }),
+            Err(FINAL_NEWLINE_RULE_NAME)
+        );
+        assert_eq!(registry.names(), Plugin::RULE_NAMES);
+        let plugin = Plugin::builder()
+            .registry(registry)
+            .with_rules_named([RULE_NAME])
+            .unwrap()
+            .build();
+        assert!(plugin.rustfmt.unwrap().options.verify_fixes);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{Violation, RUSTFMT_FAILED, RUSTFMT_TIMED_OUT, RUSTFMT_VERSION_MISMATCH};
+
+/// Whether the files checked over a run had violations, for hosts that exit
+/// with a status like `cargo fmt --check`'s rather than going through each
+/// file's violations. A [`Plugin`](crate::Plugin) records what it checks
+/// when it's given one (see [`PluginBuilder::run_status()`](crate::PluginBuilder::run_status)),
+/// and hosts can [`record()`](Self::record) the violations of rules they
+/// check themselves. Clones share the status.
+#[derive(Clone, Debug, Default)]
+pub struct RunStatus {
+    counts: Arc<Mutex<RunCounts>>,
+}
+
+#[derive(Debug, Default)]
+struct RunCounts {
+    files: usize,
+    /// By rule name.
+    violations: BTreeMap<&'static str, usize>,
+    failures: usize,
+}
+
+impl RunStatus {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records the violations `rule_name`'s rule reported on a file. The
+    /// ones about rustfmt not running (`rustfmt_failed`, `rustfmt_timed_out`
+    /// and `rustfmt_version_mismatch`) are failures rather than violations.
+    pub fn record(&self, rule_name: &'static str, violations: &[Violation]) {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        let (failures, violations) = violations
+            .iter()
+            .partition::<Vec<_>, _>(|violation| is_failure(violation.message_id));
+        counts.failures += failures.len();
+        *counts.violations.entry(rule_name).or_default() += violations.len();
+    }
+
+    /// Records that a file was checked, whether or not it had violations.
+    pub fn record_file(&self) {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .files += 1;
+    }
+
+    /// How many files were checked.
+    pub fn files(&self) -> usize {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .files
+    }
+
+    /// How many violations each rule reported, by rule name, leaving out
+    /// failures.
+    pub fn violation_counts(&self) -> BTreeMap<&'static str, usize> {
+        let counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        counts
+            .violations
+            .iter()
+            .filter(|(_, &count)| count > 0)
+            .map(|(&rule_name, &count)| (rule_name, count))
+            .collect()
+    }
+
+    /// Whether any formatting violations were found.
+    pub fn has_violations(&self) -> bool {
+        !self.violation_counts().is_empty()
+    }
+
+    /// Whether rustfmt couldn't check some file.
+    pub fn has_failures(&self) -> bool {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .failures
+            > 0
+    }
+
+    /// What `cargo fmt --check` would exit with: 0 if nothing was found, or
+    /// else 1 (as it does both for files that need formatting and when
+    /// rustfmt fails).
+    pub fn exit_code(&self) -> i32 {
+        if self.has_violations() || self.has_failures() {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Forgets what was recorded, for the next run.
+    pub fn reset(&self) {
+        *self.counts.lock().unwrap_or_else(PoisonError::into_inner) = Default::default();
+    }
+}
+
+fn is_failure(message_id: &str) -> bool {
+    [RUSTFMT_FAILED, RUSTFMT_TIMED_OUT, RUSTFMT_VERSION_MISMATCH].contains(&message_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::parse, Plugin, RustfmtRule, RULE_NAME};
+
+    #[test]
+    fn test_run_status() {
+        let status = RunStatus::new();
+        let plugin = Plugin::builder()
+            .with_rule(RustfmtRule::default())
+            .run_status(status.clone())
+            .build();
+        let source = "fn a() {}\n";
+        plugin.check(source.as_bytes(), &parse(source));
+        assert_eq!(status.files(), 1);
+        assert!(!status.has_violations());
+        assert_eq!(status.exit_code(), 0);
+
+        let source = "fn  a() {}\n";
+        plugin.check(source.as_bytes(), &parse(source));
+        assert_eq!(status.files(), 2);
+        assert_eq!(status.violation_counts(), BTreeMap::from([(RULE_NAME, 1)]));
+        assert_eq!(status.exit_code(), 1);
+
+        status.reset();
+        assert_eq!(status.exit_code(), 0);
+        // rustfmt can't parse it.
+        let source = "fn a( {\n";
+        let tree = parse(source);
+        let violations = RustfmtRule::default().check(source.as_bytes(), &tree);
+        assert_eq!(violations[0].message_id, RUSTFMT_FAILED);
+        status.record(RULE_NAME, &violations);
+        assert!(!status.has_violations());
+        assert!(status.has_failures());
+        assert_eq!(status.exit_code(), 1);
+    }
+}
@@ -0,0 +1,1260 @@
+use std::{
+    borrow::Cow,
+    env,
+    error::Error,
+    ffi::OsStr,
+    fmt,
+    io::{self, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    str::{self, Utf8Error},
+    string::FromUtf8Error,
+    sync::{mpsc, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    debug_dump::{record_exchange, record_spawn},
+    fallback::diff_mismatches,
+    ignored_unstable_settings,
+    memo::Memo,
+    mirror::{run_on_temp_file, InputMode},
+    parse_stderr, FileMismatches, MismatchFormat, RopeOrSlice, RustfmtVersion, StderrDiagnostic,
+    StyleProfile, STDIN_FILE_NAME,
+};
+
+/// How to invoke rustfmt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RustfmtOptions {
+    /// The rustup toolchain to run rustfmt from (passed as `+<toolchain>`).
+    /// `--emit json` is unstable, so this defaults to `nightly`. Other
+    /// toolchains work too, just more slowly (see
+    /// [`supports_emit_json()`](Self::supports_emit_json)).
+    pub toolchain: Option<String>,
+    /// The executable to run instead of `rustfmt` (looked up on `PATH`, or
+    /// else in Cargo's `bin` directory, where rustup installs its proxies),
+    /// eg a wrapper script or a rustfmt that's somewhere else. It has to take
+    /// rustfmt's arguments and emit what rustfmt would.
+    pub command: Option<PathBuf>,
+    /// Arguments passed before rustfmt's own (but after the toolchain), eg
+    /// `fmt-wrapped --` for a `command` of `cargo`.
+    pub args_prefix: Vec<String>,
+    /// rustfmt's `skip_macro_invocations` setting.
+    pub skip_macro_invocations: Option<SkipMacroInvocations>,
+    /// The rustfmt config file to use instead of the one rustfmt would
+    /// [discover](crate::discover_config) from the current directory.
+    pub config_path: Option<PathBuf>,
+    /// rustfmt's (unstable) `format_macro_matchers` setting: whether to
+    /// format the `($a:expr)` side of `macro_rules!` arms.
+    pub format_macro_matchers: Option<bool>,
+    /// rustfmt's (unstable) `format_macro_bodies` setting: whether to format
+    /// the bodies of `macro_rules!` arms.
+    pub format_macro_bodies: Option<bool>,
+    /// The edition to format as (passed as `--edition`). Unset, the rule
+    /// uses the edition of the Cargo package containing the current directory
+    /// (see [`discover_edition()`](crate::discover_edition)), since rustfmt
+    /// would otherwise format stdin as 2015 code.
+    pub edition: Option<String>,
+    /// How long rustfmt gets to format a file (once it's been handed the
+    /// source) before it's killed, since it can hang on pathological input.
+    /// Defaults to 30 seconds.
+    pub timeout: Option<Duration>,
+    /// How many rustfmt processes run at once, across all the threads (eg of
+    /// a host linting files in parallel) and `run_rustfmt_async()` tasks,
+    /// with the rest waiting their turn. Every limited run counts against
+    /// every other, so with different limits about, the most that run at once
+    /// is the highest of them. A [`RustfmtPool`](crate::RustfmtPool)'s
+    /// processes aren't counted at all, since it's sized itself. See also
+    /// [`PluginBuilder::max_concurrent_formatters()`](crate::PluginBuilder::max_concurrent_formatters).
+    /// Defaults to the
+    /// [`MAX_CONCURRENT_RUNS_VAR`](crate::MAX_CONCURRENT_RUNS_VAR) environment
+    /// variable, or no limit.
+    pub max_concurrent_runs: Option<usize>,
+    /// The house style to format with (see [`StyleProfile`]).
+    pub profile: StyleProfile,
+    /// Run rustfmt with only the environment variables in [`env`](Self::env)
+    /// rather than this process's, eg in a hermetic build (Bazel, Buck) that
+    /// pins everything rustfmt (and rustup's proxy) may read.
+    pub env_clear: bool,
+    /// Environment variables to set for rustfmt, eg a pinned `RUSTUP_HOME`
+    /// or `CARGO_HOME`, or to remove if their value is `None`.
+    pub env: Vec<(String, Option<String>)>,
+    /// The directory to run rustfmt in instead of the current directory. It's
+    /// also where the config file and edition are discovered from.
+    pub current_dir: Option<PathBuf>,
+    /// The file being formatted, to name in rustfmt's errors instead of the
+    /// `<stdin>` it reads it from, eg in a run over many files.
+    /// [`RustfmtRule::check_as()`](crate::RustfmtRule::check_as) sets it.
+    pub file_name: Option<PathBuf>,
+    /// How rustfmt is handed the source, by [`run_rustfmt()`] and
+    /// [`run_rustfmt_on_lines()`] (and so by the rule).
+    pub input_mode: InputMode,
+    /// A directory to write the arguments, stdin, stdout, stderr and exit
+    /// status of every rustfmt run to, each in a file of its own named after
+    /// when the run started, eg to attach to a bug report about how the rule
+    /// maps rustfmt's output. Nothing is left out, since it's all local.
+    pub debug_dump_dir: Option<PathBuf>,
+    /// Fail runs in which rustfmt ignored settings of its config file for
+    /// being unstable, as it does on a stable toolchain (with
+    /// [`RustfmtError::IgnoredUnstableSettings`]), rather than warning about
+    /// them once.
+    pub strict_config: bool,
+}
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl Default for RustfmtOptions {
+    fn default() -> Self {
+        Self {
+            toolchain: Some("nightly".to_owned()),
+            command: Default::default(),
+            args_prefix: Default::default(),
+            skip_macro_invocations: Default::default(),
+            config_path: Default::default(),
+            format_macro_matchers: Default::default(),
+            format_macro_bodies: Default::default(),
+            edition: Default::default(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            max_concurrent_runs: Default::default(),
+            profile: Default::default(),
+            env_clear: Default::default(),
+            env: Default::default(),
+            current_dir: Default::default(),
+            file_name: Default::default(),
+            input_mode: Default::default(),
+            debug_dump_dir: Default::default(),
+            strict_config: Default::default(),
+        }
+    }
+}
+
+/// Macro invocations whose contents rustfmt should leave alone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkipMacroInvocations {
+    All,
+    /// Macro names as written at the invocation, eg `foo` or `foo::bar`.
+    Names(Vec<String>),
+}
+
+impl SkipMacroInvocations {
+    /// The first rustfmt release that supports `skip_macro_invocations`.
+    pub const MIN_RUSTFMT_VERSION: RustfmtVersion = RustfmtVersion::new(1, 5, 2);
+
+    pub fn matches(&self, macro_name: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Names(names) => names.iter().any(|name| name == macro_name),
+        }
+    }
+
+    /// The value to pass via `--config`, if this can be expressed there.
+    /// rustfmt splits `--config` on commas, so at most one name fits.
+    pub fn config_value(&self) -> Option<String> {
+        match self {
+            Self::All => Some(r#"["*"]"#.to_owned()),
+            Self::Names(names) => match names.as_slice() {
+                [] => Some("[]".to_owned()),
+                [name] => Some(serde_json::to_string(&[name]).unwrap()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl RustfmtOptions {
+    /// What rustfmt's errors call the source, which is
+    /// [`STDIN_FILE_NAME`](crate::STDIN_FILE_NAME) unless there's a
+    /// [`file_name`](Self::file_name).
+    pub(crate) fn stdin_file_name(&self) -> Cow<'_, str> {
+        self.file_name
+            .as_deref()
+            .map_or(Cow::Borrowed(STDIN_FILE_NAME), Path::to_string_lossy)
+    }
+
+    /// `result` with rustfmt's errors naming the [`file_name`](Self::file_name)
+    /// rather than `<stdin>`, once what they point to has been parsed out.
+    pub(crate) fn name_stdin<T>(&self, result: Result<T, RustfmtError>) -> Result<T, RustfmtError> {
+        match result {
+            Err(RustfmtError::NonZeroExit { status, stderr }) if self.file_name.is_some() => {
+                Err(RustfmtError::NonZeroExit {
+                    status,
+                    stderr: stderr.replace(STDIN_FILE_NAME, &self.stdin_file_name()),
+                })
+            }
+            result => result,
+        }
+    }
+
+    /// Files are formatted on their own, like a single file is by `cargo fmt`
+    /// rather than with the files of its `mod foo;` declarations: stdin has
+    /// no path to find them from anyway, and `--skip-children` makes sure of
+    /// it where there are paths (eg [`format_files_batch()`](crate::format_files_batch)).
+    /// It's unstable, so rustfmts that can't `--emit json` don't get it, and
+    /// only format stdin.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = self.leading_args();
+        if self.supports_emit_json() {
+            args.extend(
+                ["--unstable-features", "--skip-children", "--emit", "json"].map(ToOwned::to_owned),
+            );
+        } else {
+            args.extend(["--emit", "stdout"].map(ToOwned::to_owned));
+        }
+        if let Some(edition) = self.edition.as_ref() {
+            args.push("--edition".to_owned());
+            args.push(edition.clone());
+        }
+        if let Some(config_path) = self.config_path.as_ref() {
+            args.push("--config-path".to_owned());
+            args.push(config_path.to_string_lossy().into_owned());
+        }
+        if let Some(config_value) = self
+            .skip_macro_invocations
+            .as_ref()
+            .and_then(SkipMacroInvocations::config_value)
+        {
+            args.push("--config".to_owned());
+            args.push(format!("skip_macro_invocations={config_value}"));
+        }
+        for (name, value) in [
+            ("format_macro_matchers", self.format_macro_matchers),
+            ("format_macro_bodies", self.format_macro_bodies),
+        ] {
+            if let Some(value) = value {
+                args.push("--config".to_owned());
+                args.push(format!("{name}={value}"));
+            }
+        }
+        let mut overrides = self.profile.config_overrides().to_vec();
+        if self.supports_emit_json() {
+            overrides.extend(self.profile.unstable_config_overrides());
+        }
+        for setting in overrides {
+            args.push("--config".to_owned());
+            args.push(setting.to_owned());
+        }
+        args
+    }
+
+    /// The toolchain (for rustup's proxy) and
+    /// [`args_prefix`](Self::args_prefix).
+    fn leading_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(toolchain) = self.toolchain.as_ref() {
+            args.push(format!("+{toolchain}"));
+        }
+        args.extend(self.args_prefix.iter().cloned());
+        args
+    }
+
+    /// How rustfmt's environment differs from this process's, as the
+    /// arguments `env` would be given to run rustfmt like this (so that they
+    /// can be part of what identifies an invocation).
+    pub(crate) fn environment(&self) -> Vec<String> {
+        let mut environment = Vec::new();
+        if self.env_clear {
+            environment.push("-i".to_owned());
+        }
+        if let Some(current_dir) = self.current_dir.as_ref() {
+            environment.push("-C".to_owned());
+            environment.push(current_dir.to_string_lossy().into_owned());
+        }
+        for (name, value) in &self.env {
+            match value {
+                Some(value) => environment.push(format!("{name}={value}")),
+                None => {
+                    environment.push("-u".to_owned());
+                    environment.push(name.clone());
+                }
+            }
+        }
+        if !environment.is_empty() {
+            environment.insert(0, "env".to_owned());
+        }
+        environment
+    }
+
+    /// The directory rustfmt runs in (see [`current_dir`](Self::current_dir)).
+    pub(crate) fn working_dir(&self) -> Option<PathBuf> {
+        self.current_dir.clone().or_else(|| env::current_dir().ok())
+    }
+
+    pub(crate) fn program(&self) -> &Path {
+        static DEFAULT_PROGRAM: OnceLock<PathBuf> = OnceLock::new();
+        self.command.as_deref().unwrap_or_else(|| {
+            DEFAULT_PROGRAM.get_or_init(|| {
+                let cargo_home = env::var_os("CARGO_HOME").map(PathBuf::from).or_else(|| {
+                    env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+                        .map(|home| Path::new(&home).join(".cargo"))
+                });
+                find_program(env::var_os("PATH").as_deref(), cargo_home.as_deref())
+            })
+        })
+    }
+
+    /// The program and leading arguments, which is what's probed (once) for
+    /// the rustfmt these options invoke.
+    pub(crate) fn invocation(&self) -> Vec<String> {
+        let mut invocation = self.environment();
+        invocation.push(self.program().to_string_lossy().into_owned());
+        invocation.extend(self.leading_args());
+        invocation
+    }
+
+    /// A command running the rustfmt these options invoke, with just the
+    /// leading arguments.
+    pub(crate) fn base_command(&self) -> Command {
+        let mut command = self.program_command();
+        command.args(self.leading_args());
+        command
+    }
+
+    /// A command running the program, in the environment these options set
+    /// up.
+    fn program_command(&self) -> Command {
+        let mut command = Command::new(self.program());
+        if self.env_clear {
+            command.env_clear();
+        }
+        for (name, value) in &self.env {
+            match value {
+                Some(value) => command.env(name, value),
+                None => command.env_remove(name),
+            };
+        }
+        if let Some(current_dir) = self.current_dir.as_ref() {
+            command.current_dir(current_dir);
+        }
+        command
+    }
+
+    /// Whether the rustfmt these options invoke accepts `--file-lines`
+    /// (which is unstable, and missing from some builds). Probed once per
+    /// command and toolchain, and cached for the rest of the process, warning once if not.
+    pub fn supports_file_lines(&self) -> bool {
+        static SUPPORTED: OnceLock<Memo<Vec<String>, bool>> = OnceLock::new();
+
+        // Probed with the toolchain's entry locked, so that concurrent checks
+        // don't all warn.
+        let supported = SUPPORTED.get_or_init(Default::default);
+        supported.get_or_insert_with(&self.invocation(), || {
+            let error = match self.probe_file_lines() {
+                Ok(()) => return true,
+                Err(error) => error,
+            };
+            log::warn!(
+                "{} doesn't support --file-lines ({error}), formatting whole files instead",
+                self.invocation().join(" ")
+            );
+            false
+        })
+    }
+
+    fn probe_file_lines(&self) -> Result<(), RustfmtError> {
+        let output = self
+            .base_command()
+            .args([
+                "--unstable-features",
+                "--emit",
+                "json",
+                "--file-lines",
+                "[]",
+            ])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(RustfmtError::SpawnFailed)?;
+        if !output.status.success() {
+            return Err(RustfmtError::NonZeroExit {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the rustfmt these options invoke accepts `--emit json`, which
+    /// is unstable. Otherwise (eg on a stable toolchain) rustfmt is run
+    /// emitting the formatted source, and the mismatches it would have
+    /// reported are worked out from a diff of that. Probed once per command
+    /// and toolchain, and cached for the rest of the process, warning once if not.
+    pub fn supports_emit_json(&self) -> bool {
+        static SUPPORTED: OnceLock<Memo<Vec<String>, bool>> = OnceLock::new();
+
+        let supported = SUPPORTED.get_or_init(Default::default);
+        supported.get_or_insert_with(&self.invocation(), || {
+            // A rustfmt that can't be run at all gets reported as such when
+            // it's run.
+            if self.version().is_err() {
+                return true;
+            }
+            let error = match self.probe_emit_json() {
+                Ok(()) => return true,
+                Err(error) => error,
+            };
+            log::warn!(
+                "{} doesn't support --emit json ({error}), diffing its output instead",
+                self.invocation().join(" ")
+            );
+            false
+        })
+    }
+
+    fn probe_emit_json(&self) -> Result<(), RustfmtError> {
+        let output = self
+            .base_command()
+            .args(["--unstable-features", "--emit", "json"])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(RustfmtError::SpawnFailed)?;
+        if !output.status.success() {
+            return Err(RustfmtError::NonZeroExit {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The format rustfmt's output is in, or `None` for the formatted source
+    /// (see [`supports_emit_json()`](Self::supports_emit_json)).
+    pub(crate) fn output_format(&self) -> Result<Option<MismatchFormat>, RustfmtError> {
+        if !self.supports_emit_json() {
+            return Ok(None);
+        }
+        self.mismatch_format().map(Some)
+    }
+
+    pub(crate) fn spawn(&self) -> Result<Child, RustfmtError> {
+        self.spawn_with_args(&[])
+    }
+
+    pub(crate) fn spawn_with_args(&self, extra_args: &[String]) -> Result<Child, RustfmtError> {
+        let mut command = self.command(extra_args);
+        let child = command.spawn().map_err(RustfmtError::SpawnFailed)?;
+        record_spawn(self, &command, child.id());
+        Ok(child)
+    }
+
+    /// rustfmt's command line, with its stdio piped.
+    pub(crate) fn command(&self, extra_args: &[String]) -> Command {
+        let mut command = self.program_command();
+        command
+            .args(self.args())
+            .args(extra_args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    }
+}
+
+/// `rustfmt` if it's on `path_var`, or else rustup's proxy in `cargo_home`
+/// if there is one (eg for an editor on Windows that was started before
+/// rustup added it to `PATH`).
+fn find_program(path_var: Option<&OsStr>, cargo_home: Option<&Path>) -> PathBuf {
+    let file_name = format!("rustfmt{}", env::consts::EXE_SUFFIX);
+    let is_on_path = path_var
+        .is_some_and(|dirs| env::split_paths(dirs).any(|dir| dir.join(&file_name).is_file()));
+    cargo_home
+        .map(|cargo_home| cargo_home.join("bin").join(&file_name))
+        .filter(|proxy| !is_on_path && proxy.is_file())
+        .unwrap_or_else(|| "rustfmt".into())
+}
+
+#[derive(Debug)]
+pub enum RustfmtError {
+    /// rustfmt couldn't be started, eg because it (or the requested
+    /// toolchain's rustup proxy) isn't installed.
+    SpawnFailed(io::Error),
+    /// Talking to an already-started rustfmt failed.
+    Io(io::Error),
+    /// rustfmt ran but failed, usually because the input doesn't parse.
+    NonZeroExit { status: ExitStatus, stderr: String },
+    /// rustfmt's output (or the text handed to it) wasn't valid UTF-8.
+    Encoding(Utf8Error),
+    /// rustfmt's output didn't have the expected shape.
+    OutputParse(String),
+    /// rustfmt was killed for taking longer than
+    /// [`RustfmtOptions::timeout`].
+    TimedOut(Duration),
+    /// The rustfmt's `--emit json` output isn't in a known
+    /// [format](MismatchFormat).
+    UnsupportedVersion(RustfmtVersion),
+    /// rustfmt formatted the file but reported (on stderr) what it couldn't
+    /// format properly, eg lines it couldn't fit within `max_width` (with
+    /// `error_on_line_overflow` set) or trailing whitespace it left behind.
+    /// `files` are its mismatches, as usual.
+    LeftUnformatted {
+        files: Vec<FileMismatches>,
+        diagnostics: Vec<StderrDiagnostic>,
+    },
+    /// rustfmt ignored these unstable settings, which only a nightly
+    /// toolchain applies, with [`RustfmtOptions::strict_config`] set (see
+    /// [`ignored_unstable_settings()`](crate::ignored_unstable_settings)).
+    IgnoredUnstableSettings(Vec<String>),
+}
+
+impl fmt::Display for RustfmtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SpawnFailed(error) => write!(f, "couldn't start rustfmt: {error}"),
+            Self::Io(error) => write!(f, "{error}"),
+            Self::NonZeroExit { status, stderr } => {
+                write!(f, "rustfmt exited with {status}: {stderr}")
+            }
+            Self::Encoding(error) => write!(f, "invalid UTF-8: {error}"),
+            Self::OutputParse(message) => write!(f, "unexpected rustfmt output: {message}"),
+            Self::TimedOut(timeout) => write!(f, "rustfmt didn't finish within {timeout:?}"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "rustfmt {version} isn't supported (the supported versions are {})",
+                MismatchFormat::SUPPORTED_VERSIONS
+                    .map(|(requirement, _)| requirement)
+                    .join("; ")
+            ),
+            Self::LeftUnformatted { diagnostics, .. } => write!(
+                f,
+                "rustfmt couldn't format everything: {}",
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            Self::IgnoredUnstableSettings(settings) => write!(
+                f,
+                "rustfmt ignored unstable settings, which only a nightly toolchain applies: {}",
+                settings.join(", ")
+            ),
+        }
+    }
+}
+
+impl Error for RustfmtError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::SpawnFailed(error) | Self::Io(error) => Some(error),
+            Self::Encoding(error) => Some(error),
+            Self::NonZeroExit { .. }
+            | Self::OutputParse(_)
+            | Self::TimedOut(_)
+            | Self::UnsupportedVersion(_)
+            | Self::LeftUnformatted { .. }
+            | Self::IgnoredUnstableSettings(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for RustfmtError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<Utf8Error> for RustfmtError {
+    fn from(error: Utf8Error) -> Self {
+        Self::Encoding(error)
+    }
+}
+
+impl From<FromUtf8Error> for RustfmtError {
+    fn from(error: FromUtf8Error) -> Self {
+        Self::Encoding(error.utf8_error())
+    }
+}
+
+impl From<serde_json::Error> for RustfmtError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::OutputParse(error.to_string())
+    }
+}
+
+/// Runs rustfmt over `source` (as stdin, unless the [`input_mode`](RustfmtOptions::input_mode)
+/// says otherwise) and returns its `--emit json` output.
+pub fn run_rustfmt(
+    source: &[u8],
+    options: &RustfmtOptions,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    if options.uses_temp_file() {
+        return run_on_temp_file(source, options, |_| Default::default());
+    }
+    options.limited(|| communicate(options.spawn()?, source.into(), options))
+}
+
+/// Like [`run_rustfmt()`] but only formats the given lines, which are 0-based
+/// half-open row ranges (as in tree-sitter `Point`s). Formats the whole of
+/// `source` if the rustfmt doesn't [support](RustfmtOptions::supports_file_lines)
+/// `--file-lines`.
+pub fn run_rustfmt_on_lines(
+    source: &[u8],
+    options: &RustfmtOptions,
+    lines: &[Range<usize>],
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    if !options.supports_file_lines() {
+        return run_rustfmt(source, options);
+    }
+    if options.uses_temp_file() {
+        return run_on_temp_file(source, options, |path| {
+            file_lines_args_for(lines, path).to_vec()
+        });
+    }
+    options.limited(|| {
+        communicate(
+            options.spawn_with_args(&file_lines_args(lines))?,
+            source.into(),
+            options,
+        )
+    })
+}
+
+/// rustfmt's `--file-lines` takes 1-based inclusive ranges.
+pub fn file_lines_args(lines: &[Range<usize>]) -> [String; 2] {
+    file_lines_args_for(lines, "stdin")
+}
+
+/// [`file_lines_args()`] for the file at `path` rather than stdin.
+fn file_lines_args_for(lines: &[Range<usize>], path: &str) -> [String; 2] {
+    [
+        "--file-lines".to_owned(),
+        serde_json::to_string(
+            &lines
+                .iter()
+                .filter(|lines| !lines.is_empty())
+                .map(|lines| {
+                    serde_json::json!({
+                        "file": path,
+                        "range": [lines.start + 1, lines.end],
+                    })
+                })
+                .collect::<Vec<_>>(),
+        )
+        .unwrap(),
+    ]
+}
+
+pub(crate) fn communicate(
+    child: Child,
+    source: RopeOrSlice,
+    options: &RustfmtOptions,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+    let result = exchange(child, source, options);
+    #[cfg(feature = "metrics")]
+    crate::metrics::record(started.elapsed(), source.len(), &result);
+    result
+}
+
+fn exchange(
+    child: Child,
+    source: RopeOrSlice,
+    options: &RustfmtOptions,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    let output = write_and_wait(child, source, options)?;
+    options.name_stdin(parse_output(output, source, options.output_format()?))
+}
+
+/// Hands `source` to `child` and waits for its output. `source` is written
+/// from another thread while the output is read, since rustfmt can fill its
+/// stdout pipe (and stop reading stdin) before it's read all of a large file.
+pub(crate) fn write_and_wait(
+    mut child: Child,
+    source: RopeOrSlice,
+    options: &RustfmtOptions,
+) -> Result<Output, RustfmtError> {
+    let child_id = child.id();
+    let mut stdin = child.stdin.take().ok_or_else(|| {
+        RustfmtError::Io(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "rustfmt's stdin isn't piped",
+        ))
+    })?;
+    let (written, output) = thread::scope(|scope| {
+        let writer = scope.spawn(move || {
+            for chunk in source.chunks() {
+                stdin.write_all(chunk)?;
+            }
+            // Closing stdin, which is what rustfmt waits for.
+            drop(stdin);
+            io::Result::Ok(())
+        });
+        let output = match options.timeout {
+            Some(timeout) => wait_with_timeout(child, timeout),
+            None => child.wait_with_output().map_err(RustfmtError::from),
+        };
+        let written = writer
+            .join()
+            .expect("writing rustfmt's stdin shouldn't panic");
+        (written, output)
+    });
+    record_exchange(options, child_id, source, output.as_ref());
+    let output = output?;
+    check_unstable_settings(options, &output)?;
+    match written {
+        // rustfmt stopped reading (eg it failed reading its config), which its
+        // exit status and stderr should explain.
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe || output.status.success() => {
+            Err(error.into())
+        }
+        _ => Ok(output),
+    }
+}
+
+/// Warns (once per rustfmt and set of settings) about the unstable settings
+/// a finished rustfmt ignored, or fails with them if `options` are
+/// [strict](RustfmtOptions::strict_config).
+pub(crate) fn check_unstable_settings(
+    options: &RustfmtOptions,
+    output: &Output,
+) -> Result<(), RustfmtError> {
+    static WARNED: OnceLock<Memo<Vec<String>, ()>> = OnceLock::new();
+
+    let settings = ignored_unstable_settings(&String::from_utf8_lossy(&output.stderr));
+    if settings.is_empty() {
+        return Ok(());
+    }
+    if options.strict_config {
+        return Err(RustfmtError::IgnoredUnstableSettings(settings));
+    }
+    let mut key = options.invocation();
+    key.extend(settings.iter().cloned());
+    WARNED
+        .get_or_init(Default::default)
+        .get_or_insert_with(&key, || {
+            log::warn!(
+                "{} ignored unstable settings, which only a nightly toolchain applies: {}",
+                options.invocation().join(" "),
+                settings.join(", ")
+            );
+        });
+    Ok(())
+}
+
+/// The mismatches in the output of a finished rustfmt over `source`, which is
+/// in `format`, or the formatted source for `None`.
+pub(crate) fn parse_output(
+    output: Output,
+    source: RopeOrSlice,
+    format: Option<MismatchFormat>,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    // rustfmt's own errors (which it exits with 1 for) come with its output
+    // for the rest of the file, as long as there aren't others.
+    let diagnostics = parse_stderr(&String::from_utf8_lossy(&output.stderr));
+    let is_left_unformatted = diagnostics.iter().any(StderrDiagnostic::is_internal)
+        && diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.level == "error")
+            .all(StderrDiagnostic::is_internal);
+    if is_left_unformatted {
+        let files = str::from_utf8(&output.stdout)
+            .map_err(RustfmtError::from)
+            .and_then(|stdout| parse_stdout(stdout, source, format));
+        if let Ok(files) = files {
+            return Err(RustfmtError::LeftUnformatted {
+                files,
+                diagnostics: diagnostics
+                    .into_iter()
+                    .filter(StderrDiagnostic::is_internal)
+                    .collect(),
+            });
+        }
+    }
+    parse_stdout(&stdout(output)?, source, format)
+}
+
+/// The stdout of a rustfmt that succeeded.
+pub(crate) fn stdout(output: Output) -> Result<String, RustfmtError> {
+    if !output.status.success() {
+        return Err(RustfmtError::NonZeroExit {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// The mismatches in what rustfmt wrote to stdout for `source`, which is in
+/// `format`, or the formatted source for `None`.
+pub(crate) fn parse_stdout(
+    stdout: &str,
+    source: RopeOrSlice,
+    format: Option<MismatchFormat>,
+) -> Result<Vec<FileMismatches>, RustfmtError> {
+    match format {
+        Some(format) => Ok(format.parse(stdout)?),
+        None => {
+            let source = source.chunks().flatten().copied().collect::<Vec<_>>();
+            Ok(diff_mismatches(str::from_utf8(&source)?, stdout))
+        }
+    }
+}
+
+/// Like [`Child::wait_with_output()`], but killing `child` if its output
+/// isn't complete within `timeout`.
+pub(crate) fn wait_with_timeout(
+    mut child: Child,
+    timeout: Duration,
+) -> Result<Output, RustfmtError> {
+    fn read_all(mut pipe: impl Read) -> io::Result<Vec<u8>> {
+        let mut read = Vec::new();
+        pipe.read_to_end(&mut read)?;
+        Ok(read)
+    }
+
+    let (stdout, stderr) = child
+        .stdout
+        .take()
+        .zip(child.stderr.take())
+        .ok_or_else(|| {
+            RustfmtError::Io(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "rustfmt's output isn't piped",
+            ))
+        })?;
+    // The pipes close when rustfmt exits, so reading them to the end is
+    // waiting for it.
+    let (sender, receiver) = mpsc::channel();
+    let stderr = thread::spawn(move || read_all(stderr));
+    thread::spawn(move || sender.send(read_all(stdout)));
+    let stdout = match receiver.recv_timeout(timeout) {
+        Ok(stdout) => stdout?,
+        Err(_) => {
+            if let Err(error) = child.kill() {
+                log::warn!("couldn't kill rustfmt: {error}");
+            }
+            child.wait()?;
+            return Err(RustfmtError::TimedOut(timeout));
+        }
+    };
+    let stderr = stderr.join().expect("reading stderr shouldn't panic")?;
+    Ok(Output {
+        status: child.wait()?,
+        stdout,
+        stderr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{parse_mismatches, Mismatch, STDIN_FILE_NAME};
+
+    #[test]
+    fn test_args() {
+        assert_eq!(
+            RustfmtOptions::default().args(),
+            [
+                "+nightly",
+                "--unstable-features",
+                "--skip-children",
+                "--emit",
+                "json"
+            ]
+        );
+        let options = RustfmtOptions {
+            toolchain: None,
+            ..Default::default()
+        };
+        let emit_args: &[&str] = if options.supports_emit_json() {
+            &["--unstable-features", "--skip-children", "--emit", "json"]
+        } else {
+            &["--emit", "stdout"]
+        };
+        assert_eq!(options.args(), emit_args);
+        assert_eq!(
+            RustfmtOptions {
+                skip_macro_invocations: Some(SkipMacroInvocations::All),
+                ..Default::default()
+            }
+            .args()[5..],
+            ["--config", r#"skip_macro_invocations=["*"]"#]
+        );
+        assert_eq!(
+            RustfmtOptions {
+                skip_macro_invocations: Some(SkipMacroInvocations::Names(vec!["foo".to_owned()])),
+                ..Default::default()
+            }
+            .args()[5..],
+            ["--config", r#"skip_macro_invocations=["foo"]"#]
+        );
+        assert_eq!(
+            RustfmtOptions {
+                skip_macro_invocations: Some(SkipMacroInvocations::Names(vec![
+                    "foo".to_owned(),
+                    "bar".to_owned()
+                ])),
+                ..Default::default()
+            }
+            .args()
+            .len(),
+            5
+        );
+        assert_eq!(
+            RustfmtOptions {
+                format_macro_matchers: Some(true),
+                format_macro_bodies: Some(false),
+                ..Default::default()
+            }
+            .args()[5..],
+            [
+                "--config",
+                "format_macro_matchers=true",
+                "--config",
+                "format_macro_bodies=false"
+            ]
+        );
+        assert_eq!(
+            RustfmtOptions {
+                edition: Some("2021".to_owned()),
+                ..Default::default()
+            }
+            .args()[5..],
+            ["--edition", "2021"]
+        );
+    }
+
+    #[test]
+    fn test_find_program() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        fs::create_dir(&bin).unwrap();
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        assert_eq!(
+            find_program(Some(empty.as_os_str()), Some(dir.path())),
+            PathBuf::from("rustfmt")
+        );
+        let proxy = bin.join(format!("rustfmt{}", env::consts::EXE_SUFFIX));
+        fs::write(&proxy, "").unwrap();
+        assert_eq!(
+            find_program(Some(empty.as_os_str()), Some(dir.path())),
+            proxy
+        );
+        assert_eq!(
+            find_program(Some(bin.as_os_str()), Some(dir.path())),
+            PathBuf::from("rustfmt")
+        );
+        assert_eq!(find_program(None, None), PathBuf::from("rustfmt"));
+    }
+
+    #[test]
+    fn test_file_lines_args() {
+        assert_eq!(
+            file_lines_args(&[0..1, 3..3, 4..7]),
+            [
+                "--file-lines",
+                r#"[{"file":"stdin","range":[1,1]},{"file":"stdin","range":[5,7]}]"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_rustfmt_on_lines() {
+        let files = run_rustfmt_on_lines(
+            b"fn  a() {}
+fn  b() {}
+",
+            &Default::default(),
+            &[1..2, 1..1],
+        )
+        .unwrap();
+        assert_eq!(files[0].mismatches.len(), 1);
+        assert_eq!(files[0].mismatches[0].original, "fn  b() {}\n");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let options = RustfmtOptions {
+            timeout: Some(Duration::from_nanos(1)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            run_rustfmt(b"fn a() {}\n", &options),
+            Err(RustfmtError::TimedOut(_))
+        ));
+        assert!(run_rustfmt(b"fn a() {}\n", &Default::default()).is_ok());
+    }
+
+    #[test]
+    fn test_supports_file_lines() {
+        assert!(RustfmtOptions::default().supports_file_lines());
+        let stable = RustfmtOptions {
+            toolchain: Some("stable".to_owned()),
+            ..Default::default()
+        };
+        assert!(!stable.supports_file_lines());
+        assert!(!stable.supports_file_lines());
+    }
+
+    #[test]
+    fn test_run_rustfmt() {
+        assert_eq!(
+            run_rustfmt(b"fn main( ) {\nlet x=1;\n}\n", &Default::default()).unwrap(),
+            vec![FileMismatches {
+                name: STDIN_FILE_NAME.to_owned(),
+                mismatches: vec![Mismatch {
+                    original_begin_line: 1,
+                    original_end_line: 2,
+                    expected_begin_line: 1,
+                    expected_end_line: 2,
+                    original: "fn main( ) {\nlet x=1;\n".to_owned(),
+                    expected: "fn main() {\n    let x = 1;\n".to_owned(),
+                }],
+            }]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = dir.path().join("fmt-wrapper");
+        fs::write(
+            &wrapper,
+            "#!/bin/sh\n[ \"$2\" = fmt-wrapped ] || exit 2\ntoolchain=$1\nshift 2\nexec rustfmt \"$toolchain\" \"$@\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+        let options = RustfmtOptions {
+            command: Some(wrapper),
+            args_prefix: vec!["fmt-wrapped".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(
+            options.args()[..3],
+            ["+nightly", "fmt-wrapped", "--unstable-features"]
+        );
+        assert_eq!(
+            options.version().unwrap(),
+            RustfmtOptions::default().version().unwrap()
+        );
+        assert_eq!(
+            run_rustfmt(b"fn  a() {}\n", &options).unwrap(),
+            run_rustfmt(b"fn  a() {}\n", &Default::default()).unwrap()
+        );
+
+        assert!(matches!(
+            run_rustfmt(
+                b"fn a() {}\n",
+                &RustfmtOptions {
+                    command: Some(dir.path().join("no-such-rustfmt")),
+                    ..Default::default()
+                }
+            ),
+            Err(RustfmtError::SpawnFailed(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_environment() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = dir.path().join("fmt-wrapper");
+        fs::write(
+            &wrapper,
+            "#!/bin/sh\n[ \"$SANDBOXED\" = 1 ] && [ -z \"$CARGO_PKG_NAME\" ] && [ -e fmt-wrapper ] || exit 2\nexec rustfmt \"$@\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut env = [
+            "PATH",
+            "HOME",
+            "RUSTUP_HOME",
+            "CARGO_HOME",
+            "RUSTUP_TOOLCHAIN",
+        ]
+        .into_iter()
+        .filter_map(|name| Some((name.to_owned(), Some(env::var(name).ok()?))))
+        .collect::<Vec<_>>();
+        env.push(("SANDBOXED".to_owned(), Some("1".to_owned())));
+        let options = RustfmtOptions {
+            command: Some(wrapper.clone()),
+            env_clear: true,
+            env,
+            current_dir: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.environment()[..4],
+            ["env", "-i", "-C", &*dir.path().to_string_lossy()]
+        );
+        assert_eq!(
+            run_rustfmt(b"fn  a() {}\n", &options).unwrap(),
+            run_rustfmt(b"fn  a() {}\n", &Default::default()).unwrap()
+        );
+
+        let options = RustfmtOptions {
+            command: Some(wrapper),
+            env: vec![("SANDBOXED".to_owned(), None)],
+            ..Default::default()
+        };
+        assert_eq!(options.environment(), ["env", "-u", "SANDBOXED"]);
+        assert!(matches!(
+            run_rustfmt(b"fn a() {}\n", &options),
+            Err(RustfmtError::NonZeroExit { .. })
+        ));
+    }
+
+    #[test]
+    fn test_run_rustfmt_clean() {
+        assert_eq!(
+            run_rustfmt(b"fn main() {}\n", &Default::default()).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_run_rustfmt_syntax_error() {
+        assert!(matches!(
+            run_rustfmt(b"fn main( {\n", &Default::default()),
+            Err(RustfmtError::NonZeroExit { stderr, .. })
+                if stderr.contains("unclosed delimiter")
+        ));
+    }
+
+    /// A `sh` stand-in for rustfmt that closes its stdin straight away.
+    #[cfg(unix)]
+    fn spawn_closing_stdin(script: &str) -> Child {
+        Command::new("sh")
+            .args(["-c", &format!("exec 0<&-; {script}")])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stdin_closed_early() {
+        // Bigger than a pipe's buffer, so that writing it fails.
+        let source = "fn a() {}\n".repeat(100_000);
+        let options = RustfmtOptions::default();
+        assert!(matches!(
+            communicate(
+                spawn_closing_stdin("echo 'error: internal error' >&2; exit 101"),
+                source.as_bytes().into(),
+                &options
+            ),
+            Err(RustfmtError::NonZeroExit { status, stderr })
+                if status.code() == Some(101) && stderr == "error: internal error\n"
+        ));
+        assert!(matches!(
+            communicate(spawn_closing_stdin("exit 0"), source.as_bytes().into(), &options),
+            Err(RustfmtError::Io(error)) if error.kind() == io::ErrorKind::BrokenPipe
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        fs::write(&config_path, "max_width = \"wide\"\n").unwrap();
+        assert!(matches!(
+            run_rustfmt(
+                source.as_bytes(),
+                &RustfmtOptions {
+                    config_path: Some(config_path),
+                    ..Default::default()
+                }
+            ),
+            Err(RustfmtError::NonZeroExit { stderr, .. }) if stderr.contains("max_width")
+        ));
+    }
+
+    /// A stand-in for rustfmt that writes output before it's read all of its
+    /// input, which would block on a full stdout pipe if it wasn't read.
+    #[cfg(unix)]
+    #[test]
+    fn test_output_before_input_is_read() {
+        let source = "fn a() {}\n".repeat(100_000);
+        let child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let output =
+            write_and_wait(child, source.as_bytes().into(), &RustfmtOptions::default()).unwrap();
+        assert_eq!(output.stdout, source.as_bytes());
+    }
+
+    #[test]
+    fn test_left_unformatted() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        fs::write(&config_path, "error_on_line_overflow = true\n").unwrap();
+        let source = format!("fn a() {{\n    let x = {};\n}}\n", "a".repeat(112));
+        let result = run_rustfmt(
+            source.as_bytes(),
+            &RustfmtOptions {
+                config_path: Some(config_path),
+                ..Default::default()
+            },
+        );
+        let Err(RustfmtError::LeftUnformatted { files, diagnostics }) = result else {
+            panic!("{result:?}");
+        };
+        assert_eq!(files, vec![]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].position, Some((2, 101)));
+        assert!(diagnostics[0].is_internal());
+    }
+
+    #[test]
+    fn test_strict_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("rustfmt.toml");
+        fs::write(&config_path, "imports_granularity = \"Crate\"\n").unwrap();
+        let stable = RustfmtOptions {
+            toolchain: Some("stable".to_owned()),
+            config_path: Some(config_path),
+            ..Default::default()
+        };
+        let source = b"fn  a() {}\n";
+        assert_eq!(run_rustfmt(source, &stable).unwrap()[0].mismatches.len(), 1);
+        assert!(run_rustfmt(
+            source,
+            &RustfmtOptions {
+                toolchain: Some("nightly".to_owned()),
+                strict_config: true,
+                ..stable.clone()
+            }
+        )
+        .is_ok());
+        let strict = RustfmtOptions {
+            strict_config: true,
+            ..stable
+        };
+        let result = run_rustfmt(source, &strict);
+        let Err(RustfmtError::IgnoredUnstableSettings(settings)) = result else {
+            panic!("{result:?}");
+        };
+        assert_eq!(settings, ["imports_granularity = Crate"]);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        assert_eq!(
+            RustfmtError::UnsupportedVersion(RustfmtVersion::new(2, 0, 0)).to_string(),
+            "rustfmt 2.0.0 isn't supported (the supported versions are >=1.4.24, <2)"
+        );
+    }
+
+    #[test]
+    fn test_error_conversions() {
+        assert!(matches!(
+            RustfmtError::from(parse_mismatches("[{").unwrap_err()),
+            RustfmtError::OutputParse(_)
+        ));
+        assert!(matches!(
+            RustfmtError::from(String::from_utf8(vec![0xff]).unwrap_err()),
+            RustfmtError::Encoding(error) if error.valid_up_to() == 0
+        ));
+    }
+}
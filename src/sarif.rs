@@ -0,0 +1,251 @@
+//! [SARIF](https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html)
+//! 2.1.0 logs of the rules' violations, for CI systems that ingest static
+//! analysis results (eg GitHub code scanning). Violations are collected per
+//! file over a run, then turned into one log with [`log()`].
+
+use std::{
+    collections::BTreeSet,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+use crate::{LineOffsets, RustfmtRule, Severity, Violation, ViolationData, RULE_NAME};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// A violation as it goes in a SARIF log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SarifResult {
+    /// The rule that reported it (eg [`RULE_NAME`]), which is the result's
+    /// `ruleId`. The violation's message id is kept in its properties.
+    pub rule_name: String,
+    pub severity: Severity,
+    pub violation: ViolationData,
+}
+
+/// The violations reported on a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SarifFile {
+    /// The file's path as it should appear in the log, usually relative to
+    /// the repository's root.
+    pub path: PathBuf,
+    /// The file's contents when it was checked, for turning the violations'
+    /// byte ranges into lines and columns.
+    pub source: Vec<u8>,
+    pub results: Vec<SarifResult>,
+}
+
+impl SarifFile {
+    pub fn new(path: impl Into<PathBuf>, source: &[u8]) -> Self {
+        Self {
+            path: path.into(),
+            source: source.to_owned(),
+            results: Default::default(),
+        }
+    }
+
+    /// Adds the violations `rule` reported on this file, with the severities
+    /// it gives them.
+    pub fn push_violations(&mut self, rule: &RustfmtRule, violations: &[Violation]) {
+        self.results
+            .extend(violations.iter().map(|violation| SarifResult {
+                rule_name: RULE_NAME.to_owned(),
+                severity: rule.severity_of(violation),
+                violation: violation.data(),
+            }));
+    }
+}
+
+/// A SARIF log with one run of this plugin, with the results of `files`.
+pub fn log(files: &[SarifFile]) -> Value {
+    let rule_names = files
+        .iter()
+        .flat_map(|file| &file.results)
+        .map(|result| result.rule_name.as_str())
+        .collect::<BTreeSet<_>>();
+    let results = files
+        .iter()
+        .flat_map(|file| {
+            let line_offsets = LineOffsets::new(&file.source);
+            let uri = uri(&file.path);
+            file.results
+                .iter()
+                .map(move |result| sarif_result(file, &line_offsets, &uri, result))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rule_names
+                        .into_iter()
+                        .map(|name| json!({"id": name}))
+                        .collect::<Vec<_>>(),
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+fn sarif_result(
+    file: &SarifFile,
+    line_offsets: &LineOffsets,
+    uri: &str,
+    result: &SarifResult,
+) -> Value {
+    let violation = &result.violation;
+    let mut sarif_result = json!({
+        "ruleId": result.rule_name,
+        "level": match result.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        },
+        "message": {"text": violation.message},
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": {"uri": uri},
+                "region": region(&file.source, line_offsets, violation.range.clone()),
+            },
+        }],
+        "properties": {"messageId": violation.message_id},
+    });
+    if let Some(fix) = violation.fix.as_ref() {
+        sarif_result["fixes"] = json!([{
+            "description": {"text": "Format with rustfmt"},
+            "artifactChanges": [{
+                "artifactLocation": {"uri": uri},
+                "replacements": [{
+                    "deletedRegion": region(&file.source, line_offsets, fix.range.clone()),
+                    "insertedContent": {"text": fix.replacement},
+                }],
+            }],
+        }]);
+    }
+    sarif_result
+}
+
+/// The lines and columns (in characters, both 1-based and with an exclusive
+/// end) of `range`, along with its bytes.
+fn region(source: &[u8], line_offsets: &LineOffsets, range: Range<usize>) -> Value {
+    let (start_line, start_column) = position(source, line_offsets, range.start);
+    let (end_line, end_column) = position(source, line_offsets, range.end);
+    json!({
+        "startLine": start_line,
+        "startColumn": start_column,
+        "endLine": end_line,
+        "endColumn": end_column,
+        "byteOffset": range.start,
+        "byteLength": range.len(),
+    })
+}
+
+/// The 1-based line and character column of the byte at `offset`. Each
+/// invalid UTF-8 sequence counts as one character.
+fn position(source: &[u8], line_offsets: &LineOffsets, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let line = line_offsets.line_of_offset(offset);
+    let line_start = line_offsets.line_start(line).unwrap_or_default();
+    // The end of a file that ends with a newline is on the line after it.
+    if line_offsets
+        .line_start(line + 1)
+        .is_some_and(|next_line_start| next_line_start <= offset)
+    {
+        return (line + 1, 1);
+    }
+    let column = source[line_start..offset]
+        .utf8_chunks()
+        .map(|chunk| chunk.valid().chars().count() + usize::from(!chunk.invalid().is_empty()))
+        .sum::<usize>();
+    (line, column + 1)
+}
+
+/// `path` as a relative (or, if absolute, `file://`) URI reference.
+fn uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::with_capacity(path.len());
+    for char in path.chars() {
+        match char {
+            '%' | ' ' | '#' | '?' => uri.push_str(&format!("%{:02X}", char as u32)),
+            _ => uri.push(char),
+        }
+    }
+    if uri.starts_with('/') {
+        format!("file://{uri}")
+    } else {
+        uri
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::parse, CommentMismatches, RustfmtRuleOptions, COMMENT_FORMATTING,
+        UNEXPECTED_FORMATTING,
+    };
+
+    #[test]
+    fn test_log() {
+        let source = "fn  a() {}\n\nfn b() {\n    // é  \n}\n";
+        let tree = parse(source);
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            comment_mismatches: CommentMismatches::Downgrade(Severity::Warning),
+            ..Default::default()
+        });
+        let violations = rule.check(source.as_bytes(), &tree);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.message_id)
+                .collect::<Vec<_>>(),
+            [UNEXPECTED_FORMATTING, COMMENT_FORMATTING]
+        );
+        let mut file = SarifFile::new("src/my lib.rs", source.as_bytes());
+        file.push_violations(&rule, &violations);
+        let log = log(&[file, SarifFile::new("src/clean.rs", b"fn a() {}\n")]);
+
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"], json!([{"id": RULE_NAME}]));
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], RULE_NAME);
+        assert_eq!(results[0]["level"], "error");
+        assert_eq!(results[0]["properties"]["messageId"], UNEXPECTED_FORMATTING);
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/my%20lib.rs");
+        assert_eq!(location["region"]["startLine"], 1);
+        assert_eq!(location["region"]["startColumn"], 4);
+        let replacement = &results[0]["fixes"][0]["artifactChanges"][0]["replacements"][0];
+        assert_eq!(replacement["insertedContent"]["text"], "");
+        assert_eq!(replacement["deletedRegion"]["byteOffset"], 3);
+        assert_eq!(replacement["deletedRegion"]["byteLength"], 1);
+
+        assert_eq!(results[1]["level"], "warning");
+        let region = &results[1]["locations"][0]["physicalLocation"]["region"];
+        // After the two-byte `é`, which is one column.
+        assert_eq!(region["startLine"], 4);
+        assert_eq!(region["startColumn"], 9);
+    }
+
+    #[test]
+    fn test_position() {
+        let source = b"ab\n\xffc\n";
+        let line_offsets = LineOffsets::new(source);
+        assert_eq!(position(source, &line_offsets, 0), (1, 1));
+        assert_eq!(position(source, &line_offsets, 2), (1, 3));
+        assert_eq!(position(source, &line_offsets, 3), (2, 1));
+        assert_eq!(position(source, &line_offsets, 5), (2, 3));
+        assert_eq!(position(source, &line_offsets, 6), (3, 1));
+        assert_eq!(uri("/tmp/a#b.rs".as_ref()), "file:///tmp/a%23b.rs");
+    }
+}
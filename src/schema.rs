@@ -0,0 +1,61 @@
+//! JSON schemas for the plugin's structured output, for tools (review bots,
+//! dashboards) that consume it without this crate, and for the rule's
+//! options, for editors to check configs against. They're also committed
+//! in the repository's `schema/` directory.
+
+use serde_json::Value;
+
+use crate::{FileMismatches, FormattingReport, RustfmtRuleConfig, ViolationData};
+
+/// The schema of a [`FormattingReport`] serialized as JSON.
+pub fn formatting_report() -> Value {
+    serde_json::to_value(schemars::schema_for!(FormattingReport)).unwrap()
+}
+
+/// The schema of a [`ViolationData`] serialized as JSON.
+pub fn violation() -> Value {
+    serde_json::to_value(schemars::schema_for!(ViolationData)).unwrap()
+}
+
+/// The schema of a [`FileMismatches`] (an entry of rustfmt's `--emit json`
+/// output) serialized as JSON.
+pub fn file_mismatches() -> Value {
+    serde_json::to_value(schemars::schema_for!(FileMismatches)).unwrap()
+}
+
+/// The schema of the rule's options in a host's config (see
+/// [`RustfmtRuleConfig`]).
+pub fn rule_config() -> Value {
+    serde_json::to_value(schemars::schema_for!(RustfmtRuleConfig)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path};
+
+    use super::*;
+
+    /// The committed schemas are regenerated by running the tests with
+    /// `UPDATE_SCHEMAS` set.
+    #[test]
+    fn test_committed_schemas() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schema");
+        for (name, schema) in [
+            ("formatting-report.json", formatting_report()),
+            ("violation.json", violation()),
+            ("file-mismatches.json", file_mismatches()),
+            ("rule-config.json", rule_config()),
+        ] {
+            let path = dir.join(name);
+            if env::var_os("UPDATE_SCHEMAS").is_some() {
+                fs::write(&path, serde_json::to_string_pretty(&schema).unwrap() + "\n").unwrap();
+            }
+            let committed = fs::read_to_string(&path).unwrap();
+            assert_eq!(
+                serde_json::from_str::<Value>(&committed).unwrap(),
+                schema,
+                "{name} is out of date, run the tests with UPDATE_SCHEMAS=1"
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::{fmt, ops::Range, sync::Arc};
+
+use tree_sitter::{Language, Node, Query, QueryCursor, QueryError, Tree};
+
+use crate::{
+    line_ranges::{byte_range_to_rows, merge},
+    LineOffsets,
+};
+
+/// The parts of a file the rule formats, eg only function bodies. rustfmt gets
+/// the lines of the matching nodes as `--file-lines`, so it formats whole
+/// lines: a `block`'s first line is formatted along with the signature on it.
+#[derive(Clone)]
+pub enum FormattingScope {
+    /// Nodes of these kinds, eg `block`.
+    NodeKinds(Vec<String>),
+    /// The nodes captured by a query. See [`FormattingScope::query()`].
+    Query { source: String, query: Arc<Query> },
+    /// Just these (0-based, half-open) rows, eg a file's
+    /// [changed lines](crate::ChangedLines).
+    Lines(Vec<Range<usize>>),
+}
+
+impl fmt::Debug for FormattingScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NodeKinds(kinds) => f.debug_tuple("NodeKinds").field(kinds).finish(),
+            Self::Query { source, .. } => f.debug_struct("Query").field("source", source).finish(),
+            Self::Lines(lines) => f.debug_tuple("Lines").field(lines).finish(),
+        }
+    }
+}
+
+impl FormattingScope {
+    /// Scopes formatting to the nodes `source` (a tree-sitter query, eg
+    /// `(function_item parameters: (_) @signature)`) captures.
+    pub fn query(language: Language, source: &str) -> Result<Self, QueryError> {
+        Ok(Self::Query {
+            source: source.to_owned(),
+            query: Arc::new(Query::new(language, source)?),
+        })
+    }
+
+    /// The (0-based, half-open, merged) rows of the nodes in scope.
+    pub fn rows(&self, tree: &Tree, source: &[u8]) -> Vec<Range<usize>> {
+        let line_offsets = LineOffsets::new(source);
+        let rows = |node: Node| byte_range_to_rows(&line_offsets, &node.byte_range());
+        match self {
+            Self::NodeKinds(kinds) => {
+                let mut found = Vec::new();
+                let mut cursor = tree.walk();
+                loop {
+                    let node = cursor.node();
+                    let is_in_scope = kinds.iter().any(|kind| kind == node.kind());
+                    if is_in_scope {
+                        found.push(rows(node));
+                    }
+                    // Nested nodes are already covered.
+                    if !is_in_scope && cursor.goto_first_child() {
+                        continue;
+                    }
+                    while !cursor.goto_next_sibling() {
+                        if !cursor.goto_parent() {
+                            return merge(found);
+                        }
+                    }
+                }
+            }
+            Self::Query { query, .. } => merge(
+                QueryCursor::new()
+                    .matches(query, tree.root_node(), source)
+                    .flat_map(|query_match| {
+                        query_match
+                            .captures
+                            .iter()
+                            .map(|capture| rows(capture.node))
+                            .collect::<Vec<_>>()
+                    }),
+            ),
+            Self::Lines(lines) => merge(lines.iter().cloned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    const SOURCE: &str = "fn a(\n    b: u8,\n) {\n    c();\n}\n\nconst D: u8 = {\n    1\n};\n";
+
+    #[test]
+    fn test_node_kinds_rows() {
+        let scope = FormattingScope::NodeKinds(vec!["block".to_owned()]);
+        assert_eq!(scope.rows(&parse(SOURCE), SOURCE.as_bytes()), [2..5, 6..9]);
+    }
+
+    #[test]
+    fn test_query_rows() {
+        let scope = FormattingScope::query(
+            tree_sitter_rust::language(),
+            "(function_item parameters: (_) @signature)",
+        )
+        .unwrap();
+        assert_eq!(scope.rows(&parse(SOURCE), SOURCE.as_bytes()), [0..3]);
+        assert!(FormattingScope::query(tree_sitter_rust::language(), "(nope)").is_err());
+    }
+
+    #[test]
+    fn test_lines_rows() {
+        let scope = FormattingScope::Lines(vec![3..4, 0..2, 1..3]);
+        assert_eq!(scope.rows(&parse(SOURCE), SOURCE.as_bytes()), [0..4]);
+    }
+}
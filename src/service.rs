@@ -0,0 +1,195 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use tree_sitter::{Parser, Tree};
+
+use crate::{
+    format_node, node_cache::NodeCache, Fix, FormattingReport, FormattingStatus, ReportCallback,
+    RustfmtError, RustfmtOptions,
+};
+
+/// What rustfmt makes of the files of a run, for other rules in it to ask,
+/// eg one whose fixes insert code and wants to format it first so that
+/// rustfmt doesn't need a second fixing pass. The rustfmt rule tells it about
+/// the files it checks through its [`on_report()`](Self::on_report) callback
+/// (as set for
+/// [`RustfmtRuleOptions::on_report`](crate::RustfmtRuleOptions::on_report)),
+/// and hosts hand clones of it to the other rules. Clones share the reports.
+#[derive(Clone, Debug, Default)]
+pub struct RustfmtService {
+    options: RustfmtOptions,
+    reports: Arc<Mutex<HashMap<PathBuf, FormattingReport>>>,
+}
+
+impl RustfmtService {
+    /// A service formatting snippets with `options`, which should be the
+    /// rule's.
+    pub fn new(options: RustfmtOptions) -> Self {
+        Self {
+            options,
+            reports: Default::default(),
+        }
+    }
+
+    /// Records the reports of checks of files with a path (see
+    /// [`RustfmtRule::check_as()`](crate::RustfmtRule::check_as)), replacing
+    /// earlier ones for the same file.
+    pub fn on_report(&self) -> ReportCallback {
+        let reports = self.reports.clone();
+        Arc::new(move |report| {
+            if let Some(path) = report.path.as_ref() {
+                reports
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(path.clone(), report.clone());
+            }
+        })
+    }
+
+    /// Whether rustfmt found nothing to change in `file_id` when the rule
+    /// last checked it, or `None` if it hasn't been checked (or rustfmt
+    /// failed on it).
+    pub fn is_format_clean(&self, file_id: &Path) -> Option<bool> {
+        let reports = self.reports.lock().unwrap_or_else(PoisonError::into_inner);
+        let report = reports.get(file_id)?;
+        match report.status {
+            FormattingStatus::Failed { .. } => None,
+            _ => Some(report.is_clean()),
+        }
+    }
+
+    /// The text of the node spanning exactly `range` of `source`, as rustfmt
+    /// would format it where it is (see [`format_node()`]), or `None` if no
+    /// node does.
+    pub fn expected_text(
+        &self,
+        source: &[u8],
+        tree: &Tree,
+        range: Range<usize>,
+    ) -> Result<Option<String>, RustfmtError> {
+        let mut node = NodeCache::for_source(tree.root_node(), source)
+            .descendant_for_byte_range(range.clone());
+        // The smallest node spanning `range` can be inside a larger one that
+        // has the same bytes (eg an expression statement's expression).
+        while let Some(parent) = node.parent() {
+            if parent.byte_range() != node.byte_range() {
+                break;
+            }
+            node = parent;
+        }
+        if node.byte_range() != range {
+            return Ok(None);
+        }
+        format_node(node, source, &self.options).map(Some)
+    }
+
+    /// Formats the code other rules' `fixes` insert into `source` (eg in a
+    /// fixing loop, before applying them), so that rustfmt doesn't report it
+    /// on the next pass. A fix's replacement is formatted where it ends up
+    /// (see [`format_node()`]) if, leading and trailing whitespace aside,
+    /// it's a single syntax node once the fix is applied. Others, and those
+    /// rustfmt fails on, are kept as they are.
+    pub fn preformat_fixes(&self, source: &[u8], tree: &Tree, fixes: Vec<Fix>) -> Vec<Fix> {
+        let mut parser = Parser::new();
+        if parser.set_language(tree.language()).is_err() {
+            return fixes;
+        }
+        fixes
+            .into_iter()
+            .map(|fix| self.preformat_fix(&mut parser, source, fix))
+            .collect()
+    }
+
+    fn preformat_fix(&self, parser: &mut Parser, source: &[u8], fix: Fix) -> Fix {
+        let trimmed = fix.replacement.trim();
+        if trimmed.is_empty() {
+            return fix;
+        }
+        let leading = fix.replacement.len() - fix.replacement.trim_start().len();
+        let inserted = fix.range.start + leading..fix.range.start + leading + trimmed.len();
+        let fixed = fix.apply(source);
+        let Some(fixed_tree) = parser.parse(&fixed, None) else {
+            return fix;
+        };
+        let formatted = match self.expected_text(&fixed, &fixed_tree, inserted) {
+            Ok(Some(formatted)) => formatted,
+            Ok(None) => return fix,
+            Err(error) => {
+                log::debug!("couldn't format a fix's replacement: {error}");
+                return fix;
+            }
+        };
+        Fix {
+            replacement: fix.replacement.replacen(trimmed, &formatted, 1),
+            ..fix
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::parse, RustfmtRule, RustfmtRuleOptions};
+
+    #[test]
+    fn test_service() {
+        let service = RustfmtService::default();
+        let rule = RustfmtRule::new(RustfmtRuleOptions {
+            on_report: Some(service.on_report()),
+            ..Default::default()
+        });
+        let unformatted = "fn a() {\n    b( );\n}\n";
+        let tree = parse(unformatted);
+        rule.check_as(unformatted.as_bytes(), &tree, Path::new("a.rs"));
+        let clean = "fn a() {}\n";
+        rule.check_as(clean.as_bytes(), &parse(clean), Path::new("b.rs"));
+
+        assert_eq!(service.is_format_clean(Path::new("a.rs")), Some(false));
+        assert_eq!(service.is_format_clean(Path::new("b.rs")), Some(true));
+        assert_eq!(service.is_format_clean(Path::new("c.rs")), None);
+
+        assert_eq!(
+            service
+                .expected_text(unformatted.as_bytes(), &tree, 13..18)
+                .unwrap(),
+            Some("b();".to_owned())
+        );
+        assert_eq!(
+            service
+                .expected_text(unformatted.as_bytes(), &tree, 13..15)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_preformat_fixes() {
+        let source = "impl A {\n    fn a() {}\n}\n";
+        let tree = parse(source);
+        let fixes = vec![
+            Fix {
+                range: 22..22,
+                replacement: "\n    fn  b( x:u8 ) { x+1 }".to_owned(),
+            },
+            Fix {
+                range: 0..0,
+                replacement: "// x  y\n".to_owned(),
+            },
+            Fix {
+                range: 12..13,
+                replacement: "b c".to_owned(),
+            },
+        ];
+        let preformatted =
+            RustfmtService::default().preformat_fixes(source.as_bytes(), &tree, fixes.clone());
+        assert_eq!(
+            preformatted[0].replacement,
+            "\n    fn b(x: u8) {\n        x + 1\n    }"
+        );
+        assert_eq!(preformatted[1..], fixes[1..]);
+    }
+}
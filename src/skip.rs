@@ -0,0 +1,410 @@
+use std::ops::Range;
+
+use tree_sitter::{Node, Tree};
+
+use crate::{LineOffsets, SkipMacroInvocations, RULE_NAME};
+
+/// Byte ranges the rule must neither report nor fix:
+///
+/// - items (and statements, fields, match arms etc) annotated with
+///   `#[rustfmt::skip]`
+/// - the modules and blocks with an inner `#![rustfmt::skip]` (a file with
+///   one at the top is left alone altogether, see [`is_file_skipped()`])
+/// - lines between `// tree-sitter-lint-disable rustfmt` and
+///   `// tree-sitter-lint-enable rustfmt` (or the end of the file)
+/// - the line after `// tree-sitter-lint-disable-next-line rustfmt`
+///
+/// Directives without any rule names apply to all rules, including this one.
+pub fn skip_regions(tree: &Tree, source: &[u8]) -> Vec<Range<usize>> {
+    let line_offsets = LineOffsets::new(source);
+    let mut regions = Vec::new();
+    let mut disabled_since: Option<usize> = None;
+    walk(tree.root_node(), &mut |node| match node.kind() {
+        "attribute_item" => {
+            if is_rustfmt_skip(node, source) {
+                regions.push(skip_attribute_target_range(node));
+            }
+            false
+        }
+        "inner_attribute_item" => {
+            if let Some(parent) = node.parent().filter(|_| is_rustfmt_skip(node, source)) {
+                regions.push(parent.byte_range());
+            }
+            false
+        }
+        "line_comment" | "block_comment" => {
+            match node
+                .utf8_text(source)
+                .ok()
+                .and_then(parse_disable_directive)
+            {
+                Some(DisableDirective::Disable) => {
+                    disabled_since.get_or_insert(node.start_byte());
+                }
+                Some(DisableDirective::Enable) => {
+                    if let Some(start) = disabled_since.take() {
+                        regions.push(start..node.end_byte());
+                    }
+                }
+                Some(DisableDirective::DisableNextLine) => {
+                    // Rows are 0-based, lines 1-based.
+                    let next_line = node.end_position().row + 2;
+                    if let Some(next_line) =
+                        line_offsets.line_range_to_byte_range(next_line, next_line)
+                    {
+                        regions.push(next_line);
+                    }
+                }
+                None => {}
+            }
+            false
+        }
+        _ => true,
+    });
+    if let Some(start) = disabled_since {
+        regions.push(start..source.len());
+    }
+    regions
+}
+
+/// Whether the file starts with `#![rustfmt::skip]` among its inner
+/// attributes, which rustfmt leaves the whole file alone for.
+pub fn is_file_skipped(tree: &Tree, source: &[u8]) -> bool {
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let is_skipped = root
+        .named_children(&mut cursor)
+        .take_while(|node| {
+            matches!(
+                node.kind(),
+                "shebang" | "inner_attribute_item" | "line_comment" | "block_comment"
+            )
+        })
+        .any(|node| match node.kind() {
+            "inner_attribute_item" => is_rustfmt_skip(node, source),
+            // tree-sitter-rust parses an inner attribute on the first line as
+            // a shebang.
+            "shebang" => node.utf8_text(source).is_ok_and(|shebang| {
+                shebang
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .eq("#![rustfmt::skip]".chars())
+            }),
+            _ => false,
+        });
+    is_skipped
+}
+
+/// Whether an (outer or inner) attribute item is `rustfmt::skip`.
+fn is_rustfmt_skip(attribute_item: Node, source: &[u8]) -> bool {
+    attribute_item
+        .named_child(0)
+        .and_then(|attribute| attribute.utf8_text(source).ok())
+        == Some("rustfmt::skip")
+}
+
+/// Byte ranges of the items annotated with any of `attributes`, which are
+/// paths (eg `sqlx::test`) matched whatever arguments the attribute has. An
+/// inner attribute (`#![...]`) covers the module, block or file it's in.
+pub fn attribute_skip_regions(
+    tree: &Tree,
+    source: &[u8],
+    attributes: &[String],
+) -> Vec<Range<usize>> {
+    let mut regions = Vec::new();
+    if attributes.is_empty() {
+        return regions;
+    }
+    walk(tree.root_node(), &mut |node| {
+        let is_inner = match node.kind() {
+            "attribute_item" => false,
+            "inner_attribute_item" => true,
+            _ => return true,
+        };
+        let is_skipped = node
+            .named_child(0)
+            .and_then(|attribute| attribute.named_child(0))
+            .and_then(|path| path.utf8_text(source).ok())
+            .is_some_and(|path| attributes.iter().any(|attribute| attribute == path));
+        if is_skipped {
+            regions.push(match node.parent() {
+                Some(parent) if is_inner => parent.byte_range(),
+                _ => skip_attribute_target_range(node),
+            });
+        }
+        false
+    });
+    regions
+}
+
+/// An outer attribute applies to the next sibling that isn't another
+/// attribute or a comment, except on match arms, where attributes are parsed as
+/// children of the arm.
+fn skip_attribute_target_range(attribute: Node) -> Range<usize> {
+    if let Some(match_arm) = attribute
+        .parent()
+        .filter(|parent| parent.kind() == "match_arm")
+    {
+        return match_arm.byte_range();
+    }
+    let mut sibling = attribute.next_named_sibling();
+    while let Some(node) = sibling {
+        if !matches!(
+            node.kind(),
+            "attribute_item" | "line_comment" | "block_comment"
+        ) {
+            return attribute.start_byte()..node.end_byte();
+        }
+        sibling = node.next_named_sibling();
+    }
+    attribute.byte_range()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DisableDirective {
+    Disable,
+    Enable,
+    DisableNextLine,
+}
+
+fn parse_disable_directive(comment: &str) -> Option<DisableDirective> {
+    let body = comment
+        .strip_prefix("//")
+        .or_else(|| comment.strip_prefix("/*")?.strip_suffix("*/"))?
+        .trim();
+    let (directive, rule_names) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+    let directive = match directive {
+        "tree-sitter-lint-disable" => DisableDirective::Disable,
+        "tree-sitter-lint-enable" => DisableDirective::Enable,
+        "tree-sitter-lint-disable-next-line" => DisableDirective::DisableNextLine,
+        _ => return None,
+    };
+    let mut rule_names = rule_names
+        .split([',', ' ', '\t'])
+        .filter(|rule_name| !rule_name.is_empty())
+        .peekable();
+    (rule_names.peek().is_none() || rule_names.any(|rule_name| rule_name == RULE_NAME))
+        .then_some(directive)
+}
+
+/// Byte ranges of the macro invocations matched by `skip`.
+pub fn skipped_macro_invocation_ranges(
+    tree: &Tree,
+    source: &[u8],
+    skip: &SkipMacroInvocations,
+) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    walk(tree.root_node(), &mut |node| {
+        if node.kind() != "macro_invocation" {
+            return true;
+        }
+        let is_skipped = node
+            .child_by_field_name("macro")
+            .and_then(|name| name.utf8_text(source).ok())
+            .is_some_and(|name| skip.matches(name));
+        if is_skipped {
+            ranges.push(node.byte_range());
+        }
+        // Nested invocations are covered by this one if it's skipped, and
+        // don't parse as invocations inside its token tree otherwise.
+        false
+    });
+    ranges
+}
+
+/// Whether two byte ranges share any bytes. An empty range (an insertion
+/// point) overlaps a range that strictly contains it.
+pub fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Visits `node` and its descendants in pre-order, only descending into a
+/// node's children if `visit` returns `true` for it.
+pub(crate) fn walk<'tree>(node: Node<'tree>, visit: &mut impl FnMut(Node<'tree>) -> bool) {
+    if !visit(node) {
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        walk(child, visit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    #[test]
+    fn test_skipped_macro_invocation_ranges() {
+        let source = "fn f() {\n    foo!(a);\n    bar::baz!(b);\n    qux!(c);\n}\n";
+        let tree = parse(source);
+        assert_eq!(
+            skipped_macro_invocation_ranges(
+                &tree,
+                source.as_bytes(),
+                &SkipMacroInvocations::Names(vec!["foo".to_owned(), "bar::baz".to_owned()])
+            ),
+            [13..20, 26..38]
+        );
+        assert_eq!(
+            skipped_macro_invocation_ranges(&tree, source.as_bytes(), &SkipMacroInvocations::All)
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_skip_regions_attributes() {
+        let source = "#[rustfmt::skip]
+fn a() {}
+impl A {
+    #[rustfmt::skip]
+    // comment
+    #[inline]
+    fn b() {}
+}
+fn c() {
+    match x {
+        #[rustfmt::skip]
+        1 => {}
+        _ => {}
+    }
+}
+#[rustfmt::skip::macros(foo)]
+fn d() {}
+";
+        let tree = parse(source);
+        let regions = skip_regions(&tree, source.as_bytes())
+            .into_iter()
+            .map(|range| &source[range])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            [
+                "#[rustfmt::skip]\nfn a() {}",
+                "#[rustfmt::skip]\n    // comment\n    #[inline]\n    fn b() {}",
+                "#[rustfmt::skip]\n        1 => {}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip_regions_inner_attributes() {
+        let source = "mod a {
+    #![rustfmt::skip]
+    fn b( ) {}
+}
+fn c() {
+    #![rustfmt::skip]
+    d( );
+}
+mod e {
+    #![rustfmt::skip::macros(foo)]
+}
+";
+        let tree = parse(source);
+        let regions = skip_regions(&tree, source.as_bytes())
+            .into_iter()
+            .map(|range| &source[range])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            [
+                "{\n    #![rustfmt::skip]\n    fn b( ) {}\n}",
+                "{\n    #![rustfmt::skip]\n    d( );\n}",
+            ]
+        );
+        assert!(!is_file_skipped(&tree, source.as_bytes()));
+    }
+
+    #[test]
+    fn test_is_file_skipped() {
+        let is_file_skipped = |source: &str| is_file_skipped(&parse(source), source.as_bytes());
+        assert!(is_file_skipped("#![rustfmt::skip]\nfn a( ) {}\n"));
+        assert!(is_file_skipped(
+            "//! Docs.\n#![allow(dead_code)]\n#![rustfmt::skip]\nfn a( ) {}\n"
+        ));
+        assert!(!is_file_skipped("#![allow(dead_code)]\nfn a( ) {}\n"));
+        assert!(!is_file_skipped(
+            "#!/usr/bin/env run-cargo-script\nfn a( ) {}\n"
+        ));
+        assert!(!is_file_skipped("#[rustfmt::skip]\nfn a( ) {}\n"));
+        assert!(!is_file_skipped("fn a() {\n    #![rustfmt::skip]\n}\n"));
+    }
+
+    #[test]
+    fn test_attribute_skip_regions() {
+        let source = "#[sqlx::test(migrations = \"a\")]
+async fn b() {}
+#[test]
+fn c() {}
+mod d {
+    #![cynic::schema(\"e\")]
+    fn f() {}
+}
+#[sqlx::test::other]
+fn g() {}
+";
+        let tree = parse(source);
+        let regions = |attributes: &[&str]| {
+            attribute_skip_regions(
+                &tree,
+                source.as_bytes(),
+                &attributes
+                    .iter()
+                    .map(|attribute| attribute.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .into_iter()
+            .map(|range| &source[range])
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            regions(&["sqlx::test", "cynic::schema"]),
+            [
+                "#[sqlx::test(migrations = \"a\")]\nasync fn b() {}",
+                "{\n    #![cynic::schema(\"e\")]\n    fn f() {}\n}",
+            ]
+        );
+        assert_eq!(regions(&["test"]), ["#[test]\nfn c() {}"]);
+        assert_eq!(regions(&[]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_skip_regions_comments() {
+        let source = "fn a() {}
+// tree-sitter-lint-disable rustfmt
+fn b() {}
+// tree-sitter-lint-enable rustfmt
+fn c() {}
+// tree-sitter-lint-disable-next-line other-rule
+fn d() {}
+/* tree-sitter-lint-disable-next-line */
+fn e() {}
+// tree-sitter-lint-disable other-rule, rustfmt
+fn f() {}
+";
+        let tree = parse(source);
+        let regions = skip_regions(&tree, source.as_bytes())
+            .into_iter()
+            .map(|range| &source[range])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            [
+                "// tree-sitter-lint-disable rustfmt\nfn b() {}\n// tree-sitter-lint-enable rustfmt",
+                "fn e() {}\n",
+                "// tree-sitter-lint-disable other-rule, rustfmt\nfn f() {}\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overlaps() {
+        assert!(overlaps(&(0..5), &(4..6)));
+        assert!(!overlaps(&(0..5), &(5..6)));
+        assert!(overlaps(&(3..3), &(2..4)));
+        assert!(!overlaps(&(2..2), &(2..4)));
+    }
+}
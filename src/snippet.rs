@@ -0,0 +1,226 @@
+use tree_sitter::Node;
+
+use crate::{format_to_string, RustfmtError, RustfmtOptions};
+
+const INDENT_WIDTH: usize = 4;
+
+/// `node`'s text formatted as it would be at its current indentation depth,
+/// ready to replace `node.byte_range()`: the first line isn't indented and
+/// there's no trailing newline.
+///
+/// rustfmt only formats whole files, so the text is wrapped in a shell of
+/// dummy items or blocks that puts it at the same depth (and in the same kind
+/// of context, eg an `impl` for associated items) before formatting, and
+/// unwrapped afterwards.
+pub fn format_node(
+    node: Node,
+    source: &[u8],
+    options: &RustfmtOptions,
+) -> Result<String, RustfmtError> {
+    let text = node.utf8_text(source)?;
+    let indent = line_indent(source, node.start_byte());
+    let depth = indent
+        .iter()
+        .map(|&byte| if byte == b'\t' { INDENT_WIDTH } else { 1 })
+        .sum::<usize>()
+        / INDENT_WIDTH;
+    let headers = shell_headers(node, depth);
+    format_in_shell(text, &headers, &String::from_utf8_lossy(indent), options)
+}
+
+/// `snippet` (items, statements or an expression) formatted as it would be
+/// `indent` columns in, with every line but blank ones indented that much
+/// and no trailing newline, eg for code generators. `None` if rustfmt can't
+/// format it.
+///
+/// Like [`format_node()`], it's formatted in a shell of dummy items, or a
+/// function body if it isn't items, as deep as the indentation.
+pub fn format_snippet(snippet: &str, indent: usize, options: &RustfmtOptions) -> Option<String> {
+    let depth = indent / INDENT_WIDTH;
+    let indent = " ".repeat(indent);
+    let item_headers = vec!["mod __m {"; depth];
+    let mut block_headers = vec!["{"; depth.max(1)];
+    block_headers[0] = "fn __f() {";
+    let formatted = format_in_shell(snippet.trim(), &item_headers, &indent, options)
+        .or_else(|_| format_in_shell(snippet.trim(), &block_headers, &indent, options))
+        .ok()?;
+    Some(format!("{indent}{formatted}"))
+}
+
+/// Formats `text` inside the shell opened by `headers`, and takes it back
+/// out: the first line isn't indented, and the others get `indent` instead of
+/// the shell's indentation.
+fn format_in_shell(
+    text: &str,
+    headers: &[&str],
+    indent: &str,
+    options: &RustfmtOptions,
+) -> Result<String, RustfmtError> {
+    let mut shell = String::new();
+    for header in headers {
+        shell.push_str(header);
+        shell.push('\n');
+    }
+    shell.push_str(text);
+    shell.push('\n');
+    for _ in headers {
+        shell.push_str("}\n");
+    }
+    let formatted = format_to_string(shell.as_bytes(), options)?;
+
+    let lines = formatted.lines().collect::<Vec<_>>();
+    if lines.len() < headers.len() * 2 {
+        return Err(RustfmtError::OutputParse(format!(
+            "formatting shell wasn't preserved: {formatted:?}"
+        )));
+    }
+    let shell_indent = " ".repeat(headers.len() * INDENT_WIDTH);
+    let mut snippet = String::new();
+    for (index, line) in lines[headers.len()..lines.len() - headers.len()]
+        .iter()
+        .enumerate()
+    {
+        // Lines without the shell's indentation are blank or inside multi-line
+        // literals, and are kept as is.
+        match line.strip_prefix(&shell_indent) {
+            Some(line) if index == 0 => snippet.push_str(line),
+            Some(line) => {
+                snippet.push('\n');
+                snippet.push_str(indent);
+                snippet.push_str(line);
+            }
+            None => {
+                if index > 0 {
+                    snippet.push('\n');
+                }
+                snippet.push_str(line);
+            }
+        }
+    }
+    Ok(snippet)
+}
+
+/// The leading whitespace of the line containing `offset`.
+fn line_indent(source: &[u8], offset: usize) -> &[u8] {
+    let line_start = source[..offset]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let indent_len = source[line_start..]
+        .iter()
+        .take_while(|&&byte| byte == b' ' || byte == b'\t')
+        .count();
+    &source[line_start..line_start + indent_len]
+}
+
+/// The opening lines of a shell putting an item or statement at `depth`. Each
+/// is closed by a `}` line.
+fn shell_headers(node: Node, depth: usize) -> Vec<&'static str> {
+    match node.parent() {
+        None => vec!["mod __m {"; depth],
+        Some(parent) if parent.kind() == "source_file" => vec!["mod __m {"; depth],
+        Some(parent) if parent.kind() == "declaration_list" => {
+            let mut headers = vec!["mod __m {"; depth.max(1)];
+            *headers.last_mut().unwrap() = match parent.parent().map(|parent| parent.kind()) {
+                Some("impl_item") => "impl __S {",
+                Some("trait_item") => "trait __T {",
+                _ => "mod __m {",
+            };
+            headers
+        }
+        // Statements and expressions need a function body (and blocks for any
+        // further depth).
+        Some(_) => {
+            let mut headers = vec!["{"; depth.max(1)];
+            headers[0] = "fn __f() {";
+            headers
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    fn format_node_str(source: &str, text: &str, kind: &str) -> String {
+        let tree = parse(source);
+        let start = source.find(text).unwrap();
+        let mut node = tree
+            .root_node()
+            .descendant_for_byte_range(start, start + text.len())
+            .unwrap();
+        while node.kind() != kind {
+            node = node.parent().unwrap();
+        }
+        format_node(node, source.as_bytes(), &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_format_expression() {
+        assert_eq!(
+            format_node_str(
+                "impl A {\n    fn b() {\n        if x {\n            bar( x,y )\n        }\n    }\n}\n",
+                "bar( x,y )",
+                "call_expression"
+            ),
+            "bar(x, y)"
+        );
+    }
+
+    #[test]
+    fn test_format_statement() {
+        let source = "fn a() {
+    let x = foo(aaaaaaaaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbbbbbbbbbbbb, cccccccccccccccccccccccccc, d);
+}
+";
+        assert_eq!(
+            format_node_str(source, "let x", "let_declaration"),
+            "let x = foo(
+        aaaaaaaaaaaaaaaaaaaaaa,
+        bbbbbbbbbbbbbbbbbbbbbbbbbbbbbb,
+        cccccccccccccccccccccccccc,
+        d,
+    );"
+        );
+    }
+
+    #[test]
+    fn test_format_snippet() {
+        let options = RustfmtOptions::default();
+        assert_eq!(
+            format_snippet("fn  a( ) { b( ) }\n\nfn c() {}", 4, &options).unwrap(),
+            "    fn a() {\n        b()\n    }\n\n    fn c() {}"
+        );
+        assert_eq!(
+            format_snippet("let x=1;x+1", 8, &options).unwrap(),
+            "        let x = 1;\n        x + 1"
+        );
+        assert_eq!(format_snippet("x+1", 0, &options).unwrap(), "x + 1");
+        assert_eq!(format_snippet("fn (", 0, &options), None);
+    }
+
+    #[test]
+    fn test_format_items() {
+        assert_eq!(
+            format_node_str(
+                "impl A {\n    fn  b( &self ) { 1 }\n}\n",
+                "fn  b",
+                "function_item"
+            ),
+            "fn b(&self) {\n        1\n    }"
+        );
+        assert_eq!(
+            format_node_str(
+                "mod a {\n    trait B {\n        fn  c( &self );\n    }\n}\n",
+                "fn  c",
+                "function_signature_item"
+            ),
+            "fn c(&self);"
+        );
+        assert_eq!(
+            format_node_str("struct  A { b:u8 }\n", "struct", "struct_item"),
+            "struct A {\n    b: u8,\n}"
+        );
+    }
+}
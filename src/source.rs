@@ -0,0 +1,127 @@
+use std::{fs::File, io::Read, ops::Deref, path::Path};
+
+use ropey::Rope;
+
+/// A buffer as a tree-sitter-lint host may hold it: a plain byte slice, or a
+/// rope while it's being edited by the fixing loop.
+#[derive(Clone, Copy, Debug)]
+pub enum RopeOrSlice<'a> {
+    Slice(&'a [u8]),
+    Rope(&'a Rope),
+}
+
+impl<'a> RopeOrSlice<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Slice(slice) => slice.len(),
+            Self::Rope(rope) => rope.len_bytes(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The contents in order, without copying.
+    pub fn chunks(&self) -> Box<dyn Iterator<Item = &'a [u8]> + 'a> {
+        match *self {
+            Self::Slice(slice) => Box::new(std::iter::once(slice)),
+            Self::Rope(rope) => Box::new(rope.chunks().map(str::as_bytes)),
+        }
+    }
+}
+
+impl<'a> From<&'a [u8]> for RopeOrSlice<'a> {
+    fn from(slice: &'a [u8]) -> Self {
+        Self::Slice(slice)
+    }
+}
+
+impl<'a> From<&'a Rope> for RopeOrSlice<'a> {
+    fn from(rope: &'a Rope) -> Self {
+        Self::Rope(rope)
+    }
+}
+
+/// Files at least this big are memory-mapped (with the `mmap` feature) instead
+/// of being read into memory.
+pub const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// The contents of a file being linted.
+#[derive(Debug)]
+pub enum SourceBuffer {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl SourceBuffer {
+    /// Reads the file at `path`, memory-mapping it if it's larger than
+    /// [`MMAP_THRESHOLD`] and the `mmap` feature is enabled.
+    ///
+    /// A mapped file must not be modified by another process while the buffer
+    /// is alive.
+    pub fn read(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        #[cfg(feature = "mmap")]
+        if len >= MMAP_THRESHOLD {
+            // SAFETY: see the doc comment, the file is assumed not to change
+            // underneath us for the duration of the lint run.
+            return Ok(Self::Mapped(unsafe { memmap2::Mmap::map(&file)? }));
+        }
+        let mut contents = Vec::with_capacity(len.try_into().unwrap_or_default());
+        file.read_to_end(&mut contents)?;
+        Ok(Self::Owned(contents))
+    }
+}
+
+impl Deref for SourceBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Owned(contents) => contents,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+impl AsRef<[u8]> for SourceBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_read_small_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"fn main() {}\n").unwrap();
+
+        let source = SourceBuffer::read(file.path()).unwrap();
+        assert!(matches!(source, SourceBuffer::Owned(_)));
+        assert_eq!(&*source, b"fn main() {}\n");
+    }
+
+    #[test]
+    fn test_read_large_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let line = b"fn f() {}\n";
+        for _ in 0..MMAP_THRESHOLD as usize / line.len() + 1 {
+            file.write_all(line).unwrap();
+        }
+
+        let source = SourceBuffer::read(file.path()).unwrap();
+        #[cfg(feature = "mmap")]
+        assert!(matches!(source, SourceBuffer::Mapped(_)));
+        assert!(source.len() as u64 > MMAP_THRESHOLD);
+        assert!(source.starts_with(line));
+    }
+}
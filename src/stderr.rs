@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+
+use crate::STDIN_FILE_NAME;
+
+/// A diagnostic rustfmt (or the rustc parser it embeds) printed to stderr.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StderrDiagnostic {
+    /// eg `error` or `warning`
+    pub level: String,
+    /// What's in brackets after the level, eg `E0001`, or `internal` for
+    /// what rustfmt couldn't format (see [`is_internal()`](Self::is_internal)).
+    pub code: Option<String>,
+    pub message: String,
+    /// 1-based line and (character) column of the primary span, if it was in
+    /// the formatted input.
+    pub position: Option<(usize, usize)>,
+}
+
+impl StderrDiagnostic {
+    /// Whether this is one of rustfmt's own errors about code it formatted
+    /// but couldn't get right (eg `line formatted, but exceeded maximum
+    /// width`), rather than about code it couldn't parse.
+    pub fn is_internal(&self) -> bool {
+        self.level == "error" && self.code.as_deref() == Some("internal")
+    }
+}
+
+/// Parses rustc-style diagnostics out of rustfmt's stderr (which may be
+/// colored):
+///
+/// ```text
+/// error: this file contains an unclosed delimiter
+///  --> <stdin>:1:12
+///   |
+/// ```
+pub fn parse_stderr(stderr: &str) -> Vec<StderrDiagnostic> {
+    parse_stderr_as(stderr, STDIN_FILE_NAME)
+}
+
+/// Like [`parse_stderr()`] for a rustfmt whose errors call the formatted input
+/// `file_name` (see [`RustfmtOptions::file_name`](crate::RustfmtOptions::file_name)).
+pub fn parse_stderr_as(stderr: &str, file_name: &str) -> Vec<StderrDiagnostic> {
+    let mut diagnostics: Vec<StderrDiagnostic> = Default::default();
+    for line in strip_ansi_escapes(stderr).lines() {
+        if let Some(diagnostic) = parse_header(line) {
+            diagnostics.push(diagnostic);
+            continue;
+        }
+        let Some(location) = line.trim_start().strip_prefix("--> ") else {
+            continue;
+        };
+        let Some(diagnostic) = diagnostics.last_mut() else {
+            continue;
+        };
+        if diagnostic.position.is_none() {
+            diagnostic.position = parse_location(location, file_name);
+        }
+    }
+    diagnostics
+}
+
+/// The settings rustfmt's stderr says it ignored for being unstable, which
+/// only a nightly toolchain applies, each as rustfmt wrote it (eg
+/// `imports_granularity = Crate` for a config file's
+/// `imports_granularity = "Crate"`):
+///
+/// ```text
+/// Warning: can't set `imports_granularity = Crate`, unstable features are only available in nightly channel.
+/// ```
+pub fn ignored_unstable_settings(stderr: &str) -> Vec<String> {
+    let mut settings: Vec<String> = Default::default();
+    for line in strip_ansi_escapes(stderr).lines() {
+        let Some((setting, _)) = line
+            .strip_prefix("Warning: can't set `")
+            .and_then(|rest| rest.split_once("`, unstable features are only available in nightly"))
+        else {
+            continue;
+        };
+        if !settings.iter().any(|seen| seen == setting) {
+            settings.push(setting.to_owned());
+        }
+    }
+    settings
+}
+
+fn parse_header(line: &str) -> Option<StderrDiagnostic> {
+    let (level, message) = line.split_once(": ")?;
+    let (bare_level, code) = match level.split_once('[') {
+        Some((bare_level, code)) => (bare_level, code.strip_suffix(']')),
+        None => (level, None),
+    };
+    if !matches!(bare_level, "error" | "warning") {
+        return None;
+    }
+    Some(StderrDiagnostic {
+        level: bare_level.to_owned(),
+        code: code.map(ToOwned::to_owned),
+        message: message.to_owned(),
+        position: None,
+    })
+}
+
+fn parse_location(location: &str, file_name: &str) -> Option<(usize, usize)> {
+    let position = location.strip_prefix(file_name)?.strip_prefix(':')?;
+    let parts = position
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts[..] {
+        [line, column] => Some((line, column)),
+        // rustfmt's own errors repeat the line: `<stdin>:2:2:101`.
+        [line, _, column] => Some((line, column)),
+        _ => None,
+    }
+}
+
+/// `text` without the (SGR) escape sequences that color it.
+fn strip_ansi_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        stripped.push_str(&rest[..start]);
+        let sequence = &rest[start + 2..];
+        rest = match sequence.find(|char: char| !(char.is_ascii_digit() || char == ';')) {
+            Some(end) if sequence[end..].starts_with('m') => &sequence[end + 1..],
+            _ => sequence,
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stderr() {
+        assert_eq!(
+            parse_stderr(
+                "error: mismatched closing delimiter: `}`
+ --> <stdin>:2:14
+  |
+1 | fn main() {
+  |           - closing delimiter possibly meant for this
+2 |     let x = é(;
+  |              ^ unclosed delimiter
+3 | }
+  | ^ mismatched closing delimiter
+
+"
+            ),
+            vec![StderrDiagnostic {
+                level: "error".to_owned(),
+                code: None,
+                message: "mismatched closing delimiter: `}`".to_owned(),
+                position: Some((2, 14)),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ignored_unstable_settings() {
+        let warning = |setting: &str| {
+            format!("Warning: can't set `{setting}`, unstable features are only available in nightly channel.\n")
+        };
+        assert_eq!(
+            ignored_unstable_settings(&format!(
+                "{}\x1b[1m{}\x1b[0m{}error: expected item, found `(`\n",
+                warning("imports_granularity = Crate"),
+                warning("wrap_comments = true"),
+                warning("imports_granularity = Crate"),
+            )),
+            ["imports_granularity = Crate", "wrap_comments = true"]
+        );
+        assert_eq!(
+            ignored_unstable_settings("Warning: can't set `[unstable]` []\n"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_parse_stderr_without_position() {
+        assert_eq!(
+            parse_stderr("error[E0001]: something went wrong\n --> src/other.rs:1:1\n"),
+            vec![StderrDiagnostic {
+                level: "error".to_owned(),
+                code: Some("E0001".to_owned()),
+                message: "something went wrong".to_owned(),
+                position: None,
+            }]
+        );
+        assert_eq!(parse_stderr("garbage\n --> <stdin>:1:1\n"), vec![]);
+    }
+
+    #[test]
+    fn test_parse_stderr_as() {
+        let stderr = "error: expected item\n --> src/a.rs:3:7\n";
+        assert_eq!(
+            parse_stderr_as(stderr, "src/a.rs")[0].position,
+            Some((3, 7))
+        );
+        assert_eq!(parse_stderr(stderr)[0].position, None);
+    }
+
+    #[test]
+    fn test_parse_stderr_internal() {
+        let diagnostics = parse_stderr(
+            "\x1b[1m\x1b[91merror[internal]\x1b[0m: \x1b[1mleft behind trailing whitespace\x1b[0m
+ \x1b[1m\x1b[94m-->\x1b[0m <stdin>:2:2:114
+  \x1b[1m\x1b[94m|\x1b[0m
+
+\x1b[1m\x1b[33mwarning\x1b[0m: \x1b[1mrustfmt has failed to format. See previous 1 errors.\x1b[0m
+",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                StderrDiagnostic {
+                    level: "error".to_owned(),
+                    code: Some("internal".to_owned()),
+                    message: "left behind trailing whitespace".to_owned(),
+                    position: Some((2, 114)),
+                },
+                StderrDiagnostic {
+                    level: "warning".to_owned(),
+                    code: None,
+                    message: "rustfmt has failed to format. See previous 1 errors.".to_owned(),
+                    position: None,
+                }
+            ]
+        );
+        assert!(diagnostics[0].is_internal());
+        assert!(!diagnostics[1].is_internal());
+    }
+}
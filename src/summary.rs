@@ -0,0 +1,165 @@
+use std::{fmt, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::FormattingReport;
+
+/// How far a file was from rustfmt's formatting when it was checked.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FileDiffStat {
+    pub path: PathBuf,
+    /// The regions rustfmt would change (its mismatches).
+    pub regions: usize,
+    /// The lines of rustfmt's formatting of the regions that aren't in the
+    /// file, as in a diff from the file to it.
+    pub lines_added: usize,
+    /// The lines of the regions that aren't in rustfmt's formatting of them.
+    pub lines_removed: usize,
+    /// Whether the rule's fixes were applied to the file.
+    pub fixed: bool,
+}
+
+impl FileDiffStat {
+    /// The stats of the file at `path` from the report of its check (before
+    /// any fixes).
+    pub fn new(path: PathBuf, report: &FormattingReport, fixed: bool) -> Self {
+        let (mut lines_added, mut lines_removed) = (0, 0);
+        for mismatch in &report.mismatches {
+            for line in diff::lines(&mismatch.original, &mismatch.expected) {
+                match line {
+                    diff::Result::Left(_) => lines_removed += 1,
+                    diff::Result::Right(_) => lines_added += 1,
+                    diff::Result::Both(..) => {}
+                }
+            }
+        }
+        Self {
+            path,
+            regions: report.mismatches.len(),
+            lines_added,
+            lines_removed,
+            fixed,
+        }
+    }
+}
+
+/// A run's [`FileDiffStat`]s, for tracking formatting debt over time (eg
+/// from CI). It serializes to JSON with serde, and displays as a table of
+/// the files that weren't formatted and the totals.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DiffStatSummary {
+    pub files: Vec<FileDiffStat>,
+}
+
+impl DiffStatSummary {
+    pub fn regions(&self) -> usize {
+        self.files.iter().map(|file| file.regions).sum()
+    }
+
+    pub fn lines_added(&self) -> usize {
+        self.files.iter().map(|file| file.lines_added).sum()
+    }
+
+    pub fn lines_removed(&self) -> usize {
+        self.files.iter().map(|file| file.lines_removed).sum()
+    }
+
+    /// How many files had fixes applied.
+    pub fn fixed(&self) -> usize {
+        self.files.iter().filter(|file| file.fixed).count()
+    }
+}
+
+impl fmt::Display for DiffStatSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unformatted = self
+            .files
+            .iter()
+            .filter(|file| file.regions > 0)
+            .collect::<Vec<_>>();
+        let total = format!("{} of {} files", unformatted.len(), self.files.len());
+        let width = unformatted
+            .iter()
+            .map(|file| file.path.display().to_string().chars().count())
+            .chain([total.len(), "file".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:width$}  {:>7}  {:>5}  {:>7}  fixed",
+            "file", "regions", "added", "removed"
+        )?;
+        for file in &unformatted {
+            writeln!(
+                f,
+                "{:width$}  {:>7}  {:>5}  {:>7}  {}",
+                file.path.display().to_string(),
+                file.regions,
+                file.lines_added,
+                file.lines_removed,
+                if file.fixed { "yes" } else { "no" }
+            )?;
+        }
+        writeln!(
+            f,
+            "{total:width$}  {:>7}  {:>5}  {:>7}  {}",
+            self.regions(),
+            self.lines_added(),
+            self.lines_removed(),
+            self.fixed()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mismatch;
+
+    #[test]
+    fn test_diff_stat_summary() {
+        let report = FormattingReport::from_mismatches(vec![
+            Mismatch {
+                original_begin_line: 1,
+                original_end_line: 1,
+                expected_begin_line: 1,
+                expected_end_line: 3,
+                original: "fn a() { b( ); }\n".to_owned(),
+                expected: "fn a() {\n    b();\n}\n".to_owned(),
+            },
+            Mismatch {
+                original_begin_line: 3,
+                original_end_line: 3,
+                expected_begin_line: 5,
+                expected_end_line: 5,
+                original: "use  c;\n".to_owned(),
+                expected: "use c;\n".to_owned(),
+            },
+        ]);
+        let summary = DiffStatSummary {
+            files: vec![
+                FileDiffStat::new("src/a.rs".into(), &report, true),
+                FileDiffStat::new("src/b.rs".into(), &FormattingReport::clean(), false),
+            ],
+        };
+        assert_eq!(
+            (summary.files[0].lines_added, summary.files[0].lines_removed),
+            (4, 2)
+        );
+        assert_eq!(
+            summary.to_string(),
+            "\
+file          regions  added  removed  fixed
+src/a.rs            2      4        2  yes
+1 of 2 files        2      4        2  1
+"
+        );
+        let json = serde_json::to_string(&summary).unwrap();
+        assert_eq!(
+            serde_json::from_str::<DiffStatSummary>(&json).unwrap(),
+            summary
+        );
+    }
+}
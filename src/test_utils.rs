@@ -0,0 +1,39 @@
+use tree_sitter::{Parser, Tree};
+
+use crate::{
+    fallback::diff_mismatches, FileMismatches, Formatter, FormatterCapabilities, RustfmtError,
+    RustfmtOptions,
+};
+
+pub fn parse(source: &str) -> Tree {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    parser.parse(source, None).unwrap()
+}
+
+/// A formatter (for any language) squeezing runs of spaces into one, like
+/// `sed 's/  */ /g'` but without needing sed.
+pub struct SqueezeSpaces;
+
+impl Formatter for SqueezeSpaces {
+    fn name(&self) -> &str {
+        "squeeze-spaces"
+    }
+
+    fn capabilities(&self, _options: &RustfmtOptions) -> FormatterCapabilities {
+        Default::default()
+    }
+
+    fn format_full(
+        &self,
+        source: &[u8],
+        _options: &RustfmtOptions,
+    ) -> Result<Vec<FileMismatches>, RustfmtError> {
+        let source = std::str::from_utf8(source)?;
+        let mut formatted = source.to_owned();
+        while formatted.contains("  ") {
+            formatted = formatted.replace("  ", " ");
+        }
+        Ok(diff_mismatches(source, &formatted))
+    }
+}
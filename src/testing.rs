@@ -0,0 +1,122 @@
+//! Assertions for downstream test suites that code is rustfmt-clean, eg for
+//! generated code:
+//!
+//! ```no_run
+//! use tree_sitter_lint_plugin_rustfmt::{assert_formatted, assert_formatted_file};
+//!
+//! assert_formatted!("fn main() {}\n");
+//! assert_formatted_file!("src/generated.rs");
+//! ```
+//!
+//! rustfmt is run the way the rule runs it, including the edition and config
+//! discovery.
+
+use std::{env, fs, path::Path};
+
+use crate::{discover_edition, format_source, render_hunk, RustfmtOptions, SymlinkPolicy};
+
+/// Panics with rustfmt's diff unless `source` is formatted. Takes optional
+/// [`RustfmtOptions`]; the edition defaults to that of the Cargo package
+/// containing the current directory.
+#[macro_export]
+macro_rules! assert_formatted {
+    ($source:expr $(,)?) => {
+        $crate::assert_formatted!($source, &$crate::RustfmtOptions::default())
+    };
+    ($source:expr, $options:expr $(,)?) => {
+        if let Err(message) = $crate::check_formatted($source, $options) {
+            panic!("{}", message);
+        }
+    };
+}
+
+/// Panics with rustfmt's diff unless the file at the given path is formatted.
+/// Takes optional [`RustfmtOptions`]; the rustfmt config and edition default
+/// to the ones that apply to the file.
+#[macro_export]
+macro_rules! assert_formatted_file {
+    ($path:expr $(,)?) => {
+        $crate::assert_formatted_file!($path, &$crate::RustfmtOptions::default())
+    };
+    ($path:expr, $options:expr $(,)?) => {
+        if let Err(message) = $crate::check_formatted_file($path, $options) {
+            panic!("{}", message);
+        }
+    };
+}
+
+/// What [`assert_formatted!`] checks: `Err` holds a description of what
+/// rustfmt would change, or why it couldn't be run.
+pub fn check_formatted(source: &str, options: &RustfmtOptions) -> Result<(), String> {
+    let mut options = options.clone();
+    if options.edition.is_none() {
+        options.edition = env::current_dir()
+            .ok()
+            .and_then(|dir| discover_edition(&dir));
+    }
+    check(source.as_bytes(), &options).map_err(|message| format!("source {message}"))
+}
+
+/// What [`assert_formatted_file!`] checks.
+pub fn check_formatted_file(
+    path: impl AsRef<Path>,
+    options: &RustfmtOptions,
+) -> Result<(), String> {
+    let path = path.as_ref();
+    let source =
+        fs::read(path).map_err(|error| format!("couldn't read {}: {error}", path.display()))?;
+    let options = options.for_path(path, SymlinkPolicy::Resolve);
+    check(&source, &options).map_err(|message| format!("{} {message}", path.display()))
+}
+
+fn check(source: &[u8], options: &RustfmtOptions) -> Result<(), String> {
+    let mismatches = format_source(source, options)
+        .map_err(|error| format!("couldn't be formatted: {error}"))?;
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    Err(mismatches
+        .iter()
+        .fold("isn't rustfmt-clean:\n".to_owned(), |message, mapped| {
+            message + &render_hunk(&mapped.mismatch, None)
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_formatted() {
+        assert_formatted!("fn main() {}\n");
+        assert_eq!(
+            check_formatted("fn  main() {}\n", &Default::default()),
+            Err(
+                "source isn't rustfmt-clean:\n@@ -1 +1 @@\n-fn  main() {}\n+fn main() {}\n"
+                    .to_owned()
+            )
+        );
+        assert!(check_formatted("fn main() {", &Default::default())
+            .unwrap_err()
+            .starts_with("source couldn't be formatted: "));
+    }
+
+    #[test]
+    fn test_assert_formatted_file() {
+        assert_formatted_file!(file!());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs");
+        fs::write(dir.path().join("rustfmt.toml"), "tab_spaces = 2\n").unwrap();
+        fs::write(&path, "fn main() {\n  let x = 1;\n}\n").unwrap();
+        assert_formatted_file!(&path);
+        assert!(check_formatted_file(
+            &path,
+            &RustfmtOptions {
+                config_path: Some(dir.path().join("missing.toml")),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+}
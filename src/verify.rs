@@ -0,0 +1,182 @@
+use tree_sitter::{Language, Node, Parser, Tree};
+
+/// Punctuation rustfmt adds or removes as part of formatting (trailing commas
+/// and semicolons, blocks around match arms, redundant parentheses).
+const IGNORED_TOKENS: &[&str] = &[",", ";", "{", "}", "(", ")"];
+
+/// Whether `fixed` has the same tokens as `original`, and the same syntax
+/// tree structure, ignoring whitespace and the punctuation, orderings and
+/// wrapping nodes (eg redundant parentheses) that rustfmt is expected to
+/// change.
+///
+/// This is a safety net against fixes being applied to the wrong bytes (which
+/// almost always changes identifiers, literals or operators), not a proof of
+/// semantic equivalence.
+pub fn is_pure_reformat(language: Language, original: &[u8], fixed: &[u8]) -> bool {
+    let (Some(original_tree), Some(fixed_tree)) =
+        (parse(language, original), parse(language, fixed))
+    else {
+        return false;
+    };
+    if fixed_tree.root_node().has_error() && !original_tree.root_node().has_error() {
+        return false;
+    }
+    significant_tokens(original_tree.root_node(), original)
+        == significant_tokens(fixed_tree.root_node(), fixed)
+}
+
+fn parse(language: Language, source: &[u8]) -> Option<Tree> {
+    let mut parser = Parser::new();
+    parser.set_language(language).ok()?;
+    parser.parse(source, None)
+}
+
+fn significant_tokens(node: Node, source: &[u8]) -> Vec<String> {
+    let mut tokens = Vec::new();
+    push_tokens(node, source, &mut tokens);
+    tokens
+}
+
+fn push_tokens(node: Node, source: &[u8], tokens: &mut Vec<String>) {
+    let text = || String::from_utf8_lossy(&source[node.byte_range()]);
+    match node.kind() {
+        "line_comment" | "block_comment" => {
+            tokens.push(text().split_whitespace().collect::<Vec<_>>().join(" "));
+            return;
+        }
+        "string_literal" | "raw_string_literal" | "char_literal" => {
+            tokens.push(text().into_owned());
+            return;
+        }
+        _ => (),
+    }
+    if node.is_missing() {
+        tokens.push(format!("<missing {}>", node.kind()));
+        return;
+    }
+    if node.child_count() == 0 {
+        let text = text();
+        if !IGNORED_TOKENS.contains(&&*text) {
+            tokens.push(text.into_owned());
+        }
+        return;
+    }
+    // The nesting of the other nodes, so that eg `(a - b) * c` doesn't
+    // become `a - b * c`.
+    if node.is_named() && !is_wrapper(node) {
+        tokens.push(format!("<{}>", node.kind()));
+        push_children(node, source, tokens);
+        tokens.push("</>".to_owned());
+    } else {
+        push_children(node, source, tokens);
+    }
+}
+
+/// Nodes that rustfmt adds or removes around others: redundant parentheses,
+/// blocks around match arms, and statements of trailing expressions it adds
+/// a `;` to.
+fn is_wrapper(node: Node) -> bool {
+    match node.kind() {
+        "parenthesized_expression" | "expression_statement" => true,
+        "block" => node
+            .parent()
+            .is_some_and(|parent| parent.kind() == "match_arm"),
+        _ => false,
+    }
+}
+
+fn push_children(node: Node, source: &[u8], tokens: &mut Vec<String>) {
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect::<Vec<_>>();
+    if node.kind() == "use_list" {
+        // `reorder_imports` sorts the items of `use a::{c, b};`.
+        let mut items = children
+            .into_iter()
+            .map(|child| significant_tokens(child, source))
+            .collect::<Vec<_>>();
+        items.sort();
+        tokens.extend(items.into_iter().flatten());
+        return;
+    }
+    // `reorder_imports`/`reorder_modules` sort runs of adjacent `use`s,
+    // `extern crate`s and `mod foo;`s.
+    let mut run: Vec<Vec<String>> = Default::default();
+    for child in children {
+        if is_reorderable(child) {
+            run.push(significant_tokens(child, source));
+            continue;
+        }
+        flush_sorted(&mut run, tokens);
+        push_tokens(child, source, tokens);
+    }
+    flush_sorted(&mut run, tokens);
+}
+
+fn is_reorderable(node: Node) -> bool {
+    match node.kind() {
+        "use_declaration" | "extern_crate_declaration" => true,
+        "mod_item" => node.child_by_field_name("body").is_none(),
+        _ => false,
+    }
+}
+
+fn flush_sorted(run: &mut Vec<Vec<String>>, tokens: &mut Vec<String>) {
+    run.sort();
+    tokens.extend(run.drain(..).flatten());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_pure_reformat_str(original: &str, fixed: &str) -> bool {
+        is_pure_reformat(
+            tree_sitter_rust::language(),
+            original.as_bytes(),
+            fixed.as_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_pure_reformats() {
+        for (original, fixed) in [
+            ("fn  a( ) {}", "fn a() {}\n"),
+            ("fn a() { let x=1; }", "fn a() {\n    let x = 1;\n}\n"),
+            ("fn a(b: u8,) {}", "fn a(b: u8) {}"),
+            (
+                "fn a() { match x { 1 => foo(), _ => {} } }",
+                "fn a() {\n    match x {\n        1 => {\n            foo()\n        }\n        _ => {}\n    }\n}\n",
+            ),
+            ("use b;\nuse a::{d, c};\n", "use a::{c, d};\nuse b;\n"),
+            ("mod b;\nmod a;\n", "mod a;\nmod b;\n"),
+            ("// a  comment  \nfn a() {}", "// a comment\nfn a() {}"),
+            ("fn a() { return 1 }", "fn a() {\n    return 1;\n}"),
+            ("fn a() -> u8 { (1) + 2 }", "fn a() -> u8 {\n    1 + 2\n}\n"),
+        ] {
+            assert!(
+                is_pure_reformat_str(original, fixed),
+                "{original:?} -> {fixed:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_changed_tokens() {
+        for (original, fixed) in [
+            ("fn a() {}\nfn b() {}\n", "fn a() {}\n"),
+            ("fn a() {}\nfn b() {}\n", "fn b() {}\nfn a() {}\n"),
+            ("fn a() { let x = 1; }", "fn a() { let x = 2; }"),
+            ("fn a() { \"a  b\" }", "fn a() { \"a b\" }"),
+            ("// a comment\nfn a() {}", "// a\nfn a() {}"),
+            ("fn a() {}", "fn a() {"),
+            ("use a;\nfn f() {}\nuse b;\n", "use b;\nfn f() {}\nuse a;\n"),
+            ("fn a() -> u8 { (1 - 2) * 3 }", "fn a() -> u8 { 1 - 2 * 3 }"),
+            ("fn a() { b(c, d) }", "fn a() { b(c)(d) }"),
+        ] {
+            assert!(
+                !is_pure_reformat_str(original, fixed),
+                "{original:?} -> {fixed:?}"
+            );
+        }
+    }
+}
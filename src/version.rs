@@ -0,0 +1,148 @@
+use std::{fmt, sync::OnceLock};
+
+use semver::{Version, VersionReq};
+
+use crate::{memo::Memo, MismatchFormat, RustfmtError, RustfmtOptions};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RustfmtVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl RustfmtVersion {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses the output of `rustfmt --version`, eg
+    /// `rustfmt 1.6.0-nightly (f5b8f44e 2023-08-17)`.
+    pub fn parse(version_output: &str) -> Option<Self> {
+        let version = version_output
+            .trim()
+            .strip_prefix("rustfmt ")?
+            .split([' ', '-'])
+            .next()?;
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+        let version = Self::new(parts.next()??, parts.next()??, parts.next()??);
+        parts.next().is_none().then_some(version)
+    }
+
+    /// Whether this version satisfies a rustfmt config's `required_version`.
+    /// Like rustfmt, a bare version (eg `1.5` or `1.5.1`) has to match
+    /// exactly, while anything else is read as a semver requirement (eg
+    /// `>=1.5`). `None` means the requirement doesn't parse.
+    pub fn meets_requirement(self, requirement: &str) -> Option<bool> {
+        let requirement = requirement.trim();
+        let requirement = if requirement.starts_with(|char: char| char.is_ascii_digit()) {
+            VersionReq::parse(&format!("={requirement}"))
+        } else {
+            VersionReq::parse(requirement)
+        }
+        .ok()?;
+        Some(requirement.matches(&Version::new(self.major, self.minor, self.patch)))
+    }
+}
+
+impl fmt::Display for RustfmtVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl RustfmtOptions {
+    /// The version of the rustfmt these options invoke. Probed once per
+    /// command and toolchain, and cached for the rest of the process.
+    pub fn version(&self) -> Result<RustfmtVersion, RustfmtError> {
+        let output = self.version_output()?;
+        RustfmtVersion::parse(&output)
+            .ok_or_else(|| RustfmtError::OutputParse(format!("unknown version: {output:?}")))
+    }
+
+    /// What the rustfmt these options invoke prints for `--version`, eg
+    /// `rustfmt 1.6.0-nightly (f5b8f44e 2023-08-17)`, trimmed. Probed (and
+    /// cached) along with [`version()`](Self::version).
+    pub fn version_output(&self) -> Result<String, RustfmtError> {
+        static OUTPUTS: OnceLock<Memo<Vec<String>, String>> = OnceLock::new();
+
+        OUTPUTS
+            .get_or_init(Default::default)
+            .get_or_try_insert_with(&self.invocation(), || self.probe_version())
+    }
+
+    /// The `--emit json` format of the rustfmt these options invoke, failing
+    /// with [`RustfmtError::UnsupportedVersion`] for versions with no known
+    /// format.
+    pub fn mismatch_format(&self) -> Result<MismatchFormat, RustfmtError> {
+        let version = self.version()?;
+        MismatchFormat::for_version(version).ok_or(RustfmtError::UnsupportedVersion(version))
+    }
+
+    fn probe_version(&self) -> Result<String, RustfmtError> {
+        let output = self
+            .base_command()
+            .arg("--version")
+            .output()
+            .map_err(RustfmtError::SpawnFailed)?;
+        if !output.status.success() {
+            return Err(RustfmtError::NonZeroExit {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            RustfmtVersion::parse("rustfmt 1.6.0-nightly (f5b8f44e 2023-08-17)\n"),
+            Some(RustfmtVersion::new(1, 6, 0))
+        );
+        assert_eq!(
+            RustfmtVersion::parse("rustfmt 1.4.38-stable (fc594f15 2021-11-04)"),
+            Some(RustfmtVersion::new(1, 4, 38))
+        );
+        assert_eq!(
+            RustfmtVersion::parse("rustfmt 1.5.1"),
+            Some(RustfmtVersion::new(1, 5, 1))
+        );
+        assert_eq!(RustfmtVersion::parse("rustfmt 1.5"), None);
+        assert_eq!(RustfmtVersion::parse("rustfmt 1.5.1.2"), None);
+        assert_eq!(
+            RustfmtVersion::parse("error: toolchain not installed"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_meets_requirement() {
+        let version = RustfmtVersion::new(1, 6, 0);
+        assert_eq!(version.meets_requirement("1.6.0"), Some(true));
+        assert_eq!(version.meets_requirement("1.6"), Some(true));
+        assert_eq!(version.meets_requirement("1.5.1"), Some(false));
+        assert_eq!(version.meets_requirement("1"), Some(true));
+        assert_eq!(version.meets_requirement(">=1.5"), Some(true));
+        assert_eq!(version.meets_requirement("<1.6"), Some(false));
+        assert_eq!(version.meets_requirement("latest"), None);
+    }
+
+    #[test]
+    fn test_version() {
+        let version = RustfmtOptions::default().version().unwrap();
+        assert!(version >= RustfmtVersion::new(1, 0, 0));
+        assert_eq!(RustfmtOptions::default().version().unwrap(), version);
+        let output = RustfmtOptions::default().version_output().unwrap();
+        assert_eq!(RustfmtVersion::parse(&output), Some(version));
+    }
+}
@@ -0,0 +1,204 @@
+use tree_sitter::{Node, Tree};
+
+use crate::{node_cache::NodeCache, Fix, LineOffsets, Violation};
+
+pub const TRAILING_WHITESPACE_RULE_NAME: &str = "trailing-whitespace";
+pub const FINAL_NEWLINE_RULE_NAME: &str = "final-newline";
+
+pub const TRAILING_WHITESPACE: &str = "trailing_whitespace";
+pub const MISSING_FINAL_NEWLINE: &str = "missing_final_newline";
+pub const EXTRA_FINAL_NEWLINES: &str = "extra_final_newlines";
+
+/// Reports spaces and tabs at the ends of lines, except inside string
+/// literals (where they're part of the string). Doesn't need rustfmt.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrailingWhitespaceRule;
+
+impl TrailingWhitespaceRule {
+    pub fn check<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        let mut nodes = NodeCache::for_source(tree.root_node(), source);
+        let mut violations = Vec::new();
+        let mut line_start = 0;
+        while line_start < source.len() {
+            let line_end = source[line_start..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(source.len(), |newline| line_start + newline);
+            let content_end = if source[line_start..line_end].ends_with(b"\r") {
+                line_end - 1
+            } else {
+                line_end
+            };
+            let whitespace_start = source[line_start..content_end]
+                .iter()
+                .rposition(|&byte| byte != b' ' && byte != b'\t')
+                .map_or(line_start, |last| line_start + last + 1);
+            if whitespace_start < content_end {
+                let range = whitespace_start..content_end;
+                let node = nodes.descendant_for_byte_range(range.clone());
+                if !is_in_string(node) {
+                    violations.push(Violation {
+                        message_id: TRAILING_WHITESPACE,
+                        message: "Trailing whitespace.".to_owned(),
+                        node,
+                        range: range.clone(),
+                        fix: Some(Fix {
+                            range,
+                            replacement: Default::default(),
+                        }),
+                    });
+                }
+            }
+            line_start = line_end + 1;
+        }
+        violations
+    }
+}
+
+fn is_in_string(node: Node) -> bool {
+    let mut node = Some(node);
+    while let Some(current) = node {
+        if matches!(current.kind(), "string_literal" | "raw_string_literal") {
+            return true;
+        }
+        node = current.parent();
+    }
+    false
+}
+
+/// Requires non-empty files to end with exactly one newline (`\n` or
+/// `\r\n`, either of which counts as one). Doesn't need rustfmt.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FinalNewlineRule;
+
+impl FinalNewlineRule {
+    pub fn check<'tree>(&self, source: &[u8], tree: &'tree Tree) -> Vec<Violation<'tree>> {
+        let line_ending = if LineOffsets::new(source).has_crlf() {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let content_end = source
+            .iter()
+            .rposition(|&byte| byte != b'\n' && byte != b'\r')
+            .map_or(0, |last| last + 1);
+        if content_end == 0 {
+            return Default::default();
+        }
+        // A `\r\n` (or a stray `\r`) is one line ending, so that a CRLF
+        // file's last line ending with just `\n` isn't taken for a blank
+        // line. Which ending it is is left to rustfmt's line ending checks.
+        let trailing = &source[content_end..];
+        let first_ending = match trailing {
+            [b'\r', b'\n', ..] => "\r\n",
+            [b'\r', ..] => "\r",
+            _ => "\n",
+        };
+        let (message_id, message, replacement) = match line_ending_count(trailing) {
+            0 => (
+                MISSING_FINAL_NEWLINE,
+                "Missing newline at the end of the file.",
+                line_ending,
+            ),
+            1 => return Default::default(),
+            _ => (
+                EXTRA_FINAL_NEWLINES,
+                "Blank lines at the end of the file.",
+                first_ending,
+            ),
+        };
+        let range = content_end..source.len();
+        vec![Violation {
+            message_id,
+            message: message.to_owned(),
+            node: tree.root_node(),
+            range: range.clone(),
+            fix: Some(Fix {
+                range,
+                replacement: replacement.to_owned(),
+            }),
+        }]
+    }
+}
+
+/// How many line endings `text` (which is all `\r`s and `\n`s) is.
+fn line_ending_count(text: &[u8]) -> usize {
+    let mut count = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        rest = &rest[if rest.starts_with(b"\r\n") { 2 } else { 1 }..];
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::parse;
+
+    #[test]
+    fn test_trailing_whitespace() {
+        let source = "fn a() { \t\n    let b = \"c  \n\";  \r\n    // d \n}\n  ";
+        let tree = parse(source);
+        let violations = TrailingWhitespaceRule.check(source.as_bytes(), &tree);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| violation.range.clone())
+                .collect::<Vec<_>>(),
+            [8..10, 30..32, 42..43, 46..48]
+        );
+        assert_eq!(
+            String::from_utf8(Fix::apply_all(
+                source.as_bytes(),
+                violations
+                    .iter()
+                    .filter_map(|violation| violation.fix.as_ref())
+            ))
+            .unwrap(),
+            "fn a() {\n    let b = \"c  \n\";\r\n    // d\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_final_newline() {
+        let check = |source: &str| {
+            let tree = parse(source);
+            FinalNewlineRule
+                .check(source.as_bytes(), &tree)
+                .into_iter()
+                .map(|violation| {
+                    (
+                        violation.message_id,
+                        violation.fix.unwrap().apply(source.as_bytes()),
+                    )
+                })
+                .next()
+        };
+        assert_eq!(check("fn a() {}\n"), None);
+        assert_eq!(check(""), None);
+        assert_eq!(
+            check("fn a() {}"),
+            Some((MISSING_FINAL_NEWLINE, b"fn a() {}\n".to_vec()))
+        );
+        assert_eq!(
+            check("fn a() {}\r\n\r\n"),
+            Some((EXTRA_FINAL_NEWLINES, b"fn a() {}\r\n".to_vec()))
+        );
+        // One line ending, whichever it is.
+        assert_eq!(check("fn a() {}\r\nfn b() {}\n"), None);
+        assert_eq!(check("fn a() {}\nfn b() {}\r\n"), None);
+        assert_eq!(
+            check("fn a() {}\r\nfn b() {}"),
+            Some((
+                MISSING_FINAL_NEWLINE,
+                b"fn a() {}\r\nfn b() {}\r\n".to_vec()
+            ))
+        );
+        assert_eq!(
+            check("fn a() {}\r\nfn b() {}\n\r\n"),
+            Some((EXTRA_FINAL_NEWLINES, b"fn a() {}\r\nfn b() {}\n".to_vec()))
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Runs the rule over each directory in `tests/fixtures/`, which has an
+//! `input.rs`, the `expected.rs` that applying the rule's fixes should turn it
+//! into, and optionally a `rustfmt.toml` to format it with. Fixtures are
+//! checked both as a whole and with `--file-lines` (over all of their lines),
+//! and their expected output has to be clean.
+//!
+//! The expected outputs are regenerated by running the tests with
+//! `UPDATE_FIXTURES` set.
+
+#![allow(clippy::single_range_in_vec_init)]
+
+use std::{env, fs, path::Path};
+
+use tree_sitter::{Parser, Tree};
+use tree_sitter_lint_plugin_rustfmt::api::{
+    Fix, RustfmtOptions, RustfmtRule, RustfmtRuleOptions, Violation,
+};
+
+fn parse(source: &[u8]) -> Tree {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    parser.parse(source, None).unwrap()
+}
+
+fn fixed(source: &[u8], violations: &[Violation]) -> String {
+    let fixed = Fix::apply_all(
+        source,
+        violations
+            .iter()
+            .filter_map(|violation| violation.fix.as_ref()),
+    );
+    String::from_utf8(fixed).unwrap()
+}
+
+fn rule_for(fixture: &Path) -> RustfmtRule {
+    let config_path = fixture.join("rustfmt.toml");
+    RustfmtRule::new(RustfmtRuleOptions {
+        rustfmt: RustfmtOptions {
+            config_path: config_path.is_file().then_some(config_path),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+}
+
+/// How `fixture` comes out when checked as a whole and by lines, and whether
+/// checking its expected output reports anything.
+fn check_fixture(fixture: &Path, expected: &str) -> Vec<String> {
+    let rule = rule_for(fixture);
+    let source = fs::read(fixture.join("input.rs")).unwrap();
+    let tree = parse(&source);
+    let line_count = source.split_inclusive(|&byte| byte == b'\n').count();
+
+    let mut failures = Vec::new();
+    for (mode, output) in [
+        ("full", fixed(&source, &rule.check(&source, &tree))),
+        (
+            "file-lines",
+            fixed(
+                &source,
+                &rule
+                    .check_lines(&source, &tree, &[0..line_count])
+                    .violations,
+            ),
+        ),
+    ] {
+        if output != expected {
+            failures.push(format!(
+                "{} ({mode}):\n--- expected\n{expected}--- actual\n{output}",
+                fixture.display()
+            ));
+        }
+    }
+    let expected_tree = parse(expected.as_bytes());
+    let leftover = rule.check(expected.as_bytes(), &expected_tree);
+    if !leftover.is_empty() {
+        failures.push(format!(
+            "{}: expected.rs isn't clean: {:?}",
+            fixture.display(),
+            leftover
+                .iter()
+                .map(|violation| &violation.message)
+                .collect::<Vec<_>>()
+        ));
+    }
+    failures
+}
+
+#[test]
+fn test_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut fixtures = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+
+    let mut failures = Vec::new();
+    for fixture in &fixtures {
+        let expected_path = fixture.join("expected.rs");
+        if env::var_os("UPDATE_FIXTURES").is_some() {
+            let source = fs::read(fixture.join("input.rs")).unwrap();
+            let tree = parse(&source);
+            fs::write(
+                &expected_path,
+                fixed(&source, &rule_for(fixture).check(&source, &tree)),
+            )
+            .unwrap();
+        }
+        let expected = fs::read_to_string(&expected_path).unwrap();
+        failures.extend(check_fixture(fixture, &expected));
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\n(run the tests with UPDATE_FIXTURES=1 to regenerate expected.rs files)",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,3 @@
+fn main() {
+    let sum = first_value + second_value + third_value;
+}
@@ -0,0 +1,5 @@
+fn a( ) {
+    b( );
+}
+
+fn  c() {}
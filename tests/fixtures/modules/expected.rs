@@ -0,0 +1,11 @@
+//! `mod` declarations are left to their own files, which are never formatted
+//! along with this one.
+
+mod child;
+#[path = "other.rs"]
+mod other;
+
+mod inline {
+    mod grandchild;
+    fn a() {}
+}
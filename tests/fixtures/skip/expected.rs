@@ -0,0 +1,12 @@
+#[rustfmt::skip]
+const MATRIX: [i32; 4] = [
+    1, 0,
+    0, 1,
+];
+
+fn a() {}
+
+#[rustfmt::skip]
+mod b {
+    fn  c() {}
+}
@@ -0,0 +1,178 @@
+//! Checks the mapping of rustfmt's mismatches to byte ranges against
+//! generated files: random Rust-ish items with random spacing, indentation,
+//! blank lines and line endings. The slice and rope code paths have to map
+//! them the same way, and applying every fix has to give exactly what
+//! rustfmt formats the file as (in the file's line endings, which fixes keep
+//! even though rustfmt's own output only has `\n`s).
+//!
+//! The files come from a fixed xorshift sequence, so failures are
+//! reproducible: a failing case is reported with its seed and source.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use ropey::Rope;
+use tree_sitter::{Parser, Tree};
+use tree_sitter_lint_plugin_rustfmt::api::{
+    format_source, format_to_string, Fix, RustfmtRule, RustfmtRuleOptions,
+};
+
+const CASES: u64 = 24;
+
+const ITEMS: &[&str] = &[
+    "use std::{fmt, io};",
+    "use a::b as c;",
+    "const NAME: u8 = 1;",
+    "static mut COUNT: usize = 0;",
+    "fn name(a: u8, b: u16) -> u32 { a as u32 + b as u32 }",
+    "fn name() {\nlet x = 1;\nif x > 0 {\nprintln!(\"{}\", x);\n}\n}",
+    "struct Name { a: u8, b: Vec<u16> }",
+    "enum Name { A, B(u8), C { d: u16 } }",
+    "impl Name {\nfn get(&self) -> u8 { self.a }\n}",
+    "// A comment.",
+    "/// Docs.\n#[derive(Debug)]\nstruct Unit;",
+    "type Alias = Result<(), Box<dyn std::error::Error>>;",
+    "mod inner {\npub fn f() -> [u8; 2] { [1, 2] }\n}",
+];
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        self.next() % bound
+    }
+}
+
+/// A file of `ITEMS` with their spaces, indentation and the blank lines
+/// between them scrambled, in LF or CRLF line endings.
+fn generate(rng: &mut Rng) -> String {
+    let mut lines = Vec::new();
+    for _ in 0..rng.below(3) {
+        lines.push(String::new());
+    }
+    for _ in 0..1 + rng.below(40) {
+        for line in ITEMS[rng.below(ITEMS.len())].lines() {
+            let mut scrambled = " ".repeat(rng.below(3) * 2);
+            for (index, word) in line.split(' ').enumerate() {
+                if index > 0 {
+                    scrambled.push_str(&" ".repeat(1 + rng.below(2) * rng.below(3)));
+                }
+                scrambled.push_str(word);
+            }
+            if rng.below(6) == 0 {
+                scrambled.push_str(&" ".repeat(1 + rng.below(2)));
+            }
+            lines.push(scrambled);
+        }
+        for _ in 0..rng.below(4) {
+            lines.push(String::new());
+        }
+    }
+    let line_ending = if rng.below(3) == 0 { "\r\n" } else { "\n" };
+    let mut source = lines.join(line_ending);
+    if rng.below(8) != 0 {
+        source.push_str(line_ending);
+    }
+    source
+}
+
+/// A rope holding `source` whose chunks end at arbitrary points.
+fn rope(rng: &mut Rng, source: &str) -> Rope {
+    let mut rope = Rope::new();
+    let mut rest = source;
+    while !rest.is_empty() {
+        let mut split = (1 + rng.below(64)).min(rest.len());
+        while !rest.is_char_boundary(split) {
+            split += 1;
+        }
+        let (piece, remainder) = rest.split_at(split);
+        rope.insert(rope.len_chars(), piece);
+        rest = remainder;
+    }
+    rope
+}
+
+fn parse(source: &[u8]) -> Tree {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    parser.parse(source, None).unwrap()
+}
+
+/// `source` as rustfmt formats it itself, in its line endings.
+fn rustfmt(source: &str) -> String {
+    let mut child = Command::new("rustfmt")
+        .args(["--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "rustfmt failed on:\n{source}");
+    let formatted = String::from_utf8(output.stdout).unwrap();
+    if source.contains("\r\n") {
+        formatted.replace('\n', "\r\n")
+    } else {
+        formatted
+    }
+}
+
+fn fixed(rule: &RustfmtRule, source: &str) -> String {
+    let tree = parse(source.as_bytes());
+    let violations = rule.check(source.as_bytes(), &tree);
+    let fixed = Fix::apply_all(
+        source.as_bytes(),
+        violations
+            .iter()
+            .filter_map(|violation| violation.fix.as_ref()),
+    );
+    String::from_utf8(fixed).unwrap()
+}
+
+#[test]
+fn test_range_mapping() {
+    let rule = RustfmtRule::new(RustfmtRuleOptions::default());
+    let options = Default::default();
+    for seed in 1..=CASES {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let source = generate(&mut rng);
+        let rope = rope(&mut rng, &source);
+        let context = format!("seed {seed}, source:\n{source:?}");
+
+        let mapped = format_source(source.as_bytes(), &options).unwrap();
+        assert_eq!(format_source(&rope, &options).unwrap(), mapped, "{context}");
+        for mapped in &mapped {
+            assert!(
+                mapped.range.end <= source.len() && source.is_char_boundary(mapped.range.start),
+                "{context}"
+            );
+        }
+
+        let formatted = rustfmt(&source);
+        assert_eq!(
+            format_to_string(source.as_bytes(), &options).unwrap(),
+            formatted,
+            "{context}"
+        );
+        assert_eq!(
+            format_to_string(&rope, &options).unwrap(),
+            formatted,
+            "{context}"
+        );
+        assert_eq!(fixed(&rule, &source), formatted, "{context}");
+    }
+}